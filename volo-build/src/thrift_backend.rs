@@ -812,7 +812,7 @@ impl pilota_build::CodegenBackend for VoloThriftBackend {
                     {res_recv_name},
                     ::volo::net::dial::DefaultMakeTransport,
                     ::volo_thrift::codec::default::DefaultMakeCodec<::volo_thrift::codec::default::ttheader::MakeTTHeaderCodec<::volo_thrift::codec::default::framed::MakeFramedCodec<::volo_thrift::codec::default::thrift::MakeThriftCodec>>>,
-                    ::volo::loadbalance::LbConfig<::volo::loadbalance::random::WeightedRandomBalance<()>, ::volo::discovery::DummyDiscover>,
+                    ::volo::loadbalance::LbConfig<::volo::loadbalance::random::WeightedRandomBalance<()>, ::volo::discovery::DummyDiscover, ::volo::loadbalance::MetaInfoHashKey, ::volo::loadbalance::hedge::Hedging>,
                 >
                 {{
                     ::volo_thrift::client::ClientBuilder::new(service_name, {mk_client_name})
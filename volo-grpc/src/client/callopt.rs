@@ -41,6 +41,9 @@ pub struct CallOpt {
     /// Sets the callee faststr_tags for the call.
     pub callee_faststr_tags: FastStrMap,
     /// Sets the callee tags for the call.
+    ///
+    /// For example, a [`HedgePolicy`](volo::loadbalance::hedge::HedgePolicy) enables hedging for
+    /// the call.
     pub callee_tags: TypeMap,
     /// Sets the address for the call.
     ///
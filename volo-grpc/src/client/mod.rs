@@ -24,7 +24,7 @@ use volo::{
     context::{Endpoint, Role, RpcInfo},
     discovery::Discover,
     loadbalance::{MkLbLayer, random::WeightedRandomBalance},
-    net::Address,
+    net::{Address, sockopt::SocketOptions},
 };
 
 use self::{dns::DnsResolver, layer::timeout::TimeoutLayer};
@@ -85,11 +85,11 @@ impl<C, T, U>
     }
 }
 
-impl<IL, OL, C, LB, T, U, DISC> ClientBuilder<IL, OL, C, LbConfig<LB, DISC>, T, U> {
+impl<IL, OL, C, LB, T, U, DISC, R, H> ClientBuilder<IL, OL, C, LbConfig<LB, DISC, R, H>, T, U> {
     pub fn load_balance<NLB>(
         self,
         load_balance: NLB,
-    ) -> ClientBuilder<IL, OL, C, LbConfig<NLB, DISC>, T, U> {
+    ) -> ClientBuilder<IL, OL, C, LbConfig<NLB, DISC, R, H>, T, U> {
         ClientBuilder {
             http2_config: self.http2_config,
             rpc_config: self.rpc_config,
@@ -110,7 +110,7 @@ impl<IL, OL, C, LB, T, U, DISC> ClientBuilder<IL, OL, C, LbConfig<LB, DISC>, T,
     pub fn discover<NDISC>(
         self,
         discover: NDISC,
-    ) -> ClientBuilder<IL, OL, C, LbConfig<LB, NDISC>, T, U> {
+    ) -> ClientBuilder<IL, OL, C, LbConfig<LB, NDISC, R, H>, T, U> {
         ClientBuilder {
             http2_config: self.http2_config,
             rpc_config: self.rpc_config,
@@ -127,6 +127,58 @@ impl<IL, OL, C, LB, T, U, DISC> ClientBuilder<IL, OL, C, LbConfig<LB, DISC>, T,
             tls_config: self.tls_config,
         }
     }
+
+    /// Sets how to copy the requests for the hedged attempts.
+    ///
+    /// Hedging is enabled per call by inserting a
+    /// [`HedgePolicy`](volo::loadbalance::hedge::HedgePolicy) into [`CallOpt::callee_tags`], and
+    /// it does not take effect for the requests which cannot be copied.
+    pub fn replay<NR>(
+        self,
+        replay: NR,
+    ) -> ClientBuilder<IL, OL, C, LbConfig<LB, DISC, NR, H>, T, U> {
+        ClientBuilder {
+            http2_config: self.http2_config,
+            rpc_config: self.rpc_config,
+            callee_name: self.callee_name,
+            caller_name: self.caller_name,
+            target: self.target,
+            inner_layer: self.inner_layer,
+            outer_layer: self.outer_layer,
+            mk_client: self.mk_client,
+            mk_lb: self.mk_lb.replay(replay),
+            _marker: PhantomData,
+
+            #[cfg(feature = "__tls")]
+            tls_config: self.tls_config,
+        }
+    }
+
+    /// Sets how to compute the [`RequestHash`](volo::loadbalance::RequestHash) of the requests
+    /// for the hashing load balancers, e.g.,
+    /// [`MetadataHashKey`](crate::layer::loadbalance::MetadataHashKey).
+    ///
+    /// The [`RequestHash`](volo::loadbalance::RequestHash) in the metainfo is used by default.
+    pub fn hash_key<NH>(
+        self,
+        hash_key: NH,
+    ) -> ClientBuilder<IL, OL, C, LbConfig<LB, DISC, R, NH>, T, U> {
+        ClientBuilder {
+            http2_config: self.http2_config,
+            rpc_config: self.rpc_config,
+            callee_name: self.callee_name,
+            caller_name: self.caller_name,
+            target: self.target,
+            inner_layer: self.inner_layer,
+            outer_layer: self.outer_layer,
+            mk_client: self.mk_client,
+            mk_lb: self.mk_lb.hash_key(hash_key),
+            _marker: PhantomData,
+
+            #[cfg(feature = "__tls")]
+            tls_config: self.tls_config,
+        }
+    }
 }
 
 impl<IL, OL, C, LB, T, U> ClientBuilder<IL, OL, C, LB, T, U> {
@@ -258,6 +310,12 @@ impl<IL, OL, C, LB, T, U> ClientBuilder<IL, OL, C, LB, T, U> {
         self
     }
 
+    /// Sets the socket options for the connections, e.g., TCP keepalive and the buffer sizes.
+    pub fn socket_options(mut self, options: SocketOptions) -> Self {
+        self.rpc_config.socket_options = Some(options);
+        self
+    }
+
     /// Sets the timeout for the response.
     ///
     /// Default is no timeout.
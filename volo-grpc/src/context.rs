@@ -3,7 +3,10 @@ use std::time::Duration;
 use chrono::{DateTime, Local};
 use paste::paste;
 pub use volo::context::*;
-use volo::newtype_impl_context;
+use volo::{
+    loadbalance::hedge::ForkContext, net::sockopt::SocketOptions, newtype_impl_context,
+    retry::RpcTimeout,
+};
 
 use crate::codec::compression::CompressionEncoding;
 
@@ -85,6 +88,26 @@ impl ClientContext {
     }
 }
 
+impl ForkContext for ClientContext {
+    fn fork(&self) -> Self {
+        let ri = self.rpc_info();
+        let caller = ri.caller().fork();
+        let callee = ri.callee().fork();
+        Self::new(RpcInfo::new(
+            ri.role(),
+            ri.method().clone(),
+            caller,
+            callee,
+            ri.config().clone(),
+        ))
+    }
+
+    fn join(&mut self, fork: Self) {
+        self.rpc_info_mut().callee_mut().address = fork.rpc_info().callee().address();
+        self.0.inner = fork.0.inner;
+    }
+}
+
 impl Default for ClientContext {
     fn default() -> Self {
         Self::new(RpcInfo::with_role(Role::Client))
@@ -154,17 +177,27 @@ pub struct Config {
     pub(crate) read_timeout: Option<Duration>,
     /// Amount of time to wait reading response.
     pub(crate) write_timeout: Option<Duration>,
+    /// Options of the sockets for connecting.
+    pub(crate) socket_options: Option<SocketOptions>,
 
     pub(crate) accept_compressions: Option<Vec<CompressionEncoding>>,
     pub(crate) send_compressions: Option<Vec<CompressionEncoding>>,
 }
 
+impl RpcTimeout for Config {
+    #[inline]
+    fn rpc_timeout(&self) -> Option<Duration> {
+        self.rpc_timeout
+    }
+}
+
 impl Reusable for Config {
     fn clear(&mut self) {
         self.rpc_timeout = None;
         self.connect_timeout = None;
         self.read_timeout = None;
         self.write_timeout = None;
+        self.socket_options = None;
         if let Some(v) = self.accept_compressions.as_mut() {
             v.clear();
         }
@@ -188,6 +221,9 @@ impl Config {
         if let Some(t) = other.write_timeout {
             self.write_timeout = Some(t);
         }
+        if let Some(o) = other.socket_options {
+            self.socket_options = Some(o);
+        }
         if let Some(e) = other.accept_compressions {
             self.accept_compressions = Some(e);
         }
@@ -7,15 +7,44 @@ use volo::{
     Layer,
     context::Context,
     discovery::Discover,
-    loadbalance::{LoadBalance, MkLbLayer, error::LoadBalanceError},
+    loadbalance::{
+        CallOutcome, CallTracker, HashKeyExtractor, LoadBalance, MetaInfoHashKey, MkLbLayer,
+        RequestHash,
+        error::LoadBalanceError,
+        hedge::{ForkContext, HedgePolicy, LatencyTracker, call_hedged},
+    },
+    retry::{NoReplay, Replay},
 };
 
-use crate::Request;
+use crate::{Request, metadata::AsciiMetadataKey};
+
+/// [`HashKeyExtractor`] which hashes the value of a metadata key of the requests.
+///
+/// If the key is missing, the [`RequestHash`] in the metainfo is used.
+#[derive(Clone, Debug)]
+pub struct MetadataHashKey {
+    key: AsciiMetadataKey,
+}
+
+impl MetadataHashKey {
+    pub fn new(key: AsciiMetadataKey) -> Self {
+        Self { key }
+    }
+}
+
+impl<Cx, T> HashKeyExtractor<Cx, Request<T>> for MetadataHashKey {
+    fn extract(&self, _: &Cx, req: &Request<T>) -> Option<RequestHash> {
+        let value = req.metadata().get(&self.key)?;
+        Some(RequestHash::from_key(value.as_bytes()))
+    }
+}
 
 #[derive(Clone, Default, Copy)]
-pub struct LoadBalanceLayer<D, LB> {
+pub struct LoadBalanceLayer<D, LB, R = NoReplay, H = MetaInfoHashKey> {
     discover: D,
     load_balance: LB,
+    replay: R,
+    hash_key: H,
 }
 
 impl<D, LB> LoadBalanceLayer<D, LB> {
@@ -23,26 +52,56 @@ impl<D, LB> LoadBalanceLayer<D, LB> {
         LoadBalanceLayer {
             discover,
             load_balance,
+            replay: NoReplay,
+            hash_key: MetaInfoHashKey,
         }
     }
 }
 
-impl<D, LB, S> Layer<S> for LoadBalanceLayer<D, LB>
+impl<D, LB, R, H> LoadBalanceLayer<D, LB, R, H> {
+    /// Sets how to copy the requests for the hedged attempts.
+    pub fn with_replay<NR>(self, replay: NR) -> LoadBalanceLayer<D, LB, NR, H> {
+        LoadBalanceLayer {
+            discover: self.discover,
+            load_balance: self.load_balance,
+            replay,
+            hash_key: self.hash_key,
+        }
+    }
+
+    /// Sets the [`HashKeyExtractor`] for the hashing load balancers.
+    pub fn with_hash_key<NH>(self, hash_key: NH) -> LoadBalanceLayer<D, LB, R, NH> {
+        LoadBalanceLayer {
+            discover: self.discover,
+            load_balance: self.load_balance,
+            replay: self.replay,
+            hash_key,
+        }
+    }
+}
+
+impl<D, LB, R, H, S> Layer<S> for LoadBalanceLayer<D, LB, R, H>
 where
     D: Discover,
     LB: LoadBalance<D>,
 {
-    type Service = LoadBalanceService<D, LB, S>;
+    type Service = LoadBalanceService<D, LB, S, R, H>;
 
     fn layer(self, inner: S) -> Self::Service {
         LoadBalanceService::new(self.discover, self.load_balance, inner)
+            .with_replay(self.replay)
+            .with_hash_key(self.hash_key)
     }
 }
+
 #[derive(Clone)]
-pub struct LoadBalanceService<D, LB, S> {
+pub struct LoadBalanceService<D, LB, S, R = NoReplay, H = MetaInfoHashKey> {
     discover: D,
     load_balance: Arc<LB>,
     service: S,
+    replay: R,
+    hash_key: H,
+    latency: Arc<LatencyTracker>,
 }
 
 impl<D, LB, S> LoadBalanceService<D, LB, S>
@@ -57,8 +116,26 @@ where
             discover,
             load_balance: lb.clone(),
             service,
+            replay: NoReplay,
+            hash_key: MetaInfoHashKey,
+            latency: Arc::new(LatencyTracker::new()),
         };
 
+        if let Some(mut evicted) = service.discover.watch_evicted() {
+            let lb = lb.clone();
+            tokio::spawn(async move {
+                loop {
+                    match evicted.recv().await {
+                        Ok(key) => lb.evict(&key),
+                        Err(err) => match err {
+                            RecvError::Closed => break,
+                            _ => warn!("[VOLO] discovering subscription error {:?}", err),
+                        },
+                    }
+                }
+            });
+        }
+
         if let Some(mut channel) = service.discover.watch(None) {
             tokio::spawn(async move {
                 loop {
@@ -76,22 +153,56 @@ where
     }
 }
 
-impl<Cx, T, D, LB, S> Service<Cx, Request<T>> for LoadBalanceService<D, LB, S>
+impl<D, LB, S, R, H> LoadBalanceService<D, LB, S, R, H> {
+    /// Sets how to copy the requests for the hedged attempts.
+    ///
+    /// The requests are not copied by default, so hedging does not take effect.
+    pub fn with_replay<NR>(self, replay: NR) -> LoadBalanceService<D, LB, S, NR, H> {
+        LoadBalanceService {
+            discover: self.discover,
+            load_balance: self.load_balance,
+            service: self.service,
+            replay,
+            hash_key: self.hash_key,
+            latency: self.latency,
+        }
+    }
+
+    /// Sets the [`HashKeyExtractor`] for the hashing load balancers.
+    pub fn with_hash_key<NH>(self, hash_key: NH) -> LoadBalanceService<D, LB, S, R, NH> {
+        LoadBalanceService {
+            discover: self.discover,
+            load_balance: self.load_balance,
+            service: self.service,
+            replay: self.replay,
+            hash_key,
+            latency: self.latency,
+        }
+    }
+}
+
+impl<Cx, T, D, LB, S, R, H> Service<Cx, Request<T>> for LoadBalanceService<D, LB, S, R, H>
 where
     <Cx as Context>::Config: Sync,
-    Cx: 'static + Context + Send + Sync,
+    Cx: 'static + Context + ForkContext + Send + Sync,
     D: Discover,
     LB: LoadBalance<D>,
     S: Service<Cx, Request<T>> + 'static + Send + Sync,
+    S::Response: Send,
     LoadBalanceError: Into<S::Error>,
-    S::Error: Debug,
+    S::Error: Debug + Send,
     T: Send + 'static,
+    R: Replay<Request<T>> + Send + Sync,
+    H: HashKeyExtractor<Cx, Request<T>>,
 {
     type Response = S::Response;
 
     type Error = S::Error;
 
     async fn call(&self, cx: &mut Cx, req: Request<T>) -> Result<Self::Response, Self::Error> {
+        if let Some(hash) = self.hash_key.extract(cx, &req) {
+            cx.rpc_info_mut().callee_mut().insert(hash);
+        }
         let callee = cx.rpc_info().callee();
 
         let mut picker = match &callee.address {
@@ -105,12 +216,32 @@ where
             }
         };
 
+        if let Some(policy) = callee.get::<HedgePolicy>().cloned() {
+            return call_hedged(
+                &self.service,
+                self.load_balance.as_ref(),
+                cx,
+                req,
+                picker,
+                &policy,
+                &self.latency,
+                None,
+                |req: &Request<T>| self.replay.replay(req),
+            )
+            .await;
+        }
+
         if let Some(addr) = picker.next() {
             cx.rpc_info_mut().callee_mut().address = Some(addr.clone());
 
+            let tracker = CallTracker::<D, LB>::new(self.load_balance.as_ref(), addr.clone());
             return match self.service.call(cx, req).await {
-                Ok(resp) => Ok(resp),
+                Ok(resp) => {
+                    tracker.finish(CallOutcome::Success);
+                    Ok(resp)
+                }
                 Err(err) => {
+                    tracker.finish(CallOutcome::Failure);
                     warn!("[VOLO] call endpoint: {:?} error: {:?}", addr, err);
                     Err(err)
                 }
@@ -122,7 +253,7 @@ where
     }
 }
 
-impl<D, LB, S> Debug for LoadBalanceService<D, LB, S>
+impl<D, LB, S, R, H> Debug for LoadBalanceService<D, LB, S, R, H>
 where
     D: Debug,
     LB: Debug,
@@ -136,9 +267,11 @@ where
     }
 }
 
-pub struct LbConfig<L, DISC> {
+pub struct LbConfig<L, DISC, R = NoReplay, H = MetaInfoHashKey> {
     load_balance: L,
     discover: DISC,
+    replay: R,
+    hash_key: H,
 }
 
 impl<L, DISC> LbConfig<L, DISC> {
@@ -146,28 +279,64 @@ impl<L, DISC> LbConfig<L, DISC> {
         LbConfig {
             load_balance,
             discover,
+            replay: NoReplay,
+            hash_key: MetaInfoHashKey,
         }
     }
+}
 
-    pub fn load_balance<NL>(self, load_balance: NL) -> LbConfig<NL, DISC> {
+impl<L, DISC, R, H> LbConfig<L, DISC, R, H> {
+    pub fn load_balance<NL>(self, load_balance: NL) -> LbConfig<NL, DISC, R, H> {
         LbConfig {
             load_balance,
             discover: self.discover,
+            replay: self.replay,
+            hash_key: self.hash_key,
         }
     }
 
-    pub fn discover<NDISC>(self, discover: NDISC) -> LbConfig<L, NDISC> {
+    pub fn discover<NDISC>(self, discover: NDISC) -> LbConfig<L, NDISC, R, H> {
         LbConfig {
             load_balance: self.load_balance,
             discover,
+            replay: self.replay,
+            hash_key: self.hash_key,
+        }
+    }
+
+    /// Sets how to copy the requests for the hedged attempts, e.g.
+    /// [`CloneReplay`](volo::retry::CloneReplay) for the requests with cloneable messages.
+    ///
+    /// Hedging does not take effect for the requests which cannot be copied, including the
+    /// streaming requests.
+    pub fn replay<NR>(self, replay: NR) -> LbConfig<L, DISC, NR, H> {
+        LbConfig {
+            load_balance: self.load_balance,
+            discover: self.discover,
+            replay,
+            hash_key: self.hash_key,
+        }
+    }
+
+    /// Sets the [`HashKeyExtractor`] for the hashing load balancers, e.g., [`MetadataHashKey`].
+    ///
+    /// The [`RequestHash`] in the metainfo is used by default.
+    pub fn hash_key<NH>(self, hash_key: NH) -> LbConfig<L, DISC, R, NH> {
+        LbConfig {
+            load_balance: self.load_balance,
+            discover: self.discover,
+            replay: self.replay,
+            hash_key,
         }
     }
 }
 
-impl<LB, DISC> MkLbLayer for LbConfig<LB, DISC> {
-    type Layer = LoadBalanceLayer<DISC, LB>;
+impl<LB, DISC, R, H> MkLbLayer for LbConfig<LB, DISC, R, H> {
+    type Layer = LoadBalanceLayer<DISC, LB, R, H>;
 
     fn make(self) -> Self::Layer {
         LoadBalanceLayer::new(self.discover, self.load_balance)
+            .with_replay(self.replay)
+            .with_hash_key(self.hash_key)
     }
 }
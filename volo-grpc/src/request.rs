@@ -7,7 +7,7 @@ use http::Extensions;
 
 use crate::metadata::MetadataMap;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Request<T> {
     metadata: MetadataMap,
     message: T,
@@ -1,7 +1,11 @@
 use std::task::{Context, Poll};
 
 use hyper::body::Incoming;
-use volo::net::Address;
+use volo::net::{
+    Address,
+    conn::{PeerCertificate, PeerCredentials, TlsServerName},
+    incoming::proxy_protocol::ProxyHeader,
+};
 
 use crate::{
     body::{BoxBody, boxed},
@@ -12,11 +16,50 @@ use crate::{
 pub struct IncomingService<S> {
     inner: S,
     peer_addr: Option<Address>,
+    peer_certificate: Option<PeerCertificate>,
+    server_name: Option<TlsServerName>,
+    proxy_header: Option<ProxyHeader>,
+    peer_credentials: Option<PeerCredentials>,
 }
 
 impl<S> IncomingService<S> {
     pub fn new(inner: S, peer_addr: Option<Address>) -> Self {
-        Self { inner, peer_addr }
+        Self {
+            inner,
+            peer_addr,
+            peer_certificate: None,
+            server_name: None,
+            proxy_header: None,
+            peer_credentials: None,
+        }
+    }
+
+    /// Sets the certificate of the peer, which is passed to the server context by the request
+    /// extensions.
+    pub fn with_peer_certificate(mut self, peer_certificate: Option<PeerCertificate>) -> Self {
+        self.peer_certificate = peer_certificate;
+        self
+    }
+
+    /// Sets the server name indicated by the peer, which is passed to the server context by the
+    /// request extensions.
+    pub fn with_server_name(mut self, server_name: Option<TlsServerName>) -> Self {
+        self.server_name = server_name;
+        self
+    }
+
+    /// Sets the PROXY protocol header of the connection, which is passed to the server context by
+    /// the request extensions.
+    pub fn with_proxy_header(mut self, proxy_header: Option<ProxyHeader>) -> Self {
+        self.proxy_header = proxy_header;
+        self
+    }
+
+    /// Sets the credentials of the peer process, which is passed to the server context by the
+    /// request extensions.
+    pub fn with_peer_credentials(mut self, peer_credentials: Option<PeerCredentials>) -> Self {
+        self.peer_credentials = peer_credentials;
+        self
     }
 }
 
@@ -42,6 +85,18 @@ where
                 }
             }
         }
+        if let Some(peer_certificate) = &self.peer_certificate {
+            req.extensions_mut().insert(peer_certificate.clone());
+        }
+        if let Some(server_name) = &self.server_name {
+            req.extensions_mut().insert(server_name.clone());
+        }
+        if let Some(proxy_header) = &self.proxy_header {
+            req.extensions_mut().insert(proxy_header.clone());
+        }
+        if let Some(peer_credentials) = self.peer_credentials {
+            req.extensions_mut().insert(peer_credentials);
+        }
 
         self.inner.call(req.map(boxed))
     }
@@ -3,7 +3,14 @@ use std::{cell::RefCell, net::SocketAddr, str::FromStr, sync::Arc, task::Poll};
 use futures::{FutureExt, future::BoxFuture};
 use metainfo::{Backward, Forward};
 use tracing::Instrument;
-use volo::{FastStr, Service, context::Context};
+use volo::{
+    FastStr, Service,
+    context::Context,
+    net::{
+        conn::{PeerCertificate, PeerCredentials, TlsServerName},
+        incoming::proxy_protocol::ProxyHeader,
+    },
+};
 
 use crate::{
     Request, Response, Status,
@@ -70,6 +77,22 @@ where
                     cx.rpc_info.set_method(FastStr::new(req.uri().path()));
 
                     let mut volo_req = Request::from_http(req);
+                    if let Some(peer_certificate) =
+                        volo_req.extensions_mut().remove::<PeerCertificate>()
+                    {
+                        cx.rpc_info_mut().caller_mut().insert(peer_certificate);
+                    }
+                    if let Some(server_name) = volo_req.extensions_mut().remove::<TlsServerName>() {
+                        cx.rpc_info_mut().callee_mut().insert(server_name);
+                    }
+                    if let Some(proxy_header) = volo_req.extensions_mut().remove::<ProxyHeader>() {
+                        cx.rpc_info_mut().caller_mut().insert(proxy_header);
+                    }
+                    if let Some(peer_credentials) =
+                        volo_req.extensions_mut().remove::<PeerCredentials>()
+                    {
+                        cx.rpc_info_mut().caller_mut().insert(peer_credentials);
+                    }
 
                     let metadata = volo_req.metadata_mut();
 
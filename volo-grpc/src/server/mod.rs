@@ -22,7 +22,8 @@ use tower::util::BoxCloneService;
 #[cfg(feature = "__tls")]
 use volo::net::tls::ServerTlsConfig;
 use volo::{
-    net::{conn::Conn, incoming::Incoming},
+    net::{conn::Conn, incoming::Incoming, sockopt::SocketOptions},
+    registry::Registration,
     spawn,
 };
 
@@ -52,6 +53,8 @@ pub struct Server<IL = Identity, OL = tower::layer::util::Identity, SP = Default
     http2_config: Http2Config,
     router: Router,
     span_provider: SP,
+    registration: Option<Registration>,
+    socket_options: Option<SocketOptions>,
 
     #[cfg(feature = "__tls")]
     tls_config: Option<ServerTlsConfig>,
@@ -72,6 +75,8 @@ impl Server<Identity, tower::layer::util::Identity, DefaultProvider> {
             http2_config: Http2Config::default(),
             router: Router::new(),
             span_provider: DefaultProvider,
+            registration: None,
+            socket_options: None,
 
             #[cfg(feature = "__tls")]
             tls_config: None,
@@ -80,6 +85,15 @@ impl Server<Identity, tower::layer::util::Identity, DefaultProvider> {
 }
 
 impl<IL, OL, SP> Server<IL, OL, SP> {
+    /// Registers the server to a registry once the listener is bound.
+    ///
+    /// The server will be deregistered at the start of graceful shutdown, before the
+    /// connections are drained.
+    pub fn registry(mut self, registration: Registration) -> Self {
+        self.registration = Some(registration);
+        self
+    }
+
     #[cfg(feature = "__tls")]
     #[cfg_attr(docsrs, doc(cfg(any(feature = "rustls", feature = "native-tls"))))]
     /// Sets the TLS configuration for the server.
@@ -90,6 +104,15 @@ impl<IL, OL, SP> Server<IL, OL, SP> {
         self
     }
 
+    /// Sets the socket options applied to the accepted connections, e.g., TCP keepalive.
+    ///
+    /// The options of the listeners, e.g., `SO_REUSEPORT`, are set by running the server with a
+    /// [`ListenConfig`](volo::net::ListenConfig).
+    pub fn socket_options(mut self, options: SocketOptions) -> Self {
+        self.socket_options = Some(options);
+        self
+    }
+
     /// Sets the `SETTINGS_INITIAL_WINDOW_SIZE` option for HTTP2
     /// stream-level flow control.
     ///
@@ -213,6 +236,8 @@ impl<IL, OL, SP> Server<IL, OL, SP> {
             http2_config: self.http2_config,
             router: self.router,
             span_provider: self.span_provider,
+            registration: self.registration,
+            socket_options: self.socket_options,
             #[cfg(feature = "__tls")]
             tls_config: self.tls_config,
         }
@@ -239,6 +264,8 @@ impl<IL, OL, SP> Server<IL, OL, SP> {
             http2_config: self.http2_config,
             router: self.router,
             span_provider: self.span_provider,
+            registration: self.registration,
+            socket_options: self.socket_options,
             #[cfg(feature = "__tls")]
             tls_config: self.tls_config,
         }
@@ -268,6 +295,8 @@ impl<IL, OL, SP> Server<IL, OL, SP> {
             http2_config: self.http2_config,
             router: self.router,
             span_provider: self.span_provider,
+            registration: self.registration,
+            socket_options: self.socket_options,
             #[cfg(feature = "__tls")]
             tls_config: self.tls_config,
         }
@@ -289,6 +318,8 @@ impl<IL, OL, SP> Server<IL, OL, SP> {
             http2_config: self.http2_config,
             router: self.router.add_service(s),
             span_provider: self.span_provider,
+            registration: self.registration,
+            socket_options: self.socket_options,
             #[cfg(feature = "__tls")]
             tls_config: self.tls_config,
         }
@@ -302,6 +333,8 @@ impl<IL, OL, SP> Server<IL, OL, SP> {
             http2_config: self.http2_config,
             router: self.router,
             span_provider: provider,
+            registration: self.registration,
+            socket_options: self.socket_options,
             #[cfg(feature = "__tls")]
             tls_config: self.tls_config,
         }
@@ -344,6 +377,11 @@ impl<IL, OL, SP> Server<IL, OL, SP> {
         let mut incoming = incoming.make_incoming().await?;
         tracing::info!("[VOLO] server start at: {:?}", incoming);
 
+        let mut registered = match &self.registration {
+            Some(registration) => Some(registration.register(incoming.local_addr()).await?),
+            None => None,
+        };
+
         let service = self
             .outer_layer
             .layer(BoxCloneService::new(MetaService::new(
@@ -354,26 +392,35 @@ impl<IL, OL, SP> Server<IL, OL, SP> {
         tokio::pin!(signal);
         let (tx, rx) = tokio::sync::watch::channel(());
 
-        loop {
+        let res = loop {
             tokio::select! {
                 _ = &mut signal => {
+                    if let Some(registered) = registered.take() {
+                        registered.deregister().await;
+                    }
                     drop(rx);
                     tracing::info!("[VOLO] graceful shutdown");
                     let _ = tx.send(());
                     // Waits for receivers to drop.
                     tx.closed().await;
-                    return Ok(());
+                    break Ok(());
                 },
                 conn = incoming.accept() => {
-                    let conn: Conn = match conn? {
-                        Some(c) => c,
-                        None => return Ok(()),
+                    let conn: Conn = match conn {
+                        Ok(Some(c)) => c,
+                        Ok(None) => break Ok(()),
+                        Err(e) => break Err(e.into()),
                     };
+                    if let Some(options) = &self.socket_options {
+                        if let Err(err) = options.apply_to_conn(&conn) {
+                            tracing::warn!("[VOLO] failed to set socket options: {err}");
+                        }
+                    }
                     #[cfg(feature = "__tls")]
                     let conn = {
                         let Conn {
                             stream,
-                            info,
+                            mut info,
                         } = conn;
                         // Only perform TLS handshake if either rustls or native-tls is configured
                         match (stream, self.tls_config.as_ref().map(|o| &o.acceptor)) {
@@ -385,6 +432,8 @@ impl<IL, OL, SP> Server<IL, OL, SP> {
                                         continue;
                                     },
                                 };
+                                info.peer_certificate = stream.peer_certificate();
+                                info.server_name = stream.server_name();
                                 Conn {
                                     stream,
                                     info,
@@ -400,7 +449,11 @@ impl<IL, OL, SP> Server<IL, OL, SP> {
                     tracing::trace!("[VOLO] recv a connection from: {:?}", conn.info.peer_addr);
                     let peer_addr = conn.info.peer_addr.clone();
 
-                    let service = IncomingService::new(service.clone(), peer_addr);
+                    let service = IncomingService::new(service.clone(), peer_addr)
+                        .with_peer_certificate(conn.info.peer_certificate.clone())
+                        .with_server_name(conn.info.server_name.clone())
+                        .with_proxy_header(conn.info.proxy_header.clone())
+                        .with_peer_credentials(conn.info.peer_credentials);
 
                     // init server
                     let mut server = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new());
@@ -449,7 +502,13 @@ impl<IL, OL, SP> Server<IL, OL, SP> {
                     });
                 },
             }
+        };
+
+        // deregister on every exit path, so that the clients stop sending requests to the server
+        if let Some(registered) = registered {
+            registered.deregister().await;
         }
+        res
     }
 
     /// The main entry point for the server.
@@ -8,7 +8,12 @@ use http::header::{HeaderMap, HeaderValue};
 use percent_encoding::{AsciiSet, CONTROLS, percent_decode, percent_encode};
 use tower::BoxError;
 use tracing::{debug, trace, warn};
-use volo::loadbalance::error::{LoadBalanceError, Retryable};
+use volo::{
+    circuit_breaker::{BreakerFailure, CircuitOpenError},
+    fault::FaultAbort,
+    loadbalance::error::{LoadBalanceError, Retryable},
+    rate_limit::RateLimitError,
+};
 
 use crate::{BASE64_ENGINE, body::BoxBody, metadata::MetadataMap};
 
@@ -764,6 +769,28 @@ impl From<LoadBalanceError> for Status {
     }
 }
 
+impl From<CircuitOpenError> for Status {
+    fn from(err: CircuitOpenError) -> Self {
+        Self::unavailable(err.to_string())
+    }
+}
+
+/// The [`FaultAbort`] is kept as the source, so that the injected faults are not failures of
+/// the callee.
+impl From<FaultAbort> for Status {
+    fn from(err: FaultAbort) -> Self {
+        let mut status = Self::new(Code::from(err.code as i32), err.message.clone());
+        status.source = Some(Arc::new(err));
+        status
+    }
+}
+
+impl From<RateLimitError> for Status {
+    fn from(err: RateLimitError) -> Self {
+        Self::resource_exhausted(err.to_string())
+    }
+}
+
 impl From<anyhow::Error> for Status {
     fn from(err: anyhow::Error) -> Self {
         Self::from_error(err.into())
@@ -779,6 +806,22 @@ impl Retryable for Status {
     }
 }
 
+impl BreakerFailure for Status {
+    fn is_failure(&self) -> bool {
+        if self
+            .source
+            .as_ref()
+            .is_some_and(|source| source.is::<FaultAbort>())
+        {
+            return false;
+        }
+        matches!(
+            self.code,
+            Code::Unavailable | Code::DeadlineExceeded | Code::ResourceExhausted
+        )
+    }
+}
+
 impl fmt::Display for Status {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -53,6 +53,7 @@ impl<U> ClientTransport<U> {
             rpc_config.read_timeout,
             rpc_config.write_timeout,
         );
+        let socket_options = rpc_config.socket_options.clone().unwrap_or_default();
         let http_client = hyper_util::client::legacy::Client::builder(TokioExecutor::new())
             .timer(TokioTimer::new())
             .http2_only(true)
@@ -65,7 +66,7 @@ impl<U> ClientTransport<U> {
             .http2_keep_alive_while_idle(http2_config.http2_keepalive_while_idle)
             .http2_max_concurrent_reset_streams(http2_config.max_concurrent_reset_streams)
             .http2_max_send_buf_size(http2_config.max_send_buf_size)
-            .build(Connector::new(Some(config)));
+            .build(Connector::new(Some(config)).with_socket_options(socket_options));
 
         ClientTransport {
             http_client,
@@ -85,6 +86,7 @@ impl<U> ClientTransport<U> {
             rpc_config.read_timeout,
             rpc_config.write_timeout,
         );
+        let socket_options = rpc_config.socket_options.clone().unwrap_or_default();
         let http_client = hyper_util::client::legacy::Client::builder(TokioExecutor::new())
             .timer(TokioTimer::new())
             .http2_only(true)
@@ -97,7 +99,10 @@ impl<U> ClientTransport<U> {
             .http2_keep_alive_while_idle(http2_config.http2_keepalive_while_idle)
             .http2_max_concurrent_reset_streams(http2_config.max_concurrent_reset_streams)
             .http2_max_send_buf_size(http2_config.max_send_buf_size)
-            .build(Connector::new_with_tls(Some(config), tls_config));
+            .build(
+                Connector::new_with_tls(Some(config), tls_config)
+                    .with_socket_options(socket_options),
+            );
 
         ClientTransport {
             http_client,
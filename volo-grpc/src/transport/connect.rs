@@ -19,6 +19,7 @@ use volo::net::{
     Address,
     conn::Conn,
     dial::{Config, DefaultMakeTransport, MakeTransport},
+    sockopt::SocketOptions,
 };
 
 #[derive(Clone, Debug)]
@@ -40,15 +41,20 @@ impl Connector {
         Self::Default(mt)
     }
 
+    /// Sets the options applied to the sockets before connecting.
+    pub fn with_socket_options(mut self, options: SocketOptions) -> Self {
+        match &mut self {
+            Self::Default(mt) => mt.set_socket_options(options),
+            #[cfg(feature = "__tls")]
+            Self::Tls(mt) => mt.set_socket_options(options),
+        }
+        self
+    }
+
     #[cfg(feature = "__tls")]
     #[cfg_attr(docsrs, doc(cfg(any(feature = "rustls", feature = "native-tls"))))]
     pub fn new_with_tls(cfg: Option<Config>, tls_config: ClientTlsConfig) -> Self {
-        let mut mt = TlsMakeTransport::new(cfg.unwrap_or_default(), tls_config);
-        if let Some(cfg) = cfg {
-            mt.set_connect_timeout(cfg.connect_timeout);
-            mt.set_read_timeout(cfg.read_timeout);
-            mt.set_write_timeout(cfg.write_timeout);
-        }
+        let mt = TlsMakeTransport::new(cfg.unwrap_or_default(), tls_config);
         Self::Tls(mt)
     }
 }
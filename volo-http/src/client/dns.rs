@@ -3,6 +3,8 @@
 //! This module implements [`DnsResolver`] as a [`Discover`] for client.
 
 use std::{
+    borrow::Cow,
+    collections::HashMap,
     net::{IpAddr, SocketAddr},
     ops::Deref,
     sync::Arc,
@@ -42,6 +44,14 @@ impl Deref for Port {
     }
 }
 
+/// The tag of the instances discovered by [`DnsResolver`], whose value is the resolved domain
+/// name.
+///
+/// The instances with the same value of this tag are the addresses of the same host, so the
+/// connector can dial them together by Happy Eyeballs. A custom [`Discover`] can also tag its
+/// instances with it to enable that.
+pub const RESOLVED_HOST_TAG: &str = "resolved_host";
+
 /// A service discover implementation for DNS.
 #[derive(Clone)]
 pub struct DnsResolver {
@@ -61,9 +71,17 @@ impl DnsResolver {
 
     /// Resolve a host to an IP address.
     pub async fn resolve(&self, host: &str) -> Option<IpAddr> {
+        self.resolve_all(host).await.into_iter().next()
+    }
+
+    /// Resolve a host to all its IP addresses.
+    pub async fn resolve_all(&self, host: &str) -> Vec<IpAddr> {
         // Note that the Resolver will try to parse the host as an IP address first, so we don't
         // need to parse it manually.
-        self.resolver.lookup_ip(host).await.ok()?.into_iter().next()
+        match self.resolver.lookup_ip(host).await {
+            Ok(lookup) => lookup.into_iter().collect(),
+            Err(_) => Vec::new(),
+        }
     }
 }
 
@@ -71,24 +89,11 @@ impl Default for DnsResolver {
     fn default() -> Self {
         let (conf, mut opts) = hickory_resolver::system_conf::read_system_conf()
             .expect("[Volo-HTTP] DnsResolver: failed to parse dns config");
-        if conf
-            .name_servers()
-            .first()
-            .expect("[Volo-HTTP] DnsResolver: no nameserver found")
-            .socket_addr
-            .is_ipv6()
-        {
-            // The default `LookupIpStrategy` is always `Ipv4thenIpv6`, it may not work in an IPv6
-            // only environment.
-            //
-            // Here we trust the system configuration and check its first name server.
-            //
-            // If the first nameserver is an IPv4 address, we keep the default configuration.
-            //
-            // If the first nameserver is an IPv6 address, we need to update the policy to prefer
-            // IPv6 addresses.
-            opts.ip_strategy = LookupIpStrategy::Ipv6thenIpv4;
-        }
+        // The default `LookupIpStrategy` is `Ipv4thenIpv6`, it only returns IPv4 addresses if there
+        // are any, and it may not work in an IPv6 only environment.
+        //
+        // Here we query both of them, and the connector picks the reachable one by Happy Eyeballs.
+        opts.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
         Self::new(conf, opts)
     }
 }
@@ -134,15 +139,25 @@ impl Discover for DnsResolver {
             }
         };
 
-        if let Some(ip) = self.resolve(endpoint.service_name_ref()).await {
-            let address = Address::Ip(SocketAddr::new(ip, port));
-            let instance = Instance {
-                address,
-                weight: 10,
-                tags: Default::default(),
-            };
-            return Ok(vec![Arc::new(instance)]);
-        };
+        let ips = self.resolve_all(endpoint.service_name_ref()).await;
+        if !ips.is_empty() {
+            let host = endpoint.service_name();
+            return Ok(ips
+                .into_iter()
+                .map(|ip| {
+                    let mut tags = HashMap::with_capacity(1);
+                    tags.insert(
+                        Cow::Borrowed(RESOLVED_HOST_TAG),
+                        Cow::Owned(host.to_string()),
+                    );
+                    Arc::new(Instance {
+                        address: Address::Ip(SocketAddr::new(ip, port)),
+                        weight: 10,
+                        tags,
+                    })
+                })
+                .collect());
+        }
         tracing::error!("[Volo-HTTP] DnsResolver: no address resolved");
         Err(LoadBalanceError::Discover(Box::new(bad_host_name(
             endpoint.service_name(),
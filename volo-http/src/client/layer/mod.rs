@@ -7,12 +7,14 @@ pub mod header;
 #[cfg(feature = "http1")]
 pub mod http_proxy;
 mod redirect;
+mod retry;
 mod timeout;
 pub(crate) mod utils;
 
 pub use self::{
     fail_on_status::{FailOnStatus, StatusCodeError},
     redirect::{FollowRedirect, RedirectPredicate},
+    retry::ReplayRequest,
     timeout::Timeout,
     utils::TargetLayer,
 };
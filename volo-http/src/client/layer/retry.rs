@@ -0,0 +1,39 @@
+use volo::retry::Replay;
+
+use crate::{body::Body, request::Request};
+
+/// Replays the requests for [`RetryLayer`](volo::retry::RetryLayer).
+///
+/// Only the requests with in-memory bodies can be replayed, the requests with streaming bodies
+/// are sent only once.
+///
+/// The requests which fail to connect are retried, while the ones which fail after being sent
+/// are retried only if their methods are idempotent, see
+/// [`Method::is_idempotent`](http::Method::is_idempotent).
+///
+/// # Example
+///
+/// ```
+/// use volo::retry::{RetryLayer, RetryPolicy};
+/// use volo_http::client::{Client, layer::ReplayRequest};
+///
+/// let client = Client::builder()
+///     .layer_outer(RetryLayer::new(RetryPolicy::new(3)).with_replay(ReplayRequest))
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReplayRequest;
+
+impl Replay<Request<Body>> for ReplayRequest {
+    fn replay(&self, req: &Request<Body>) -> Option<Request<Body>> {
+        let body = req.body().try_clone()?;
+        let mut new_req = Request::new(body);
+        *new_req.method_mut() = req.method().clone();
+        *new_req.uri_mut() = req.uri().clone();
+        *new_req.version_mut() = req.version();
+        *new_req.headers_mut() = req.headers().clone();
+        *new_req.extensions_mut() = req.extensions().clone();
+        Some(new_req)
+    }
+}
@@ -8,17 +8,23 @@
 //! DNS resolver for pick a target address (the DNS resolver picks only one because it does not
 //! need load balance).
 
-use std::{fmt::Debug, sync::Arc};
+use std::{collections::HashMap, fmt::Debug, net::SocketAddr, sync::Arc};
 
 use async_broadcast::RecvError;
+use http::header::HeaderName;
 use motore::{layer::Layer, service::Service};
+use parking_lot::RwLock;
 use volo::{
-    context::Context,
-    discovery::Discover,
-    loadbalance::{LoadBalance, MkLbLayer, random::WeightedRandomBalance},
+    context::{Context, Endpoint},
+    discovery::{Discover, Instance},
+    loadbalance::{
+        CallOutcome, CallTracker, HashKeyExtractor, LoadBalance, MetaInfoHashKey, MkLbLayer,
+        RequestHash, random::WeightedRandomBalance,
+    },
+    net::Address,
 };
 
-use super::dns::{DiscoverKey, DnsResolver};
+use super::dns::{DiscoverKey, DnsResolver, RESOLVED_HOST_TAG};
 use crate::{
     context::ClientContext,
     error::{
@@ -34,10 +40,75 @@ pub type DefaultLb = LbConfig<WeightedRandomBalance<DiscoverKey>, DnsResolver>;
 pub type DefaultLbService<S> =
     LoadBalanceService<WeightedRandomBalance<DiscoverKey>, DnsResolver, S>;
 
+/// The other IP addresses of the same host as the picked one, i.e., the instances with the same
+/// [`RESOLVED_HOST_TAG`], e.g., all the resolved addresses of a domain name by [`DnsResolver`].
+///
+/// The connector dials them together with the picked one by Happy Eyeballs, so that an unreachable
+/// address family does not stall the requests until the connect timeout. The address actually
+/// connected is pooled by the picked one, so the following requests to it reuse the connection.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FallbackAddresses(pub Vec<SocketAddr>);
+
+/// All the addresses of each host, indexed by each address of them.
+type HostAddresses = HashMap<SocketAddr, Arc<[SocketAddr]>>;
+
+/// Groups the instances by [`RESOLVED_HOST_TAG`], the hosts with only one address are skipped
+/// since they have no fallback addresses.
+fn host_addresses(instances: &[Arc<Instance>]) -> HostAddresses {
+    let mut hosts = HashMap::<&str, Vec<SocketAddr>>::new();
+    for instance in instances {
+        let (Address::Ip(addr), Some(host)) =
+            (&instance.address, instance.tags.get(RESOLVED_HOST_TAG))
+        else {
+            continue;
+        };
+        hosts.entry(host.as_ref()).or_default().push(*addr);
+    }
+
+    let mut addresses = HashMap::new();
+    for addrs in hosts.into_values() {
+        if addrs.len() < 2 {
+            continue;
+        }
+        let addrs = Arc::<[SocketAddr]>::from(addrs);
+        for addr in addrs.iter() {
+            addresses.insert(*addr, addrs.clone());
+        }
+    }
+    addresses
+}
+
+/// [`HashKeyExtractor`] which hashes the value of a header of the requests
+///
+/// If the header is missing, the [`RequestHash`] in the metainfo is used.
+#[derive(Clone, Debug)]
+pub struct HeaderHashKey(pub HeaderName);
+
+impl<B> HashKeyExtractor<ClientContext, Request<B>> for HeaderHashKey {
+    fn extract(&self, _: &ClientContext, req: &Request<B>) -> Option<RequestHash> {
+        let value = req.headers().get(&self.0)?;
+        Some(RequestHash::from_key(value.as_bytes()))
+    }
+}
+
+/// [`HashKeyExtractor`] which hashes the path of the requests
+///
+/// For hashing a param in the path, a closure can be used, e.g.,
+/// `|_: &ClientContext, req: &Request| Some(RequestHash::from_key(...))`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PathHashKey;
+
+impl<B> HashKeyExtractor<ClientContext, Request<B>> for PathHashKey {
+    fn extract(&self, _: &ClientContext, req: &Request<B>) -> Option<RequestHash> {
+        Some(RequestHash::from_key(req.uri().path()))
+    }
+}
+
 /// Load balance layer generator with a [`LoadBalance`] and a [`Discover`]
-pub struct LbConfig<L, D> {
+pub struct LbConfig<L, D, H = MetaInfoHashKey> {
     load_balance: L,
     discover: D,
+    hash_key: H,
 }
 
 impl Default for DefaultLb {
@@ -52,84 +123,131 @@ impl<L, D> LbConfig<L, D> {
         LbConfig {
             load_balance,
             discover,
+            hash_key: MetaInfoHashKey,
         }
     }
+}
 
+impl<L, D, H> LbConfig<L, D, H> {
     /// Set a [`LoadBalance`] to the [`LbConfig`] and replace the previous one
-    pub fn load_balance<NL>(self, load_balance: NL) -> LbConfig<NL, D> {
+    pub fn load_balance<NL>(self, load_balance: NL) -> LbConfig<NL, D, H> {
         LbConfig {
             load_balance,
             discover: self.discover,
+            hash_key: self.hash_key,
         }
     }
 
     /// Set a [`Discover`] to the [`LbConfig`] and replace the previous one
-    pub fn discover<ND>(self, discover: ND) -> LbConfig<L, ND> {
+    pub fn discover<ND>(self, discover: ND) -> LbConfig<L, ND, H> {
         LbConfig {
             load_balance: self.load_balance,
             discover,
+            hash_key: self.hash_key,
+        }
+    }
+
+    /// Set a [`HashKeyExtractor`] to the [`LbConfig`] for the hashing load balancers
+    ///
+    /// The [`RequestHash`] in the metainfo is used by default.
+    pub fn hash_key<NH>(self, hash_key: NH) -> LbConfig<L, D, NH> {
+        LbConfig {
+            load_balance: self.load_balance,
+            discover: self.discover,
+            hash_key,
         }
     }
 }
 
-impl<LB, D> MkLbLayer for LbConfig<LB, D> {
-    type Layer = LoadBalanceLayer<LB, D>;
+impl<LB, D, H> MkLbLayer for LbConfig<LB, D, H> {
+    type Layer = LoadBalanceLayer<LB, D, H>;
 
     fn make(self) -> Self::Layer {
-        LoadBalanceLayer::new(self.load_balance, self.discover)
+        LoadBalanceLayer::new(self.load_balance, self.discover, self.hash_key)
     }
 }
 
 /// [`Layer`] for load balance generated by [`LbConfig`]
 #[derive(Clone, Default, Copy)]
-pub struct LoadBalanceLayer<LB, D> {
+pub struct LoadBalanceLayer<LB, D, H = MetaInfoHashKey> {
     load_balance: LB,
     discover: D,
+    hash_key: H,
 }
 
-impl<LB, D> LoadBalanceLayer<LB, D> {
-    fn new(load_balance: LB, discover: D) -> Self {
+impl<LB, D, H> LoadBalanceLayer<LB, D, H> {
+    fn new(load_balance: LB, discover: D, hash_key: H) -> Self {
         LoadBalanceLayer {
             load_balance,
             discover,
+            hash_key,
         }
     }
 }
 
-impl<LB, D, S> Layer<S> for LoadBalanceLayer<LB, D>
+impl<LB, D, H, S> Layer<S> for LoadBalanceLayer<LB, D, H>
 where
     LB: LoadBalance<D>,
     D: Discover,
 {
-    type Service = LoadBalanceService<LB, D, S>;
+    type Service = LoadBalanceService<LB, D, S, H>;
 
     fn layer(self, inner: S) -> Self::Service {
-        LoadBalanceService::new(self.load_balance, self.discover, inner)
+        LoadBalanceService::new(self.load_balance, self.discover, inner, self.hash_key)
     }
 }
 
 /// [`Service`] for load balance generated by [`LoadBalanceLayer`]
 #[derive(Clone)]
-pub struct LoadBalanceService<LB, D, S> {
+pub struct LoadBalanceService<LB, D, S, H = MetaInfoHashKey>
+where
+    D: Discover,
+{
     load_balance: Arc<LB>,
     discover: D,
     service: S,
+    hash_key: H,
+    hosts: Arc<RwLock<HashMap<D::Key, Arc<HostAddresses>>>>,
 }
 
-impl<LB, D, S> LoadBalanceService<LB, D, S>
+impl<LB, D, S, H> LoadBalanceService<LB, D, S, H>
 where
     LB: LoadBalance<D>,
     D: Discover,
 {
-    fn new(load_balance: LB, discover: D, service: S) -> Self {
+    fn new(load_balance: LB, discover: D, service: S, hash_key: H) -> Self {
         let lb = Arc::new(load_balance);
+        let hosts = Arc::new(RwLock::new(HashMap::new()));
 
         let service = Self {
             load_balance: lb.clone(),
             discover,
             service,
+            hash_key,
+            hosts: hosts.clone(),
         };
 
+        if let Some(mut evicted) = service.discover.watch_evicted() {
+            let lb = lb.clone();
+            let hosts = hosts.clone();
+            tokio::spawn(async move {
+                loop {
+                    match evicted.recv().await {
+                        Ok(key) => {
+                            hosts.write().remove(&key);
+                            lb.evict(&key)
+                        }
+                        Err(err) => match err {
+                            RecvError::Closed => break,
+                            _ => {
+                                tracing::warn!("[Volo-HTTP] discovering subscription error: {err}")
+                            }
+                        },
+                    }
+                }
+            });
+        }
+
         let Some(mut channel) = service.discover.watch(None) else {
             return service;
         };
@@ -137,7 +255,12 @@ where
         tokio::spawn(async move {
             loop {
                 match channel.recv().await {
-                    Ok(recv) => lb.rebalance(recv),
+                    Ok(recv) => {
+                        hosts
+                            .write()
+                            .insert(recv.key.clone(), Arc::new(host_addresses(&recv.all)));
+                        lb.rebalance(recv)
+                    }
                     Err(err) => match err {
                         RecvError::Closed => break,
                         _ => tracing::warn!("[Volo-HTTP] discovering subscription error: {err}"),
@@ -150,11 +273,49 @@ where
     }
 }
 
-impl<LB, D, S, B> Service<ClientContext, Request<B>> for LoadBalanceService<LB, D, S>
+impl<LB, D, S, H> LoadBalanceService<LB, D, S, H>
+where
+    D: Discover,
+{
+    /// The other addresses of the same host as the picked one, i.e., the instances with the same
+    /// [`RESOLVED_HOST_TAG`].
+    ///
+    /// The instances are grouped once for each discovery result and updated with the changes, as
+    /// the load balancer caches them. The instances of the other hosts are different backends, and
+    /// dialing them would bypass the load balancer.
+    async fn same_host_addresses(&self, callee: &Endpoint, picked: &SocketAddr) -> Vec<SocketAddr> {
+        let key = self.discover.key(callee);
+        let cached = self.hosts.read().get(&key).cloned();
+        let hosts = match cached {
+            Some(hosts) => hosts,
+            None => {
+                let Ok(instances) = self.discover.discover(callee).await else {
+                    return Vec::new();
+                };
+                let hosts = Arc::new(host_addresses(&instances));
+                // a newer result from the watch channel takes precedence
+                self.hosts.write().entry(key).or_insert(hosts).clone()
+            }
+        };
+        hosts
+            .get(picked)
+            .map(|addrs| {
+                addrs
+                    .iter()
+                    .filter(|addr| *addr != picked)
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl<LB, D, S, H, B> Service<ClientContext, Request<B>> for LoadBalanceService<LB, D, S, H>
 where
     LB: LoadBalance<D>,
     D: Discover,
     S: Service<ClientContext, Request<B>, Error = ClientError> + Send + Sync,
+    H: HashKeyExtractor<ClientContext, Request<B>>,
     B: Send,
 {
     type Response = S::Response;
@@ -165,6 +326,9 @@ where
         cx: &mut ClientContext,
         req: Request<B>,
     ) -> Result<Self::Response, Self::Error> {
+        if let Some(hash) = self.hash_key.extract(cx, &req) {
+            cx.rpc_info_mut().callee_mut().insert(hash);
+        }
         let callee = cx.rpc_info().callee();
 
         let mut picker = match &callee.address {
@@ -179,16 +343,29 @@ where
         };
 
         let addr = picker.next().ok_or_else(no_available_endpoint)?;
-        cx.rpc_info_mut().callee_mut().set_address(addr);
+        let fallbacks = match &addr {
+            Address::Ip(picked) => self.same_host_addresses(callee, picked).await,
+            _ => Vec::new(),
+        };
+        let callee = cx.rpc_info_mut().callee_mut();
+        callee.set_address(addr.clone());
+        callee.insert(FallbackAddresses(fallbacks));
 
-        self.service.call(cx, req).await
+        let tracker = CallTracker::<D, LB>::new(self.load_balance.as_ref(), addr);
+        let res = self.service.call(cx, req).await;
+        tracker.finish(if res.is_ok() {
+            CallOutcome::Success
+        } else {
+            CallOutcome::Failure
+        });
+        res
     }
 }
 
-impl<LB, D, S> Debug for LoadBalanceService<LB, D, S>
+impl<LB, D, S, H> Debug for LoadBalanceService<LB, D, S, H>
 where
     LB: Debug,
-    D: Debug,
+    D: Discover + Debug,
     S: Debug,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -198,3 +375,194 @@ where
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        borrow::Cow,
+        convert::Infallible,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use http::header::HeaderName;
+    use motore::service::{Service, service_fn};
+    use volo::{
+        context::Context,
+        context::Endpoint,
+        discovery::{Change, Discover, Instance, StaticDiscover},
+        loadbalance::{
+            MetaInfoHashKey,
+            consistent_hash::{ConsistentHashBalance, ConsistentHashOption},
+            random::WeightedRandomBalance,
+        },
+        net::Address,
+    };
+
+    use super::{FallbackAddresses, HeaderHashKey, LoadBalanceService, RESOLVED_HOST_TAG};
+    use crate::{context::ClientContext, error::ClientError, request::Request};
+
+    async fn callee_address(
+        cx: &mut ClientContext,
+        _: Request<()>,
+    ) -> Result<Option<Address>, ClientError> {
+        Ok(cx.rpc_info().callee().address())
+    }
+
+    #[tokio::test]
+    async fn hash_key_without_metainfo() {
+        let discover = StaticDiscover::from(vec![
+            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.2:8000".parse().unwrap(),
+            "127.0.0.3:8000".parse().unwrap(),
+        ]);
+        let service = LoadBalanceService::new(
+            ConsistentHashBalance::new(ConsistentHashOption::default()),
+            discover,
+            service_fn(callee_address),
+            HeaderHashKey(HeaderName::from_static("x-user-id")),
+        );
+        let request = |user: &str| {
+            Request::builder()
+                .header("x-user-id", user)
+                .body(())
+                .unwrap()
+        };
+
+        let mut picked = std::collections::HashSet::new();
+        for user in 0..32 {
+            let user = user.to_string();
+            let first = service
+                .call(&mut ClientContext::new(), request(&user))
+                .await
+                .unwrap();
+            let second = service
+                .call(&mut ClientContext::new(), request(&user))
+                .await
+                .unwrap();
+            assert!(first.is_some());
+            assert_eq!(first, second);
+            picked.insert(first);
+        }
+        assert!(picked.len() > 1);
+
+        // the hash is required by the consistent hash balancer
+        assert!(
+            service
+                .call(
+                    &mut ClientContext::new(),
+                    Request::builder().body(()).unwrap()
+                )
+                .await
+                .is_err()
+        );
+    }
+
+    /// Counts the calls of [`Discover::discover`].
+    struct CountingDiscover(StaticDiscover, Arc<AtomicUsize>);
+
+    impl Discover for CountingDiscover {
+        type Key = ();
+        type Error = Infallible;
+
+        async fn discover<'s>(
+            &'s self,
+            endpoint: &'s Endpoint,
+        ) -> Result<Vec<Arc<Instance>>, Self::Error> {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            self.0.discover(endpoint).await
+        }
+
+        fn key(&self, _: &Endpoint) -> Self::Key {}
+
+        fn watch(
+            &self,
+            _: Option<&[Self::Key]>,
+        ) -> Option<async_broadcast::Receiver<Change<Self::Key>>> {
+            None
+        }
+    }
+
+    #[tokio::test]
+    async fn fallback_addresses_of_same_host() {
+        let instance = |addr: &str, host: &'static str| {
+            Arc::new(Instance {
+                address: Address::Ip(addr.parse().unwrap()),
+                weight: 10,
+                tags: [(Cow::Borrowed(RESOLVED_HOST_TAG), Cow::Borrowed(host))].into(),
+            })
+        };
+        let discovered = Arc::new(AtomicUsize::new(0));
+        let discover = CountingDiscover(
+            StaticDiscover::new(vec![
+                instance("127.0.0.1:8000", "a.example.com"),
+                instance("[::1]:8000", "a.example.com"),
+                instance("127.0.0.2:8000", "b.example.com"),
+            ]),
+            discovered.clone(),
+        );
+        let service = LoadBalanceService::new(
+            WeightedRandomBalance::new(),
+            discover,
+            service_fn(|cx: &mut ClientContext, _: Request<()>| {
+                let callee = cx.rpc_info().callee();
+                let picked = callee.address();
+                let fallbacks = callee.get::<FallbackAddresses>().unwrap().0.clone();
+                async move { Ok::<_, ClientError>((picked, fallbacks)) }
+            }),
+            MetaInfoHashKey,
+        );
+
+        let host_a: [std::net::SocketAddr; 2] =
+            ["127.0.0.1:8000", "[::1]:8000"].map(|addr| addr.parse().unwrap());
+        for _ in 0..32 {
+            let (picked, fallbacks) = service
+                .call(
+                    &mut ClientContext::new(),
+                    Request::builder().body(()).unwrap(),
+                )
+                .await
+                .unwrap();
+            let Some(Address::Ip(picked)) = picked else {
+                panic!("unexpected address: {picked:?}");
+            };
+            // only the other address of the same host is a fallback
+            if host_a.contains(&picked) {
+                assert_eq!(fallbacks.len(), 1);
+                assert!(host_a.contains(&fallbacks[0]) && fallbacks[0] != picked);
+            } else {
+                assert!(fallbacks.is_empty());
+            }
+        }
+        // once by the load balancer and once for grouping the instances by hosts
+        assert_eq!(discovered.load(Ordering::Relaxed), 2);
+
+        // the instances without the tag are different backends
+        let discover = StaticDiscover::from(host_a.to_vec());
+        let service = LoadBalanceService::new(
+            WeightedRandomBalance::new(),
+            discover,
+            service_fn(|cx: &mut ClientContext, _: Request<()>| {
+                let fallbacks = cx
+                    .rpc_info()
+                    .callee()
+                    .get::<FallbackAddresses>()
+                    .unwrap()
+                    .0
+                    .clone();
+                async move { Ok::<_, ClientError>(fallbacks) }
+            }),
+            MetaInfoHashKey,
+        );
+        let fallbacks = service
+            .call(
+                &mut ClientContext::new(),
+                Request::builder().body(()).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(fallbacks.is_empty());
+    }
+}
@@ -25,7 +25,10 @@ use volo::{
     client::{MkClient, OneShotService},
     context::Context,
     loadbalance::MkLbLayer,
-    net::dial::{DefaultMakeTransport, MakeTransport},
+    net::{
+        dial::{DefaultMakeTransport, MakeTransport},
+        sockopt::SocketOptions,
+    },
 };
 
 use self::{
@@ -125,12 +128,12 @@ impl Default for ClientBuilder<Identity, Identity, DefaultMkClient, DefaultLb> {
     }
 }
 
-impl<IL, OL, C, LB, DISC> ClientBuilder<IL, OL, C, LbConfig<LB, DISC>> {
+impl<IL, OL, C, LB, DISC, H> ClientBuilder<IL, OL, C, LbConfig<LB, DISC, H>> {
     /// Set load balancer for the client.
     pub fn load_balance<NLB>(
         self,
         load_balance: NLB,
-    ) -> ClientBuilder<IL, OL, C, LbConfig<NLB, DISC>> {
+    ) -> ClientBuilder<IL, OL, C, LbConfig<NLB, DISC, H>> {
         ClientBuilder {
             http_config: self.http_config,
             client_config: self.client_config,
@@ -151,7 +154,10 @@ impl<IL, OL, C, LB, DISC> ClientBuilder<IL, OL, C, LbConfig<LB, DISC>> {
     }
 
     /// Set service discover for the client.
-    pub fn discover<NDISC>(self, discover: NDISC) -> ClientBuilder<IL, OL, C, LbConfig<LB, NDISC>> {
+    pub fn discover<NDISC>(
+        self,
+        discover: NDISC,
+    ) -> ClientBuilder<IL, OL, C, LbConfig<LB, NDISC, H>> {
         ClientBuilder {
             http_config: self.http_config,
             client_config: self.client_config,
@@ -170,6 +176,30 @@ impl<IL, OL, C, LB, DISC> ClientBuilder<IL, OL, C, LbConfig<LB, DISC>> {
             tls_config: self.tls_config,
         }
     }
+
+    /// Set how to compute the [`RequestHash`](volo::loadbalance::RequestHash) of the requests
+    /// for the hashing load balancers, e.g., [`HeaderHashKey`](loadbalance::HeaderHashKey).
+    ///
+    /// The [`RequestHash`](volo::loadbalance::RequestHash) in the metainfo is used by default.
+    pub fn hash_key<NH>(self, hash_key: NH) -> ClientBuilder<IL, OL, C, LbConfig<LB, DISC, NH>> {
+        ClientBuilder {
+            http_config: self.http_config,
+            client_config: self.client_config,
+            pool_config: self.pool_config,
+            connector: self.connector,
+            timeout: self.timeout,
+            user_agent: self.user_agent,
+            host_mode: self.host_mode,
+            headers: self.headers,
+            inner_layer: self.inner_layer,
+            outer_layer: self.outer_layer,
+            mk_client: self.mk_client,
+            mk_lb: self.mk_lb.hash_key(hash_key),
+            status: self.status,
+            #[cfg(feature = "__tls")]
+            tls_config: self.tls_config,
+        }
+    }
 }
 
 impl<IL, OL, C, LB> ClientBuilder<IL, OL, C, LB> {
@@ -516,6 +546,12 @@ impl<IL, OL, C, LB> ClientBuilder<IL, OL, C, LB> {
         self
     }
 
+    /// Set the socket options for the connections, e.g., TCP keepalive and the buffer sizes.
+    pub fn set_socket_options(&mut self, options: SocketOptions) -> &mut Self {
+        self.connector.set_socket_options(options);
+        self
+    }
+
     /// Set the maximum idle time for reading data from the connection.
     pub fn set_read_timeout(&mut self, timeout: Duration) -> &mut Self {
         self.connector.set_read_timeout(Some(timeout));
@@ -657,6 +693,7 @@ impl<IL, OL, C, LB> ClientBuilder<IL, OL, C, LB> {
             self.http_config,
             self.client_config,
             self.pool_config,
+            self.connector,
             #[cfg(feature = "__tls")]
             self.tls_config,
         );
@@ -1,6 +1,8 @@
+use std::net::SocketAddr;
+
 use http::uri::Scheme;
 use motore::service::UnaryService;
-use volo::net::{Address, conn::Conn};
+use volo::net::{Address, conn::Conn, dial::DefaultMakeTransport};
 
 use super::{plain::PlainMakeConnection, protocol::ClientTransportConfig};
 use crate::error::{ClientError, client::bad_scheme};
@@ -12,8 +14,8 @@ pub struct ConnectorBuilder<'a> {
 }
 
 impl<'a> ConnectorBuilder<'a> {
-    pub fn new(config: &'a ClientTransportConfig) -> Self {
-        let mk_conn = HttpMakeConnection::Plain(PlainMakeConnection::default());
+    pub fn new(config: &'a ClientTransportConfig, mk_conn: DefaultMakeTransport) -> Self {
+        let mk_conn = HttpMakeConnection::Plain(PlainMakeConnection::new(mk_conn));
         Self { mk_conn, config }
     }
 
@@ -71,6 +73,8 @@ fn default_tls_connector() -> volo::net::tls::TlsConnector {
 pub(super) struct PeerInfo {
     pub scheme: Scheme,
     pub address: Address,
+    /// Other addresses of the peer for Happy Eyeballs
+    pub fallbacks: Vec<SocketAddr>,
     #[cfg(feature = "__tls")]
     pub name: faststr::FastStr,
 }
@@ -83,8 +87,11 @@ pub enum HttpMakeConnection {
 }
 
 impl HttpMakeConnection {
-    pub fn builder(config: &ClientTransportConfig) -> ConnectorBuilder<'_> {
-        ConnectorBuilder::new(config)
+    pub fn builder(
+        config: &ClientTransportConfig,
+        mk_conn: DefaultMakeTransport,
+    ) -> ConnectorBuilder<'_> {
+        ConnectorBuilder::new(config, mk_conn)
     }
 }
 
@@ -1,10 +1,10 @@
-use std::error::Error;
+use std::{error::Error, net::SocketAddr};
 
 use motore::{make::MakeConnection, service::UnaryService};
 use volo::net::{Address, dial::DefaultMakeTransport};
 
 use super::connector::PeerInfo;
-use crate::error::{ClientError, client::request_error};
+use crate::error::{ClientError, client::connect_error};
 
 #[derive(Clone, Debug)]
 pub struct PlainMakeConnection<MkC = DefaultMakeTransport> {
@@ -28,19 +28,33 @@ impl Default for PlainMakeConnection<DefaultMakeTransport> {
 
 impl<MkC> UnaryService<PeerInfo> for PlainMakeConnection<MkC>
 where
-    MkC: MakeConnection<Address> + Sync,
-    MkC::Error: Error + Send + Sync + 'static,
+    MkC: MakeConnection<Address>
+        + UnaryService<
+            Vec<SocketAddr>,
+            Response = <MkC as MakeConnection<Address>>::Connection,
+            Error = <MkC as MakeConnection<Address>>::Error,
+        > + Sync,
+    <MkC as MakeConnection<Address>>::Error: Error + Send + Sync + 'static,
 {
-    type Response = MkC::Connection;
+    type Response = <MkC as MakeConnection<Address>>::Connection;
     type Error = ClientError;
 
     async fn call(&self, req: PeerInfo) -> Result<Self::Response, Self::Error> {
         tracing::debug!("[Volo-HTTP] connecting to target: {req:?}");
-        match self.mk_conn.make_connection(req.address.clone()).await {
+        let res = match &req.address {
+            Address::Ip(addr) if !req.fallbacks.is_empty() => {
+                let mut addrs = Vec::with_capacity(req.fallbacks.len() + 1);
+                addrs.push(*addr);
+                addrs.extend_from_slice(&req.fallbacks);
+                UnaryService::call(&self.mk_conn, addrs).await
+            }
+            _ => self.mk_conn.make_connection(req.address.clone()).await,
+        };
+        match res {
             Ok(conn) => Ok(conn),
             Err(err) => {
                 tracing::warn!("[Volo-HTTP] failed to make connection, error: {err}");
-                Err(request_error(err).with_address(req.address))
+                Err(connect_error(err).with_address(req.address))
             }
         }
     }
@@ -14,7 +14,10 @@ use http::{
 use hyper::client::conn;
 use hyper_util::rt::TokioIo;
 use motore::{make::MakeConnection, service::Service};
-use volo::{context::Context, net::Address};
+use volo::{
+    context::Context,
+    net::{Address, dial::DefaultMakeTransport},
+};
 
 use super::{
     connector::{HttpMakeConnection, PeerInfo},
@@ -22,6 +25,7 @@ use super::{
 };
 use crate::{
     body::Body,
+    client::loadbalance::FallbackAddresses,
     context::ClientContext,
     error::{
         BoxError, ClientError,
@@ -94,6 +98,7 @@ impl<B> ClientTransport<B> {
         http_config: ClientConfig,
         transport_config: ClientTransportConfig,
         pool_config: pool::Config,
+        mk_conn: DefaultMakeTransport,
         #[cfg(feature = "__tls")] tls_connector: Option<volo::net::tls::TlsConnector>,
     ) -> Self {
         #[cfg(feature = "http1")]
@@ -101,7 +106,7 @@ impl<B> ClientTransport<B> {
         #[cfg(feature = "http2")]
         let h2_client = super::http2::client(&http_config.h2);
 
-        let builder = HttpMakeConnection::builder(&transport_config);
+        let builder = HttpMakeConnection::builder(&transport_config, mk_conn);
         #[cfg(feature = "__tls")]
         let builder = match tls_connector {
             Some(connector) => builder.with_tls_connector(connector),
@@ -130,6 +135,9 @@ impl<B> ClientTransport<B> {
         B::Data: Send,
         B::Error: Into<BoxError> + 'static,
     {
+        // The connection may be established to a fallback address of the same host, but it is still
+        // pooled by the picked address, so that the following requests to the picked address reuse it
+        // rather than waiting for the unreachable address again.
         let key = pool_key(&peer);
         let connector = self.connector.clone();
         let pool = self.pool.clone();
@@ -351,6 +359,10 @@ where
         let peer = PeerInfo {
             scheme: cx.target().scheme().cloned().unwrap_or(Scheme::HTTP),
             address,
+            fallbacks: callee
+                .get::<FallbackAddresses>()
+                .map(|fallbacks| fallbacks.0.clone())
+                .unwrap_or_default(),
             #[cfg(feature = "__tls")]
             name: callee.service_name(),
         };
@@ -635,6 +647,7 @@ where
     B::Error: Into<Box<dyn std::error::Error + Send + Sync>> + 'static,
 {
     pub async fn send_request(&mut self, req: Request<B>) -> Result<Response> {
+        let idempotent = req.method().is_idempotent();
         let res = match self {
             #[cfg(feature = "http1")]
             Self::H1(h1) => h1.send_request(req).await,
@@ -643,7 +656,7 @@ where
         };
         match res {
             Ok(resp) => Ok(resp.map(Body::from_incoming)),
-            Err(err) => Err(request_error(err)),
+            Err(err) => Err(request_error(err).with_idempotent(idempotent)),
         }
     }
 }
@@ -694,6 +707,10 @@ mod h1_connection_reuse_tests {
     use std::{
         future::{Future, pending, poll_fn},
         net::{Ipv4Addr, SocketAddr},
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
         task::Poll,
         time::Duration,
     };
@@ -703,15 +720,25 @@ mod h1_connection_reuse_tests {
     use http_body_util::Empty;
     use hyper::client::conn;
     use hyper_util::rt::TokioIo;
+    use motore::service::Service;
     use tokio::{
         io::{AsyncReadExt, AsyncWriteExt, DuplexStream},
+        net::TcpListener,
         sync::{mpsc, oneshot},
         time::timeout,
     };
-    use volo::net::Address;
+    use volo::{
+        context::Context,
+        net::{Address, dial::DefaultMakeTransport},
+    };
 
-    use super::{H1Lease, HttpConnection, ManagedH1Connection, PoolKey, pool};
-    use crate::body::BodyConversion;
+    use super::{
+        ClientConfig, ClientTransport, ClientTransportConfig, H1Lease, HttpConnection,
+        ManagedH1Connection, PoolKey, pool,
+    };
+    use crate::{
+        body::BodyConversion, client::loadbalance::FallbackAddresses, context::ClientContext,
+    };
 
     fn pool_key() -> PoolKey {
         (
@@ -1050,4 +1077,95 @@ mod h1_connection_reuse_tests {
         drop(sender_a);
         drop(return_tx);
     }
+
+    #[tokio::test]
+    async fn fallback_connection_is_pooled_by_picked_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // the port of a dropped listener refuses the connections
+        let refused = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let server_task = tokio::spawn({
+            let accepted = accepted.clone();
+            async move {
+                loop {
+                    let (mut stream, _) = listener.accept().await.unwrap();
+                    accepted.fetch_add(1, Ordering::Relaxed);
+                    tokio::spawn(async move {
+                        let mut buf = Vec::new();
+                        let mut chunk = [0_u8; 1024];
+                        loop {
+                            let read = stream.read(&mut chunk).await.unwrap_or(0);
+                            if read == 0 {
+                                return;
+                            }
+                            buf.extend_from_slice(&chunk[..read]);
+                            while let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                                buf.drain(..end + 4);
+                                stream
+                                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                                    .await
+                                    .unwrap();
+                            }
+                        }
+                    });
+                }
+            }
+        });
+
+        let transport = ClientTransport::<Empty<Bytes>>::new(
+            ClientConfig::default(),
+            ClientTransportConfig::default(),
+            pool::Config::default(),
+            DefaultMakeTransport::new(),
+            #[cfg(feature = "__tls")]
+            None,
+        );
+        let key: PoolKey = (
+            Scheme::HTTP,
+            Address::Ip(refused),
+            #[cfg(feature = "__tls")]
+            None,
+        );
+
+        for _ in 0..2 {
+            let mut cx = ClientContext::new();
+            let callee = cx.rpc_info_mut().callee_mut();
+            callee.set_address(Address::Ip(refused));
+            callee.insert(FallbackAddresses(vec![addr]));
+
+            let response = timeout(
+                Duration::from_secs(1),
+                transport.call(&mut cx, empty_request()),
+            )
+            .await
+            .expect("request timed out")
+            .expect("request failed");
+            assert_eq!(
+                response
+                    .into_body()
+                    .into_vec()
+                    .await
+                    .expect("collect response"),
+                b"ok"
+            );
+
+            // Wait for the driver to return the connection, and put it back into the pool.
+            drop(
+                timeout(Duration::from_secs(1), transport.pool.checkout(key.clone()))
+                    .await
+                    .expect("connection is not pooled by the picked address")
+                    .expect("checkout failed"),
+            );
+        }
+
+        // The second request reuses the connection to the fallback address.
+        assert_eq!(accepted.load(Ordering::Relaxed), 1);
+        server_task.abort();
+    }
 }
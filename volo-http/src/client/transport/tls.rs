@@ -6,7 +6,7 @@ use volo::net::{
 };
 
 use super::{connector::PeerInfo, plain::PlainMakeConnection};
-use crate::error::{ClientError, client::request_error};
+use crate::error::{ClientError, client::connect_error};
 
 #[derive(Clone, Debug)]
 pub struct TlsMakeConnection<S = PlainMakeConnection> {
@@ -41,20 +41,22 @@ where
         let target_name = req.name;
         tracing::debug!("[Volo-HTTP] try to make tls handshake, name: {target_name:?}");
 
+        // the connected one may be a fallback address rather than `req.address`
+        let peer_addr = conn.info.peer_addr;
         let tcp_stream = match conn.stream {
             ConnStream::Tcp(tcp_stream) => tcp_stream,
             _ => unreachable!(),
         };
         match self.tls_connector.connect(&target_name, tcp_stream).await {
-            Ok(stream) => Ok(Conn {
-                stream,
-                info: ConnInfo {
-                    peer_addr: Some(req.address),
-                },
-            }),
+            Ok(stream) => {
+                let mut info = ConnInfo::new(peer_addr);
+                info.peer_certificate = stream.peer_certificate();
+                info.server_name = stream.server_name();
+                Ok(Conn { info, stream })
+            }
             Err(err) => {
                 tracing::warn!("[Volo-HTTP] failed to make tls connection, error: {err}");
-                Err(request_error(err))
+                Err(connect_error(err))
             }
         }
     }
@@ -0,0 +1,756 @@
+//! Service discovery and registration through [Consul](https://www.consul.io/).
+//!
+//! [`ConsulDiscover`] implements [`Discover`] by the health API of Consul, and watches the
+//! changes of the services through blocking queries. [`ConsulRegistry`] implements [`Registry`]
+//! by the agent API of Consul, and keeps the registered services alive through TTL checks.
+//!
+//! The tags and the meta of a Consul service are mapped into [`Instance::tags`]: a tag
+//! `key=value` is mapped into the tag `key` with value `value`, other tags are mapped into tags
+//! with empty values, and the meta are mapped as they are, which overrides the tags with the same
+//! keys. On the contrary, [`ConsulRegistry`] registers the [`Instance::tags`] as the meta of the
+//! service.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use volo::registry::Registration;
+//! use volo_http::{
+//!     client::Client,
+//!     consul::{ConsulClient, ConsulDiscover, ConsulRegistry},
+//! };
+//!
+//! let consul = ConsulClient::new(Client::default(), "http://127.0.0.1:8500");
+//!
+//! // client side
+//! let discover = ConsulDiscover::new(consul.clone());
+//!
+//! // server side
+//! let registration = Registration::new(ConsulRegistry::new(consul), "echo")
+//!     .with_address("10.0.0.1:8080".parse::<std::net::SocketAddr>().unwrap());
+//! ```
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+use async_broadcast::{InactiveReceiver, Receiver, Sender};
+use dashmap::DashMap;
+use faststr::FastStr;
+use http::{Method, header::HeaderName};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use serde::{Deserialize, Serialize};
+use tokio::task::AbortHandle;
+use volo::{
+    context::Endpoint,
+    discovery::{Change, Discover, Instance, diff_instances},
+    loadbalance::error::LoadBalanceError,
+    net::Address,
+    registry::Registry,
+    retry::Backoff,
+};
+
+use crate::{
+    body::BodyConversion,
+    client::{Client, RequestBuilder},
+    error::{
+        ClientError,
+        client::{Result, other_error},
+    },
+};
+
+const CONSUL_TOKEN: HeaderName = HeaderName::from_static("x-consul-token");
+const CONSUL_INDEX: HeaderName = HeaderName::from_static("x-consul-index");
+
+const DEFAULT_WAIT: Duration = Duration::from_secs(30);
+const DEFAULT_TTL: Duration = Duration::from_secs(10);
+const DEFAULT_DEREGISTER_AFTER: Duration = Duration::from_secs(60);
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+const RETRY_BACKOFF: Backoff = Backoff::exponential(RETRY_INTERVAL, MAX_RETRY_INTERVAL);
+const CHANNEL_CAPACITY: usize = 64;
+
+/// The characters to be percent-encoded in a path segment, the unreserved characters of
+/// RFC 3986 are kept.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+fn encode_path_segment(segment: &str) -> String {
+    utf8_percent_encode(segment, PATH_SEGMENT).to_string()
+}
+
+/// The HTTP client of the Consul API, which is shared by [`ConsulDiscover`] and
+/// [`ConsulRegistry`].
+#[derive(Clone)]
+pub struct ConsulClient {
+    client: Client,
+    address: FastStr,
+    token: Option<FastStr>,
+}
+
+impl ConsulClient {
+    /// Create a [`ConsulClient`] which sends requests to the Consul agent at `address` through
+    /// `client`, e.g., `http://127.0.0.1:8500`.
+    pub fn new(client: Client, address: impl Into<FastStr>) -> Self {
+        Self {
+            client,
+            address: address.into(),
+            token: None,
+        }
+    }
+
+    /// Set the ACL token for the requests.
+    pub fn with_token(mut self, token: impl Into<FastStr>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder<Client> {
+        let address = self.address.trim_end_matches('/');
+        let builder = self.client.request(method, format!("{address}{path}"));
+        match &self.token {
+            Some(token) => builder.header(CONSUL_TOKEN, token.as_str()),
+            None => builder,
+        }
+    }
+
+    async fn send(&self, builder: RequestBuilder<Client>) -> Result<crate::response::Response> {
+        let resp = builder.send().await?;
+        if !resp.status().is_success() {
+            return Err(other_error(format!(
+                "consul responded with status {}",
+                resp.status()
+            )));
+        }
+        Ok(resp)
+    }
+
+    /// Query the passing instances of the service, and return them with the index of Consul.
+    ///
+    /// If `index` is not zero, the query blocks until the index changes or `wait` elapses.
+    async fn health(
+        &self,
+        service: &str,
+        index: u64,
+        wait: Duration,
+    ) -> Result<(u64, Vec<Arc<Instance>>)> {
+        let mut path = format!(
+            "/v1/health/service/{}?passing=true",
+            encode_path_segment(service)
+        );
+        if index != 0 {
+            path.push_str(&format!("&index={index}&wait={}ms", wait.as_millis()));
+        }
+        let resp = self.send(self.request(Method::GET, &path)).await?;
+        let index = resp
+            .headers()
+            .get(CONSUL_INDEX)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let entries: Vec<ServiceEntry> = resp.into_body().into_json().await.map_err(other_error)?;
+        let instances = entries
+            .into_iter()
+            .filter_map(ServiceEntry::into_instance)
+            .map(Arc::new)
+            .collect();
+        Ok((index, instances))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ServiceEntry {
+    node: Node,
+    service: AgentService,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Node {
+    address: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AgentService {
+    #[serde(default)]
+    address: String,
+    port: u16,
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+    #[serde(default)]
+    meta: Option<HashMap<String, String>>,
+    #[serde(default)]
+    weights: Option<Weights>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Weights {
+    passing: u32,
+    warning: u32,
+}
+
+impl ServiceEntry {
+    fn into_instance(self) -> Option<Instance> {
+        let service = self.service;
+        let host = if service.address.is_empty() {
+            self.node.address
+        } else {
+            service.address
+        };
+        let Ok(ip) = host.parse::<IpAddr>() else {
+            tracing::warn!("[Volo-HTTP] consul: ignore the instance with invalid ip {host}");
+            return None;
+        };
+
+        let mut tags = HashMap::new();
+        for tag in service.tags.into_iter().flatten() {
+            let (key, value) = match tag.split_once('=') {
+                Some((key, value)) => (key.to_owned(), value.to_owned()),
+                None => (tag, String::new()),
+            };
+            tags.insert(Cow::Owned(key), Cow::Owned(value));
+        }
+        for (key, value) in service.meta.into_iter().flatten() {
+            tags.insert(Cow::Owned(key), Cow::Owned(value));
+        }
+
+        Some(Instance {
+            address: Address::Ip(SocketAddr::new(ip, service.port)),
+            weight: service.weights.map(|w| w.passing).unwrap_or(1),
+            tags,
+        })
+    }
+}
+
+struct DiscoverInner {
+    consul: ConsulClient,
+    wait: Duration,
+    services: DashMap<FastStr, Vec<Arc<Instance>>>,
+    sender: Sender<Change<FastStr>>,
+    receiver: InactiveReceiver<Change<FastStr>>,
+}
+
+impl DiscoverInner {
+    fn update(&self, service: &FastStr, next: Vec<Arc<Instance>>) {
+        let prev = self
+            .services
+            .insert(service.clone(), next.clone())
+            .unwrap_or_default();
+        let (change, changed) = diff_instances(service.clone(), prev, next);
+        if changed {
+            let _ = self.sender.try_broadcast(change);
+        }
+    }
+}
+
+/// Watch the service through blocking queries until the [`ConsulDiscover`] is dropped.
+///
+/// The queries are backed off if they fail, or return without advancing the index before the
+/// wait time, e.g., the index is missing, so that Consul is not flooded.
+async fn watch_service(inner: Weak<DiscoverInner>, service: FastStr, mut index: u64) {
+    let mut retries = 0;
+    loop {
+        let Some(inner) = inner.upgrade() else {
+            break;
+        };
+        let start = Instant::now();
+        let advanced = match inner.consul.health(&service, index, inner.wait).await {
+            Ok((next, instances)) => {
+                inner.update(&service, instances);
+                let advanced = next > index;
+                // the index may go backwards, and the query should restart from zero
+                index = if next < index { 0 } else { next };
+                // a blocking query which times out does not advance the index either
+                advanced || start.elapsed() >= RETRY_INTERVAL
+            }
+            Err(err) => {
+                tracing::warn!("[Volo-HTTP] consul: failed to watch service {service}: {err}");
+                false
+            }
+        };
+        drop(inner);
+        if advanced {
+            retries = 0;
+        } else {
+            retries += 1;
+            tokio::time::sleep(RETRY_BACKOFF.delay(retries)).await;
+        }
+    }
+}
+
+/// [`ConsulDiscover`] discovers the passing instances of the services from Consul.
+///
+/// Once a service is discovered, it is watched through blocking queries, and the changes of its
+/// instances are sent to the receivers of [`Discover::watch`].
+#[derive(Clone)]
+pub struct ConsulDiscover {
+    inner: Arc<DiscoverInner>,
+}
+
+impl ConsulDiscover {
+    /// Create a [`ConsulDiscover`] with the default wait time of blocking queries, 30 seconds.
+    pub fn new(consul: ConsulClient) -> Self {
+        Self::with_wait(consul, DEFAULT_WAIT)
+    }
+
+    /// Create a [`ConsulDiscover`] with the given wait time of blocking queries.
+    pub fn with_wait(consul: ConsulClient, wait: Duration) -> Self {
+        let (mut sender, receiver) = async_broadcast::broadcast(CHANNEL_CAPACITY);
+        sender.set_overflow(true);
+        Self {
+            inner: Arc::new(DiscoverInner {
+                consul,
+                wait,
+                services: DashMap::new(),
+                sender,
+                receiver: receiver.deactivate(),
+            }),
+        }
+    }
+}
+
+impl Discover for ConsulDiscover {
+    type Key = FastStr;
+    type Error = LoadBalanceError;
+
+    async fn discover<'s>(
+        &'s self,
+        endpoint: &'s Endpoint,
+    ) -> Result<Vec<Arc<Instance>>, Self::Error> {
+        let service = endpoint.service_name();
+        if let Some(instances) = self.inner.services.get(&service) {
+            return Ok(instances.clone());
+        }
+        let (index, instances) = self
+            .inner
+            .consul
+            .health(&service, 0, self.inner.wait)
+            .await
+            .map_err(|err| LoadBalanceError::Discover(Box::new(err)))?;
+        match self.inner.services.entry(service.clone()) {
+            // the service has been discovered and watched concurrently
+            dashmap::Entry::Occupied(entry) => Ok(entry.get().clone()),
+            dashmap::Entry::Vacant(entry) => {
+                entry.insert(instances.clone());
+                tokio::spawn(watch_service(Arc::downgrade(&self.inner), service, index));
+                Ok(instances)
+            }
+        }
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        endpoint.service_name()
+    }
+
+    fn watch(&self, _keys: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+        Some(self.inner.receiver.activate_cloned())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ServiceRegistration<'a> {
+    #[serde(rename = "ID")]
+    id: &'a str,
+    name: &'a str,
+    address: String,
+    port: u16,
+    meta: HashMap<&'a str, &'a str>,
+    weights: Weights,
+    check: Check,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Check {
+    #[serde(rename = "TTL")]
+    ttl: String,
+    deregister_critical_service_after: String,
+}
+
+/// [`ConsulRegistry`] registers the instances to the Consul agent with a TTL check, and passes
+/// the check periodically until the instance is deregistered.
+///
+/// The ID of the registered service is `{service_name}-{address}`.
+#[derive(Clone)]
+pub struct ConsulRegistry {
+    consul: ConsulClient,
+    ttl: Duration,
+    deregister_after: Duration,
+    heartbeats: Arc<DashMap<String, AbortHandle>>,
+}
+
+impl ConsulRegistry {
+    /// Create a [`ConsulRegistry`] with the default TTL, 10 seconds.
+    pub fn new(consul: ConsulClient) -> Self {
+        Self {
+            consul,
+            ttl: DEFAULT_TTL,
+            deregister_after: DEFAULT_DEREGISTER_AFTER,
+            heartbeats: Default::default(),
+        }
+    }
+
+    /// Set the TTL of the check, the check is passed every half of the TTL.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Set the timeout after which Consul removes the service if its check is critical, the
+    /// default is 1 minute.
+    pub fn with_deregister_after(mut self, timeout: Duration) -> Self {
+        self.deregister_after = timeout;
+        self
+    }
+
+    fn service_id(service_name: &str, instance: &Instance) -> Result<(String, SocketAddr)> {
+        match &instance.address {
+            Address::Ip(addr) => Ok((format!("{service_name}-{addr}"), *addr)),
+            #[allow(unreachable_patterns)]
+            address => Err(other_error(format!(
+                "consul: cannot register the non-ip address {address}"
+            ))),
+        }
+    }
+
+    fn spawn_heartbeat(&self, id: String) {
+        let consul = self.consul.clone();
+        let path = format!("/v1/agent/check/pass/service:{}", encode_path_segment(&id));
+        let interval = self.ttl / 2;
+        let handle = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(err) = consul.send(consul.request(Method::PUT, &path)).await {
+                    tracing::warn!("[Volo-HTTP] consul: failed to pass the check {path}: {err}");
+                }
+            }
+        });
+        if let Some(prev) = self.heartbeats.insert(id, handle.abort_handle()) {
+            prev.abort();
+        }
+    }
+}
+
+impl Registry for ConsulRegistry {
+    type Error = ClientError;
+
+    async fn register<'s>(
+        &'s self,
+        service_name: &'s FastStr,
+        instance: &'s Instance,
+    ) -> Result<(), Self::Error> {
+        let (id, addr) = Self::service_id(service_name, instance)?;
+        let registration = ServiceRegistration {
+            id: &id,
+            name: service_name,
+            address: addr.ip().to_string(),
+            port: addr.port(),
+            meta: instance
+                .tags
+                .iter()
+                .map(|(k, v)| (k.as_ref(), v.as_ref()))
+                .collect(),
+            weights: Weights {
+                passing: instance.weight,
+                warning: 1,
+            },
+            check: Check {
+                ttl: format!("{}ms", self.ttl.as_millis()),
+                deregister_critical_service_after: format!(
+                    "{}ms",
+                    self.deregister_after.as_millis()
+                ),
+            },
+        };
+        self.consul
+            .send(
+                self.consul
+                    .request(Method::PUT, "/v1/agent/service/register")
+                    .json(&registration),
+            )
+            .await?;
+        self.spawn_heartbeat(id);
+        Ok(())
+    }
+
+    async fn deregister<'s>(
+        &'s self,
+        service_name: &'s FastStr,
+        instance: &'s Instance,
+    ) -> Result<(), Self::Error> {
+        let (id, _) = Self::service_id(service_name, instance)?;
+        if let Some((_, heartbeat)) = self.heartbeats.remove(&id) {
+            heartbeat.abort();
+        }
+        self.consul
+            .send(self.consul.request(
+                Method::PUT,
+                &format!("/v1/agent/service/deregister/{}", encode_path_segment(&id)),
+            ))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use std::{
+        collections::HashMap,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use faststr::FastStr;
+    use percent_encoding::percent_decode_str;
+    use serde::Deserialize;
+    use volo::{
+        context::Endpoint,
+        discovery::{Discover, Instance},
+        net::Address,
+        registry::Registry,
+    };
+
+    use super::{ConsulClient, ConsulDiscover, ConsulRegistry, encode_path_segment};
+    use crate::{
+        client::{ClientBuilder, test_helpers::MockTransport},
+        server::{
+            extract::{Json, Query},
+            param::PathParams,
+            route::{Router, get, put},
+        },
+    };
+
+    #[derive(Clone, Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct MockRegistration {
+        #[serde(rename = "ID")]
+        id: String,
+        name: String,
+        address: String,
+        port: u16,
+        meta: HashMap<String, String>,
+    }
+
+    #[derive(Default)]
+    struct MockState {
+        index: u64,
+        services: Vec<MockRegistration>,
+        passes: usize,
+    }
+
+    #[derive(Deserialize)]
+    struct HealthQuery {
+        index: Option<u64>,
+        wait: Option<String>,
+    }
+
+    fn health(state: &MockState, name: &str) -> String {
+        let entries: Vec<String> = state
+            .services
+            .iter()
+            .filter(|s| s.name == name)
+            .map(|s| {
+                let meta: Vec<String> = s
+                    .meta
+                    .iter()
+                    .map(|(k, v)| format!("\"{k}\":\"{v}\""))
+                    .collect();
+                format!(
+                    "{{\"Node\":{{\"Address\":\"127.0.0.1\"}},\"Service\":{{\"ID\":\"{}\",\
+                     \"Service\":\"{}\",\"Address\":\"{}\",\"Port\":{},\"Tags\":[\"canary\",\
+                     \"zone=b\"],\"Meta\":{{{}}},\"Weights\":{{\"Passing\":10,\"Warning\":1}}}}}}",
+                    s.id,
+                    s.name,
+                    s.address,
+                    s.port,
+                    meta.join(","),
+                )
+            })
+            .collect();
+        format!("[{}]", entries.join(","))
+    }
+
+    /// A mock of the Consul agent, which supports the blocking queries of the health API.
+    fn mock_consul(state: Arc<Mutex<MockState>>) -> Router {
+        let register_state = state.clone();
+        let deregister_state = state.clone();
+        let pass_state = state.clone();
+        Router::new()
+            .route(
+                "/v1/agent/service/register",
+                put(move |Json(reg): Json<MockRegistration>| {
+                    let state = register_state.clone();
+                    async move {
+                        let mut state = state.lock().unwrap();
+                        state.services.retain(|s| s.id != reg.id);
+                        state.services.push(reg);
+                        state.index += 1;
+                    }
+                }),
+            )
+            .route(
+                "/v1/agent/service/deregister/{id}",
+                put(move |PathParams(id): PathParams<String>| {
+                    let state = deregister_state.clone();
+                    async move {
+                        let id = percent_decode_str(&id).decode_utf8_lossy().into_owned();
+                        let mut state = state.lock().unwrap();
+                        state.services.retain(|s| s.id != id);
+                        state.index += 1;
+                    }
+                }),
+            )
+            .route(
+                "/v1/agent/check/pass/{id}",
+                put(move || {
+                    let state = pass_state.clone();
+                    async move {
+                        state.lock().unwrap().passes += 1;
+                    }
+                }),
+            )
+            .route(
+                "/v1/health/service/{name}",
+                get(
+                    move |PathParams(name): PathParams<String>,
+                          Query(query): Query<HealthQuery>| {
+                        let state = state.clone();
+                        async move {
+                            let wait = query
+                                .wait
+                                .and_then(|w| w.strip_suffix("ms")?.parse().ok())
+                                .map(Duration::from_millis)
+                                .unwrap_or_default();
+                            let deadline = Instant::now() + wait;
+                            let index = query.index.unwrap_or(0);
+                            while state.lock().unwrap().index <= index && Instant::now() < deadline
+                            {
+                                tokio::time::sleep(Duration::from_millis(10)).await;
+                            }
+                            let state = state.lock().unwrap();
+                            http::Response::builder()
+                                .header("X-Consul-Index", state.index)
+                                .body(health(&state, &name))
+                                .unwrap()
+                        }
+                    },
+                ),
+            )
+    }
+
+    fn instance(addr: &str) -> Instance {
+        Instance {
+            address: Address::Ip(addr.parse::<SocketAddr>().unwrap()),
+            weight: 10,
+            tags: [("zone".into(), "a".into())].into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn consul_discover_and_registry() {
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let client = ClientBuilder::new()
+            .mock(MockTransport::server_service(mock_consul(state.clone())))
+            .unwrap();
+        let consul = ConsulClient::new(client, "http://127.0.0.1:8500");
+        let registry = ConsulRegistry::new(consul.clone()).with_ttl(Duration::from_millis(100));
+        let discover = ConsulDiscover::with_wait(consul, Duration::from_secs(1));
+        let name = FastStr::from_static_str("echo");
+        let endpoint = Endpoint::new(name.clone());
+
+        registry
+            .register(&name, &instance("10.0.0.1:8080"))
+            .await
+            .unwrap();
+        let instances = discover.discover(&endpoint).await.unwrap();
+        assert_eq!(instances.len(), 1);
+        assert_eq!(
+            instances[0].address,
+            Address::Ip("10.0.0.1:8080".parse().unwrap())
+        );
+        assert_eq!(instances[0].weight, 10);
+        // the meta overrides the tag with the same key
+        assert_eq!(instances[0].tags.get("zone").unwrap(), "a");
+        assert_eq!(instances[0].tags.get("canary").unwrap(), "");
+
+        let mut receiver = discover.watch(None).unwrap();
+        registry
+            .register(&name, &instance("10.0.0.2:8080"))
+            .await
+            .unwrap();
+        let change = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(change.key, "echo");
+        assert_eq!(change.all.len(), 2);
+        assert_eq!(change.added.len(), 1);
+        assert_eq!(discover.discover(&endpoint).await.unwrap().len(), 2);
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(state.lock().unwrap().passes >= 2);
+
+        registry
+            .deregister(&name, &instance("10.0.0.1:8080"))
+            .await
+            .unwrap();
+        let change = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(change.removed.len(), 1);
+        assert_eq!(change.all.len(), 1);
+        assert_eq!(registry.heartbeats.len(), 1);
+    }
+
+    #[test]
+    fn consul_encode_path_segment() {
+        assert_eq!(encode_path_segment("echo-v1.0_a~b"), "echo-v1.0_a~b");
+        assert_eq!(encode_path_segment("echo/../x?y"), "echo%2F..%2Fx%3Fy");
+        assert_eq!(
+            encode_path_segment("echo-[::1]:80"),
+            "echo-%5B%3A%3A1%5D%3A80"
+        );
+    }
+
+    #[tokio::test]
+    async fn consul_watch_backoff() {
+        let queries = Arc::new(Mutex::new(0));
+        let queries_clone = queries.clone();
+        // the index is missing, so that the blocking queries return immediately
+        let router: Router = Router::new().route(
+            "/v1/health/service/{name}",
+            get(move || {
+                let queries = queries_clone.clone();
+                async move {
+                    *queries.lock().unwrap() += 1;
+                    "[]"
+                }
+            }),
+        );
+        let client = ClientBuilder::new()
+            .mock(MockTransport::server_service(router))
+            .unwrap();
+        let discover = ConsulDiscover::new(ConsulClient::new(client, "http://127.0.0.1:8500"));
+        discover
+            .discover(&Endpoint::new("echo".into()))
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        let queries = *queries.lock().unwrap();
+        assert!(queries <= 4, "queries: {queries}");
+    }
+}
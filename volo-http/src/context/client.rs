@@ -6,6 +6,7 @@ use chrono::{DateTime, Local};
 use volo::{
     context::{Reusable, Role, RpcCx, RpcInfo},
     newtype_impl_context,
+    retry::RpcTimeout,
 };
 
 use crate::{
@@ -104,6 +105,13 @@ impl Config {
     }
 }
 
+impl RpcTimeout for Config {
+    #[inline]
+    fn rpc_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+}
+
 impl Reusable for Config {
     fn clear(&mut self) {
         self.timeout = None;
@@ -4,7 +4,13 @@ use std::{error::Error, fmt, net::SocketAddr};
 
 use http::uri::Uri;
 use paste::paste;
-use volo::{context::Endpoint, net::Address};
+use volo::{
+    circuit_breaker::{BreakerFailure, CircuitOpenError},
+    context::Endpoint,
+    fault::FaultAbort,
+    loadbalance::error::Retryable,
+    net::Address,
+};
 
 use super::BoxError;
 use crate::body::BodyConvertError;
@@ -43,6 +49,9 @@ pub struct ClientError {
     // of `ClientError` being too large, we added `Box` to `Uri`.
     uri: Option<Box<Uri>>,
     addr: Option<SocketAddr>,
+    // Whether the failed request is idempotent, so that it can be retried even if it has been
+    // sent.
+    idempotent: bool,
 }
 
 impl ClientError {
@@ -56,9 +65,18 @@ impl ClientError {
             source: error.map(Into::into),
             uri: None,
             addr: None,
+            idempotent: false,
         }
     }
 
+    /// Mark the failed request as idempotent, e.g., its method is `GET`, so that the
+    /// [`ErrorKind::Request`] can be retried.
+    #[inline]
+    pub fn with_idempotent(mut self, idempotent: bool) -> Self {
+        self.idempotent = idempotent;
+        self
+    }
+
     /// Set a [`Uri`] to the [`ClientError`].
     #[inline]
     pub fn set_url(&mut self, uri: Uri) {
@@ -242,6 +260,40 @@ impl From<BodyConvertError> for ClientError {
     }
 }
 
+/// The [`ErrorKind::Connect`] is always retryable since the request has not been sent, while the
+/// [`ErrorKind::Request`] is retryable only if the request is idempotent, otherwise a `POST` may
+/// be handled twice by the server.
+impl Retryable for ClientError {
+    fn retryable(&self) -> bool {
+        match self.kind {
+            ErrorKind::Connect => true,
+            ErrorKind::Request => self.idempotent,
+            _ => false,
+        }
+    }
+}
+
+/// The errors of connecting and sending requests, including the timeouts, are failures.
+impl BreakerFailure for ClientError {
+    fn is_failure(&self) -> bool {
+        matches!(self.kind, ErrorKind::Connect | ErrorKind::Request)
+    }
+}
+
+impl From<CircuitOpenError> for ClientError {
+    fn from(value: CircuitOpenError) -> Self {
+        lb_error(value)
+    }
+}
+
+/// The injected faults are not retryable, and the [`FaultAbort`] with the status code is the
+/// source of the error.
+impl From<FaultAbort> for ClientError {
+    fn from(value: FaultAbort) -> Self {
+        other_error(value)
+    }
+}
+
 impl std::fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -324,10 +376,13 @@ simple_error!(LoadBalance => NoAvailableEndpoint => "no available endpoint");
 mod client_error_tests {
     use std::error::Error;
 
+    use volo::{fault::FaultAbort, loadbalance::error::Retryable};
+
     use crate::error::client::{
-        BadHostName, BadScheme, BodyNotReplayable, InvalidRedirectLocation, NoAddress,
+        BadHostName, BadScheme, BodyNotReplayable, ClientError, InvalidRedirectLocation, NoAddress,
         NoAvailableEndpoint, Timeout, bad_host_name, bad_scheme, body_not_replayable,
-        invalid_redirect_location, no_address, no_available_endpoint, timeout,
+        connect_error, invalid_redirect_location, no_address, no_available_endpoint, other_error,
+        request_error, timeout,
     };
 
     #[test]
@@ -365,4 +420,17 @@ mod client_error_tests {
                 .is::<NoAvailableEndpoint>()
         );
     }
+
+    #[test]
+    fn retryable() {
+        assert!(connect_error("refused").retryable());
+        assert!(!request_error("reset").retryable());
+        assert!(request_error("reset").with_idempotent(true).retryable());
+        assert!(!other_error("other").with_idempotent(true).retryable());
+
+        let err = ClientError::from(FaultAbort::new(503, "injected")).with_idempotent(true);
+        assert!(!err.retryable());
+        let source = err.source().unwrap();
+        assert_eq!(source.downcast_ref::<FaultAbort>().unwrap().code, 503);
+    }
 }
@@ -8,6 +8,9 @@
 pub mod body;
 #[cfg(feature = "client")]
 pub mod client;
+#[cfg(feature = "consul")]
+#[cfg_attr(docsrs, doc(cfg(feature = "consul")))]
+pub mod consul;
 pub mod context;
 pub mod error;
 pub mod request;
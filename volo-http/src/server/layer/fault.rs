@@ -0,0 +1,157 @@
+use http::StatusCode;
+use motore::{Service, layer::Layer};
+use volo::fault::{FaultAbort, FaultInjector};
+
+use crate::{context::ServerContext, request::Request, response::Response, server::IntoResponse};
+
+/// [`Layer`] for injecting faults to the requests
+///
+/// See [`FaultInjectionLayer::new`] for more details.
+#[derive(Clone, Debug)]
+pub struct FaultInjectionLayer {
+    injector: FaultInjector,
+}
+
+impl FaultInjectionLayer {
+    /// Create a new [`FaultInjectionLayer`] with the given [`FaultInjector`].
+    ///
+    /// The aborted requests are responded with [`FaultAbort::code`] as the status code and
+    /// [`FaultAbort::message`] as the body, an invalid status code is responded as
+    /// [`StatusCode::INTERNAL_SERVER_ERROR`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use volo::fault::{FaultAbort, FaultInjector, FaultRule};
+    /// use volo_http::server::{
+    ///     layer::FaultInjectionLayer,
+    ///     route::{Router, get},
+    /// };
+    ///
+    /// async fn index() -> &'static str {
+    ///     "Hello, World"
+    /// }
+    ///
+    /// let injector = FaultInjector::new(vec![
+    ///     FaultRule::new().abort(FaultAbort::new(503, "injected fault"), 10.0),
+    /// ]);
+    ///
+    /// let router: Router = Router::new()
+    ///     .route("/", get(index))
+    ///     .layer(FaultInjectionLayer::new(injector));
+    /// ```
+    pub fn new(injector: FaultInjector) -> Self {
+        Self { injector }
+    }
+}
+
+impl<S> Layer<S> for FaultInjectionLayer
+where
+    S: Send + Sync + 'static,
+{
+    type Service = FaultInjection<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        FaultInjection {
+            service: inner,
+            injector: self.injector,
+        }
+    }
+}
+
+/// [`FaultInjectionLayer`] generated [`Service`]
+///
+/// See [`FaultInjectionLayer`] for more details.
+#[derive(Clone, Debug)]
+pub struct FaultInjection<S> {
+    service: S,
+    injector: FaultInjector,
+}
+
+impl<S, B> Service<ServerContext, Request<B>> for FaultInjection<S>
+where
+    S: Service<ServerContext, Request<B>> + Send + Sync + 'static,
+    S::Response: IntoResponse,
+    B: Send,
+{
+    type Response = Response;
+    type Error = S::Error;
+
+    async fn call(
+        &self,
+        cx: &mut ServerContext,
+        req: Request<B>,
+    ) -> Result<Self::Response, Self::Error> {
+        if let Err(abort) = self.injector.inject(cx).await {
+            return Ok(abort.into_response());
+        }
+        self.service
+            .call(cx, req)
+            .await
+            .map(IntoResponse::into_response)
+    }
+}
+
+impl IntoResponse for FaultAbort {
+    fn into_response(self) -> Response {
+        let status = u16::try_from(self.code)
+            .ok()
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        (status, self.message.to_string()).into_response()
+    }
+}
+
+#[cfg(test)]
+mod fault_tests {
+    use http::{Method, StatusCode};
+    use motore::{Service, layer::Layer};
+    use volo::fault::{FaultAbort, FaultInjector, FaultRule};
+
+    use crate::{
+        body::BodyConversion,
+        server::{
+            layer::FaultInjectionLayer,
+            route::{Route, get},
+            test_helpers::empty_cx,
+        },
+        utils::test_helpers::simple_req,
+    };
+
+    #[tokio::test]
+    async fn test_fault_injection_layer() {
+        async fn index_handler() -> &'static str {
+            "Hello, World"
+        }
+
+        let injector = FaultInjector::new(vec![
+            FaultRule::new().abort(FaultAbort::new(503, "injected"), 100.0),
+        ]);
+        let route: Route<&str> = Route::new(get(index_handler));
+        let service = FaultInjectionLayer::new(injector.clone()).layer(route);
+        let mut cx = empty_cx();
+
+        // Test case 1: aborted
+        let req = simple_req(Method::GET, "/", "");
+        let resp = service.call(&mut cx, req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.into_body().into_string().await.unwrap(), "injected");
+
+        // Test case 2: invalid status code
+        injector.update(vec![
+            FaultRule::new().abort(FaultAbort::new(14, "injected"), 100.0),
+        ]);
+        let req = simple_req(Method::GET, "/", "");
+        let resp = service.call(&mut cx, req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        // Test case 3: no fault
+        injector.update(Vec::new());
+        let req = simple_req(Method::GET, "/", "");
+        let resp = service.call(&mut cx, req).await.unwrap();
+        assert_eq!(
+            resp.into_body().into_string().await.unwrap(),
+            "Hello, World"
+        );
+    }
+}
@@ -1,9 +1,13 @@
 //! Collections of some useful `Layer`s.
 
 mod body_limit;
+mod fault;
 mod filter;
+mod rate_limit;
 mod timeout;
 
 pub use body_limit::BodyLimitLayer;
+pub use fault::{FaultInjection, FaultInjectionLayer};
 pub use filter::FilterLayer;
+pub use rate_limit::{ClientIpKey, RateLimit, RateLimitLayer};
 pub use timeout::TimeoutLayer;
@@ -0,0 +1,213 @@
+use std::sync::Arc;
+
+use faststr::FastStr;
+use http::{StatusCode, header};
+use motore::{Service, layer::Layer};
+use volo::{
+    context::Context,
+    rate_limit::{RateLimitError, RateLimitKey, RateLimiter},
+};
+
+use crate::{
+    context::ServerContext,
+    request::Request,
+    response::Response,
+    server::{IntoResponse, utils::client_ip::ClientIp},
+};
+
+/// Uses the [`ClientIp`] as the key of rate limit.
+///
+/// The [`ClientIpLayer`](crate::server::utils::client_ip::ClientIpLayer) should be added before
+/// the [`RateLimitLayer`], otherwise the requests are not limited.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClientIpKey;
+
+impl RateLimitKey<ServerContext> for ClientIpKey {
+    fn key(&self, cx: &ServerContext) -> Option<FastStr> {
+        let ClientIp(Some(ip)) = cx.extensions().get::<ClientIp>()? else {
+            return None;
+        };
+        Some(FastStr::new(ip.to_string()))
+    }
+}
+
+/// [`Layer`] for limiting the rate of requests
+///
+/// See [`RateLimitLayer::new`] for more details.
+pub struct RateLimitLayer<K, L> {
+    key: Arc<K>,
+    limiter: Arc<L>,
+}
+
+impl<K, L> RateLimitLayer<K, L> {
+    /// Create a new [`RateLimitLayer`] with the given [`RateLimitKey`] and [`RateLimiter`].
+    ///
+    /// The over-limit requests are responded with [`StatusCode::TOO_MANY_REQUESTS`] and the
+    /// `Retry-After` header if the time to wait is known.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use volo::rate_limit::TokenBucketLimiter;
+    /// use volo_http::server::{
+    ///     layer::{ClientIpKey, RateLimitLayer},
+    ///     route::{Router, get},
+    ///     utils::client_ip::ClientIpLayer,
+    /// };
+    ///
+    /// async fn index() -> &'static str {
+    ///     "Hello, World"
+    /// }
+    ///
+    /// let router: Router = Router::new()
+    ///     .route("/", get(index))
+    ///     .layer(RateLimitLayer::new(
+    ///         ClientIpKey,
+    ///         TokenBucketLimiter::new(10.0, 20),
+    ///     ))
+    ///     .layer(ClientIpLayer::new());
+    /// ```
+    pub fn new(key: K, limiter: L) -> Self {
+        Self {
+            key: Arc::new(key),
+            limiter: Arc::new(limiter),
+        }
+    }
+}
+
+impl<K, L> Clone for RateLimitLayer<K, L> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+impl<S, K, L> Layer<S> for RateLimitLayer<K, L>
+where
+    S: Send + Sync + 'static,
+{
+    type Service = RateLimit<S, K, L>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        RateLimit {
+            service: inner,
+            key: self.key,
+            limiter: self.limiter,
+        }
+    }
+}
+
+/// [`RateLimitLayer`] generated [`Service`]
+///
+/// See [`RateLimitLayer`] for more details.
+pub struct RateLimit<S, K, L> {
+    service: S,
+    key: Arc<K>,
+    limiter: Arc<L>,
+}
+
+impl<S: Clone, K, L> Clone for RateLimit<S, K, L> {
+    fn clone(&self) -> Self {
+        Self {
+            service: self.service.clone(),
+            key: self.key.clone(),
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+impl<S, B, K, L> Service<ServerContext, Request<B>> for RateLimit<S, K, L>
+where
+    S: Service<ServerContext, Request<B>> + Send + Sync + 'static,
+    S::Response: IntoResponse,
+    B: Send,
+    K: RateLimitKey<ServerContext>,
+    L: RateLimiter,
+{
+    type Response = Response;
+    type Error = S::Error;
+
+    async fn call(
+        &self,
+        cx: &mut ServerContext,
+        req: Request<B>,
+    ) -> Result<Self::Response, Self::Error> {
+        if let Some(key) = self.key.key(cx) {
+            if let Err(err) = self.limiter.acquire(&key).await {
+                return Ok(err.into_response());
+            }
+        }
+        self.service
+            .call(cx, req)
+            .await
+            .map(IntoResponse::into_response)
+    }
+}
+
+impl IntoResponse for RateLimitError {
+    fn into_response(self) -> Response {
+        let mut resp = StatusCode::TOO_MANY_REQUESTS.into_response();
+        if let Some(retry_after) = self.retry_after() {
+            // `Retry-After` is in seconds, so round it up
+            let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            resp.headers_mut()
+                .insert(header::RETRY_AFTER, header::HeaderValue::from(secs));
+        }
+        resp
+    }
+}
+
+#[cfg(test)]
+mod rate_limit_tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use http::{Method, StatusCode, header};
+    use motore::{Service, layer::Layer};
+    use volo::{context::Context, rate_limit::TokenBucketLimiter};
+
+    use crate::{
+        body::BodyConversion,
+        server::{
+            layer::{ClientIpKey, RateLimitLayer},
+            route::{Route, get},
+            test_helpers::empty_cx,
+            utils::client_ip::ClientIp,
+        },
+        utils::test_helpers::simple_req,
+    };
+
+    #[tokio::test]
+    async fn test_rate_limit_layer() {
+        async fn index_handler() -> &'static str {
+            "Hello, World"
+        }
+
+        let route: Route<&str> = Route::new(get(index_handler));
+        let service =
+            RateLimitLayer::new(ClientIpKey, TokenBucketLimiter::new(0.5, 1)).layer(route);
+
+        // Test case 1: no client ip, not limited
+        let mut cx = empty_cx();
+        for _ in 0..2 {
+            let req = simple_req(Method::GET, "/", "");
+            let resp = service.call(&mut cx, req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        // Test case 2: limited by the client ip
+        cx.extensions_mut()
+            .insert(ClientIp(Some(IpAddr::V4(Ipv4Addr::LOCALHOST))));
+        let req = simple_req(Method::GET, "/", "");
+        let resp = service.call(&mut cx, req).await.unwrap();
+        assert_eq!(
+            resp.into_body().into_string().await.unwrap(),
+            "Hello, World"
+        );
+        let req = simple_req(Method::GET, "/", "");
+        let resp = service.call(&mut cx, req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers().get(header::RETRY_AFTER).unwrap(), "2");
+    }
+}
@@ -32,7 +32,13 @@ use tracing::Instrument;
 use volo::net::{conn::ConnStream, tls::ServerTlsConfig};
 use volo::{
     context::Context,
-    net::{Address, MakeIncoming, conn::Conn, incoming::Incoming},
+    net::{
+        Address, MakeIncoming,
+        conn::{Conn, PeerCertificate, PeerCredentials, TlsServerName},
+        incoming::{Incoming, proxy_protocol::ProxyHeader},
+        sockopt::SocketOptions,
+    },
+    registry::Registration,
 };
 
 use self::span_provider::{DefaultProvider, SpanProvider};
@@ -101,6 +107,8 @@ pub struct Server<S, L = Identity, SP = DefaultProvider> {
     server: auto::Builder<TokioExecutor>,
     config: Config,
     shutdown_hooks: Vec<Box<dyn FnOnce() -> BoxFuture<'static, ()> + Send>>,
+    registration: Option<Registration>,
+    socket_options: Option<SocketOptions>,
     span_provider: SP,
     #[cfg(feature = "__tls")]
     tls_config: Option<ServerTlsConfig>,
@@ -115,6 +123,8 @@ impl<S> Server<S, Identity, DefaultProvider> {
             server: auto::Builder::new(TokioExecutor::new()),
             config: Config::default(),
             shutdown_hooks: Vec::new(),
+            registration: None,
+            socket_options: None,
             span_provider: DefaultProvider,
             #[cfg(feature = "__tls")]
             tls_config: None,
@@ -146,6 +156,24 @@ impl<S, L, SP> Server<S, L, SP> {
         self
     }
 
+    /// Register the server to a registry once the listener is bound.
+    ///
+    /// The server will be deregistered at the start of graceful shutdown, before the shutdown
+    /// hooks are called and the connections are drained.
+    pub fn registry(mut self, registration: Registration) -> Self {
+        self.registration = Some(registration);
+        self
+    }
+
+    /// Set the socket options applied to the accepted connections, e.g., TCP keepalive.
+    ///
+    /// The options of the listeners, e.g., `SO_REUSEPORT`, are set by running the server with a
+    /// [`ListenConfig`](volo::net::ListenConfig).
+    pub fn socket_options(mut self, options: SocketOptions) -> Self {
+        self.socket_options = Some(options);
+        self
+    }
+
     /// Add a new inner layer to the server.
     ///
     /// The layer's [`Service`] should be `Send + Sync + Clone + 'static`.
@@ -164,6 +192,8 @@ impl<S, L, SP> Server<S, L, SP> {
             server: self.server,
             config: self.config,
             shutdown_hooks: self.shutdown_hooks,
+            registration: self.registration,
+            socket_options: self.socket_options,
             span_provider: self.span_provider,
             #[cfg(feature = "__tls")]
             tls_config: self.tls_config,
@@ -188,6 +218,8 @@ impl<S, L, SP> Server<S, L, SP> {
             server: self.server,
             config: self.config,
             shutdown_hooks: self.shutdown_hooks,
+            registration: self.registration,
+            socket_options: self.socket_options,
             span_provider: self.span_provider,
             #[cfg(feature = "__tls")]
             tls_config: self.tls_config,
@@ -207,6 +239,8 @@ impl<S, L, SP> Server<S, L, SP> {
             server: self.server,
             config: self.config,
             shutdown_hooks: self.shutdown_hooks,
+            registration: self.registration,
+            socket_options: self.socket_options,
             span_provider,
             #[cfg(feature = "__tls")]
             tls_config: self.tls_config,
@@ -273,6 +307,8 @@ impl<S, L, SP> Server<S, L, SP> {
             server: self.server.http1_only(),
             config: self.config,
             shutdown_hooks: self.shutdown_hooks,
+            registration: self.registration,
+            socket_options: self.socket_options,
             span_provider: self.span_provider,
             #[cfg(feature = "__tls")]
             tls_config: self.tls_config,
@@ -288,6 +324,8 @@ impl<S, L, SP> Server<S, L, SP> {
             server: self.server.http2_only(),
             config: self.config,
             shutdown_hooks: self.shutdown_hooks,
+            registration: self.registration,
+            socket_options: self.socket_options,
             span_provider: self.span_provider,
             #[cfg(feature = "__tls")]
             tls_config: self.tls_config,
@@ -311,6 +349,11 @@ impl<S, L, SP> Server<S, L, SP> {
         let incoming = mk_incoming.make_incoming().await?;
         tracing::info!("[Volo-HTTP] server start at: {:?}", incoming);
 
+        let registered = match self.registration {
+            Some(registration) => Some(registration.register(incoming.local_addr()).await?),
+            None => None,
+        };
+
         // count connections, used for graceful shutdown
         let conn_cnt = Arc::new(AtomicUsize::new(0));
         // flag for stopping serve
@@ -323,6 +366,7 @@ impl<S, L, SP> Server<S, L, SP> {
             incoming,
             service,
             self.config,
+            self.socket_options,
             exit_flag.clone(),
             conn_cnt.clone(),
             exit_notify.clone(),
@@ -332,7 +376,7 @@ impl<S, L, SP> Server<S, L, SP> {
         ));
 
         #[cfg(target_family = "unix")]
-        {
+        let res: Result<(), BoxError> = async {
             // graceful shutdown
             let mut sigint =
                 tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())?;
@@ -348,14 +392,25 @@ impl<S, L, SP> Server<S, L, SP> {
                 _ = sigterm.recv() => {}
                 _ = handler => {},
             }
+            Ok(())
         }
+        .await;
 
         // graceful shutdown handler for windows
         #[cfg(target_family = "windows")]
-        tokio::select! {
-            _ = tokio::signal::ctrl_c() => {}
-            _ = handler => {},
+        let res: Result<(), BoxError> = {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = handler => {},
+            }
+            Ok(())
+        };
+
+        // deregister on every exit path, so that the clients stop sending requests to the server
+        if let Some(registered) = registered {
+            registered.deregister().await;
         }
+        res?;
 
         if !self.shutdown_hooks.is_empty() {
             tracing::info!("[Volo-HTTP] call shutdown hooks");
@@ -398,6 +453,7 @@ async fn serve<I, S, SP>(
     mut incoming: I,
     service: S,
     config: Config,
+    socket_options: Option<SocketOptions>,
     exit_flag: Arc<RwLock<bool>>,
     conn_cnt: Arc<AtomicUsize>,
     exit_notify: Arc<Notify>,
@@ -419,9 +475,14 @@ async fn serve<I, S, SP>(
             Ok(Some(conn)) => conn,
             _ => continue,
         };
+        if let Some(options) = &socket_options {
+            if let Err(err) = options.apply_to_conn(&conn) {
+                tracing::warn!("[Volo-HTTP] failed to set socket options: {err}");
+            }
+        }
         #[cfg(feature = "__tls")]
         let conn = {
-            let Conn { stream, info } = conn;
+            let Conn { stream, mut info } = conn;
             match (stream, &tls_config) {
                 (ConnStream::Tcp(stream), Some(tls_config)) => {
                     let stream = match tls_config.acceptor.accept(stream).await {
@@ -431,6 +492,8 @@ async fn serve<I, S, SP>(
                             continue;
                         }
                     };
+                    info.peer_certificate = stream.peer_certificate();
+                    info.server_name = stream.server_name();
                     Conn { stream, info }
                 }
                 (stream, _) => Conn { stream, info },
@@ -451,6 +514,10 @@ async fn serve<I, S, SP>(
         let hyper_service = HyperService {
             inner: service.clone(),
             peer,
+            peer_certificate: conn.info.peer_certificate.clone(),
+            server_name: conn.info.server_name.clone(),
+            proxy_header: conn.info.proxy_header.clone(),
+            peer_credentials: conn.info.peer_credentials,
             config: config.clone(),
             span_provider: span_provider.clone(),
         };
@@ -510,6 +577,10 @@ async fn serve_conn<S>(
 struct HyperService<S, SP> {
     inner: S,
     peer: Address,
+    peer_certificate: Option<PeerCertificate>,
+    server_name: Option<TlsServerName>,
+    proxy_header: Option<ProxyHeader>,
+    peer_credentials: Option<PeerCredentials>,
     config: Config,
     span_provider: SP,
 }
@@ -532,6 +603,18 @@ where
         Box::pin(
             METAINFO.scope(RefCell::new(MetaInfo::default()), async move {
                 let mut cx = ServerContext::new(service.peer);
+                if let Some(peer_certificate) = service.peer_certificate {
+                    cx.rpc_info_mut().caller_mut().insert(peer_certificate);
+                }
+                if let Some(server_name) = service.server_name {
+                    cx.rpc_info_mut().callee_mut().insert(server_name);
+                }
+                if let Some(proxy_header) = service.proxy_header {
+                    cx.rpc_info_mut().caller_mut().insert(proxy_header);
+                }
+                if let Some(peer_credentials) = service.peer_credentials {
+                    cx.rpc_info_mut().caller_mut().insert(peer_credentials);
+                }
                 cx.rpc_info_mut().set_config(service.config);
                 let span = service.span_provider.on_serve(&cx);
                 let resp: http::Response<Body> = service
@@ -24,10 +24,13 @@ use volo::{
     client::WithOptService,
     context::{Context, Endpoint, Role, RpcInfo},
     discovery::{Discover, DummyDiscover},
-    loadbalance::{LbConfig, MkLbLayer, random::WeightedRandomBalance},
+    loadbalance::{
+        LbConfig, MetaInfoHashKey, MkLbLayer, hedge::Hedging, random::WeightedRandomBalance,
+    },
     net::{
         Address,
         dial::{DefaultMakeTransport, MakeTransport},
+        sockopt::SocketOptions,
     },
 };
 
@@ -51,6 +54,7 @@ pub mod layer;
 pub struct ClientBuilder<IL, OL, MkClient, Req, Resp, MkT, MkC, LB> {
     config: Config,
     pool: Option<pool::Config>,
+    socket_options: Option<SocketOptions>,
     callee_name: FastStr,
     caller_name: FastStr,
     address: Option<Address>, // maybe address use Arc avoid memory alloc
@@ -78,13 +82,19 @@ impl<C, Req, Resp>
         Resp,
         DefaultMakeTransport,
         DefaultMakeCodec<MakeTTHeaderCodec<MakeFramedCodec<MakeThriftCodec>>>,
-        LbConfig<WeightedRandomBalance<<DummyDiscover as Discover>::Key>, DummyDiscover>,
+        LbConfig<
+            WeightedRandomBalance<<DummyDiscover as Discover>::Key>,
+            DummyDiscover,
+            MetaInfoHashKey,
+            Hedging,
+        >,
     >
 {
     pub fn new(service_name: impl AsRef<str>, service_client: C) -> Self {
         ClientBuilder {
             config: Default::default(),
             pool: None,
+            socket_options: None,
             caller_name: "".into(),
             callee_name: FastStr::new(service_name),
             address: None,
@@ -93,7 +103,7 @@ impl<C, Req, Resp>
             mk_client: service_client,
             make_transport: DefaultMakeTransport::default(),
             make_codec: DefaultMakeCodec::default(),
-            mk_lb: LbConfig::new(WeightedRandomBalance::new(), DummyDiscover {}),
+            mk_lb: LbConfig::new(WeightedRandomBalance::new(), DummyDiscover {}).hedging(),
             _marker: PhantomData,
 
             disable_timeout_layer: false,
@@ -105,16 +115,18 @@ impl<C, Req, Resp>
     }
 }
 
-impl<IL, OL, C, Req, Resp, MkT, MkC, LB, DISC>
-    ClientBuilder<IL, OL, C, Req, Resp, MkT, MkC, LbConfig<LB, DISC>>
+impl<IL, OL, C, Req, Resp, MkT, MkC, LB, DISC, H, M>
+    ClientBuilder<IL, OL, C, Req, Resp, MkT, MkC, LbConfig<LB, DISC, H, M>>
 {
+    #[allow(clippy::type_complexity)]
     pub fn load_balance<NLB>(
         self,
         load_balance: NLB,
-    ) -> ClientBuilder<IL, OL, C, Req, Resp, MkT, MkC, LbConfig<NLB, DISC>> {
+    ) -> ClientBuilder<IL, OL, C, Req, Resp, MkT, MkC, LbConfig<NLB, DISC, H, M>> {
         ClientBuilder {
             config: self.config,
             pool: self.pool,
+            socket_options: self.socket_options,
             caller_name: self.caller_name,
             callee_name: self.callee_name,
             address: self.address,
@@ -134,13 +146,15 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB, DISC>
         }
     }
 
+    #[allow(clippy::type_complexity)]
     pub fn discover<NDISC>(
         self,
         discover: NDISC,
-    ) -> ClientBuilder<IL, OL, C, Req, Resp, MkT, MkC, LbConfig<LB, NDISC>> {
+    ) -> ClientBuilder<IL, OL, C, Req, Resp, MkT, MkC, LbConfig<LB, NDISC, H, M>> {
         ClientBuilder {
             config: self.config,
             pool: self.pool,
+            socket_options: self.socket_options,
             caller_name: self.caller_name,
             callee_name: self.callee_name,
             address: self.address,
@@ -160,11 +174,58 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB, DISC>
         }
     }
 
+    /// Sets how to compute the [`RequestHash`](volo::loadbalance::RequestHash) of the requests
+    /// for the hashing load balancers, e.g., from a field of the request.
+    ///
+    /// The [`RequestHash`](volo::loadbalance::RequestHash) in the metainfo is used by default.
+    #[allow(clippy::type_complexity)]
+    pub fn hash_key<NH>(
+        self,
+        hash_key: NH,
+    ) -> ClientBuilder<IL, OL, C, Req, Resp, MkT, MkC, LbConfig<LB, DISC, NH, M>> {
+        ClientBuilder {
+            config: self.config,
+            pool: self.pool,
+            socket_options: self.socket_options,
+            caller_name: self.caller_name,
+            callee_name: self.callee_name,
+            address: self.address,
+            inner_layer: self.inner_layer,
+            outer_layer: self.outer_layer,
+            mk_client: self.mk_client,
+            _marker: PhantomData,
+            make_transport: self.make_transport,
+            make_codec: self.make_codec,
+            mk_lb: self.mk_lb.hash_key(hash_key),
+
+            disable_timeout_layer: self.disable_timeout_layer,
+            enable_biz_error: self.enable_biz_error,
+
+            #[cfg(feature = "multiplex")]
+            multiplex: self.multiplex,
+        }
+    }
+
     /// Sets the retry count of the client.
     pub fn retry_count(mut self, count: usize) -> Self {
         self.mk_lb = self.mk_lb.retry_count(count);
         self
     }
+
+    /// Sets the retry policy of the client, which overrides the retry count.
+    ///
+    /// The policy can be overridden for a single call by inserting a
+    /// [`RetryPolicy`](volo::retry::RetryPolicy) into [`CallOpt::callee_tags`].
+    pub fn retry_policy(mut self, policy: volo::retry::RetryPolicy) -> Self {
+        self.mk_lb = self.mk_lb.retry_policy(policy);
+        self
+    }
+
+    /// Limits the retries of the client by the retry budget.
+    pub fn retry_budget(mut self, budget: volo::retry::RetryBudget) -> Self {
+        self.mk_lb = self.mk_lb.retry_budget(budget);
+        self
+    }
 }
 
 impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT, MkC, LB> {
@@ -190,6 +251,12 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
         self
     }
 
+    /// Sets the socket options for the connections, e.g., TCP keepalive and the buffer sizes.
+    pub fn socket_options(mut self, options: SocketOptions) -> Self {
+        self.socket_options = Some(options);
+        self
+    }
+
     /// Sets the read write timeout for the client(a.k.a. IO timeout).
     pub fn read_write_timeout(mut self, timeout: Option<Duration>) -> Self {
         self.config.set_read_write_timeout(timeout);
@@ -225,6 +292,7 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
         ClientBuilder {
             config: self.config,
             pool: self.pool,
+            socket_options: self.socket_options,
             caller_name: self.caller_name,
             callee_name: self.callee_name,
             address: self.address,
@@ -259,6 +327,7 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
         ClientBuilder {
             config: self.config,
             pool: self.pool,
+            socket_options: self.socket_options,
             caller_name: self.caller_name,
             callee_name: self.callee_name,
             address: self.address,
@@ -287,6 +356,7 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
         ClientBuilder {
             config: self.config,
             pool: self.pool,
+            socket_options: self.socket_options,
             caller_name: self.caller_name,
             callee_name: self.callee_name,
             address: self.address,
@@ -336,6 +406,7 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
         ClientBuilder {
             config: self.config,
             pool: self.pool,
+            socket_options: self.socket_options,
             caller_name: self.caller_name,
             callee_name: self.callee_name,
             address: self.address,
@@ -375,6 +446,7 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
         ClientBuilder {
             config: self.config,
             pool: self.pool,
+            socket_options: self.socket_options,
             caller_name: self.caller_name,
             callee_name: self.callee_name,
             address: self.address,
@@ -414,6 +486,7 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
         ClientBuilder {
             config: self.config,
             pool: self.pool,
+            socket_options: self.socket_options,
             caller_name: self.caller_name,
             callee_name: self.callee_name,
             address: self.address,
@@ -453,6 +526,7 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
         ClientBuilder {
             config: self.config,
             pool: self.pool,
+            socket_options: self.socket_options,
             caller_name: self.caller_name,
             callee_name: self.callee_name,
             address: self.address,
@@ -479,6 +553,7 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
         ClientBuilder {
             config: self.config,
             pool: self.pool,
+            socket_options: self.socket_options,
             caller_name: self.caller_name,
             callee_name: self.callee_name,
             address: self.address,
@@ -512,6 +587,7 @@ impl<IL, OL, C, Req, Resp, MkT, MkC, LB> ClientBuilder<IL, OL, C, Req, Resp, MkT
         ClientBuilder {
             config: self.config,
             pool: self.pool,
+            socket_options: self.socket_options,
             caller_name: self.caller_name,
             callee_name: self.callee_name,
             address: Some(shmipc_addr),
@@ -627,6 +703,9 @@ where
         if let Some(timeout) = self.config.read_write_timeout() {
             self.make_transport.set_write_timeout(Some(timeout));
         }
+        if let Some(options) = self.socket_options.take() {
+            self.make_transport.set_socket_options(options);
+        }
         let msg_svc = MessageService {
             #[cfg(not(feature = "multiplex"))]
             inner: pingpong::Client::new(self.make_transport, self.pool, self.make_codec),
@@ -6,7 +6,9 @@ use pilota::thrift::TMessageIdentifier;
 use volo::{
     FastStr,
     context::{Context, Reusable, Role, RpcCx, RpcInfo},
+    loadbalance::hedge::ForkContext,
     newtype_impl_context,
+    retry::RpcTimeout,
 };
 
 use crate::{BizError, client::CallOpt, protocol::TMessageType};
@@ -241,6 +243,26 @@ impl ClientContext {
     }
 }
 
+impl ForkContext for ClientContext {
+    fn fork(&self) -> Self {
+        let ri = self.rpc_info();
+        let caller = ri.caller().fork();
+        let callee = ri.callee().fork();
+        let mut inner = self.0.inner.clone();
+        inner.stats.reset();
+        inner.common_stats.reset();
+        Self(RpcCx::new(
+            RpcInfo::new(ri.role(), ri.method().clone(), caller, callee, *ri.config()),
+            inner,
+        ))
+    }
+
+    fn join(&mut self, fork: Self) {
+        self.rpc_info_mut().callee_mut().address = fork.rpc_info().callee().address();
+        self.0.inner = fork.0.inner;
+    }
+}
+
 impl std::ops::Deref for ClientContext {
     type Target = RpcCx<ClientCxInner, Config>;
 
@@ -500,6 +522,13 @@ impl Config {
     }
 }
 
+impl RpcTimeout for Config {
+    #[inline]
+    fn rpc_timeout(&self) -> Option<Duration> {
+        self.rpc_timeout
+    }
+}
+
 impl Reusable for Config {
     fn clear(&mut self) {
         self.rpc_timeout = None;
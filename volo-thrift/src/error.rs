@@ -5,7 +5,12 @@ pub use pilota::thrift::{
     ThriftException, TransportException, new_application_exception, new_protocol_exception,
 };
 use pilota::{AHashMap, FastStr};
-use volo::loadbalance::error::{LoadBalanceError, Retryable};
+use volo::{
+    circuit_breaker::{BreakerFailure, CircuitOpenError},
+    fault::FaultAbort,
+    loadbalance::error::{LoadBalanceError, Retryable},
+    rate_limit::RateLimitError,
+};
 
 pub type ServerResult<T> = Result<T, ServerError>;
 pub type ClientResult<T> = Result<T, ClientError>;
@@ -54,6 +59,31 @@ impl From<ClientError> for ServerError {
     }
 }
 
+/// The requests rejected by [`RateLimitLayer`](volo::rate_limit::RateLimitLayer) fail with an
+/// [`ApplicationException`] of `INTERNAL_ERROR`, which is known by all the thrift
+/// implementations, and the reason is kept in the message.
+impl From<RateLimitError> for ServerError {
+    fn from(err: RateLimitError) -> Self {
+        let msg = match err.retry_after() {
+            Some(retry_after) => format!(
+                "{err}, retry after {}ms",
+                retry_after.as_micros().div_ceil(1000)
+            ),
+            None => err.to_string(),
+        };
+        ServerError::Application(ApplicationException::new(
+            ApplicationExceptionKind::INTERNAL_ERROR,
+            msg,
+        ))
+    }
+}
+
+impl From<FaultAbort> for ServerError {
+    fn from(err: FaultAbort) -> Self {
+        ServerError::Application(fault_exception(err))
+    }
+}
+
 impl From<ThriftException> for ServerError {
     fn from(e: ThriftException) -> Self {
         thrift_exception_to_application_exception(e).into()
@@ -119,6 +149,13 @@ impl Retryable for ClientError {
     }
 }
 
+/// Only the transport errors are failures, the exceptions are returned by the application.
+impl BreakerFailure for ClientError {
+    fn is_failure(&self) -> bool {
+        matches!(self, Self::Transport(_))
+    }
+}
+
 impl From<LoadBalanceError> for ClientError {
     // TODO: use specified error code
     fn from(err: LoadBalanceError) -> Self {
@@ -129,6 +166,26 @@ impl From<LoadBalanceError> for ClientError {
     }
 }
 
+impl From<CircuitOpenError> for ClientError {
+    fn from(err: CircuitOpenError) -> Self {
+        ClientError::Application(ApplicationException::new(
+            ApplicationExceptionKind::INTERNAL_ERROR,
+            err.to_string(),
+        ))
+    }
+}
+
+impl From<FaultAbort> for ClientError {
+    fn from(err: FaultAbort) -> Self {
+        ClientError::Application(fault_exception(err))
+    }
+}
+
+/// Uses the code of [`FaultAbort`] as the type of [`ApplicationException`].
+fn fault_exception(err: FaultAbort) -> ApplicationException {
+    ApplicationException::new(ApplicationExceptionKind::from(err.code as i32), err.message)
+}
+
 impl From<ThriftException> for ClientError {
     fn from(e: ThriftException) -> Self {
         match e {
@@ -10,20 +10,20 @@ use motore::{
     layer::{Identity, Layer, Stack},
     service::Service,
 };
-use scopeguard::defer;
 use tokio::{
     io::{AsyncRead, AsyncWrite},
     sync::Notify,
 };
-use tracing::{info, trace};
+use tracing::{info, trace, warn};
 #[cfg(feature = "shmipc")]
-use volo::net::shmipc_fallback::ShmipcAddressWithFallback;
+use volo::net::{Address, shmipc_fallback::ShmipcAddressWithFallback};
 use volo::{
     net::{
-        Address,
-        conn::{OwnedReadHalf, OwnedWriteHalf},
-        incoming::Incoming,
+        conn::{ConnInfo, OwnedReadHalf, OwnedWriteHalf},
+        incoming::{Incoming, admission::ConnectionStats},
+        sockopt::SocketOptions,
     },
+    registry::Registration,
     service::BoxService,
 };
 
@@ -57,6 +57,9 @@ pub struct Server<S, L, Req, MkC, SP> {
     multiplex: bool,
     span_provider: SP,
     shutdown_hooks: Vec<Box<dyn FnOnce() -> BoxFuture<'static, ()> + Send>>,
+    registration: Option<Registration>,
+    socket_options: Option<SocketOptions>,
+    conn_stats: ConnectionStats,
     _marker: PhantomData<Req>,
 }
 
@@ -82,6 +85,9 @@ impl<S, Req>
             multiplex: false,
             span_provider: DefaultProvider {},
             shutdown_hooks: Vec::new(),
+            registration: None,
+            socket_options: None,
+            conn_stats: ConnectionStats::default(),
             _marker: PhantomData,
         }
     }
@@ -127,6 +133,9 @@ impl
             multiplex: false,
             span_provider: DefaultProvider {},
             shutdown_hooks: Vec::new(),
+            registration: None,
+            socket_options: None,
+            conn_stats: ConnectionStats::default(),
             _marker: PhantomData,
         }
     }
@@ -145,6 +154,15 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
         self
     }
 
+    /// Registers the server to a registry once the listener is bound.
+    ///
+    /// The server will be deregistered at the start of graceful shutdown, before the shutdown
+    /// hooks are called and the connections are drained.
+    pub fn registry(mut self, registration: Registration) -> Self {
+        self.registration = Some(registration);
+        self
+    }
+
     /// Adds a new inner layer to the server.
     ///
     /// The layer's `Service` should be `Send + Sync + Clone + 'static`.
@@ -166,6 +184,9 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
             multiplex: self.multiplex,
             span_provider: self.span_provider,
             shutdown_hooks: self.shutdown_hooks,
+            registration: self.registration,
+            socket_options: self.socket_options,
+            conn_stats: self.conn_stats,
             _marker: PhantomData,
         }
     }
@@ -191,10 +212,34 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
             multiplex: self.multiplex,
             span_provider: self.span_provider,
             shutdown_hooks: self.shutdown_hooks,
+            registration: self.registration,
+            socket_options: self.socket_options,
+            conn_stats: self.conn_stats,
             _marker: PhantomData,
         }
     }
 
+    /// Sets the socket options applied to the accepted connections, e.g., TCP keepalive.
+    ///
+    /// The options of the listeners, e.g., `SO_REUSEPORT`, are set by running the server with a
+    /// [`ListenConfig`](volo::net::ListenConfig).
+    pub fn socket_options(mut self, options: SocketOptions) -> Self {
+        self.socket_options = Some(options);
+        self
+    }
+
+    /// Returns the statistics of the connections served by the server, e.g., the number of the
+    /// live connections, which can be kept for exporting as metrics before running the server.
+    ///
+    /// The connections rejected by [`AdmissionControl`] are counted as well if the stats are
+    /// shared with it by [`AdmissionControl::with_stats`].
+    ///
+    /// [`AdmissionControl`]: volo::net::incoming::admission::AdmissionControl
+    /// [`AdmissionControl::with_stats`]: volo::net::incoming::admission::AdmissionControl::with_stats
+    pub fn connection_stats(&self) -> ConnectionStats {
+        self.conn_stats.clone()
+    }
+
     /// This is unstable now and may be changed in the future.
     #[doc(hidden)]
     pub fn stat_tracer(mut self, trace_fn: TraceFn) -> Self {
@@ -220,6 +265,9 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
             multiplex: self.multiplex,
             span_provider: self.span_provider,
             shutdown_hooks: self.shutdown_hooks,
+            registration: self.registration,
+            socket_options: self.socket_options,
+            conn_stats: self.conn_stats,
             _marker: PhantomData,
         }
     }
@@ -253,8 +301,12 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
         let mut incoming = make_incoming.make_incoming().await?;
         info!("[VOLO] server start at: {:?}", incoming);
 
-        let conn_cnt = Arc::new(std::sync::atomic::AtomicUsize::new(0));
-        let gconn_cnt = conn_cnt.clone();
+        let registered = match self.registration {
+            Some(registration) => Some(registration.register(incoming.local_addr()).await?),
+            None => None,
+        };
+
+        let conn_stats = self.conn_stats.clone();
         let (exit_notify, exit_flag, exit_mark) = (
             Arc::new(Notify::const_new()),
             Arc::new(parking_lot::RwLock::new(false)),
@@ -272,14 +324,19 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
                 }
                 match incoming.accept().await {
                     Ok(Some(conn)) => {
-                        let peer_addr = conn.info.peer_addr;
-                        trace!("[VOLO] accept connection from: {:?}", peer_addr);
+                        if let Some(options) = &self.socket_options {
+                            if let Err(err) = options.apply_to_conn(&conn) {
+                                warn!("[VOLO] failed to set socket options: {err}");
+                            }
+                        }
+                        let conn_info = conn.info;
+                        trace!("[VOLO] accept connection from: {:?}", conn_info.peer_addr);
                         let (rh, wh) = conn.stream.into_split();
 
                         #[cfg(feature = "multiplex")]
                         if self.multiplex {
                             #[cfg(feature = "shmipc")]
-                            if peer_addr.as_ref().is_some_and(Address::is_shmipc) {
+                            if conn_info.peer_addr.as_ref().is_some_and(Address::is_shmipc) {
                                 tracing::error!("multiplex is not supported when using shmipc");
                                 let _ = rh.shmipc_helper().close().await;
                                 continue;
@@ -292,8 +349,8 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
                                 stat_tracer.clone(),
                                 exit_notify_inner.clone(),
                                 exit_mark_inner.clone(),
-                                conn_cnt.clone(),
-                                peer_addr,
+                                self.conn_stats.clone(),
+                                conn_info,
                             ));
                         } else {
                             tokio::spawn(handle_conn(
@@ -304,8 +361,8 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
                                 stat_tracer.clone(),
                                 exit_notify_inner.clone(),
                                 exit_mark_inner.clone(),
-                                conn_cnt.clone(),
-                                peer_addr,
+                                self.conn_stats.clone(),
+                                conn_info,
                                 self.span_provider.clone(),
                             ));
                         }
@@ -318,8 +375,8 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
                             stat_tracer.clone(),
                             exit_notify_inner.clone(),
                             exit_mark_inner.clone(),
-                            conn_cnt.clone(),
-                            peer_addr,
+                            self.conn_stats.clone(),
+                            conn_info,
                             self.span_provider.clone(),
                         ));
                     }
@@ -331,7 +388,7 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
         });
 
         #[cfg(target_family = "unix")]
-        {
+        let res: Result<(), BoxError> = async {
             // graceful shutdown
             let mut sigint =
                 tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())?;
@@ -342,39 +399,32 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
 
             // graceful shutdown handler
             tokio::select! {
-                _ = sigint.recv() => {}
-                _ = sighup.recv() => {}
-                _ = sigterm.recv() => {}
-                res = handler => {
-                    match res {
-                        Ok(res) => {
-                            match res {
-                                Ok(()) => {}
-                                Err(e) => return Err(Box::new(e))
-                            };
-                        }
-                        Err(e) => return Err(Box::new(e)),
-                    }
-                }
+                _ = sigint.recv() => Ok(()),
+                _ = sighup.recv() => Ok(()),
+                _ = sigterm.recv() => Ok(()),
+                res = handler => match res {
+                    Ok(res) => res.map_err(Into::into),
+                    Err(e) => Err(e.into()),
+                },
             }
         }
+        .await;
 
         // graceful shutdown handler for windows
         #[cfg(target_family = "windows")]
-        tokio::select! {
-            _ = tokio::signal::ctrl_c() => {}
-            res = handler => {
-                match res {
-                    Ok(res) => {
-                        match res {
-                            Ok(()) => {}
-                            Err(e) => return Err(Box::new(e))
-                        };
-                    }
-                    Err(e) => return Err(Box::new(e)),
-                }
-            }
+        let res: Result<(), BoxError> = tokio::select! {
+            _ = tokio::signal::ctrl_c() => Ok(()),
+            res = handler => match res {
+                Ok(res) => res.map_err(Into::into),
+                Err(e) => Err(e.into()),
+            },
+        };
+
+        // deregister on every exit path, so that the clients stop sending requests to the server
+        if let Some(registered) = registered {
+            registered.deregister().await;
         }
+        res?;
 
         if !self.shutdown_hooks.is_empty() {
             info!("[VOLO] call shutdown hooks");
@@ -391,19 +441,19 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
 
         // Now we won't accept new connections.
         // And we want to send crrst reply to the peers in the short future.
-        if gconn_cnt.load(Ordering::Relaxed) != 0 {
+        if conn_stats.live() != 0 {
             tokio::time::sleep(Duration::from_secs(2)).await;
         }
         exit_notify.notify_waiters();
 
         // wait for all connections to be closed
         for _ in 0..28 {
-            if gconn_cnt.load(Ordering::Relaxed) == 0 {
+            if conn_stats.live() == 0 {
                 break;
             }
             trace!(
                 "[VOLO] gracefully exiting, remaining connection count: {}",
-                gconn_cnt.load(Ordering::Relaxed)
+                conn_stats.live()
             );
             tokio::time::sleep(Duration::from_secs(1)).await;
         }
@@ -456,6 +506,9 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
             multiplex,
             span_provider: self.span_provider,
             shutdown_hooks: self.shutdown_hooks,
+            registration: self.registration,
+            socket_options: self.socket_options,
+            conn_stats: self.conn_stats,
             _marker: PhantomData,
         }
     }
@@ -470,6 +523,9 @@ impl<S, L, Req, MkC, SP> Server<S, L, Req, MkC, SP> {
             multiplex: self.multiplex,
             span_provider: provider,
             shutdown_hooks: self.shutdown_hooks,
+            registration: self.registration,
+            socket_options: self.socket_options,
+            conn_stats: self.conn_stats,
             _marker: PhantomData,
         }
     }
@@ -484,8 +540,8 @@ async fn handle_conn<R, W, Req, Svc, Resp, MkC, SP>(
     stat_tracer: Arc<[TraceFn]>,
     exit_notify: Arc<Notify>,
     exit_mark: Arc<std::sync::atomic::AtomicBool>,
-    conn_cnt: Arc<std::sync::atomic::AtomicUsize>,
-    peer_addr: Option<Address>,
+    conn_stats: ConnectionStats,
+    conn_info: ConnInfo,
     span_provider: SP,
 ) where
     R: AsyncRead + Unpin + Send + Sync + 'static,
@@ -498,10 +554,7 @@ async fn handle_conn<R, W, Req, Svc, Resp, MkC, SP>(
     MkC: MakeCodec<R, W>,
     SP: SpanProvider,
 {
-    conn_cnt.fetch_add(1, Ordering::Relaxed);
-    defer! {
-        conn_cnt.fetch_sub(1, Ordering::Relaxed);
-    }
+    let _live = conn_stats.track();
 
     let (encoder, decoder) = make_codec.make_codec(rh, wh);
 
@@ -510,7 +563,7 @@ async fn handle_conn<R, W, Req, Svc, Resp, MkC, SP>(
 
     tracing::trace!(
         "[VOLO] handle conn by ping-pong, peer_addr: {:?}",
-        peer_addr
+        conn_info.peer_addr
     );
     crate::transport::pingpong::serve(
         encoder,
@@ -519,7 +572,7 @@ async fn handle_conn<R, W, Req, Svc, Resp, MkC, SP>(
         exit_mark,
         &service,
         stat_tracer,
-        peer_addr,
+        conn_info,
         span_provider,
     )
     .await;
@@ -535,8 +588,8 @@ async fn handle_conn_multiplex<R, W, Req, Svc, Resp, MkC>(
     stat_tracer: Arc<[TraceFn]>,
     exit_notify: Arc<Notify>,
     exit_mark: Arc<std::sync::atomic::AtomicBool>,
-    conn_cnt: Arc<std::sync::atomic::AtomicUsize>,
-    peer_addr: Option<Address>,
+    conn_stats: ConnectionStats,
+    conn_info: ConnInfo,
 ) where
     R: AsyncRead + Unpin + Send + Sync + 'static,
     W: AsyncWrite + Unpin + Send + Sync + 'static,
@@ -546,15 +599,12 @@ async fn handle_conn_multiplex<R, W, Req, Svc, Resp, MkC>(
     Resp: EntryMessage + Send + 'static,
     MkC: MakeCodec<R, W>,
 {
-    conn_cnt.fetch_add(1, Ordering::Relaxed);
-    defer! {
-        conn_cnt.fetch_sub(1, Ordering::Relaxed);
-    }
+    let _live = conn_stats.track();
     let (encoder, decoder) = make_codec.make_codec(rh, wh);
 
     info!(
         "[VOLO] handle conn by multiplex, peer_addr: {:?}",
-        peer_addr
+        conn_info.peer_addr
     );
     crate::transport::multiplex::serve(
         encoder,
@@ -563,7 +613,7 @@ async fn handle_conn_multiplex<R, W, Req, Svc, Resp, MkC>(
         exit_mark,
         service,
         stat_tracer,
-        peer_addr,
+        conn_info,
     )
     .await;
 }
@@ -8,7 +8,7 @@ use motore::service::Service;
 use pilota::thrift::ThriftException;
 use tokio::sync::{futures::Notified, mpsc};
 use tracing::*;
-use volo::{context::Context, net::Address, volo_unreachable};
+use volo::{context::Context, net::conn::ConnInfo, volo_unreachable};
 
 use crate::{
     DummyMessage, EntryMessage, ServerError, ThriftMessage,
@@ -21,6 +21,7 @@ use crate::{
 
 const CHANNEL_SIZE: usize = 1024;
 
+#[allow(clippy::too_many_arguments)]
 pub async fn serve<Svc, Req, Resp, E, D>(
     mut encoder: E,
     mut decoder: D,
@@ -28,7 +29,7 @@ pub async fn serve<Svc, Req, Resp, E, D>(
     exit_mark: Arc<std::sync::atomic::AtomicBool>,
     service: Svc,
     stat_tracer: Arc<[crate::server::TraceFn]>,
-    peer_addr: Option<Address>,
+    conn_info: ConnInfo,
 ) where
     Svc: Service<ServerContext, Req, Response = Resp> + Send + Clone + 'static + Sync,
     Svc::Error: Into<ServerError> + Send,
@@ -38,6 +39,7 @@ pub async fn serve<Svc, Req, Resp, E, D>(
     D: Decoder,
 {
     tokio::pin!(notified);
+    let peer_addr = conn_info.peer_addr.clone();
 
     // mpsc channel used to send responses to the loop
     let (send_tx, mut send_rx) = mpsc::channel(CHANNEL_SIZE);
@@ -139,6 +141,22 @@ pub async fn serve<Svc, Req, Resp, E, D>(
                         .caller_mut()
                         .set_address(peer_addr.clone());
                 }
+                if let Some(peer_certificate) = &conn_info.peer_certificate {
+                    cx.rpc_info_mut()
+                        .caller_mut()
+                        .insert(peer_certificate.clone());
+                }
+                if let Some(server_name) = &conn_info.server_name {
+                    cx.rpc_info_mut().callee_mut().insert(server_name.clone());
+                }
+                if let Some(proxy_header) = &conn_info.proxy_header {
+                    cx.rpc_info_mut()
+                        .caller_mut()
+                        .insert(proxy_header.clone());
+                }
+                if let Some(peer_credentials) = conn_info.peer_credentials {
+                    cx.rpc_info_mut().caller_mut().insert(peer_credentials);
+                }
 
                 tokio::select! {
                     _ = &mut notified => {
@@ -8,7 +8,7 @@ use motore::service::Service;
 use pilota::thrift::ThriftException;
 use tokio::sync::futures::Notified;
 use tracing::*;
-use volo::{net::Address, volo_unreachable};
+use volo::{net::conn::ConnInfo, volo_unreachable};
 
 use crate::{
     DummyMessage, EntryMessage, ServerError, ThriftMessage,
@@ -28,7 +28,7 @@ pub async fn serve<Svc, Req, Resp, E, D, SP>(
     exit_mark: Arc<std::sync::atomic::AtomicBool>,
     service: &Svc,
     stat_tracer: Arc<[crate::server::TraceFn]>,
-    peer_addr: Option<Address>,
+    conn_info: ConnInfo,
     span_provider: SP,
 ) where
     Svc: Service<ServerContext, Req, Response = Resp>,
@@ -40,6 +40,7 @@ pub async fn serve<Svc, Req, Resp, E, D, SP>(
     SP: SpanProvider,
 {
     tokio::pin!(notified);
+    let peer_addr = conn_info.peer_addr.clone();
 
     metainfo::METAINFO
         .scope(RefCell::new(MetaInfo::default()), async {
@@ -54,6 +55,18 @@ pub async fn serve<Svc, Req, Resp, E, D, SP>(
                 if let Some(peer_addr) = &peer_addr {
                     cx.rpc_info.caller_mut().set_address(peer_addr.clone());
                 }
+                if let Some(peer_certificate) = &conn_info.peer_certificate {
+                    cx.rpc_info.caller_mut().insert(peer_certificate.clone());
+                }
+                if let Some(server_name) = &conn_info.server_name {
+                    cx.rpc_info.callee_mut().insert(server_name.clone());
+                }
+                if let Some(proxy_header) = &conn_info.proxy_header {
+                    cx.rpc_info.caller_mut().insert(proxy_header.clone());
+                }
+                if let Some(peer_credentials) = conn_info.peer_credentials {
+                    cx.rpc_info.caller_mut().insert(peer_credentials);
+                }
 
                 let msg = tokio::select! {
                     _ = &mut notified => {
@@ -0,0 +1,803 @@
+//! A circuit breaker layer for clients.
+//!
+//! The circuit breakers are keyed by the callee service, the method and the callee address, so
+//! the layer should be added as an inner layer of the client, which is called after the load
+//! balancer picks the address. If the address is not picked yet, the breaker is shared by all
+//! the instances of the service.
+//!
+//! Each circuit breaker has three states:
+//!
+//! - [`State::Closed`]: the requests are allowed, and the results are counted in a sliding
+//!   window. If the error rate or the slow call rate reaches the threshold, the circuit is opened.
+//! - [`State::Open`]: the requests fail fast with [`CircuitOpenError`] until the open duration
+//!   elapses, then the circuit becomes half-open. The error can be converted into the protocol
+//!   errors.
+//! - [`State::HalfOpen`]: a limited number of probe requests are allowed. If all of them
+//!   succeed, the circuit is closed, otherwise it is opened again. A probe which is cancelled
+//!   before it completes, e.g., the losing call of a hedged request, is released for another
+//!   request.
+//!
+//! Only the errors which are failures of the callee by [`BreakerFailure`], e.g., the transport
+//! errors, the timeouts and the overloads, are counted. The errors of the application, the open
+//! circuits and the injected faults are counted as successes.
+//!
+//! The circuit breakers which are not used for a while, e.g., the ones of the instances which are
+//! no longer discovered, are removed.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let breaker = CircuitBreaker::new(CircuitBreakerConfig::default());
+//! breaker.on_state_change(|key, from, to| {
+//!     tracing::info!("circuit breaker of {key} changed from {from:?} to {to:?}");
+//! });
+//!
+//! let client = ClientBuilder::new("service")
+//!     .layer_inner(CircuitBreakerLayer::new(breaker.clone()))
+//!     .build();
+//! ```
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use faststr::FastStr;
+use motore::Service;
+
+use crate::{context::Context, net::Address};
+
+/// The state of a circuit breaker.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum State {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// The key of a circuit breaker.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BreakerKey {
+    pub service: FastStr,
+    pub method: FastStr,
+    pub address: Option<Address>,
+}
+
+impl fmt::Display for BreakerKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.service, self.method)?;
+        if let Some(address) = &self.address {
+            write!(f, "@{address}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The error returned when the circuit is open.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("circuit breaker of {key} is open")]
+pub struct CircuitOpenError {
+    key: Box<BreakerKey>,
+}
+
+impl CircuitOpenError {
+    /// Returns the key of the open circuit breaker.
+    pub fn key(&self) -> &BreakerKey {
+        &self.key
+    }
+}
+
+/// Tells whether an error is a failure of the callee, which is counted by the
+/// [`CircuitBreakerService`].
+pub trait BreakerFailure {
+    fn is_failure(&self) -> bool {
+        false
+    }
+}
+
+/// Configuration of [`CircuitBreaker`].
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerConfig {
+    /// The length of the sliding window.
+    window: Duration,
+    /// The number of buckets in the sliding window.
+    buckets: u32,
+    /// The minimum number of requests in the window before the circuit can be opened.
+    min_requests: u32,
+    /// The error rate that opens the circuit.
+    error_rate: f64,
+    /// The calls slower than the duration are counted as slow calls, `None` disables it.
+    slow_call_duration: Option<Duration>,
+    /// The slow call rate that opens the circuit.
+    slow_call_rate: f64,
+    /// How long the circuit stays open before it becomes half-open.
+    open_duration: Duration,
+    /// The number of probe requests allowed in the half-open state.
+    half_open_requests: u32,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(10),
+            buckets: 10,
+            min_requests: 20,
+            error_rate: 0.5,
+            slow_call_duration: None,
+            slow_call_rate: 0.5,
+            open_duration: Duration::from_secs(5),
+            half_open_requests: 5,
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the length of the sliding window and the number of buckets in it.
+    pub fn window(mut self, window: Duration, buckets: u32) -> Self {
+        self.window = window;
+        self.buckets = buckets.max(1);
+        self
+    }
+
+    pub fn min_requests(mut self, min_requests: u32) -> Self {
+        self.min_requests = min_requests;
+        self
+    }
+
+    pub fn error_rate(mut self, rate: f64) -> Self {
+        self.error_rate = rate;
+        self
+    }
+
+    /// Opens the circuit if the rate of the calls slower than `duration` reaches `rate`.
+    pub fn slow_call(mut self, duration: Duration, rate: f64) -> Self {
+        self.slow_call_duration = Some(duration);
+        self.slow_call_rate = rate;
+        self
+    }
+
+    pub fn open_duration(mut self, duration: Duration) -> Self {
+        self.open_duration = duration;
+        self
+    }
+
+    pub fn half_open_requests(mut self, requests: u32) -> Self {
+        self.half_open_requests = requests.max(1);
+        self
+    }
+
+    fn bucket_len(&self) -> Duration {
+        self.window / self.buckets
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Bucket {
+    epoch: u64,
+    requests: u32,
+    errors: u32,
+    slow: u32,
+}
+
+/// A sliding window of the call results, consisting of a ring of buckets.
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    buckets: Vec<Bucket>,
+}
+
+impl Window {
+    fn new(buckets: u32) -> Self {
+        Self {
+            start: Instant::now(),
+            buckets: vec![Bucket::default(); buckets as usize],
+        }
+    }
+
+    fn epoch(&self, now: Instant, bucket_len: Duration) -> u64 {
+        (now.saturating_duration_since(self.start).as_nanos() / bucket_len.as_nanos().max(1)) as u64
+    }
+
+    fn record(&mut self, now: Instant, bucket_len: Duration, error: bool, slow: bool) {
+        let epoch = self.epoch(now, bucket_len);
+        let len = self.buckets.len() as u64;
+        let bucket = &mut self.buckets[(epoch % len) as usize];
+        if bucket.epoch != epoch {
+            *bucket = Bucket {
+                epoch,
+                ..Default::default()
+            };
+        }
+        bucket.requests += 1;
+        bucket.errors += error as u32;
+        bucket.slow += slow as u32;
+    }
+
+    /// Returns the number of requests, errors and slow calls in the window.
+    fn sum(&self, now: Instant, bucket_len: Duration) -> (u32, u32, u32) {
+        let epoch = self.epoch(now, bucket_len);
+        let len = self.buckets.len() as u64;
+        self.buckets
+            .iter()
+            .filter(|b| b.epoch + len > epoch)
+            .fold((0, 0, 0), |(r, e, s), b| {
+                (r + b.requests, e + b.errors, s + b.slow)
+            })
+    }
+
+    fn reset(&mut self) {
+        self.buckets.fill(Bucket::default());
+        self.start = Instant::now();
+    }
+}
+
+#[derive(Debug)]
+struct Breaker {
+    state: State,
+    window: Window,
+    opened_at: Instant,
+    last_used: Instant,
+    /// The number of times the breaker becomes half-open, which identifies the probes.
+    half_open_epoch: u64,
+    /// The number of probe requests sent and succeeded in the half-open state.
+    probes: u32,
+    probe_successes: u32,
+}
+
+/// A request allowed by the circuit breaker, which is a probe if allowed in the half-open state.
+#[derive(Clone, Copy, Debug)]
+pub struct Permit {
+    probe: Option<u64>,
+}
+
+type StateListener = Arc<dyn Fn(&BreakerKey, State, State) + Send + Sync>;
+
+struct Inner {
+    config: CircuitBreakerConfig,
+    breakers: DashMap<BreakerKey, Breaker>,
+    listeners: RwLock<Vec<StateListener>>,
+    last_cleanup: Mutex<Instant>,
+}
+
+/// [`CircuitBreaker`] manages the circuit breakers of all the keys.
+///
+/// It is cheap to clone, and the clones share the same circuit breakers.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    inner: Arc<Inner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                config,
+                breakers: DashMap::new(),
+                listeners: RwLock::new(Vec::new()),
+                last_cleanup: Mutex::new(Instant::now()),
+            }),
+        }
+    }
+
+    /// Registers a listener which is called with the key, the previous state and the new state
+    /// when the state of a circuit breaker changes.
+    pub fn on_state_change<F>(&self, listener: F)
+    where
+        F: Fn(&BreakerKey, State, State) + Send + Sync + 'static,
+    {
+        self.inner
+            .listeners
+            .write()
+            .unwrap()
+            .push(Arc::new(listener));
+    }
+
+    /// Returns the state of the circuit breaker of the key.
+    pub fn state(&self, key: &BreakerKey) -> State {
+        self.inner
+            .breakers
+            .get(key)
+            .map(|b| b.state)
+            .unwrap_or(State::Closed)
+    }
+
+    fn notify(&self, key: &BreakerKey, from: State, to: State) {
+        tracing::info!("[VOLO] circuit breaker of {key} changed from {from:?} to {to:?}");
+        for listener in self.inner.listeners.read().unwrap().iter() {
+            listener(key, from, to);
+        }
+    }
+
+    /// Checks whether a request is allowed.
+    ///
+    /// The result of the allowed request should be reported by [`CircuitBreaker::record`], or
+    /// the [`Permit`] should be given back by [`CircuitBreaker::release`] if it is cancelled.
+    pub fn allow(&self, key: &BreakerKey) -> Result<Permit, CircuitOpenError> {
+        let config = &self.inner.config;
+        let now = Instant::now();
+        self.cleanup(now);
+        let mut transition = None;
+        let permit = {
+            let mut breaker = self
+                .inner
+                .breakers
+                .entry(key.clone())
+                .or_insert_with(|| Breaker {
+                    state: State::Closed,
+                    window: Window::new(config.buckets),
+                    opened_at: now,
+                    last_used: now,
+                    half_open_epoch: 0,
+                    probes: 0,
+                    probe_successes: 0,
+                });
+            breaker.last_used = now;
+            match breaker.state {
+                State::Closed => Some(Permit { probe: None }),
+                State::Open => {
+                    if now.saturating_duration_since(breaker.opened_at) >= config.open_duration {
+                        breaker.state = State::HalfOpen;
+                        breaker.half_open_epoch += 1;
+                        breaker.probes = 1;
+                        breaker.probe_successes = 0;
+                        transition = Some((State::Open, State::HalfOpen));
+                        Some(Permit {
+                            probe: Some(breaker.half_open_epoch),
+                        })
+                    } else {
+                        None
+                    }
+                }
+                State::HalfOpen => {
+                    if breaker.probes < config.half_open_requests {
+                        breaker.probes += 1;
+                        Some(Permit {
+                            probe: Some(breaker.half_open_epoch),
+                        })
+                    } else {
+                        None
+                    }
+                }
+            }
+        };
+        if let Some((from, to)) = transition {
+            self.notify(key, from, to);
+        }
+        permit.ok_or_else(|| CircuitOpenError {
+            key: Box::new(key.clone()),
+        })
+    }
+
+    /// Releases a request allowed by [`CircuitBreaker::allow`] without a result, e.g., the
+    /// request is cancelled, so that a probe of the half-open state can be sent by another
+    /// request.
+    pub fn release(&self, key: &BreakerKey, permit: Permit) {
+        let Some(epoch) = permit.probe else {
+            return;
+        };
+        if let Some(mut breaker) = self.inner.breakers.get_mut(key) {
+            // the probe of a previous half-open state does not count
+            if breaker.state == State::HalfOpen && breaker.half_open_epoch == epoch {
+                breaker.probes = breaker.probes.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Removes the circuit breakers which are not used for longer than the window and the open
+    /// duration, e.g., the ones of the instances which are no longer discovered. Their windows
+    /// are empty and the open durations have elapsed, so they are the same as the new ones.
+    fn cleanup(&self, now: Instant) {
+        let config = &self.inner.config;
+        let idle = config.window + config.open_duration;
+        {
+            let mut last_cleanup = self.inner.last_cleanup.lock().unwrap();
+            if now.saturating_duration_since(*last_cleanup) < idle {
+                return;
+            }
+            *last_cleanup = now;
+        }
+        self.inner
+            .breakers
+            .retain(|_, breaker| now.saturating_duration_since(breaker.last_used) < idle);
+    }
+
+    /// Records the result of a request allowed by [`CircuitBreaker::allow`].
+    pub fn record(&self, key: &BreakerKey, error: bool, elapsed: Duration) {
+        let config = &self.inner.config;
+        let now = Instant::now();
+        let slow = config.slow_call_duration.is_some_and(|d| elapsed >= d);
+        let transition = {
+            let Some(mut breaker) = self.inner.breakers.get_mut(key) else {
+                return;
+            };
+            breaker.last_used = now;
+            match breaker.state {
+                State::Closed => {
+                    breaker.window.record(now, config.bucket_len(), error, slow);
+                    let (requests, errors, slows) = breaker.window.sum(now, config.bucket_len());
+                    let requests_f = requests as f64;
+                    if requests >= config.min_requests
+                        && (errors as f64 >= config.error_rate * requests_f
+                            || (config.slow_call_duration.is_some()
+                                && slows as f64 >= config.slow_call_rate * requests_f))
+                    {
+                        breaker.state = State::Open;
+                        breaker.opened_at = now;
+                        Some((State::Closed, State::Open))
+                    } else {
+                        None
+                    }
+                }
+                // the request was allowed before the circuit was opened
+                State::Open => None,
+                State::HalfOpen => {
+                    if error || slow {
+                        breaker.state = State::Open;
+                        breaker.opened_at = now;
+                        Some((State::HalfOpen, State::Open))
+                    } else {
+                        breaker.probe_successes += 1;
+                        if breaker.probe_successes >= config.half_open_requests {
+                            breaker.state = State::Closed;
+                            breaker.window.reset();
+                            Some((State::HalfOpen, State::Closed))
+                        } else {
+                            None
+                        }
+                    }
+                }
+            }
+        };
+        if let Some((from, to)) = transition {
+            self.notify(key, from, to);
+        }
+    }
+}
+
+impl fmt::Debug for CircuitBreaker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CircuitBreaker")
+            .field("config", &self.inner.config)
+            .finish()
+    }
+}
+
+/// A [`Layer`](motore::layer::Layer) that applies the [`CircuitBreaker`].
+#[derive(Clone, Debug)]
+pub struct CircuitBreakerLayer {
+    breaker: CircuitBreaker,
+}
+
+impl CircuitBreakerLayer {
+    pub fn new(breaker: CircuitBreaker) -> Self {
+        Self { breaker }
+    }
+}
+
+impl<S> motore::layer::Layer<S> for CircuitBreakerLayer {
+    type Service = CircuitBreakerService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        CircuitBreakerService {
+            inner,
+            breaker: self.breaker,
+        }
+    }
+}
+
+/// The [`Service`] generated by [`CircuitBreakerLayer`].
+#[derive(Clone, Debug)]
+pub struct CircuitBreakerService<S> {
+    inner: S,
+    breaker: CircuitBreaker,
+}
+
+impl<Cx, Req, S> Service<Cx, Req> for CircuitBreakerService<S>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + Sync + 'static,
+    S::Error: BreakerFailure,
+    CircuitOpenError: Into<S::Error>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+
+    async fn call(&self, cx: &mut Cx, req: Req) -> Result<Self::Response, Self::Error> {
+        let key = {
+            let rpc_info = cx.rpc_info();
+            BreakerKey {
+                service: rpc_info.callee().service_name(),
+                method: rpc_info.method().clone(),
+                address: rpc_info.callee().address(),
+            }
+        };
+        let permit = self.breaker.allow(&key).map_err(Into::into)?;
+        let guard = PermitGuard {
+            breaker: &self.breaker,
+            key,
+            permit: Some(permit),
+        };
+        let start = Instant::now();
+        let res = self.inner.call(cx, req).await;
+        guard.record(
+            res.as_ref().is_err_and(BreakerFailure::is_failure),
+            start.elapsed(),
+        );
+        res
+    }
+}
+
+/// Releases the permit if the request is cancelled before it completes.
+struct PermitGuard<'a> {
+    breaker: &'a CircuitBreaker,
+    key: BreakerKey,
+    permit: Option<Permit>,
+}
+
+impl PermitGuard<'_> {
+    fn record(mut self, error: bool, elapsed: Duration) {
+        self.permit = None;
+        self.breaker.record(&self.key, error, elapsed);
+    }
+}
+
+impl Drop for PermitGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(permit) = self.permit.take() {
+            self.breaker.release(&self.key, permit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use motore::{Service, layer::Layer, service::service_fn};
+
+    use super::{
+        BreakerFailure, BreakerKey, CircuitBreaker, CircuitBreakerConfig, CircuitBreakerLayer,
+        CircuitOpenError, PermitGuard, State,
+    };
+    use crate::context::{Endpoint, Reusable, Role, RpcCx, RpcInfo};
+
+    fn key() -> BreakerKey {
+        BreakerKey {
+            service: "service".into(),
+            method: "method".into(),
+            address: Some(
+                "127.0.0.1:8000"
+                    .parse::<std::net::SocketAddr>()
+                    .unwrap()
+                    .into(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_circuit_breaker_states() {
+        let breaker = CircuitBreaker::new(
+            CircuitBreakerConfig::new()
+                .min_requests(4)
+                .error_rate(0.5)
+                .open_duration(Duration::from_millis(50))
+                .half_open_requests(2),
+        );
+        let changes = Arc::new(AtomicUsize::new(0));
+        let changes_clone = changes.clone();
+        breaker.on_state_change(move |_, _, _| {
+            changes_clone.fetch_add(1, Ordering::Relaxed);
+        });
+        let key = key();
+
+        for error in [false, false, true] {
+            breaker.allow(&key).unwrap();
+            breaker.record(&key, error, Duration::ZERO);
+        }
+        assert_eq!(breaker.state(&key), State::Closed);
+        breaker.allow(&key).unwrap();
+        breaker.record(&key, true, Duration::ZERO);
+        assert_eq!(breaker.state(&key), State::Open);
+        assert!(breaker.allow(&key).is_err());
+
+        // half-open, and a failed probe opens the circuit again
+        std::thread::sleep(Duration::from_millis(60));
+        breaker.allow(&key).unwrap();
+        assert_eq!(breaker.state(&key), State::HalfOpen);
+        breaker.record(&key, true, Duration::ZERO);
+        assert_eq!(breaker.state(&key), State::Open);
+
+        // all the probes succeed
+        std::thread::sleep(Duration::from_millis(60));
+        breaker.allow(&key).unwrap();
+        breaker.allow(&key).unwrap();
+        assert!(breaker.allow(&key).is_err());
+        breaker.record(&key, false, Duration::ZERO);
+        breaker.record(&key, false, Duration::ZERO);
+        assert_eq!(breaker.state(&key), State::Closed);
+
+        assert_eq!(changes.load(Ordering::Relaxed), 5);
+    }
+
+    #[test]
+    fn test_circuit_breaker_slow_calls() {
+        let breaker = CircuitBreaker::new(
+            CircuitBreakerConfig::new()
+                .min_requests(2)
+                .slow_call(Duration::from_millis(100), 0.75),
+        );
+        let key = key();
+        breaker.allow(&key).unwrap();
+        breaker.record(&key, false, Duration::from_millis(200));
+        breaker.allow(&key).unwrap();
+        breaker.record(&key, false, Duration::from_millis(10));
+        assert_eq!(breaker.state(&key), State::Closed);
+        breaker.allow(&key).unwrap();
+        breaker.record(&key, false, Duration::from_millis(200));
+        breaker.allow(&key).unwrap();
+        assert_eq!(breaker.state(&key), State::Closed);
+        breaker.allow(&key).unwrap();
+        breaker.record(&key, false, Duration::from_millis(200));
+        assert_eq!(breaker.state(&key), State::Open);
+    }
+
+    #[test]
+    fn test_circuit_breaker_cancelled_probe() {
+        let breaker = CircuitBreaker::new(
+            CircuitBreakerConfig::new()
+                .min_requests(1)
+                .open_duration(Duration::from_millis(50))
+                .half_open_requests(1),
+        );
+        let key = key();
+        breaker.allow(&key).unwrap();
+        breaker.record(&key, true, Duration::ZERO);
+        assert_eq!(breaker.state(&key), State::Open);
+
+        // the probe is cancelled, and released for another request
+        std::thread::sleep(Duration::from_millis(60));
+        let guard = PermitGuard {
+            breaker: &breaker,
+            key: key.clone(),
+            permit: Some(breaker.allow(&key).unwrap()),
+        };
+        assert!(breaker.allow(&key).is_err());
+        drop(guard);
+        assert_eq!(breaker.state(&key), State::HalfOpen);
+        let guard = PermitGuard {
+            breaker: &breaker,
+            key: key.clone(),
+            permit: Some(breaker.allow(&key).unwrap()),
+        };
+        guard.record(false, Duration::ZERO);
+        assert_eq!(breaker.state(&key), State::Closed);
+    }
+
+    #[test]
+    fn test_circuit_breaker_stale_release() {
+        let breaker = CircuitBreaker::new(
+            CircuitBreakerConfig::new()
+                .min_requests(1)
+                .open_duration(Duration::from_millis(50))
+                .half_open_requests(1),
+        );
+        let key = key();
+        let permit = breaker.allow(&key).unwrap();
+        breaker.allow(&key).unwrap();
+        breaker.record(&key, true, Duration::ZERO);
+        assert_eq!(breaker.state(&key), State::Open);
+
+        // the permit allowed in the closed state does not release the probe
+        std::thread::sleep(Duration::from_millis(60));
+        breaker.allow(&key).unwrap();
+        breaker.release(&key, permit);
+        assert!(breaker.allow(&key).is_err());
+    }
+
+    #[test]
+    fn test_circuit_breaker_cleanup() {
+        let breaker = CircuitBreaker::new(
+            CircuitBreakerConfig::new()
+                .window(Duration::from_millis(20), 2)
+                .open_duration(Duration::from_millis(20)),
+        );
+        let key = key();
+        breaker.allow(&key).unwrap();
+        breaker.record(&key, false, Duration::ZERO);
+        assert_eq!(breaker.inner.breakers.len(), 1);
+
+        std::thread::sleep(Duration::from_millis(50));
+        let other = BreakerKey {
+            address: None,
+            ..key.clone()
+        };
+        breaker.allow(&other).unwrap();
+        assert!(!breaker.inner.breakers.contains_key(&key));
+        assert!(breaker.inner.breakers.contains_key(&other));
+    }
+
+    #[derive(Debug, Default)]
+    struct TestConfig;
+
+    impl Reusable for TestConfig {
+        fn clear(&mut self) {}
+    }
+
+    type TestContext = RpcCx<(), TestConfig>;
+
+    #[derive(Debug)]
+    enum TestError {
+        Application,
+        Transport,
+        Open,
+    }
+
+    impl BreakerFailure for TestError {
+        fn is_failure(&self) -> bool {
+            matches!(self, Self::Transport)
+        }
+    }
+
+    impl From<CircuitOpenError> for TestError {
+        fn from(_: CircuitOpenError) -> Self {
+            Self::Open
+        }
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_application_errors() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig::new().min_requests(2));
+        let service = CircuitBreakerLayer::new(breaker.clone()).layer(service_fn(
+            |_: &mut TestContext, transport: bool| async move {
+                Err::<(), _>(if transport {
+                    TestError::Transport
+                } else {
+                    TestError::Application
+                })
+            },
+        ));
+        let mut cx = RpcCx::new(
+            RpcInfo::new(
+                Role::Client,
+                "method".into(),
+                Endpoint::new("caller".into()),
+                Endpoint::new("callee".into()),
+                TestConfig,
+            ),
+            (),
+        );
+        let key = BreakerKey {
+            service: "callee".into(),
+            method: "method".into(),
+            address: None,
+        };
+
+        // the application errors are not failures of the callee
+        for _ in 0..4 {
+            service.call(&mut cx, false).await.unwrap_err();
+        }
+        assert_eq!(breaker.state(&key), State::Closed);
+
+        for _ in 0..4 {
+            service.call(&mut cx, true).await.unwrap_err();
+        }
+        assert_eq!(breaker.state(&key), State::Open);
+        assert!(matches!(
+            service.call(&mut cx, false).await,
+            Err(TestError::Open)
+        ));
+    }
+}
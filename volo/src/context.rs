@@ -1,10 +1,18 @@
-use std::fmt::Debug;
+use std::{
+    any::TypeId,
+    fmt::Debug,
+    sync::{LazyLock, RwLock},
+};
 
 pub use metainfo::MetaInfo;
 use metainfo::{FastStrMap, TypeMap};
 
 use super::net::Address;
-use crate::FastStr;
+use crate::{
+    FastStr,
+    loadbalance::{RequestHash, hedge::HedgePolicy, subset::SubsetSelector},
+    retry::RetryPolicy,
+};
 
 #[macro_export]
 macro_rules! newtype_impl_context {
@@ -147,6 +155,57 @@ where
     }
 }
 
+/// Copies a tag from an [`Endpoint`] to another one.
+type CopyTag = fn(&Endpoint, &mut Endpoint);
+
+/// The tags copied by [`Endpoint::fork`], keyed by the type of the tag and whether it is a
+/// faststr tag.
+static FORK_TAGS: LazyLock<RwLock<Vec<(TypeId, bool, CopyTag)>>> = LazyLock::new(|| {
+    RwLock::new(vec![
+        (TypeId::of::<RequestHash>(), false, copy_tag::<RequestHash>),
+        (
+            TypeId::of::<SubsetSelector>(),
+            false,
+            copy_tag::<SubsetSelector>,
+        ),
+        (TypeId::of::<RetryPolicy>(), false, copy_tag::<RetryPolicy>),
+        (TypeId::of::<HedgePolicy>(), false, copy_tag::<HedgePolicy>),
+    ])
+});
+
+fn copy_tag<T: Clone + Send + Sync + 'static>(from: &Endpoint, to: &mut Endpoint) {
+    if let Some(val) = from.get::<T>() {
+        to.insert(val.clone());
+    }
+}
+
+fn copy_faststr_tag<T: Send + Sync + 'static>(from: &Endpoint, to: &mut Endpoint) {
+    if let Some(val) = from.get_faststr::<T>() {
+        to.insert_faststr::<T>(val.clone());
+    }
+}
+
+fn register_fork_tag(id: TypeId, faststr: bool, copy: CopyTag) {
+    let mut tags = FORK_TAGS.write().unwrap();
+    if !tags.iter().any(|(t, f, _)| *t == id && *f == faststr) {
+        tags.push((id, faststr, copy));
+    }
+}
+
+/// Registers the tag `T` of [`Endpoint::tags`] to be copied by [`Endpoint::fork`].
+///
+/// The tags of volo, e.g., [`RequestHash`] and [`RetryPolicy`], are registered by default. The
+/// custom tags which are read when calling an instance should be registered, otherwise they are
+/// missing in the hedged attempts.
+pub fn register_fork_tag_type<T: Clone + Send + Sync + 'static>() {
+    register_fork_tag(TypeId::of::<T>(), false, copy_tag::<T>);
+}
+
+/// Registers the tag `T` of [`Endpoint::faststr_tags`] to be copied by [`Endpoint::fork`].
+pub fn register_fork_faststr_tag_type<T: Send + Sync + 'static>() {
+    register_fork_tag(TypeId::of::<T>(), true, copy_faststr_tag::<T>);
+}
+
 /// Endpoint contains the information of the service.
 #[derive(Debug, Default)]
 pub struct Endpoint {
@@ -242,6 +301,20 @@ impl Endpoint {
         self.address.clone()
     }
 
+    /// Creates a copy of the endpoint for another attempt of the same call, e.g., a hedged
+    /// attempt.
+    ///
+    /// The tags cannot be cloned in general, so only the ones registered by
+    /// [`register_fork_tag_type`] and [`register_fork_faststr_tag_type`] are copied.
+    pub fn fork(&self) -> Self {
+        let mut endpoint = Self::new(self.service_name());
+        endpoint.address = self.address();
+        for (_, _, copy) in FORK_TAGS.read().unwrap().iter() {
+            copy(self, &mut endpoint);
+        }
+        endpoint
+    }
+
     /// Clear the information
     #[inline]
     pub fn clear(&mut self) {
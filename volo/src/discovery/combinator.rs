@@ -0,0 +1,692 @@
+//! Combinators which wrap or compose [`Discover`]s.
+//!
+//! - [`CachedDiscover`] caches the instances of a [`Discover`] for a TTL, and serves the stale
+//!   instances if the discover fails.
+//! - [`FallbackDiscover`] uses the instances of the primary [`Discover`], and falls back to the
+//!   secondary one if the primary fails or returns no instance.
+//! - [`MergedDiscover`] returns the union of the instances of two [`Discover`]s, and can annotate
+//!   the instances with tags of their sources.
+//!
+//! All of them keep the [`Discover::key`] of the (first) inner discover, and forward the changes
+//! of the inner discovers to the receivers of [`Discover::watch`], so the load balancers are
+//! rebalanced as usual.
+//!
+//! [`FallbackDiscover`] and [`MergedDiscover`] can be nested to compose more than two discovers.
+
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_broadcast::{Receiver, RecvError};
+use dashmap::DashMap;
+use futures::StreamExt;
+
+use super::{Change, Discover, Instance, diff_instances};
+use crate::{context::Endpoint, loadbalance::error::LoadBalanceError};
+
+type Instances = Vec<Arc<Instance>>;
+
+struct CacheEntry {
+    instances: Instances,
+    fetched_at: Instant,
+}
+
+/// [`CachedDiscover`] caches the instances of the inner [`Discover`] for a TTL.
+///
+/// If the inner discover fails, the cached instances are returned even if they are expired. The
+/// cache is also updated by the changes from [`Discover::watch`] of the inner discover.
+pub struct CachedDiscover<D>
+where
+    D: Discover,
+{
+    inner: D,
+    ttl: Duration,
+    cache: Arc<DashMap<D::Key, CacheEntry>>,
+}
+
+impl<D> CachedDiscover<D>
+where
+    D: Discover,
+{
+    /// Creates a [`CachedDiscover`] which caches the instances of `inner` for `ttl`.
+    pub fn new(inner: D, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            cache: Arc::new(DashMap::new()),
+        }
+    }
+}
+
+impl<D> Clone for CachedDiscover<D>
+where
+    D: Discover + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            ttl: self.ttl,
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<D> Discover for CachedDiscover<D>
+where
+    D: Discover,
+{
+    type Key = D::Key;
+    type Error = D::Error;
+
+    async fn discover<'s>(&'s self, endpoint: &'s Endpoint) -> Result<Instances, Self::Error> {
+        let key = self.inner.key(endpoint);
+        if let Some(entry) = self.cache.get(&key) {
+            if entry.fetched_at.elapsed() < self.ttl {
+                return Ok(entry.instances.clone());
+            }
+        }
+        match self.inner.discover(endpoint).await {
+            Ok(instances) => {
+                self.cache.insert(
+                    key,
+                    CacheEntry {
+                        instances: instances.clone(),
+                        fetched_at: Instant::now(),
+                    },
+                );
+                Ok(instances)
+            }
+            Err(err) => match self.cache.get(&key) {
+                Some(entry) => {
+                    tracing::warn!("[VOLO] discover failed, use the stale instances");
+                    Ok(entry.instances.clone())
+                }
+                None => Err(err),
+            },
+        }
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        self.inner.key(endpoint)
+    }
+
+    fn watch(&self, keys: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+        let mut inner = self.inner.watch(keys)?;
+        let (mut tx, rx) = async_broadcast::broadcast(inner.capacity().max(1));
+        tx.set_overflow(true);
+        let cache = self.cache.clone();
+        tokio::spawn(async move {
+            loop {
+                let change = match inner.recv().await {
+                    Ok(change) => change,
+                    Err(RecvError::Closed) => break,
+                    Err(err) => {
+                        tracing::warn!("[VOLO] cached discover subscription error: {:?}", err);
+                        continue;
+                    }
+                };
+                cache.insert(
+                    change.key.clone(),
+                    CacheEntry {
+                        instances: change.all.clone(),
+                        fetched_at: Instant::now(),
+                    },
+                );
+                if tx.broadcast(change).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Some(rx)
+    }
+}
+
+impl<D> std::fmt::Debug for CachedDiscover<D>
+where
+    D: Discover + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CachedDiscover")
+            .field("inner", &self.inner)
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+/// The last known instances of the two sources of a key, and the combined result of them.
+#[derive(Default)]
+struct Sources {
+    first: Option<Instances>,
+    second: Option<Instances>,
+    combined: Instances,
+}
+
+type Combine = fn(Option<&Instances>, Option<&Instances>) -> Instances;
+
+/// Records the instances of the sources, and returns the combined instances.
+fn record<K>(
+    sources: &DashMap<K, Sources>,
+    key: K,
+    first: Option<Instances>,
+    second: Option<Instances>,
+    combine: Combine,
+) -> (Instances, Option<Change<K>>)
+where
+    K: std::hash::Hash + Eq + Clone + Send + Sync + 'static,
+{
+    let mut entry = sources.entry(key.clone()).or_default();
+    if first.is_some() {
+        entry.first = first;
+    }
+    if second.is_some() {
+        entry.second = second;
+    }
+    let combined = combine(entry.first.as_ref(), entry.second.as_ref());
+    let prev = std::mem::replace(&mut entry.combined, combined.clone());
+    drop(entry);
+    let (change, changed) = diff_instances(key, prev, combined.clone());
+    (combined, changed.then_some(change))
+}
+
+/// Merges the changes of the two sources, and sends the changes of the combined instances.
+fn watch_sources<K>(
+    first: Option<Receiver<Change<K>>>,
+    second: Option<Receiver<Change<K>>>,
+    sources: Arc<DashMap<K, Sources>>,
+    combine: Combine,
+    annotate: impl Fn(bool, Instances) -> Instances + Send + 'static,
+) -> Option<Receiver<Change<K>>>
+where
+    K: std::hash::Hash + Eq + Clone + Send + Sync + 'static,
+{
+    if first.is_none() && second.is_none() {
+        return None;
+    }
+    let capacity = first
+        .iter()
+        .chain(second.iter())
+        .map(Receiver::capacity)
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let (mut tx, rx) = async_broadcast::broadcast(capacity);
+    tx.set_overflow(true);
+    let mut changes = futures::stream::select(
+        futures::stream::iter(first).flatten().map(|c| (true, c)),
+        futures::stream::iter(second).flatten().map(|c| (false, c)),
+    );
+    tokio::spawn(async move {
+        while let Some((is_first, change)) = changes.next().await {
+            let all = Some(annotate(is_first, change.all));
+            let (first, second) = if is_first { (all, None) } else { (None, all) };
+            let (_, change) = record(&sources, change.key, first, second, combine);
+            if let Some(change) = change {
+                if tx.broadcast(change).await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    Some(rx)
+}
+
+fn fallback(primary: Option<&Instances>, secondary: Option<&Instances>) -> Instances {
+    match primary {
+        Some(primary) if !primary.is_empty() => primary.clone(),
+        _ => secondary.cloned().unwrap_or_default(),
+    }
+}
+
+fn merge(first: Option<&Instances>, second: Option<&Instances>) -> Instances {
+    let mut addresses = HashSet::new();
+    first
+        .into_iter()
+        .chain(second)
+        .flatten()
+        .filter(|instance| addresses.insert(instance.address.clone()))
+        .cloned()
+        .collect()
+}
+
+/// [`FallbackDiscover`] uses the instances of the primary [`Discover`], and falls back to the
+/// secondary one if the primary fails or returns no instance.
+///
+/// The secondary discover should use the same key as the primary one for the same endpoint.
+pub struct FallbackDiscover<P, S>
+where
+    P: Discover,
+{
+    primary: P,
+    secondary: S,
+    sources: Arc<DashMap<P::Key, Sources>>,
+}
+
+impl<P, S> FallbackDiscover<P, S>
+where
+    P: Discover,
+    S: Discover<Key = P::Key>,
+{
+    /// Creates a [`FallbackDiscover`] which falls back from `primary` to `secondary`.
+    pub fn new(primary: P, secondary: S) -> Self {
+        Self {
+            primary,
+            secondary,
+            sources: Arc::new(DashMap::new()),
+        }
+    }
+}
+
+impl<P, S> Clone for FallbackDiscover<P, S>
+where
+    P: Discover + Clone,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            primary: self.primary.clone(),
+            secondary: self.secondary.clone(),
+            sources: self.sources.clone(),
+        }
+    }
+}
+
+impl<P, S> Discover for FallbackDiscover<P, S>
+where
+    P: Discover,
+    S: Discover<Key = P::Key>,
+{
+    type Key = P::Key;
+    type Error = LoadBalanceError;
+
+    async fn discover<'s>(&'s self, endpoint: &'s Endpoint) -> Result<Instances, Self::Error> {
+        let key = self.primary.key(endpoint);
+        // a failed primary is treated as having no instance, so the secondary is used
+        let (primary, primary_err) = match self.primary.discover(endpoint).await {
+            Ok(instances) if !instances.is_empty() => {
+                return Ok(record(&self.sources, key, Some(instances), None, fallback).0);
+            }
+            Ok(instances) => (instances, None),
+            Err(err) => (Vec::new(), Some(err.into())),
+        };
+        match self.secondary.discover(endpoint).await {
+            Ok(instances) => {
+                Ok(record(&self.sources, key, Some(primary), Some(instances), fallback).0)
+            }
+            Err(err) => match primary_err {
+                Some(_) => Err(err.into()),
+                None => Ok(record(&self.sources, key, Some(primary), None, fallback).0),
+            },
+        }
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        self.primary.key(endpoint)
+    }
+
+    fn watch(&self, keys: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+        watch_sources(
+            self.primary.watch(keys),
+            self.secondary.watch(keys),
+            self.sources.clone(),
+            fallback,
+            |_, instances| instances,
+        )
+    }
+}
+
+impl<P, S> std::fmt::Debug for FallbackDiscover<P, S>
+where
+    P: Discover + std::fmt::Debug,
+    S: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FallbackDiscover")
+            .field("primary", &self.primary)
+            .field("secondary", &self.secondary)
+            .finish()
+    }
+}
+
+type Tags = Arc<Vec<(Cow<'static, str>, Cow<'static, str>)>>;
+
+fn annotate(tags: &Tags, instances: Instances) -> Instances {
+    if tags.is_empty() {
+        return instances;
+    }
+    instances
+        .into_iter()
+        .map(|instance| {
+            let mut instance = Instance::clone(&instance);
+            instance.tags.extend(tags.iter().cloned());
+            Arc::new(instance)
+        })
+        .collect()
+}
+
+/// [`MergedDiscover`] returns the union of the instances of two [`Discover`]s.
+///
+/// If both discovers return an instance with the same address, the one from the first discover
+/// is used. The instances can be annotated with the tags of their sources by
+/// [`MergedDiscover::with_first_tag`] and [`MergedDiscover::with_second_tag`].
+///
+/// The second discover should use the same key as the first one for the same endpoint. If one of
+/// the discovers fails, the instances of the other one are returned.
+pub struct MergedDiscover<A, B>
+where
+    A: Discover,
+{
+    first: A,
+    second: B,
+    first_tags: Tags,
+    second_tags: Tags,
+    sources: Arc<DashMap<A::Key, Sources>>,
+}
+
+impl<A, B> MergedDiscover<A, B>
+where
+    A: Discover,
+    B: Discover<Key = A::Key>,
+{
+    /// Creates a [`MergedDiscover`] which merges the instances of `first` and `second`.
+    pub fn new(first: A, second: B) -> Self {
+        Self {
+            first,
+            second,
+            first_tags: Default::default(),
+            second_tags: Default::default(),
+            sources: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// Adds a tag to the instances from the first discover.
+    pub fn with_first_tag(
+        mut self,
+        key: impl Into<Cow<'static, str>>,
+        value: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        Arc::make_mut(&mut self.first_tags).push((key.into(), value.into()));
+        self
+    }
+
+    /// Adds a tag to the instances from the second discover.
+    pub fn with_second_tag(
+        mut self,
+        key: impl Into<Cow<'static, str>>,
+        value: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        Arc::make_mut(&mut self.second_tags).push((key.into(), value.into()));
+        self
+    }
+}
+
+impl<A, B> Clone for MergedDiscover<A, B>
+where
+    A: Discover + Clone,
+    B: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            first: self.first.clone(),
+            second: self.second.clone(),
+            first_tags: self.first_tags.clone(),
+            second_tags: self.second_tags.clone(),
+            sources: self.sources.clone(),
+        }
+    }
+}
+
+impl<A, B> Discover for MergedDiscover<A, B>
+where
+    A: Discover,
+    B: Discover<Key = A::Key>,
+{
+    type Key = A::Key;
+    type Error = LoadBalanceError;
+
+    async fn discover<'s>(&'s self, endpoint: &'s Endpoint) -> Result<Instances, Self::Error> {
+        let (first, second) = futures::join!(
+            async { self.first.discover(endpoint).await.map_err(Into::into) },
+            async { self.second.discover(endpoint).await.map_err(Into::into) },
+        );
+        let (first, second) = match (first, second) {
+            (Err(err), Err(_)) => return Err(err),
+            (first, second) => (
+                first
+                    .ok()
+                    .map(|instances| annotate(&self.first_tags, instances)),
+                second
+                    .ok()
+                    .map(|instances| annotate(&self.second_tags, instances)),
+            ),
+        };
+        let key = self.first.key(endpoint);
+        Ok(record(&self.sources, key, first, second, merge).0)
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        self.first.key(endpoint)
+    }
+
+    fn watch(&self, keys: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+        let first_tags = self.first_tags.clone();
+        let second_tags = self.second_tags.clone();
+        watch_sources(
+            self.first.watch(keys),
+            self.second.watch(keys),
+            self.sources.clone(),
+            merge,
+            move |is_first, instances| {
+                annotate(if is_first { &first_tags } else { &second_tags }, instances)
+            },
+        )
+    }
+}
+
+impl<A, B> std::fmt::Debug for MergedDiscover<A, B>
+where
+    A: Discover + std::fmt::Debug,
+    B: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MergedDiscover")
+            .field("first", &self.first)
+            .field("second", &self.second)
+            .field("first_tags", &self.first_tags)
+            .field("second_tags", &self.second_tags)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc, Mutex,
+            atomic::{AtomicBool, Ordering},
+        },
+        time::Duration,
+    };
+
+    use async_broadcast::{Receiver, Sender};
+    use faststr::FastStr;
+
+    use super::{CachedDiscover, FallbackDiscover, MergedDiscover};
+    use crate::{
+        context::Endpoint,
+        discovery::{Change, Discover, Instance},
+        loadbalance::error::LoadBalanceError,
+        net::Address,
+    };
+
+    fn new_instance(address: &str) -> Arc<Instance> {
+        Arc::new(Instance {
+            address: Address::Ip(address.parse().unwrap()),
+            weight: 1,
+            tags: Default::default(),
+        })
+    }
+
+    /// A discover whose instances and failures can be changed by the tests.
+    #[derive(Clone)]
+    struct MockDiscover {
+        instances: Arc<Mutex<Vec<Arc<Instance>>>>,
+        failing: Arc<AtomicBool>,
+        sender: Sender<Change<FastStr>>,
+        receiver: Receiver<Change<FastStr>>,
+    }
+
+    impl MockDiscover {
+        fn new(instances: Vec<Arc<Instance>>) -> Self {
+            let (sender, receiver) = async_broadcast::broadcast(16);
+            Self {
+                instances: Arc::new(Mutex::new(instances)),
+                failing: Default::default(),
+                sender,
+                receiver,
+            }
+        }
+
+        fn set_failing(&self, failing: bool) {
+            self.failing.store(failing, Ordering::Relaxed);
+        }
+
+        async fn change(&self, instances: Vec<Arc<Instance>>) {
+            *self.instances.lock().unwrap() = instances.clone();
+            let change = Change {
+                key: FastStr::from_static_str("service"),
+                all: instances,
+                added: Vec::new(),
+                updated: Vec::new(),
+                removed: Vec::new(),
+            };
+            self.sender.broadcast(change).await.unwrap();
+        }
+    }
+
+    impl Discover for MockDiscover {
+        type Key = FastStr;
+        type Error = LoadBalanceError;
+
+        async fn discover<'s>(
+            &'s self,
+            _: &'s Endpoint,
+        ) -> Result<Vec<Arc<Instance>>, Self::Error> {
+            if self.failing.load(Ordering::Relaxed) {
+                return Err(LoadBalanceError::Discover("failing".into()));
+            }
+            Ok(self.instances.lock().unwrap().clone())
+        }
+
+        fn key(&self, endpoint: &Endpoint) -> Self::Key {
+            endpoint.service_name()
+        }
+
+        fn watch(&self, _: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+            Some(self.receiver.clone())
+        }
+    }
+
+    async fn recv(receiver: &mut Receiver<Change<FastStr>>) -> Change<FastStr> {
+        tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_cached_discover() {
+        let endpoint = Endpoint::new("service".into());
+        let mock = MockDiscover::new(vec![new_instance("127.0.0.1:8000")]);
+        let discover = CachedDiscover::new(mock.clone(), Duration::from_secs(60));
+        let mut receiver = discover.watch(None).unwrap();
+
+        assert_eq!(discover.key(&endpoint), "service");
+        assert_eq!(discover.discover(&endpoint).await.unwrap().len(), 1);
+
+        *mock.instances.lock().unwrap() = Vec::new();
+        assert_eq!(discover.discover(&endpoint).await.unwrap().len(), 1);
+
+        mock.change(vec![
+            new_instance("127.0.0.1:8000"),
+            new_instance("127.0.0.2:8000"),
+        ])
+        .await;
+        assert_eq!(recv(&mut receiver).await.all.len(), 2);
+        assert_eq!(discover.discover(&endpoint).await.unwrap().len(), 2);
+
+        let discover = CachedDiscover::new(mock.clone(), Duration::ZERO);
+        assert_eq!(discover.discover(&endpoint).await.unwrap().len(), 2);
+        mock.set_failing(true);
+        assert_eq!(discover.discover(&endpoint).await.unwrap().len(), 2);
+        assert!(
+            discover
+                .discover(&Endpoint::new("other".into()))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fallback_discover() {
+        let endpoint = Endpoint::new("service".into());
+        let primary = MockDiscover::new(vec![new_instance("127.0.0.1:8000")]);
+        let secondary = MockDiscover::new(vec![new_instance("127.0.0.2:8000")]);
+        let discover = FallbackDiscover::new(primary.clone(), secondary.clone());
+        let mut receiver = discover.watch(None).unwrap();
+
+        let instances = discover.discover(&endpoint).await.unwrap();
+        assert_eq!(instances, vec![new_instance("127.0.0.1:8000")]);
+
+        primary.set_failing(true);
+        let instances = discover.discover(&endpoint).await.unwrap();
+        assert_eq!(instances, vec![new_instance("127.0.0.2:8000")]);
+        secondary.set_failing(true);
+        assert!(discover.discover(&endpoint).await.is_err());
+        primary.set_failing(false);
+        secondary.set_failing(false);
+        let instances = discover.discover(&endpoint).await.unwrap();
+        assert_eq!(instances, vec![new_instance("127.0.0.1:8000")]);
+
+        primary.change(Vec::new()).await;
+        let change = recv(&mut receiver).await;
+        assert_eq!(change.all, vec![new_instance("127.0.0.2:8000")]);
+
+        primary.change(vec![new_instance("127.0.0.3:8000")]).await;
+        let change = recv(&mut receiver).await;
+        assert_eq!(change.added, vec![new_instance("127.0.0.3:8000")]);
+        assert_eq!(change.removed, vec![new_instance("127.0.0.2:8000")]);
+    }
+
+    #[tokio::test]
+    async fn test_merged_discover() {
+        let endpoint = Endpoint::new("service".into());
+        let first = MockDiscover::new(vec![new_instance("127.0.0.1:8000")]);
+        let second = MockDiscover::new(vec![
+            new_instance("127.0.0.1:8000"),
+            new_instance("127.0.0.2:8000"),
+        ]);
+        let discover = MergedDiscover::new(first.clone(), second)
+            .with_first_tag("source", "mock")
+            .with_second_tag("source", "static");
+        let mut receiver = discover.watch(None).unwrap();
+
+        let instances = discover.discover(&endpoint).await.unwrap();
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[0].tags.get("source").unwrap(), "mock");
+        assert_eq!(instances[1].tags.get("source").unwrap(), "static");
+
+        first.set_failing(true);
+        assert_eq!(discover.discover(&endpoint).await.unwrap().len(), 2);
+        first.set_failing(false);
+
+        first.change(vec![new_instance("127.0.0.3:8000")]).await;
+        let change = recv(&mut receiver).await;
+        assert_eq!(change.all.len(), 3);
+        assert_eq!(change.added.len(), 1);
+        assert_eq!(change.added[0].tags.get("source").unwrap(), "mock");
+        assert!(change.removed.is_empty());
+    }
+}
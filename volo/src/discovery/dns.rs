@@ -0,0 +1,481 @@
+//! A [`Discover`] which resolves the service name through DNS.
+//!
+//! The service name of the callee is treated as `host[:port]`, and all the A/AAAA records of the
+//! host are returned as instances. The port is 80 if it is missing, which can be changed by
+//! [`DnsDiscover::with_default_port`]. If SRV is enabled by [`DnsDiscover::with_srv`], the host is
+//! looked up as a SRV name instead, and the targets with the highest priority are returned with
+//! their ports and weights.
+//!
+//! The resolved instances are cached until the TTLs of the records expire, but no longer than the
+//! refresh interval.
+
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{Arc, Once, Weak},
+    time::{Duration, Instant},
+};
+
+use async_broadcast::{InactiveReceiver, Receiver, Sender};
+use dashmap::DashMap;
+use faststr::FastStr;
+use hickory_resolver::{
+    Resolver, TokioResolver,
+    config::{ResolverConfig, ResolverOpts},
+    name_server::TokioConnectionProvider,
+};
+
+use super::{Change, Discover, Instance, diff_instances};
+use crate::{context::Endpoint, loadbalance::error::LoadBalanceError, net::Address};
+
+const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+/// The minimum interval of re-resolving, which avoids resolving too often for the records with
+/// zero TTLs.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_PORT: u16 = 80;
+const DEFAULT_WEIGHT: u32 = 10;
+const CHANNEL_CAPACITY: usize = 64;
+
+struct Entry {
+    instances: Vec<Arc<Instance>>,
+    expires_at: Instant,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Config {
+    refresh_interval: Duration,
+    srv: bool,
+    default_port: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            srv: false,
+            default_port: DEFAULT_PORT,
+        }
+    }
+}
+
+struct Inner {
+    resolver: TokioResolver,
+    config: Config,
+    cache: DashMap<FastStr, Entry>,
+    sender: Sender<Change<FastStr>>,
+    receiver: InactiveReceiver<Change<FastStr>>,
+    refreshing: Once,
+}
+
+/// The resolved instances and the time when the records expire.
+type Resolved = (Vec<Arc<Instance>>, Instant);
+
+impl Inner {
+    async fn resolve(&self, name: &str) -> Result<Resolved, LoadBalanceError> {
+        let (host, port) = parse_host_and_port(name, self.config.default_port)?;
+        let mut addrs = BTreeMap::new();
+        let valid_until;
+        if self.config.srv {
+            let lookup = self
+                .resolver
+                .srv_lookup(host)
+                .await
+                .map_err(|err| LoadBalanceError::Discover(Box::new(err)))?;
+            let Some(priority) = lookup.iter().map(|srv| srv.priority()).min() else {
+                return Ok((Vec::new(), lookup.as_lookup().valid_until()));
+            };
+            let mut until = lookup.as_lookup().valid_until();
+            for srv in lookup.iter().filter(|srv| srv.priority() == priority) {
+                let ips = self
+                    .resolver
+                    .lookup_ip(srv.target().clone())
+                    .await
+                    .map_err(|err| LoadBalanceError::Discover(Box::new(err)))?;
+                until = until.min(ips.valid_until());
+                let weight = u32::from(srv.weight()).max(1);
+                for ip in ips {
+                    addrs
+                        .entry(SocketAddr::new(ip, srv.port()))
+                        .or_insert(weight);
+                }
+            }
+            valid_until = until;
+        } else {
+            let ips = self
+                .resolver
+                .lookup_ip(host)
+                .await
+                .map_err(|err| LoadBalanceError::Discover(Box::new(err)))?;
+            valid_until = ips.valid_until();
+            for ip in ips {
+                addrs
+                    .entry(SocketAddr::new(ip, port))
+                    .or_insert(DEFAULT_WEIGHT);
+            }
+        }
+        let instances = addrs
+            .into_iter()
+            .map(|(addr, weight)| {
+                Arc::new(Instance {
+                    address: Address::Ip(addr),
+                    weight,
+                    tags: Default::default(),
+                })
+            })
+            .collect();
+        Ok((instances, valid_until))
+    }
+
+    /// Returns when the records resolved now should be re-resolved, which is bounded by the
+    /// minimum and the configured refresh interval.
+    fn expires_at(&self, now: Instant, valid_until: Instant) -> Instant {
+        let ttl = valid_until.saturating_duration_since(now).clamp(
+            MIN_REFRESH_INTERVAL,
+            self.config.refresh_interval.max(MIN_REFRESH_INTERVAL),
+        );
+        now + ttl
+    }
+
+    /// Updates the cache with the result of resolution, and sends the change if there is any.
+    ///
+    /// If the resolution fails, the last good set of the instances will be kept and returned.
+    fn update(
+        &self,
+        key: &FastStr,
+        result: Result<Resolved, LoadBalanceError>,
+    ) -> Result<Vec<Arc<Instance>>, LoadBalanceError> {
+        let now = Instant::now();
+        let (next, valid_until) = match result {
+            Ok(next) => next,
+            Err(err) => {
+                let Some(mut entry) = self.cache.get_mut(key) else {
+                    return Err(err);
+                };
+                tracing::warn!("[VOLO] failed to resolve {key}, keep the last instances: {err}");
+                // retry after the refresh interval
+                entry.expires_at = now + self.config.refresh_interval.max(MIN_REFRESH_INTERVAL);
+                return Ok(entry.instances.clone());
+            }
+        };
+        let prev = self
+            .cache
+            .insert(
+                key.clone(),
+                Entry {
+                    instances: next.clone(),
+                    expires_at: self.expires_at(now, valid_until),
+                },
+            )
+            .map(|entry| entry.instances)
+            .unwrap_or_default();
+        let (change, changed) = diff_instances(key.clone(), prev, next.clone());
+        if changed {
+            let _ = self.sender.try_broadcast(change);
+        }
+        Ok(next)
+    }
+
+    /// Re-resolves the expired names, and returns when the next name expires.
+    async fn refresh(&self) -> Instant {
+        let now = Instant::now();
+        let keys: Vec<FastStr> = self
+            .cache
+            .iter()
+            .filter(|entry| entry.expires_at <= now)
+            .map(|entry| entry.key().clone())
+            .collect();
+        for key in keys {
+            let result = self.resolve(&key).await;
+            let _ = self.update(&key, result);
+        }
+        self.cache
+            .iter()
+            .map(|entry| entry.expires_at)
+            .min()
+            .unwrap_or(now + self.config.refresh_interval)
+            .max(now + MIN_REFRESH_INTERVAL)
+    }
+}
+
+/// [`DnsDiscover`] resolves the service name through DNS, and re-resolves the names which have
+/// been discovered when their records expire.
+///
+/// The resolved addresses are deduplicated, and the changes of them are sent to the receivers of
+/// [`Discover::watch`]. If a resolution fails, the last good set of the instances is kept.
+#[derive(Clone)]
+pub struct DnsDiscover {
+    inner: Arc<Inner>,
+}
+
+impl DnsDiscover {
+    /// Creates a [`DnsDiscover`] through `ResolverConfig` and `ResolverOpts`.
+    ///
+    /// For using system config, you can create a new instance by `DnsDiscover::default()`.
+    pub fn new(config: ResolverConfig, options: ResolverOpts) -> Self {
+        let resolver = Resolver::builder_with_config(config, TokioConnectionProvider::default())
+            .with_options(options)
+            .build();
+        Self::with_resolver(resolver, Config::default())
+    }
+
+    fn with_resolver(resolver: TokioResolver, config: Config) -> Self {
+        let (mut sender, receiver) = async_broadcast::broadcast(CHANNEL_CAPACITY);
+        sender.set_overflow(true);
+        Self {
+            inner: Arc::new(Inner {
+                resolver,
+                config,
+                cache: DashMap::new(),
+                sender,
+                receiver: receiver.deactivate(),
+                refreshing: Once::new(),
+            }),
+        }
+    }
+
+    /// Sets the maximum interval of re-resolving, the default is 30 seconds.
+    ///
+    /// The names are re-resolved earlier if the TTLs of their records expire, but no more often
+    /// than once per second.
+    pub fn with_refresh_interval(self, interval: Duration) -> Self {
+        self.map_config(|config| config.refresh_interval = interval)
+    }
+
+    /// Looks up the SRV records of the service name instead of the A/AAAA records.
+    pub fn with_srv(self, srv: bool) -> Self {
+        self.map_config(|config| config.srv = srv)
+    }
+
+    /// Sets the port of the service names without ports, the default is 80.
+    ///
+    /// It does not work for SRV, whose records carry the ports.
+    pub fn with_default_port(self, port: u16) -> Self {
+        self.map_config(|config| config.default_port = port)
+    }
+
+    /// Creates a [`DnsDiscover`] with the new config, which shares the resolver with `self`, but
+    /// not the cache and the watchers.
+    fn map_config(self, f: impl FnOnce(&mut Config)) -> Self {
+        let mut config = self.inner.config;
+        f(&mut config);
+        Self::with_resolver(self.inner.resolver.clone(), config)
+    }
+
+    fn spawn_refresher(&self) {
+        let inner: Weak<Inner> = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            let mut next = Instant::now() + MIN_REFRESH_INTERVAL;
+            loop {
+                tokio::time::sleep_until(next.into()).await;
+                let Some(inner) = inner.upgrade() else {
+                    break;
+                };
+                next = inner.refresh().await;
+            }
+        });
+    }
+}
+
+impl Default for DnsDiscover {
+    fn default() -> Self {
+        let resolver = match Resolver::builder_tokio() {
+            Ok(builder) => builder.build(),
+            Err(err) => {
+                tracing::warn!("[VOLO] failed to read the system dns config, use default: {err}");
+                Resolver::builder_with_config(
+                    ResolverConfig::default(),
+                    TokioConnectionProvider::default(),
+                )
+                .build()
+            }
+        };
+        Self::with_resolver(resolver, Config::default())
+    }
+}
+
+impl Discover for DnsDiscover {
+    type Key = FastStr;
+    type Error = LoadBalanceError;
+
+    async fn discover<'s>(
+        &'s self,
+        endpoint: &'s Endpoint,
+    ) -> Result<Vec<Arc<Instance>>, Self::Error> {
+        if endpoint.address().is_some() {
+            return Ok(Vec::new());
+        }
+        let key = endpoint.service_name();
+        if let Some(entry) = self.inner.cache.get(&key) {
+            if entry.expires_at > Instant::now() {
+                return Ok(entry.instances.clone());
+            }
+        }
+        let result = self.inner.resolve(&key).await;
+        self.inner.update(&key, result)
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        endpoint.service_name()
+    }
+
+    fn watch(&self, _keys: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+        self.inner.refreshing.call_once(|| self.spawn_refresher());
+        Some(self.inner.receiver.activate_cloned())
+    }
+}
+
+fn parse_host_and_port(name: &str, default_port: u16) -> Result<(&str, u16), LoadBalanceError> {
+    if name.is_empty() {
+        return Err(LoadBalanceError::Discover("missing service name".into()));
+    }
+    let parse_port = |port: &str| {
+        port.parse::<u16>()
+            .map_err(|_| LoadBalanceError::Discover(format!("invalid port in {name}").into()))
+    };
+    if let Some(rest) = name.strip_prefix('[') {
+        let Some((host, rest)) = rest.split_once(']') else {
+            return Err(LoadBalanceError::Discover(
+                format!("bad host {name}").into(),
+            ));
+        };
+        let port = match rest.strip_prefix(':') {
+            Some(port) => parse_port(port)?,
+            None => default_port,
+        };
+        return Ok((host, port));
+    }
+    match name.rsplit_once(':') {
+        Some((host, port)) => Ok((host, parse_port(port)?)),
+        None => Ok((name, default_port)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use faststr::FastStr;
+
+    use super::{DnsDiscover, parse_host_and_port};
+    use crate::{
+        context::Endpoint,
+        discovery::{Discover, Instance},
+        loadbalance::error::LoadBalanceError,
+        net::Address,
+    };
+
+    #[test]
+    fn test_parse_host_and_port() {
+        assert_eq!(
+            parse_host_and_port("example.com", 80).unwrap(),
+            ("example.com", 80)
+        );
+        assert_eq!(
+            parse_host_and_port("example.com:8080", 80).unwrap(),
+            ("example.com", 8080)
+        );
+        assert_eq!(
+            parse_host_and_port("[::1]:8080", 80).unwrap(),
+            ("::1", 8080)
+        );
+        assert_eq!(parse_host_and_port("[::1]", 443).unwrap(), ("::1", 443));
+        assert!(parse_host_and_port("example.com:port", 80).is_err());
+        assert!(parse_host_and_port("", 80).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dns_discover() {
+        let discover = DnsDiscover::default();
+        let endpoint = Endpoint::new("127.0.0.1:8080".into());
+        let instances = discover.discover(&endpoint).await.unwrap();
+        assert_eq!(instances.len(), 1);
+        assert_eq!(
+            instances[0].address,
+            Address::Ip("127.0.0.1:8080".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keep_last_instances() {
+        let discover = DnsDiscover::default().with_refresh_interval(Duration::from_secs(1));
+        let mut receiver = discover.watch(None).unwrap();
+        let key = FastStr::from_static_str("example.com");
+        let instance = |addr: &str| {
+            Arc::new(Instance {
+                address: Address::Ip(addr.parse().unwrap()),
+                weight: 10,
+                tags: Default::default(),
+            })
+        };
+        let error = || LoadBalanceError::Discover("timeout".into());
+        let valid_until = Instant::now() + Duration::from_secs(60);
+
+        assert!(discover.inner.update(&key, Err(error())).is_err());
+
+        let instances = vec![instance("10.0.0.1:80"), instance("10.0.0.2:80")];
+        assert_eq!(
+            discover
+                .inner
+                .update(&key, Ok((instances.clone(), valid_until)))
+                .unwrap(),
+            instances
+        );
+        let change = receiver.try_recv().unwrap();
+        assert_eq!(change.added.len(), 2);
+
+        assert_eq!(
+            discover.inner.update(&key, Err(error())).unwrap(),
+            instances
+        );
+        assert!(receiver.try_recv().is_err());
+
+        let instances = vec![instance("10.0.0.2:80"), instance("10.0.0.3:80")];
+        discover
+            .inner
+            .update(&key, Ok((instances, valid_until)))
+            .unwrap();
+        let change = receiver.try_recv().unwrap();
+        assert_eq!(change.added, vec![instance("10.0.0.3:80")]);
+        assert_eq!(change.removed, vec![instance("10.0.0.1:80")]);
+    }
+
+    #[test]
+    fn test_configure_cloned() {
+        let discover = DnsDiscover::default();
+        let _cloned = discover.clone();
+        let discover = discover
+            .with_refresh_interval(Duration::from_secs(10))
+            .with_srv(true)
+            .with_default_port(8080);
+        assert_eq!(
+            discover.inner.config.refresh_interval,
+            Duration::from_secs(10)
+        );
+        assert!(discover.inner.config.srv);
+        assert_eq!(discover.inner.config.default_port, 8080);
+    }
+
+    #[test]
+    fn test_expires_at() {
+        let discover = DnsDiscover::default().with_refresh_interval(Duration::from_secs(10));
+        let now = Instant::now();
+        // zero ttl
+        assert_eq!(
+            discover.inner.expires_at(now, now),
+            now + Duration::from_secs(1)
+        );
+        assert_eq!(
+            discover.inner.expires_at(now, now + Duration::from_secs(5)),
+            now + Duration::from_secs(5)
+        );
+        assert_eq!(
+            discover
+                .inner
+                .expires_at(now, now + Duration::from_secs(300)),
+            now + Duration::from_secs(10)
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//! A [`Discover`] backed by a YAML or JSON file.
+//!
+//! The file maps service names to their instances, for example:
+//!
+//! ```yaml
+//! echo:
+//!   - address: 127.0.0.1:8080
+//!     weight: 10
+//!     tags:
+//!       zone: a
+//!   - address: 127.0.0.2:8080
+//! ```
+//!
+//! The file is parsed as JSON if its extension is `.json`, otherwise as YAML. The weight is 1 if
+//! it is not set.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Once, Weak},
+    time::Duration,
+};
+
+use arc_swap::ArcSwap;
+use async_broadcast::{InactiveReceiver, Receiver, Sender};
+use faststr::FastStr;
+use serde::Deserialize;
+
+use super::{Change, Discover, Instance, diff_instances};
+use crate::{context::Endpoint, net::Address};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+const CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Debug, Deserialize)]
+struct InstanceConfig {
+    address: SocketAddr,
+    #[serde(default = "default_weight")]
+    weight: u32,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+type Services = HashMap<FastStr, Vec<Arc<Instance>>>;
+
+fn parse(path: &Path, content: &[u8]) -> io::Result<Services> {
+    let config: HashMap<String, Vec<InstanceConfig>> =
+        if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_slice(content).map_err(io::Error::other)?
+        } else {
+            serde_yaml::from_slice(content).map_err(io::Error::other)?
+        };
+    Ok(config
+        .into_iter()
+        .map(|(service, instances)| {
+            let instances = instances
+                .into_iter()
+                .map(|instance| {
+                    Arc::new(Instance {
+                        address: Address::Ip(instance.address),
+                        weight: instance.weight,
+                        tags: instance
+                            .tags
+                            .into_iter()
+                            .map(|(k, v)| (Cow::Owned(k), Cow::Owned(v)))
+                            .collect(),
+                    })
+                })
+                .collect();
+            (FastStr::new(service), instances)
+        })
+        .collect())
+}
+
+struct Inner {
+    path: PathBuf,
+    interval: Duration,
+    content: std::sync::Mutex<Vec<u8>>,
+    services: ArcSwap<Services>,
+    sender: Sender<Change<FastStr>>,
+    receiver: InactiveReceiver<Change<FastStr>>,
+    watching: Once,
+}
+
+impl Inner {
+    /// Reloads the file, and sends the changes of the services.
+    fn reload(&self) -> io::Result<()> {
+        let content = std::fs::read(&self.path)?;
+        {
+            let mut prev = self.content.lock().unwrap();
+            if *prev == content {
+                return Ok(());
+            }
+            *prev = content.clone();
+        }
+        let next = parse(&self.path, &content)?;
+        let prev = self.services.swap(Arc::new(next.clone()));
+
+        for (service, instances) in next.iter() {
+            let prev = prev.get(service).cloned().unwrap_or_default();
+            let (change, changed) = diff_instances(service.clone(), prev, instances.clone());
+            if changed {
+                let _ = self.sender.try_broadcast(change);
+            }
+        }
+        for (service, instances) in prev.iter() {
+            if !next.contains_key(service) {
+                let (change, _) = diff_instances(service.clone(), instances.clone(), Vec::new());
+                let _ = self.sender.try_broadcast(change);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// [`FileDiscover`] loads the instances of the services from a YAML or JSON file, and watches
+/// the changes of the file.
+///
+/// The file is polled after [`Discover::watch`] is called, and the changes of the instances,
+/// including their weights and tags, are sent to the receivers.
+#[derive(Clone)]
+pub struct FileDiscover {
+    inner: Arc<Inner>,
+}
+
+impl FileDiscover {
+    /// Creates a [`FileDiscover`] by loading the file.
+    pub fn new(path: impl Into<PathBuf>) -> io::Result<Self> {
+        Self::with_interval(path, DEFAULT_INTERVAL)
+    }
+
+    /// Creates a [`FileDiscover`] which polls the file every `interval`, the default is 1 second.
+    pub fn with_interval(path: impl Into<PathBuf>, interval: Duration) -> io::Result<Self> {
+        let path = path.into();
+        let content = std::fs::read(&path)?;
+        let services = parse(&path, &content)?;
+        let (mut sender, receiver) = async_broadcast::broadcast(CHANNEL_CAPACITY);
+        sender.set_overflow(true);
+        Ok(Self {
+            inner: Arc::new(Inner {
+                path,
+                interval,
+                content: std::sync::Mutex::new(content),
+                services: ArcSwap::from_pointee(services),
+                sender,
+                receiver: receiver.deactivate(),
+                watching: Once::new(),
+            }),
+        })
+    }
+
+    /// Reloads the file immediately.
+    pub fn reload(&self) -> io::Result<()> {
+        self.inner.reload()
+    }
+
+    fn spawn_watcher(&self) {
+        let inner: Weak<Inner> = Arc::downgrade(&self.inner);
+        let interval = self.inner.interval;
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(inner) = inner.upgrade() else {
+                    break;
+                };
+                if let Err(err) = inner.reload() {
+                    tracing::warn!(
+                        "[VOLO] failed to reload file discover {}: {err}",
+                        inner.path.display()
+                    );
+                }
+            }
+        });
+    }
+}
+
+impl Discover for FileDiscover {
+    type Key = FastStr;
+    type Error = std::convert::Infallible;
+
+    async fn discover<'s>(
+        &'s self,
+        endpoint: &'s Endpoint,
+    ) -> Result<Vec<Arc<Instance>>, Self::Error> {
+        Ok(self
+            .inner
+            .services
+            .load()
+            .get(endpoint.service_name_ref())
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        endpoint.service_name()
+    }
+
+    fn watch(&self, _keys: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+        self.inner.watching.call_once(|| self.spawn_watcher());
+        Some(self.inner.receiver.activate_cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::FileDiscover;
+    use crate::{context::Endpoint, discovery::Discover, net::Address};
+
+    #[tokio::test]
+    async fn test_file_discover() {
+        let path =
+            std::env::temp_dir().join(format!("volo-file-discover-{}.yaml", std::process::id()));
+        std::fs::write(
+            &path,
+            "echo:\n  - address: 127.0.0.1:8000\n    weight: 10\n    tags:\n      zone: a\n  - \
+             address: 127.0.0.2:8000\n",
+        )
+        .unwrap();
+
+        let discover = FileDiscover::with_interval(&path, Duration::from_millis(10)).unwrap();
+        let endpoint = Endpoint::new("echo".into());
+        let instances = discover.discover(&endpoint).await.unwrap();
+        assert_eq!(instances.len(), 2);
+        let first = instances
+            .iter()
+            .find(|i| i.address == Address::Ip("127.0.0.1:8000".parse().unwrap()))
+            .unwrap();
+        assert_eq!(first.weight, 10);
+        assert_eq!(first.tags.get("zone").map(|v| v.as_ref()), Some("a"));
+        assert!(
+            discover
+                .discover(&Endpoint::new("unknown".into()))
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        let mut receiver = discover.watch(None).unwrap();
+        std::fs::write(
+            &path,
+            "echo:\n  - address: 127.0.0.1:8000\n    weight: 20\n  - address: 127.0.0.3:8000\n",
+        )
+        .unwrap();
+        let change = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(change.key, "echo");
+        assert_eq!(change.all.len(), 2);
+        assert_eq!(change.added.len(), 1);
+        assert_eq!(change.updated.len(), 1);
+        assert_eq!(change.updated[0].weight, 20);
+        assert_eq!(change.removed.len(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -17,6 +17,19 @@ use async_broadcast::Receiver;
 
 use crate::{context::Endpoint, loadbalance::error::LoadBalanceError, net::Address};
 
+mod combinator;
+#[cfg(feature = "dns-discover")]
+#[cfg_attr(docsrs, doc(cfg(feature = "dns-discover")))]
+mod dns;
+#[cfg(feature = "file-discover")]
+#[cfg_attr(docsrs, doc(cfg(feature = "file-discover")))]
+mod file;
+pub use self::combinator::{CachedDiscover, FallbackDiscover, MergedDiscover};
+#[cfg(feature = "dns-discover")]
+pub use self::dns::DnsDiscover;
+#[cfg(feature = "file-discover")]
+pub use self::file::FileDiscover;
+
 /// [`Instance`] contains information of an instance from the target service.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Instance {
@@ -42,6 +55,14 @@ pub trait Discover: Send + Sync + 'static {
     /// `watch` should return a [`async_broadcast::Receiver`] which can be used to subscribe
     /// [`Change`].
     fn watch(&self, keys: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>>;
+    /// `watch_evicted` should return a [`async_broadcast::Receiver`] of the keys which are not
+    /// used anymore, so that the load balancer drops their cached instances by
+    /// [`LoadBalance::evict`](crate::loadbalance::LoadBalance::evict).
+    ///
+    /// No key is evicted by default.
+    fn watch_evicted(&self) -> Option<Receiver<Self::Key>> {
+        None
+    }
 }
 
 /// Change indicates the change of the service discover.
@@ -120,6 +141,54 @@ where
     )
 }
 
+/// Get the difference of two instance lists.
+///
+/// Unlike [`diff_address`], [`diff_instances`] also compares the weight and tags of the instances
+/// with the same address, and puts the instances whose weight or tags changed into `updated`.
+///
+/// The bool in the return value has the same meaning as [`diff_address`].
+pub fn diff_instances<K>(
+    key: K,
+    prev: Vec<Arc<Instance>>,
+    next: Vec<Arc<Instance>>,
+) -> (Change<K>, bool)
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    let mut added = Vec::new();
+    let mut updated = Vec::new();
+    let mut removed = Vec::new();
+
+    let prev_map: HashMap<_, _> = prev.iter().map(|i| (&i.address, i)).collect();
+    let next_set: HashSet<_> = next.iter().map(|i| &i.address).collect();
+
+    for i in &next {
+        match prev_map.get(&i.address) {
+            None => added.push(i.clone()),
+            Some(p) if p.weight != i.weight || p.tags != i.tags => updated.push(i.clone()),
+            Some(_) => {}
+        }
+    }
+    for i in &prev {
+        if !next_set.contains(&i.address) {
+            removed.push(i.clone());
+        }
+    }
+
+    let changed = !added.is_empty() || !updated.is_empty() || !removed.is_empty();
+
+    (
+        Change {
+            key,
+            all: next,
+            added,
+            updated,
+            removed,
+        },
+        changed,
+    )
+}
+
 /// [`StaticDiscover`] is a simple implementation of [`Discover`] that returns a static list of
 /// instances.
 #[derive(Clone)]
@@ -241,7 +310,7 @@ impl From<Infallible> for LoadBalanceError {
 mod tests {
     use std::sync::Arc;
 
-    use super::{Discover, Instance, StaticDiscover, WeightedStaticDiscover};
+    use super::{Discover, Instance, StaticDiscover, WeightedStaticDiscover, diff_instances};
     use crate::{context::Endpoint, net::Address};
 
     #[test]
@@ -295,4 +364,35 @@ mod tests {
         ];
         assert_eq!(resp, expected);
     }
+
+    #[test]
+    fn test_diff_instances() {
+        let instance = |addr: &str, weight| {
+            Arc::new(Instance {
+                address: Address::Ip(addr.parse().unwrap()),
+                weight,
+                tags: Default::default(),
+            })
+        };
+        let prev = vec![
+            instance("127.0.0.1:8000", 1),
+            instance("127.0.0.2:8000", 1),
+            instance("127.0.0.3:8000", 1),
+        ];
+
+        let (_, changed) = diff_instances("key", prev.clone(), prev.clone());
+        assert!(!changed);
+
+        let next = vec![
+            instance("127.0.0.1:8000", 1),
+            instance("127.0.0.2:8000", 2),
+            instance("127.0.0.4:8000", 1),
+        ];
+        let (change, changed) = diff_instances("key", prev, next);
+        assert!(changed);
+        assert_eq!(change.all.len(), 3);
+        assert_eq!(change.added, vec![instance("127.0.0.4:8000", 1)]);
+        assert_eq!(change.updated, vec![instance("127.0.0.2:8000", 2)]);
+        assert_eq!(change.removed, vec![instance("127.0.0.3:8000", 1)]);
+    }
 }
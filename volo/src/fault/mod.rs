@@ -0,0 +1,370 @@
+//! A fault injection layer for chaos testing, which works for both clients and servers.
+//!
+//! A [`FaultRule`] matches the requests by the callee service, the method and the caller tags,
+//! and injects a delay and/or an abort at the configured percentages. The first matching rule
+//! takes effect. The rules are held by a [`FaultInjector`] and can be updated at runtime.
+//!
+//! The aborts fail with [`FaultAbort`], which can be converted into the protocol errors:
+//!
+//! - thrift: an `ApplicationException` with [`FaultAbort::code`] as its type.
+//! - gRPC: a `Status` with [`FaultAbort::code`] as its code.
+//! - HTTP: a response with [`FaultAbort::code`] as its status code on the server side, or a
+//!   request error on the client side.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let injector = FaultInjector::new(vec![
+//!     FaultRule::new()
+//!         .service("payment")
+//!         .method("Pay")
+//!         .caller_tag(ValueSource::MetaInfo("env".into()), "chaos")
+//!         .delay(FaultDelay::Random(Duration::from_millis(100), Duration::from_secs(1)), 50.0)
+//!         .abort(FaultAbort::new(14, "injected fault"), 10.0),
+//! ]);
+//!
+//! let client = ClientBuilder::new("service")
+//!     .layer_outer(FaultInjectionLayer::new(injector.clone()))
+//!     .build();
+//!
+//! // disable all the faults
+//! injector.update(Vec::new());
+//! ```
+
+use std::{future::Future, sync::Arc, time::Duration};
+
+use arc_swap::ArcSwap;
+use faststr::FastStr;
+use motore::Service;
+use rand::Rng;
+
+use crate::{context::Context, loadbalance::subset::ValueSource};
+
+/// The error of an injected abort.
+///
+/// The meaning of `code` depends on the protocol: the type of `ApplicationException` for thrift,
+/// the status code for gRPC and HTTP.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("fault injected: [{code}] {message}")]
+pub struct FaultAbort {
+    pub code: u32,
+    pub message: FastStr,
+}
+
+impl FaultAbort {
+    pub fn new(code: u32, message: impl Into<FastStr>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// The delay to inject.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultDelay {
+    /// Delays for the duration.
+    Fixed(Duration),
+    /// Delays for a random duration in `[min, max]`.
+    Random(Duration, Duration),
+}
+
+impl FaultDelay {
+    fn duration(&self) -> Duration {
+        match *self {
+            Self::Fixed(d) => d,
+            Self::Random(min, max) if min < max => rand::rng().random_range(min..=max),
+            Self::Random(min, _) => min,
+        }
+    }
+}
+
+/// A rule of fault injection.
+///
+/// The unset conditions match all the requests, and the percentages are in `[0, 100]`.
+#[derive(Clone, Debug, Default)]
+pub struct FaultRule {
+    service: Option<FastStr>,
+    method: Option<FastStr>,
+    caller_tags: Vec<(ValueSource, FastStr)>,
+    delay: Option<(FaultDelay, f64)>,
+    abort: Option<(FaultAbort, f64)>,
+}
+
+impl FaultRule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Matches the requests to the callee service.
+    pub fn service(mut self, service: impl Into<FastStr>) -> Self {
+        self.service = Some(service.into());
+        self
+    }
+
+    /// Matches the requests of the method.
+    pub fn method(mut self, method: impl Into<FastStr>) -> Self {
+        self.method = Some(method.into());
+        self
+    }
+
+    /// Matches the requests whose value from `source` equals to `value`, e.g., a tag of the
+    /// caller or a metainfo. All the tags must match.
+    pub fn caller_tag(mut self, source: ValueSource, value: impl Into<FastStr>) -> Self {
+        self.caller_tags.push((source, value.into()));
+        self
+    }
+
+    /// Injects the delay to `percentage` percent of the matched requests.
+    pub fn delay(mut self, delay: FaultDelay, percentage: f64) -> Self {
+        self.delay = Some((delay, percentage));
+        self
+    }
+
+    /// Aborts `percentage` percent of the matched requests with the error.
+    pub fn abort(mut self, abort: FaultAbort, percentage: f64) -> Self {
+        self.abort = Some((abort, percentage));
+        self
+    }
+
+    fn matches<Cx: Context>(&self, cx: &Cx) -> bool {
+        let rpc_info = cx.rpc_info();
+        if let Some(service) = &self.service {
+            if rpc_info.callee().service_name_ref() != service.as_str() {
+                return false;
+            }
+        }
+        if let Some(method) = &self.method {
+            if rpc_info.method() != method {
+                return false;
+            }
+        }
+        self.caller_tags
+            .iter()
+            .all(|(source, value)| source.value(cx).as_ref() == Some(value))
+    }
+}
+
+fn hit(percentage: f64) -> bool {
+    percentage >= 100.0 || (percentage > 0.0 && rand::random::<f64>() * 100.0 < percentage)
+}
+
+/// The rules of fault injection, which can be shared and updated at runtime.
+#[derive(Clone, Debug, Default)]
+pub struct FaultInjector {
+    rules: Arc<ArcSwap<Vec<FaultRule>>>,
+}
+
+impl FaultInjector {
+    pub fn new(rules: Vec<FaultRule>) -> Self {
+        Self {
+            rules: Arc::new(ArcSwap::from_pointee(rules)),
+        }
+    }
+
+    /// Replaces all the rules, which takes effect for the following requests.
+    pub fn update(&self, rules: Vec<FaultRule>) {
+        self.rules.store(Arc::new(rules));
+    }
+
+    /// Returns the current rules.
+    pub fn rules(&self) -> Arc<Vec<FaultRule>> {
+        self.rules.load_full()
+    }
+
+    /// Injects the faults of the first rule matching the request.
+    ///
+    /// The faults are decided when this method is called, and the returned future sleeps for
+    /// the delay before returning the abort.
+    pub fn inject<Cx: Context>(
+        &self,
+        cx: &Cx,
+    ) -> impl Future<Output = Result<(), FaultAbort>> + Send + 'static {
+        let rules = self.rules.load();
+        let (delay, abort) = match rules.iter().find(|rule| rule.matches(cx)) {
+            Some(rule) => (
+                rule.delay
+                    .as_ref()
+                    .filter(|(_, percentage)| hit(*percentage))
+                    .map(|(delay, _)| delay.duration()),
+                rule.abort
+                    .as_ref()
+                    .filter(|(_, percentage)| hit(*percentage))
+                    .map(|(abort, _)| abort.clone()),
+            ),
+            None => (None, None),
+        };
+        async move {
+            if let Some(delay) = delay {
+                tokio::time::sleep(delay).await;
+            }
+            match abort {
+                Some(abort) => Err(abort),
+                None => Ok(()),
+            }
+        }
+    }
+}
+
+/// A [`Layer`](motore::layer::Layer) that injects the faults of the [`FaultInjector`].
+#[derive(Clone, Debug)]
+pub struct FaultInjectionLayer {
+    injector: FaultInjector,
+}
+
+impl FaultInjectionLayer {
+    pub fn new(injector: FaultInjector) -> Self {
+        Self { injector }
+    }
+}
+
+impl<S> motore::layer::Layer<S> for FaultInjectionLayer {
+    type Service = FaultInjectionService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        FaultInjectionService {
+            inner,
+            injector: self.injector,
+        }
+    }
+}
+
+/// The [`Service`] generated by [`FaultInjectionLayer`].
+#[derive(Clone, Debug)]
+pub struct FaultInjectionService<S> {
+    inner: S,
+    injector: FaultInjector,
+}
+
+impl<Cx, Req, S> Service<Cx, Req> for FaultInjectionService<S>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + Sync + 'static,
+    FaultAbort: Into<S::Error>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+
+    async fn call(&self, cx: &mut Cx, req: Req) -> Result<Self::Response, Self::Error> {
+        self.injector.inject(cx).await.map_err(Into::into)?;
+        self.inner.call(cx, req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use motore::{Service, layer::Layer, service::service_fn};
+
+    use super::{FaultAbort, FaultDelay, FaultInjectionLayer, FaultInjector, FaultRule};
+    use crate::{
+        context::{Endpoint, Reusable, Role, RpcCx, RpcInfo},
+        loadbalance::subset::ValueSource,
+    };
+
+    #[derive(Debug, Default)]
+    struct TestConfig;
+
+    impl Reusable for TestConfig {
+        fn clear(&mut self) {}
+    }
+
+    type TestContext = RpcCx<(), TestConfig>;
+
+    struct Zone;
+
+    fn new_cx(method: &'static str, zone: &'static str) -> TestContext {
+        let mut caller = Endpoint::new("caller".into());
+        caller.insert_faststr::<Zone>(zone.into());
+        RpcCx::new(
+            RpcInfo::new(
+                Role::Client,
+                method.into(),
+                caller,
+                Endpoint::new("callee".into()),
+                TestConfig,
+            ),
+            (),
+        )
+    }
+
+    async fn handle(_: &mut TestContext, _: ()) -> Result<(), FaultAbort> {
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fault_injection() {
+        let injector = FaultInjector::new(vec![
+            FaultRule::new()
+                .service("callee")
+                .method("abort")
+                .caller_tag(
+                    ValueSource::Caller(|caller| caller.get_faststr::<Zone>().cloned()),
+                    "zone-a",
+                )
+                .abort(FaultAbort::new(503, "unavailable"), 100.0),
+            FaultRule::new()
+                .method("delay")
+                .delay(FaultDelay::Fixed(Duration::from_millis(50)), 100.0),
+        ]);
+        let service = FaultInjectionLayer::new(injector.clone()).layer(service_fn(handle));
+
+        let err = service
+            .call(&mut new_cx("abort", "zone-a"), ())
+            .await
+            .unwrap_err();
+        assert_eq!(err, FaultAbort::new(503, "unavailable"));
+        // the caller tag does not match
+        service
+            .call(&mut new_cx("abort", "zone-b"), ())
+            .await
+            .unwrap();
+
+        let start = Instant::now();
+        service
+            .call(&mut new_cx("delay", "zone-a"), ())
+            .await
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // the rules are updated at runtime
+        injector.update(vec![
+            FaultRule::new().abort(FaultAbort::new(1, "all"), 100.0),
+        ]);
+        assert!(
+            service
+                .call(&mut new_cx("any", "zone-a"), ())
+                .await
+                .is_err()
+        );
+        injector.update(Vec::new());
+        service
+            .call(&mut new_cx("abort", "zone-a"), ())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fault_percentage() {
+        let injector = FaultInjector::new(vec![
+            FaultRule::new().abort(FaultAbort::new(1, "abort"), 30.0),
+        ]);
+        let cx = new_cx("method", "zone-a");
+        let mut aborted = 0;
+        for _ in 0..1000 {
+            if injector.inject(&cx).await.is_err() {
+                aborted += 1;
+            }
+        }
+        assert!((200..400).contains(&aborted), "aborted: {aborted}");
+
+        injector.update(vec![
+            FaultRule::new().abort(FaultAbort::new(1, "abort"), 0.0),
+        ]);
+        for _ in 0..100 {
+            assert!(injector.inject(&cx).await.is_ok());
+        }
+    }
+}
@@ -87,7 +87,8 @@ impl TryFrom<u8> for HotRestartMsgType {
 enum HotRestartMessage {
     TerminateParentRequest,
     PassFdRequest(String),
-    PassFdResponse(RawFd),
+    /// The fd of the listener, or `None` if the parent does not have the listener.
+    PassFdResponse(Option<RawFd>),
 }
 
 pub struct HotRestart {
@@ -194,15 +195,16 @@ impl HotRestart {
             parent_sock.readable().await?;
             match Self::recv_msg(&parent_sock) {
                 Ok(HotRestartMessage::PassFdRequest(addr)) => {
-                    if let Some(fd) = fds.lock().unwrap().get(&addr) {
-                        tracing::info!("hot_restart parent passfd: {}, addr: {}", fd, addr);
-                        Self::send_msg(
-                            &parent_sock,
-                            child_sock_path.as_path(),
-                            HotRestartMsgType::PassFdResponse,
-                            HotRestartMessage::PassFdResponse(*fd),
-                        )?;
-                    }
+                    let fd = fds.lock().unwrap().get(&addr).copied();
+                    tracing::info!("hot_restart parent passfd: {:?}, addr: {}", fd, addr);
+                    // respond even if the listener does not exist, e.g., the child binds more
+                    // listeners than the parent, so that the child does not wait forever
+                    Self::send_msg(
+                        &parent_sock,
+                        child_sock_path.as_path(),
+                        HotRestartMsgType::PassFdResponse,
+                        HotRestartMessage::PassFdResponse(fd),
+                    )?;
                 }
                 Ok(HotRestartMessage::TerminateParentRequest) => {
                     tracing::info!("hot_restart parent terminate");
@@ -263,14 +265,7 @@ impl HotRestart {
                                     break;
                                 }
                             }
-                            if let Some(fd) = raw_fd {
-                                Ok(HotRestartMessage::PassFdResponse(fd))
-                            } else {
-                                Err(io::Error::new(
-                                    io::ErrorKind::InvalidData,
-                                    "PassFdResponse without fd",
-                                ))
-                            }
+                            Ok(HotRestartMessage::PassFdResponse(raw_fd))
                         }
                         HotRestartMsgType::TerminateParentRequest => {
                             Ok(HotRestartMessage::TerminateParentRequest)
@@ -306,8 +301,11 @@ impl HotRestart {
             HotRestartMsgType::PassFdResponse => {
                 sbuf.push(msg_type as u8);
                 if let HotRestartMessage::PassFdResponse(fd) = body {
-                    fds.push(fd);
-                    cmsg.push(ControlMessage::ScmRights(&fds));
+                    // the message without fd means the listener does not exist
+                    fds.extend(fd);
+                    if !fds.is_empty() {
+                        cmsg.push(ControlMessage::ScmRights(&fds));
+                    }
                 } else {
                     return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid data"));
                 }
@@ -404,7 +402,7 @@ impl HotRestart {
                         Ok::<(), io::Error>(())
                     });
                 }
-                Ok(Some(fd))
+                Ok(fd)
             }
             Ok(_) => Err(io::Error::new(
                 io::ErrorKind::InvalidData,
@@ -8,10 +8,15 @@ pub use motore::{Service, layer, layer::Layer, service};
 pub use tokio::main;
 
 pub mod catch_panic;
+pub mod circuit_breaker;
 pub mod context;
 pub mod discovery;
+pub mod fault;
 pub mod loadbalance;
 pub mod net;
+pub mod rate_limit;
+pub mod registry;
+pub mod retry;
 pub mod util;
 pub use hack::Unwrap;
 #[cfg(target_family = "unix")]
@@ -0,0 +1,357 @@
+//! Consistent hashing with bounded loads, described in the paper
+//! [Consistent Hashing with Bounded Loads](https://arxiv.org/abs/1608.01350).
+//!
+//! The instances are placed on the same ring as
+//! [`ConsistentHashBalance`](super::consistent_hash::ConsistentHashBalance), but an instance whose
+//! in-flight requests exceed `(1 + ε)` times the average is skipped, and the request spills to
+//! the next instance on the ring, so that a hot key cannot overload a single instance.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use dashmap::{DashMap, mapref::entry::Entry};
+
+use super::{
+    CallFeedback, LoadBalance,
+    consistent_hash::{self, ConsistentHashOption, WeightedInstances},
+    current_request_hash,
+    error::LoadBalanceError,
+};
+use crate::{
+    context::Endpoint,
+    discovery::{Change, Discover, Instance},
+    net::Address,
+};
+
+const DEFAULT_BALANCE_FACTOR: f64 = 0.25;
+
+#[derive(Debug)]
+struct BoundedInstances {
+    ring: Arc<WeightedInstances>,
+    /// The index of each instance in `loads` and `weights`.
+    index: HashMap<Address, usize>,
+    loads: Vec<Arc<AtomicUsize>>,
+    weights: Vec<u32>,
+    sum_of_weights: u64,
+}
+
+impl BoundedInstances {
+    /// Returns whether the instance can take one more request without exceeding its capacity,
+    /// which is `(1 + ε)` times its share of the total in-flight requests.
+    fn has_capacity(&self, address: &Address, total: usize, balance_factor: f64) -> bool {
+        let Some(&i) = self.index.get(address) else {
+            return false;
+        };
+        let share = self.weights[i] as f64 / self.sum_of_weights as f64;
+        let capacity = ((1.0 + balance_factor) * (total + 1) as f64 * share).ceil() as usize;
+        self.loads[i].load(Ordering::Relaxed) < capacity
+    }
+}
+
+#[derive(Debug)]
+pub struct InstancePicker {
+    instances: Arc<BoundedInstances>,
+
+    /// All the instances in the order of the ring.
+    ring: consistent_hash::InstancePicker,
+
+    /// The total in-flight requests of the instances when the picker is created.
+    total: usize,
+
+    balance_factor: f64,
+
+    /// The overloaded instances which are skipped, they are picked at last in the order of the
+    /// ring.
+    overloaded: Vec<Address>,
+
+    /// The number of replicas left to pick.
+    replicas: usize,
+}
+
+impl Iterator for InstancePicker {
+    type Item = Address;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.replicas == 0 {
+            return None;
+        }
+        for addr in self.ring.by_ref() {
+            if self
+                .instances
+                .has_capacity(&addr, self.total, self.balance_factor)
+            {
+                self.replicas -= 1;
+                return Some(addr);
+            }
+            self.overloaded.push(addr);
+        }
+        if self.overloaded.is_empty() {
+            return None;
+        }
+        self.replicas -= 1;
+        Some(self.overloaded.remove(0))
+    }
+}
+
+/// [`BoundedLoadBalance`] is the consistent hashing load balancer with bounded loads.
+///
+/// The in-flight requests of the instances are collected by [`LoadBalance::on_call_start`] and
+/// [`LoadBalance::on_call_end`], so it only works with the load balance layers which report the
+/// calls. If [`ConsistentHashOption`] is weighted, the capacity of each instance is in
+/// proportion to its weight.
+#[derive(Debug)]
+pub struct BoundedLoadBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    option: ConsistentHashOption,
+    balance_factor: f64,
+    router: DashMap<K, Arc<BoundedInstances>>,
+    loads: DashMap<Address, Arc<AtomicUsize>>,
+}
+
+impl<K> BoundedLoadBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    pub fn with_discover<D>(&mut self, _: &D) -> &mut Self
+    where
+        D: Discover<Key = K>,
+    {
+        self
+    }
+
+    pub fn new(option: ConsistentHashOption) -> Self {
+        Self {
+            option,
+            balance_factor: DEFAULT_BALANCE_FACTOR,
+            router: DashMap::new(),
+            loads: DashMap::new(),
+        }
+    }
+
+    /// Sets the ε of the bound, an instance is skipped if its in-flight requests exceed
+    /// `(1 + ε)` times the average, the default is 0.25.
+    ///
+    /// The smaller the value, the more balanced the load, and the more requests are moved away
+    /// from their hashed instances.
+    pub fn with_balance_factor(mut self, balance_factor: f64) -> Self {
+        self.balance_factor = balance_factor;
+        self
+    }
+
+    fn build_instances(&self, instances: Vec<Arc<Instance>>) -> BoundedInstances {
+        let ring = WeightedInstances::new(&self.option, instances);
+        let mut index = HashMap::with_capacity(ring.real_nodes.len());
+        let mut loads = Vec::with_capacity(ring.real_nodes.len());
+        let mut weights = Vec::with_capacity(ring.real_nodes.len());
+        for (i, node) in ring.real_nodes.iter().enumerate() {
+            index.insert(node.0.address.clone(), i);
+            loads.push(
+                self.loads
+                    .entry(node.0.address.clone())
+                    .or_default()
+                    .clone(),
+            );
+            weights.push(if self.option.weighted {
+                node.0.weight
+            } else {
+                1
+            });
+        }
+        let sum_of_weights = weights.iter().map(|w| *w as u64).sum();
+        BoundedInstances {
+            ring: Arc::new(ring),
+            index,
+            loads,
+            weights,
+            sum_of_weights,
+        }
+    }
+}
+
+impl<D> LoadBalance<D> for BoundedLoadBalance<D::Key>
+where
+    D: Discover,
+{
+    type InstanceIter = InstancePicker;
+
+    async fn get_picker<'future>(
+        &'future self,
+        endpoint: &'future Endpoint,
+        discover: &'future D,
+    ) -> Result<Self::InstanceIter, LoadBalanceError> {
+        let request_hash = current_request_hash(endpoint)?;
+        let key = discover.key(endpoint);
+        let instances = if let Some(instances) = self.router.get(&key) {
+            instances.clone()
+        } else {
+            let instances = Arc::new(
+                self.build_instances(
+                    discover
+                        .discover(endpoint)
+                        .await
+                        .map_err(|err| err.into())?,
+                ),
+            );
+            self.router.insert(key, instances.clone());
+            instances
+        };
+        let total = instances
+            .loads
+            .iter()
+            .map(|load| load.load(Ordering::Relaxed))
+            .sum();
+        let nodes = instances.ring.real_nodes.len();
+        Ok(InstancePicker {
+            ring: consistent_hash::InstancePicker::new(instances.ring.clone(), request_hash, nodes),
+            instances,
+            total,
+            balance_factor: self.balance_factor,
+            overloaded: Vec::new(),
+            replicas: self.option.replicas.min(nodes),
+        })
+    }
+
+    fn rebalance(&self, changes: Change<D::Key>) {
+        for instance in changes.removed.iter() {
+            self.loads.remove(&instance.address);
+        }
+        if let Entry::Occupied(entry) = self.router.entry(changes.key.clone()) {
+            entry.replace_entry(Arc::new(self.build_instances(changes.all)));
+        }
+    }
+
+    fn evict(&self, key: &D::Key) {
+        self.router.remove(key);
+    }
+
+    fn on_call_start(&self, address: &Address) {
+        self.loads
+            .entry(address.clone())
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_call_end(&self, address: &Address, _feedback: CallFeedback) {
+        if let Some(load) = self.loads.get(address) {
+            let _ = load.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::HashMap, time::Duration};
+
+    use metainfo::{METAINFO, MetaInfo};
+
+    use super::{BoundedLoadBalance, LoadBalance};
+    use crate::{
+        context::Endpoint,
+        discovery::StaticDiscover,
+        loadbalance::{
+            CallFeedback, CallOutcome, RequestHash, consistent_hash::ConsistentHashOption,
+        },
+        net::Address,
+    };
+
+    fn set_request_hash(code: u64) {
+        METAINFO
+            .try_with(|m| m.borrow_mut().insert(RequestHash(code)))
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bounded_load_balance() {
+        METAINFO
+            .scope(RefCell::new(MetaInfo::new()), async {
+                let empty = Endpoint::new("".into());
+                let discover = StaticDiscover::from(vec![
+                    "127.0.0.1:8000".parse().unwrap(),
+                    "127.0.0.2:8000".parse().unwrap(),
+                    "127.0.0.3:8000".parse().unwrap(),
+                    "127.0.0.4:8000".parse().unwrap(),
+                ]);
+                let lb = BoundedLoadBalance::new(ConsistentHashOption::new(2, 10, true));
+
+                // the same hot key without finishing the calls
+                set_request_hash(42);
+                let all = lb
+                    .get_picker(&empty, &discover)
+                    .await
+                    .unwrap()
+                    .collect::<Vec<_>>();
+                assert_eq!(all.len(), 2);
+                assert_ne!(all[0], all[1]);
+                let hashed = all[0].clone();
+
+                let mut counts: HashMap<Address, usize> = HashMap::new();
+                for _ in 0..100 {
+                    let mut picker = lb.get_picker(&empty, &discover).await.unwrap();
+                    let addr = picker.next().unwrap();
+                    LoadBalance::<StaticDiscover>::on_call_start(&lb, &addr);
+                    *counts.entry(addr).or_default() += 1;
+                }
+                // the load of each instance is bounded by (1 + 0.25) * average
+                assert_eq!(counts.len(), 4);
+                for count in counts.values() {
+                    assert!(*count <= 32, "counts: {counts:?}");
+                }
+
+                // the hot key goes back to its instance once the load is released
+                for (addr, count) in counts {
+                    for _ in 0..count {
+                        LoadBalance::<StaticDiscover>::on_call_end(
+                            &lb,
+                            &addr,
+                            CallFeedback {
+                                outcome: CallOutcome::Success,
+                                elapsed: Duration::from_millis(1),
+                            },
+                        );
+                    }
+                }
+                let mut picker = lb.get_picker(&empty, &discover).await.unwrap();
+                assert_eq!(picker.next(), Some(hashed));
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_bounded_load_overloaded_replicas() {
+        METAINFO
+            .scope(RefCell::new(MetaInfo::new()), async {
+                let empty = Endpoint::new("".into());
+                let discover = StaticDiscover::from(vec![
+                    "127.0.0.1:8000".parse().unwrap(),
+                    "127.0.0.2:8000".parse().unwrap(),
+                ]);
+                let lb = BoundedLoadBalance::new(ConsistentHashOption::new(3, 10, true))
+                    .with_balance_factor(0.0);
+                set_request_hash(7);
+                let all = lb
+                    .get_picker(&empty, &discover)
+                    .await
+                    .unwrap()
+                    .collect::<Vec<_>>();
+                assert_eq!(all.len(), 2);
+
+                // the overloaded instance is still picked as the last replica
+                LoadBalance::<StaticDiscover>::on_call_start(&lb, &all[0]);
+                let spilled = lb
+                    .get_picker(&empty, &discover)
+                    .await
+                    .unwrap()
+                    .collect::<Vec<_>>();
+                assert_eq!(spilled, vec![all[1].clone(), all[0].clone()]);
+            })
+            .await;
+    }
+}
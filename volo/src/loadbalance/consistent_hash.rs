@@ -2,7 +2,7 @@ use std::{cmp::min, collections::HashSet, hash::Hash, sync::Arc};
 
 use dashmap::{DashMap, mapref::entry::Entry};
 
-use super::{LoadBalance, RequestHash, error::LoadBalanceError};
+use super::{LoadBalance, RequestHash, current_request_hash, error::LoadBalanceError};
 use crate::{
     context::Endpoint,
     discovery::{Change, Discover, Instance},
@@ -14,14 +14,14 @@ pub struct ConsistentHashOption {
     /// If it is set to a value greater than 1, replicas will be used when connect to the primary
     /// node fails. This brings extra mem and cpu cost.
     /// If it is set to 1, error will be returned immediately when connect fails.
-    replicas: usize,
+    pub(super) replicas: usize,
 
     /// The number of virtual nodes corresponding to each real node
     /// The larger the value, the higher the memory and computational cost, and the more balanced
     /// the load When the number of nodes is large, it can be set smaller; conversely, it can be
     /// set larger The median VirtualFactor * Weight (if Weighted is true) is recommended to be
     /// around 1000 The recommended total number of virtual nodes is within 2000W
-    virtual_factor: u32,
+    pub(super) virtual_factor: u32,
 
     /// Whether to follow Weight for load balancing
     /// If false, Weight is ignored for each instance, and VirtualFactor virtual nodes are
@@ -29,7 +29,7 @@ pub struct ConsistentHashOption {
     /// nodes are generated for each instance Note that for instance with weight 0, no virtual
     /// nodes will be generated regardless of the VirtualFactor number It is recommended to set
     /// it to true, but be careful to reduce the VirtualFactor appropriately
-    weighted: bool,
+    pub(super) weighted: bool,
 }
 
 impl ConsistentHashOption {
@@ -54,7 +54,7 @@ impl Default for ConsistentHashOption {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 /// RealNode is a wrapper of Instance
-struct RealNode(Instance);
+pub(super) struct RealNode(pub(super) Instance);
 
 impl From<Instance> for RealNode {
     fn from(instance: Instance) -> Self {
@@ -83,11 +83,69 @@ impl Ord for VirtualNode {
 }
 
 #[derive(Debug, Clone)]
-struct WeightedInstances {
-    real_nodes: Vec<Arc<RealNode>>,
+pub(super) struct WeightedInstances {
+    pub(super) real_nodes: Vec<Arc<RealNode>>,
     virtual_nodes: Vec<VirtualNode>,
 }
 
+impl WeightedInstances {
+    pub(super) fn new(option: &ConsistentHashOption, instances: Vec<Arc<Instance>>) -> Self {
+        let mut real_nodes = Vec::with_capacity(instances.len());
+        // total number of virtual nodes
+        let virtual_factor = option.virtual_factor;
+        let sum_of_nodes = if option.weighted {
+            instances
+                .iter()
+                .fold(0, |lhs, rhs| lhs + (rhs.weight * virtual_factor) as usize)
+        } else {
+            instances.len() * virtual_factor as usize
+        };
+        let mut virtual_nodes = Vec::with_capacity(sum_of_nodes);
+        for instance in instances {
+            let real_node = Arc::new(RealNode::from((*instance).clone()));
+            real_nodes.push(real_node.clone());
+            let mut weight = 1;
+            if option.weighted {
+                weight = instance.weight;
+            }
+            let str = instance.address.to_string();
+            let vnode_lens = virtual_factor * weight;
+            // try to reuse the buffer
+            let mut buf = format!("{str}#{vnode_lens}").into_bytes();
+            let mut sharp_pos = 0;
+            for (i, bytei) in buf.iter().enumerate() {
+                if *bytei == b'#' {
+                    sharp_pos = i;
+                    break;
+                }
+            }
+            for i in 0..(virtual_factor * weight) {
+                let mut serial = i;
+                let mut pos = buf.len();
+                while serial > 0 {
+                    pos -= 1;
+                    buf[pos] = b'0' + (serial % 10) as u8;
+                    serial /= 10;
+                }
+                for bytej in buf.iter_mut().take(pos).skip(sharp_pos + 1) {
+                    *bytej = b'0';
+                }
+                // get address#i with leading zeros
+                let hash = mur3::murmurhash3_x64_128(&buf, 0).0;
+                virtual_nodes.push(VirtualNode {
+                    real_node: real_node.clone(),
+                    hash,
+                });
+            }
+        }
+        virtual_nodes.sort_unstable();
+        WeightedInstances {
+            real_nodes,
+            virtual_nodes,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct InstancePicker {
     shared_instances: Arc<WeightedInstances>,
@@ -105,6 +163,22 @@ pub struct InstancePicker {
     replicas: usize,
 }
 
+impl InstancePicker {
+    pub(super) fn new(
+        shared_instances: Arc<WeightedInstances>,
+        request_hash: RequestHash,
+        replicas: usize,
+    ) -> Self {
+        Self {
+            shared_instances,
+            request_hash,
+            last_pick: None,
+            used: HashSet::new(),
+            replicas,
+        }
+    }
+}
+
 impl Iterator for InstancePicker {
     type Item = Address;
 
@@ -188,59 +262,7 @@ where
     }
 
     fn build_weighted_instances(&self, instances: Vec<Arc<Instance>>) -> WeightedInstances {
-        let mut real_nodes = Vec::with_capacity(instances.len());
-        // total number of virtual nodes
-        let virtual_factor = self.option.virtual_factor;
-        let sum_of_nodes = if self.option.weighted {
-            instances
-                .iter()
-                .fold(0, |lhs, rhs| lhs + (rhs.weight * virtual_factor) as usize)
-        } else {
-            instances.len() * virtual_factor as usize
-        };
-        let mut virtual_nodes = Vec::with_capacity(sum_of_nodes);
-        for instance in instances {
-            let real_node = Arc::new(RealNode::from((*instance).clone()));
-            real_nodes.push(real_node.clone());
-            let mut weight = 1;
-            if self.option.weighted {
-                weight = instance.weight;
-            }
-            let str = instance.address.to_string();
-            let vnode_lens = virtual_factor * weight;
-            // try to reuse the buffer
-            let mut buf = format!("{str}#{vnode_lens}").into_bytes();
-            let mut sharp_pos = 0;
-            for (i, bytei) in buf.iter().enumerate() {
-                if *bytei == b'#' {
-                    sharp_pos = i;
-                    break;
-                }
-            }
-            for i in 0..(virtual_factor * weight) {
-                let mut serial = i;
-                let mut pos = buf.len();
-                while serial > 0 {
-                    pos -= 1;
-                    buf[pos] = b'0' + (serial % 10) as u8;
-                    serial /= 10;
-                }
-                for bytej in buf.iter_mut().take(pos).skip(sharp_pos + 1) {
-                    *bytej = b'0';
-                }
-                // get address#i with leading zeros
-                let hash = mur3::murmurhash3_x64_128(&buf, 0).0;
-                virtual_nodes.push(VirtualNode {
-                    real_node: real_node.clone(),
-                    hash,
-                });
-            }
-        }
-        virtual_nodes.sort_unstable();
-        WeightedInstances {
-            real_nodes,
-            virtual_nodes,
-        }
+        WeightedInstances::new(&self.option, instances)
     }
 }
 
@@ -254,13 +276,7 @@ where
         endpoint: &'future Endpoint,
         discover: &'future D,
     ) -> Result<Self::InstanceIter, LoadBalanceError> {
-        let request_hash = metainfo::METAINFO
-            .try_with(|m| m.borrow().get::<RequestHash>().copied())
-            .map_err(|_| LoadBalanceError::MissRequestHash)?;
-        if request_hash.is_none() {
-            return Err(LoadBalanceError::MissRequestHash);
-        }
-        let request_hash = request_hash.unwrap();
+        let request_hash = current_request_hash(endpoint)?;
         let key = discover.key(endpoint);
         let weighted_list = if let Some(instances) = self.router.get(&key) {
             instances.clone()
@@ -276,13 +292,11 @@ where
             self.router.insert(key, Arc::clone(&instances));
             instances
         };
-        Ok(InstancePicker {
-            shared_instances: weighted_list,
+        Ok(InstancePicker::new(
+            weighted_list,
             request_hash,
-            last_pick: None,
-            used: HashSet::new(),
-            replicas: self.option.replicas,
-        })
+            self.option.replicas,
+        ))
     }
 
     fn rebalance(&self, changes: Change<<D as Discover>::Key>) {
@@ -290,6 +304,10 @@ where
             entry.replace_entry(Arc::new(self.build_weighted_instances(changes.all)));
         }
     }
+
+    fn evict(&self, key: &<D as Discover>::Key) {
+        self.router.remove(key);
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,497 @@
+//! Hedged requests.
+//!
+//! If the first attempt of a call has not completed after a delay, a backup attempt is sent to
+//! the next instance of the same picker. The first successful response is taken, and the other
+//! attempts are cancelled.
+//!
+//! Hedging is opt-in per call by inserting a [`HedgePolicy`] into the callee tags, e.g. by the
+//! `CallOpt` of `volo-thrift` and `volo-grpc`. Since the request is sent more than once, it
+//! should only be used for idempotent methods.
+//!
+//! The load balance layer of `volo` hedges the calls only in the [`Hedging`] mode, which is
+//! enabled by [`LbConfig::hedging`](super::LbConfig::hedging) and used by `volo-thrift`.
+//!
+//! The hedged calls are not retried: [`HedgePolicy::max_attempts`] takes the place of the
+//! [`RetryPolicy`](crate::retry::RetryPolicy), and a failed attempt does not trigger a backup
+//! attempt immediately. The backup attempts are withdrawn from the
+//! [`RetryBudget`], so hedging is also stopped when the budget is exhausted.
+//!
+//! The context of a backup attempt is created by [`ForkContext::fork`], which copies the
+//! endpoint tags registered by [`register_fork_tag_type`](crate::context::register_fork_tag_type).
+
+use std::{
+    fmt::Debug,
+    pin::pin,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use faststr::FastStr;
+use futures::{
+    StreamExt,
+    future::{Either, select},
+    stream::FuturesUnordered,
+};
+use motore::Service;
+
+use super::{CallOutcome, CallTracker, LoadBalance, error::LoadBalanceError};
+use crate::{context::Context, discovery::Discover, net::Address, retry::RetryBudget};
+
+const LATENCY_SAMPLES: usize = 128;
+const MIN_LATENCY_SAMPLES: usize = 16;
+
+/// Contexts which can be forked for the hedged attempts.
+pub trait ForkContext: Sized {
+    /// Creates a context for another attempt of the same call.
+    fn fork(&self) -> Self;
+
+    /// Takes the state of a forked context whose attempt wins.
+    fn join(&mut self, fork: Self);
+}
+
+/// The mode of the load balance layer which does not hedge the calls, so the context only needs to
+/// implement [`Context`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoHedging;
+
+/// The mode of the load balance layer which hedges the calls with the [`HedgePolicy`] in the
+/// callee tags, and does not start a retry if its backoff would exceed the rpc timeout.
+///
+/// The context must implement [`ForkContext`] and its config must implement
+/// [`RpcTimeout`](crate::retry::RpcTimeout), e.g., the context of `volo-thrift`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Hedging;
+
+/// The delay before sending a backup attempt.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HedgeDelay {
+    Fixed(Duration),
+    /// The percentile (from 0 to 100) of the observed latency of the method.
+    ///
+    /// The fallback delay is used until enough latency is observed.
+    Percentile {
+        percentile: f64,
+        fallback: Duration,
+    },
+}
+
+/// [`HedgePolicy`] enables hedging for a call.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HedgePolicy {
+    delay: HedgeDelay,
+    max_attempts: usize,
+}
+
+impl HedgePolicy {
+    /// Sends a backup attempt if the call has not completed after `delay`.
+    pub fn fixed(delay: Duration) -> Self {
+        Self {
+            delay: HedgeDelay::Fixed(delay),
+            max_attempts: 2,
+        }
+    }
+
+    /// Sends a backup attempt if the call has not completed after the `percentile` of the
+    /// observed latency of the method, e.g. `95.0` for p95.
+    pub fn percentile(percentile: f64, fallback: Duration) -> Self {
+        Self {
+            delay: HedgeDelay::Percentile {
+                percentile: percentile.clamp(0.0, 100.0),
+                fallback,
+            },
+            max_attempts: 2,
+        }
+    }
+
+    /// Sets the maximum number of attempts including the first one, the default is 2.
+    pub fn with_max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn delay(&self) -> &HedgeDelay {
+        &self.delay
+    }
+
+    pub fn max_attempts(&self) -> usize {
+        self.max_attempts
+    }
+}
+
+#[derive(Debug, Default)]
+struct Samples {
+    values: Vec<Duration>,
+    next: usize,
+}
+
+/// [`LatencyTracker`] keeps the recent latency of the hedged calls of each method.
+#[derive(Debug, Default)]
+pub struct LatencyTracker {
+    methods: DashMap<FastStr, Mutex<Samples>>,
+}
+
+impl LatencyTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, method: &FastStr, latency: Duration) {
+        let samples = self.methods.entry(method.clone()).or_default();
+        let mut samples = samples.lock().unwrap();
+        if samples.values.len() < LATENCY_SAMPLES {
+            samples.values.push(latency);
+        } else {
+            let next = samples.next;
+            samples.values[next] = latency;
+        }
+        samples.next = (samples.next + 1) % LATENCY_SAMPLES;
+    }
+
+    /// Returns the percentile (from 0 to 100) of the recent latency of the method, or `None` if
+    /// there are not enough samples.
+    pub fn percentile(&self, method: &FastStr, percentile: f64) -> Option<Duration> {
+        let samples = self.methods.get(method)?;
+        let mut values = samples.lock().unwrap().values.clone();
+        if values.len() < MIN_LATENCY_SAMPLES {
+            return None;
+        }
+        values.sort_unstable();
+        let rank = (percentile / 100.0 * (values.len() - 1) as f64).round() as usize;
+        values.get(rank).copied()
+    }
+
+    /// Returns the delay before sending a backup attempt.
+    pub fn delay(&self, method: &FastStr, policy: &HedgePolicy) -> Duration {
+        match policy.delay {
+            HedgeDelay::Fixed(delay) => delay,
+            HedgeDelay::Percentile {
+                percentile,
+                fallback,
+            } => self.percentile(method, percentile).unwrap_or(fallback),
+        }
+    }
+}
+
+enum AttemptCx<'a, Cx> {
+    Origin(&'a mut Cx),
+    Fork(Cx),
+}
+
+async fn attempt<Cx, Req, S, D, LB>(
+    service: &S,
+    load_balance: &LB,
+    latency: &LatencyTracker,
+    method: &FastStr,
+    mut cx: AttemptCx<'_, Cx>,
+    req: Req,
+    addr: Address,
+) -> (Result<S::Response, S::Error>, Option<Cx>)
+where
+    Cx: Context + Send,
+    Req: Send,
+    S: Service<Cx, Req>,
+    D: Discover,
+    LB: LoadBalance<D>,
+{
+    let start = Instant::now();
+    let tracker = CallTracker::<D, LB>::new(load_balance, addr);
+    let res = match &mut cx {
+        AttemptCx::Origin(cx) => service.call(cx, req).await,
+        AttemptCx::Fork(cx) => service.call(cx, req).await,
+    };
+    if res.is_ok() {
+        tracker.finish(CallOutcome::Success);
+        latency.record(method, start.elapsed());
+    } else {
+        tracker.finish(CallOutcome::Failure);
+    }
+    match cx {
+        AttemptCx::Origin(_) => (res, None),
+        AttemptCx::Fork(cx) => (res, Some(cx)),
+    }
+}
+
+/// Calls the service with the instances from the picker by the [`HedgePolicy`].
+///
+/// The first attempt uses `cx`, and the backup attempts use the contexts forked from it. The
+/// backup attempts are only sent if `replay` can copy the request, and the `budget` allows.
+#[allow(clippy::too_many_arguments)]
+pub async fn call_hedged<Cx, Req, S, D, LB, I, R>(
+    service: &S,
+    load_balance: &LB,
+    cx: &mut Cx,
+    req: Req,
+    mut picker: I,
+    policy: &HedgePolicy,
+    latency: &LatencyTracker,
+    budget: Option<&RetryBudget>,
+    replay: R,
+) -> Result<S::Response, S::Error>
+where
+    Cx: Context + ForkContext + Send,
+    Req: Send,
+    S: Service<Cx, Req> + Sync,
+    S::Response: Send,
+    S::Error: Send + Debug,
+    D: Discover,
+    LB: LoadBalance<D>,
+    I: Iterator<Item = Address>,
+    R: Fn(&Req) -> Option<Req>,
+    LoadBalanceError: Into<S::Error>,
+{
+    let Some(addr) = picker.next() else {
+        return Err(LoadBalanceError::Retry.into());
+    };
+    let method = cx.rpc_info().method().clone();
+    let delay = latency.delay(&method, policy);
+    if let Some(budget) = budget {
+        budget.deposit_once(cx.rpc_info_mut().callee_mut());
+    }
+
+    cx.rpc_info_mut().callee_mut().address = Some(addr.clone());
+    let template = cx.fork();
+    let mut next_req = replay(&req);
+    let mut next_at = Instant::now() + delay;
+    let mut attempts = 1;
+
+    let mut in_flight = FuturesUnordered::new();
+    in_flight.push(attempt::<Cx, Req, S, D, LB>(
+        service,
+        load_balance,
+        latency,
+        &method,
+        AttemptCx::Origin(&mut *cx),
+        req,
+        addr,
+    ));
+
+    loop {
+        let hedge = attempts < policy.max_attempts && next_req.is_some();
+        let next = if hedge {
+            let sleep = pin!(tokio::time::sleep_until(next_at.into()));
+            match select(in_flight.next(), sleep).await {
+                Either::Left((next, _)) => next,
+                Either::Right(_) => {
+                    if budget.is_some_and(|budget| !budget.withdraw()) {
+                        // the retry budget is exhausted
+                        attempts = policy.max_attempts;
+                        continue;
+                    }
+                    let Some(addr) = picker.next() else {
+                        // no more instances to hedge
+                        attempts = policy.max_attempts;
+                        continue;
+                    };
+                    let req = next_req.take().expect("the request to hedge exists");
+                    next_req = replay(&req);
+                    let mut fork = template.fork();
+                    fork.rpc_info_mut().callee_mut().address = Some(addr.clone());
+                    in_flight.push(attempt::<Cx, Req, S, D, LB>(
+                        service,
+                        load_balance,
+                        latency,
+                        &method,
+                        AttemptCx::Fork(fork),
+                        req,
+                        addr,
+                    ));
+                    attempts += 1;
+                    next_at = Instant::now() + delay;
+                    continue;
+                }
+            }
+        } else {
+            in_flight.next().await
+        };
+
+        // `in_flight` is not empty, so the next result always exists
+        let Some((res, fork)) = next else {
+            unreachable!()
+        };
+        match res {
+            Ok(resp) => {
+                // cancel the other attempts
+                drop(in_flight);
+                if let Some(fork) = fork {
+                    cx.join(fork);
+                }
+                return Ok(resp);
+            }
+            Err(err) => {
+                if in_flight.is_empty() {
+                    return Err(err);
+                }
+                tracing::debug!("[VOLO] hedged attempt failed, error: {:?}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use motore::service::service_fn;
+
+    use super::{ForkContext, HedgePolicy, LatencyTracker, call_hedged};
+    use crate::{
+        context::{
+            Context, Endpoint, Reusable, Role, RpcCx, RpcInfo, register_fork_faststr_tag_type,
+        },
+        discovery::StaticDiscover,
+        loadbalance::{RequestHash, error::LoadBalanceError, random::WeightedRandomBalance},
+        net::Address,
+        retry::RetryBudget,
+    };
+
+    #[derive(Debug, Default)]
+    struct TestConfig;
+
+    impl Reusable for TestConfig {
+        fn clear(&mut self) {}
+    }
+
+    type TestContext = RpcCx<(), TestConfig>;
+
+    fn new_cx() -> TestContext {
+        RpcCx::new(
+            RpcInfo::new(
+                Role::Client,
+                "method".into(),
+                Endpoint::new("caller".into()),
+                Endpoint::new("callee".into()),
+                TestConfig,
+            ),
+            (),
+        )
+    }
+
+    impl ForkContext for TestContext {
+        fn fork(&self) -> Self {
+            let mut cx = new_cx();
+            cx.rpc_info_mut().callee_mut().address = self.rpc_info().callee().address();
+            cx
+        }
+
+        fn join(&mut self, fork: Self) {
+            self.rpc_info_mut().callee_mut().address = fork.rpc_info().callee().address();
+        }
+    }
+
+    fn addr(s: &str) -> Address {
+        Address::Ip(s.parse().unwrap())
+    }
+
+    async fn handle(cx: &mut TestContext, slow: Address) -> Result<Address, LoadBalanceError> {
+        let addr = cx.rpc_info().callee().address().unwrap();
+        let delay = if addr == slow { 1000 } else { 10 };
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+        Ok(addr)
+    }
+
+    #[test]
+    fn test_latency_percentile() {
+        let tracker = LatencyTracker::new();
+        let method = "method".into();
+        for i in 1..=10 {
+            tracker.record(&method, Duration::from_millis(i));
+        }
+        assert_eq!(tracker.percentile(&method, 50.0), None);
+        assert_eq!(
+            tracker.delay(
+                &method,
+                &HedgePolicy::percentile(50.0, Duration::from_secs(1))
+            ),
+            Duration::from_secs(1)
+        );
+        for i in 11..=100 {
+            tracker.record(&method, Duration::from_millis(i));
+        }
+        assert_eq!(
+            tracker.percentile(&method, 90.0),
+            Some(Duration::from_millis(90))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hedged_call() {
+        let slow = addr("127.0.0.1:8000");
+        let fast = addr("127.0.0.2:8000");
+        let service = service_fn(handle);
+        let lb = WeightedRandomBalance::<()>::new();
+        let tracker = LatencyTracker::new();
+
+        // the backup attempt to the fast instance wins
+        let mut cx = new_cx();
+        let resp = call_hedged::<_, _, _, StaticDiscover, _, _, _>(
+            &service,
+            &lb,
+            &mut cx,
+            slow.clone(),
+            vec![slow.clone(), fast.clone()].into_iter(),
+            &HedgePolicy::fixed(Duration::from_millis(20)),
+            &tracker,
+            None,
+            |req: &Address| Some(req.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp, fast);
+        assert_eq!(cx.rpc_info().callee().address(), Some(fast.clone()));
+
+        // the retry budget is exhausted, so no backup attempt is sent
+        let budget = RetryBudget::new(0.0).with_min_retries(0);
+        let mut cx = new_cx();
+        let resp = call_hedged::<_, _, _, StaticDiscover, _, _, _>(
+            &service,
+            &lb,
+            &mut cx,
+            slow.clone(),
+            vec![slow.clone(), fast.clone()].into_iter(),
+            &HedgePolicy::fixed(Duration::from_millis(20)),
+            &tracker,
+            Some(&budget),
+            |req: &Address| Some(req.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp, slow);
+
+        // the first attempt completes before the delay
+        let mut cx = new_cx();
+        let resp = call_hedged::<_, _, _, StaticDiscover, _, _, _>(
+            &service,
+            &lb,
+            &mut cx,
+            slow.clone(),
+            vec![fast.clone(), slow.clone()].into_iter(),
+            &HedgePolicy::fixed(Duration::from_millis(500)),
+            &tracker,
+            None,
+            |_: &Address| None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp, fast);
+    }
+
+    #[test]
+    fn test_endpoint_fork() {
+        struct Zone;
+
+        let mut endpoint = Endpoint::new("callee".into());
+        endpoint.set_address(addr("127.0.0.1:8000"));
+        endpoint.insert(RequestHash(1));
+        endpoint.insert_faststr::<Zone>("zone-a".into());
+
+        let fork = endpoint.fork();
+        assert_eq!(fork.address(), endpoint.address());
+        assert_eq!(fork.get::<RequestHash>(), Some(&RequestHash(1)));
+        assert!(fork.get_faststr::<Zone>().is_none());
+
+        register_fork_faststr_tag_type::<Zone>();
+        let fork = endpoint.fork();
+        assert_eq!(fork.get_faststr::<Zone>().unwrap(), "zone-a");
+    }
+}
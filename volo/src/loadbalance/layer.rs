@@ -1,18 +1,31 @@
-use std::{fmt::Debug, sync::Arc};
+use std::{fmt::Debug, sync::Arc, time::Duration};
 
 use async_broadcast::RecvError;
 use motore::Service;
 use tracing::warn;
 
 use super::error::{LoadBalanceError, Retryable};
-use crate::{Layer, context::Context, discovery::Discover, loadbalance::LoadBalance};
+use crate::{
+    Layer,
+    context::Context,
+    discovery::Discover,
+    loadbalance::{
+        CallOutcome, CallTracker, HashKeyExtractor, LoadBalance, MetaInfoHashKey,
+        hedge::{ForkContext, HedgePolicy, Hedging, LatencyTracker, NoHedging, call_hedged},
+    },
+    retry::{RetryBudget, RetryPolicy, RetryState, RpcTimeout},
+};
 
 #[derive(Clone)]
-pub struct LoadBalanceService<D, LB, S> {
+pub struct LoadBalanceService<D, LB, S, H = MetaInfoHashKey, M = NoHedging> {
     discover: D,
     load_balance: Arc<LB>,
     service: S,
-    retry: usize,
+    hash_key: H,
+    retry: RetryPolicy,
+    retry_budget: Option<RetryBudget>,
+    latency: Arc<LatencyTracker>,
+    mode: M,
 }
 
 impl<D, LB, S> LoadBalanceService<D, LB, S>
@@ -27,9 +40,28 @@ where
             discover,
             load_balance: lb.clone(),
             service,
-            retry,
+            hash_key: MetaInfoHashKey,
+            retry: RetryPolicy::new(retry + 1),
+            retry_budget: None,
+            latency: Arc::new(LatencyTracker::new()),
+            mode: NoHedging,
         };
 
+        if let Some(mut evicted) = service.discover.watch_evicted() {
+            let lb = lb.clone();
+            tokio::spawn(async move {
+                loop {
+                    match evicted.recv().await {
+                        Ok(key) => lb.evict(&key),
+                        Err(err) => match err {
+                            RecvError::Closed => break,
+                            _ => warn!("[VOLO] discovering subscription error: {:?}", err),
+                        },
+                    }
+                }
+            });
+        }
+
         if let Some(mut channel) = service.discover.watch(None) {
             tokio::spawn(async move {
                 loop {
@@ -47,43 +79,114 @@ where
     }
 }
 
-impl<Cx, Req, D, LB, S> Service<Cx, Req> for LoadBalanceService<D, LB, S>
-where
-    Cx: 'static + Context + Send + Sync,
-    D: Discover,
-    LB: LoadBalance<D>,
-    S: Service<Cx, Req> + 'static + Send + Sync,
-    LoadBalanceError: Into<S::Error>,
-    S::Error: Debug + Retryable,
-    Req: Clone + Send + Sync + 'static,
-{
-    type Response = S::Response;
+impl<D, LB, S, H, M> LoadBalanceService<D, LB, S, H, M> {
+    /// Sets the [`RetryPolicy`], which overrides the retry count.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
 
-    type Error = S::Error;
+    /// Limits the retries by the [`RetryBudget`].
+    pub fn with_retry_budget(mut self, budget: Option<RetryBudget>) -> Self {
+        self.retry_budget = budget;
+        self
+    }
 
-    async fn call(&self, cx: &mut Cx, req: Req) -> Result<Self::Response, Self::Error> {
+    /// Sets the [`HashKeyExtractor`] for the hashing load balancers.
+    pub fn with_hash_key<NH>(self, hash_key: NH) -> LoadBalanceService<D, LB, S, NH, M> {
+        LoadBalanceService {
+            discover: self.discover,
+            load_balance: self.load_balance,
+            service: self.service,
+            hash_key,
+            retry: self.retry,
+            retry_budget: self.retry_budget,
+            latency: self.latency,
+            mode: self.mode,
+        }
+    }
+
+    /// Enables [`Hedging`], which requires more bounds of the context.
+    pub fn with_hedging(self) -> LoadBalanceService<D, LB, S, H, Hedging> {
+        LoadBalanceService {
+            discover: self.discover,
+            load_balance: self.load_balance,
+            service: self.service,
+            hash_key: self.hash_key,
+            retry: self.retry,
+            retry_budget: self.retry_budget,
+            latency: self.latency,
+            mode: Hedging,
+        }
+    }
+
+    /// Returns the picker of the callee, or `None` if the address of the callee is specified.
+    async fn picker<Cx, Req>(
+        &self,
+        cx: &mut Cx,
+        req: &Req,
+    ) -> Result<Option<LB::InstanceIter>, S::Error>
+    where
+        Cx: Context,
+        D: Discover,
+        LB: LoadBalance<D>,
+        S: Service<Cx, Req>,
+        LoadBalanceError: Into<S::Error>,
+        H: HashKeyExtractor<Cx, Req>,
+    {
+        if let Some(hash) = self.hash_key.extract(cx, req) {
+            cx.rpc_info_mut().callee_mut().insert(hash);
+        }
         let callee = cx.rpc_info().callee();
+        if callee.address.is_some() {
+            return Ok(None);
+        }
+        self.load_balance
+            .get_picker(callee, &self.discover)
+            .await
+            .map(Some)
+            .map_err(|err| err.into())
+    }
 
-        let picker = match &callee.address {
-            None => self
-                .load_balance
-                .get_picker(callee, &self.discover)
-                .await
-                .map_err(|err| err.into())?,
-            _ => {
-                return self.service.call(cx, req).await;
-            }
-        };
+    /// Calls the instances of the picker in turn until one succeeds, under the [`RetryPolicy`].
+    async fn call_with_retry<Cx, Req>(
+        &self,
+        cx: &mut Cx,
+        req: Req,
+        picker: LB::InstanceIter,
+        rpc_timeout: Option<Duration>,
+    ) -> Result<S::Response, S::Error>
+    where
+        Cx: Context,
+        D: Discover,
+        LB: LoadBalance<D>,
+        S: Service<Cx, Req>,
+        LoadBalanceError: Into<S::Error>,
+        S::Error: Debug + Retryable,
+        Req: Clone,
+    {
+        let mut retry = RetryState::new(
+            &self.retry,
+            self.retry_budget.as_ref(),
+            cx.rpc_info_mut().callee_mut(),
+            rpc_timeout,
+        );
         let mut call_count = 0;
-        for (addr, _) in picker.zip(0..self.retry + 1) {
+        for addr in picker {
+            if call_count > 0 && !retry.backoff().await {
+                break;
+            }
             call_count += 1;
             cx.rpc_info_mut().callee_mut().address = Some(addr.clone());
 
+            let tracker = CallTracker::<D, LB>::new(self.load_balance.as_ref(), addr);
             match self.service.call(cx, req.clone()).await {
                 Ok(resp) => {
+                    tracker.finish(CallOutcome::Success);
                     return Ok(resp);
                 }
                 Err(err) => {
+                    tracker.finish(CallOutcome::Failure);
                     warn!("[VOLO] call rpcinfo: {:?}, error: {:?}", cx.rpc_info(), err);
                     if !err.retryable() {
                         return Err(err);
@@ -98,7 +201,72 @@ where
     }
 }
 
-impl<D, LB, S> Debug for LoadBalanceService<D, LB, S>
+impl<Cx, Req, D, LB, S, H> Service<Cx, Req> for LoadBalanceService<D, LB, S, H, NoHedging>
+where
+    Cx: 'static + Context + Send + Sync,
+    D: Discover,
+    LB: LoadBalance<D>,
+    S: Service<Cx, Req> + 'static + Send + Sync,
+    LoadBalanceError: Into<S::Error>,
+    S::Error: Debug + Retryable,
+    Req: Clone + Send + Sync + 'static,
+    H: HashKeyExtractor<Cx, Req>,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    async fn call(&self, cx: &mut Cx, req: Req) -> Result<Self::Response, Self::Error> {
+        let Some(picker) = self.picker(cx, &req).await? else {
+            return self.service.call(cx, req).await;
+        };
+        self.call_with_retry(cx, req, picker, None).await
+    }
+}
+
+impl<Cx, Req, D, LB, S, H> Service<Cx, Req> for LoadBalanceService<D, LB, S, H, Hedging>
+where
+    Cx: 'static + Context + ForkContext + Send + Sync,
+    Cx::Config: RpcTimeout,
+    D: Discover,
+    LB: LoadBalance<D>,
+    S: Service<Cx, Req> + 'static + Send + Sync,
+    S::Response: Send,
+    LoadBalanceError: Into<S::Error>,
+    S::Error: Debug + Retryable + Send,
+    Req: Clone + Send + Sync + 'static,
+    H: HashKeyExtractor<Cx, Req>,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    async fn call(&self, cx: &mut Cx, req: Req) -> Result<Self::Response, Self::Error> {
+        let Some(picker) = self.picker(cx, &req).await? else {
+            return self.service.call(cx, req).await;
+        };
+        // the hedged calls are not retried, since the backup attempts work like retries, but
+        // they are limited by the retry budget
+        if let Some(policy) = cx.rpc_info().callee().get::<HedgePolicy>().cloned() {
+            return call_hedged(
+                &self.service,
+                self.load_balance.as_ref(),
+                cx,
+                req,
+                picker,
+                &policy,
+                &self.latency,
+                self.retry_budget.as_ref(),
+                |req: &Req| Some(req.clone()),
+            )
+            .await;
+        }
+        let rpc_timeout = cx.rpc_info().config().rpc_timeout();
+        self.call_with_retry(cx, req, picker, rpc_timeout).await
+    }
+}
+
+impl<D, LB, S, H, M> Debug for LoadBalanceService<D, LB, S, H, M>
 where
     D: Debug,
     LB: Debug,
@@ -112,11 +280,14 @@ where
     }
 }
 
-#[derive(Clone, Default, Copy)]
-pub struct LoadBalanceLayer<D, LB> {
+#[derive(Clone, Default)]
+pub struct LoadBalanceLayer<D, LB, H = MetaInfoHashKey, M = NoHedging> {
     discover: D,
     load_balance: LB,
-    retry_count: usize,
+    hash_key: H,
+    retry_policy: RetryPolicy,
+    retry_budget: Option<RetryBudget>,
+    mode: M,
 }
 
 impl<D, LB> LoadBalanceLayer<D, LB> {
@@ -124,31 +295,114 @@ impl<D, LB> LoadBalanceLayer<D, LB> {
         LoadBalanceLayer {
             discover,
             load_balance,
-            retry_count,
+            hash_key: MetaInfoHashKey,
+            retry_policy: RetryPolicy::new(retry_count + 1),
+            retry_budget: None,
+            mode: NoHedging,
+        }
+    }
+}
+
+impl<D, LB, H, M> LoadBalanceLayer<D, LB, H, M> {
+    /// Sets the [`HashKeyExtractor`] for the hashing load balancers.
+    pub fn with_hash_key<NH>(self, hash_key: NH) -> LoadBalanceLayer<D, LB, NH, M> {
+        LoadBalanceLayer {
+            discover: self.discover,
+            load_balance: self.load_balance,
+            hash_key,
+            retry_policy: self.retry_policy,
+            retry_budget: self.retry_budget,
+            mode: self.mode,
+        }
+    }
+
+    /// Enables [`Hedging`], which requires more bounds of the context.
+    pub fn with_hedging(self) -> LoadBalanceLayer<D, LB, H, Hedging> {
+        self.with_mode(Hedging)
+    }
+
+    pub(crate) fn with_mode<NM>(self, mode: NM) -> LoadBalanceLayer<D, LB, H, NM> {
+        LoadBalanceLayer {
+            discover: self.discover,
+            load_balance: self.load_balance,
+            hash_key: self.hash_key,
+            retry_policy: self.retry_policy,
+            retry_budget: self.retry_budget,
+            mode,
         }
     }
+
+    /// Sets the [`RetryPolicy`], which overrides the retry count.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Limits the retries by the [`RetryBudget`].
+    pub fn with_retry_budget(mut self, budget: Option<RetryBudget>) -> Self {
+        self.retry_budget = budget;
+        self
+    }
+}
+
+impl<D, LB, H, S> Layer<S> for LoadBalanceLayer<D, LB, H>
+where
+    D: Discover,
+    LB: LoadBalance<D>,
+{
+    type Service = LoadBalanceService<D, LB, S, H>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        LoadBalanceService::new(self.discover, self.load_balance, inner, 0)
+            .with_retry_policy(self.retry_policy)
+            .with_retry_budget(self.retry_budget)
+            .with_hash_key(self.hash_key)
+    }
 }
 
-impl<D, LB, S> Layer<S> for LoadBalanceLayer<D, LB>
+impl<D, LB, H, S> Layer<S> for LoadBalanceLayer<D, LB, H, Hedging>
 where
     D: Discover,
     LB: LoadBalance<D>,
 {
-    type Service = LoadBalanceService<D, LB, S>;
+    type Service = LoadBalanceService<D, LB, S, H, Hedging>;
 
     fn layer(self, inner: S) -> Self::Service {
-        LoadBalanceService::new(self.discover, self.load_balance, inner, self.retry_count)
+        LoadBalanceService::new(self.discover, self.load_balance, inner, 0)
+            .with_retry_policy(self.retry_policy)
+            .with_retry_budget(self.retry_budget)
+            .with_hash_key(self.hash_key)
+            .with_hedging()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::convert::Infallible;
+    use std::{
+        convert::Infallible,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
 
-    use motore::service::service_fn;
+    use motore::{
+        layer::Layer,
+        service::{Service, service_fn},
+    };
 
     use super::LoadBalanceService;
-    use crate::{discovery::StaticDiscover, loadbalance::random::WeightedRandomBalance};
+    use crate::{
+        context::{Context, Endpoint, Reusable, Role, RpcCx, RpcInfo},
+        discovery::StaticDiscover,
+        loadbalance::{
+            error::{LoadBalanceError, Retryable},
+            random::WeightedRandomBalance,
+        },
+        net::Address,
+        retry::{RetryBudget, RetryLayer, RetryPolicy, RpcTimeout},
+    };
 
     #[derive(Debug)]
     struct MotoreContext;
@@ -166,4 +420,114 @@ mod tests {
 
         LoadBalanceService::new(discover, lb, service, 1);
     }
+
+    /// A config without [`RpcTimeout`](crate::retry::RpcTimeout).
+    #[derive(Debug, Default)]
+    struct PlainConfig;
+
+    impl Reusable for PlainConfig {
+        fn clear(&mut self) {}
+    }
+
+    /// A context without [`ForkContext`](crate::loadbalance::hedge::ForkContext).
+    type PlainContext = RpcCx<(), PlainConfig>;
+
+    #[derive(Debug)]
+    struct RetryableError;
+
+    impl Retryable for RetryableError {
+        fn retryable(&self) -> bool {
+            true
+        }
+    }
+
+    impl From<LoadBalanceError> for RetryableError {
+        fn from(_: LoadBalanceError) -> Self {
+            RetryableError
+        }
+    }
+
+    #[tokio::test]
+    async fn test_plain_context() {
+        let discover = StaticDiscover::from(vec![
+            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.2:8000".parse().unwrap(),
+        ]);
+        let lb = WeightedRandomBalance::with_discover(&discover);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service = service_fn({
+            let calls = calls.clone();
+            move |cx: &mut PlainContext, _: ()| {
+                let addr = cx.rpc_info().callee().address();
+                let first = calls.fetch_add(1, Ordering::Relaxed) == 0;
+                async move {
+                    if first {
+                        Err(RetryableError)
+                    } else {
+                        Ok::<_, RetryableError>(addr)
+                    }
+                }
+            }
+        });
+        let service = LoadBalanceService::new(discover, lb, service, 1);
+
+        let mut cx = RpcCx::new(
+            RpcInfo::new(
+                Role::Client,
+                "method".into(),
+                Endpoint::new("caller".into()),
+                Endpoint::new("callee".into()),
+                PlainConfig,
+            ),
+            (),
+        );
+        let addr: Option<Address> = service.call(&mut cx, ()).await.unwrap();
+        assert!(addr.is_some());
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[derive(Debug, Default)]
+    struct TimeoutConfig;
+
+    impl Reusable for TimeoutConfig {
+        fn clear(&mut self) {}
+    }
+
+    impl RpcTimeout for TimeoutConfig {
+        fn rpc_timeout(&self) -> Option<Duration> {
+            None
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stacked_retry_budget() {
+        let discover = StaticDiscover::from(vec!["127.0.0.1:8000".parse().unwrap()]);
+        let lb = WeightedRandomBalance::with_discover(&discover);
+        let service = service_fn(|_: &mut RpcCx<(), TimeoutConfig>, _: ()| async {
+            Ok::<_, RetryableError>(())
+        });
+        // one retry is allowed for each request
+        let budget = RetryBudget::new(1.0).with_min_retries(0);
+        let service = LoadBalanceService::new(discover, lb, service, 1)
+            .with_retry_budget(Some(budget.clone()));
+        let service = RetryLayer::new(RetryPolicy::new(2))
+            .with_budget(budget.clone())
+            .layer(service);
+
+        let mut cx = RpcCx::new(
+            RpcInfo::new(
+                Role::Client,
+                "method".into(),
+                Endpoint::new("caller".into()),
+                Endpoint::new("callee".into()),
+                TimeoutConfig,
+            ),
+            (),
+        );
+        service.call(&mut cx, ()).await.unwrap();
+
+        // the request goes through both retries, but it is recorded only once
+        assert!(budget.withdraw());
+        assert!(!budget.withdraw());
+    }
 }
@@ -0,0 +1,378 @@
+//! Maglev consistent hashing, described in the paper
+//! [Maglev: A Fast and Reliable Software Network Load Balancer](https://research.google/pubs/pub44824/).
+//!
+//! Instead of a ring of virtual nodes, each instance fills the slots of a fixed size lookup table
+//! in the order of its own permutation, so the memory is bounded by the table size and a pick is
+//! a single lookup. When an instance is added or removed, only a small part of the slots is
+//! changed.
+
+use std::{cmp::min, collections::HashSet, hash::Hash, sync::Arc};
+
+use dashmap::{DashMap, mapref::entry::Entry};
+
+use super::{LoadBalance, RequestHash, current_request_hash, error::LoadBalanceError};
+use crate::{
+    context::Endpoint,
+    discovery::{Change, Discover, Instance},
+    net::Address,
+};
+
+/// The default size of the lookup table, which is a prime number.
+const DEFAULT_TABLE_SIZE: usize = 65537;
+
+#[derive(Debug, Clone)]
+pub struct MaglevOption {
+    /// If it is set to a value greater than 1, replicas will be used when connect to the primary
+    /// node fails. The replicas are the next different instances in the lookup table.
+    /// If it is set to 1, error will be returned immediately when connect fails.
+    replicas: usize,
+
+    /// The size of the lookup table, which is rounded up to a prime number.
+    /// It should be much larger than the number of instances, e.g., 100 times, for the slots to
+    /// be evenly distributed.
+    table_size: usize,
+
+    /// Whether to follow Weight for load balancing.
+    /// If true, the number of slots of each instance is proportional to its weight, and the
+    /// instances with weight 0 get no slots.
+    weighted: bool,
+}
+
+impl MaglevOption {
+    pub fn new(replicas: usize, table_size: usize, weighted: bool) -> Self {
+        MaglevOption {
+            replicas,
+            table_size: next_prime(table_size),
+            weighted,
+        }
+    }
+}
+
+impl Default for MaglevOption {
+    fn default() -> Self {
+        MaglevOption {
+            replicas: 1,
+            table_size: DEFAULT_TABLE_SIZE,
+            weighted: true,
+        }
+    }
+}
+
+fn next_prime(n: usize) -> usize {
+    let is_prime = |n: usize| n >= 2 && (2..).take_while(|i| i * i <= n).all(|i| n % i != 0);
+    (n..).find(|n| is_prime(*n)).unwrap()
+}
+
+#[derive(Debug)]
+struct LookupTable {
+    instances: Vec<Arc<Instance>>,
+    /// The index of the instance of each slot.
+    slots: Vec<u32>,
+}
+
+impl LookupTable {
+    fn new(option: &MaglevOption, instances: Vec<Arc<Instance>>) -> Self {
+        let size = option.table_size;
+        let weights: Vec<u64> = instances
+            .iter()
+            .map(|instance| {
+                if option.weighted {
+                    instance.weight as u64
+                } else {
+                    1
+                }
+            })
+            .collect();
+        let max_weight = weights.iter().copied().max().unwrap_or(0);
+        if max_weight == 0 {
+            return Self {
+                instances,
+                slots: Vec::new(),
+            };
+        }
+
+        // (offset, skip) of the permutation of each instance
+        let permutations: Vec<(usize, usize)> = instances
+            .iter()
+            .map(|instance| {
+                let (h1, h2) =
+                    mur3::murmurhash3_x64_128(instance.address.to_string().as_bytes(), 0);
+                (
+                    (h1 % size as u64) as usize,
+                    (h2 % (size as u64 - 1)) as usize + 1,
+                )
+            })
+            .collect();
+        let mut next = vec![0; instances.len()];
+        let mut credits = vec![0; instances.len()];
+        let mut slots = vec![u32::MAX; size];
+        let mut filled = 0;
+        while filled < size {
+            for (i, &(offset, skip)) in permutations.iter().enumerate() {
+                // the instance with the max weight fills a slot in every round, and the others
+                // fill slots in proportion to their weights
+                credits[i] += weights[i];
+                if credits[i] < max_weight {
+                    continue;
+                }
+                credits[i] -= max_weight;
+                let mut slot = (offset + next[i] * skip) % size;
+                while slots[slot] != u32::MAX {
+                    next[i] += 1;
+                    slot = (offset + next[i] * skip) % size;
+                }
+                slots[slot] = i as u32;
+                next[i] += 1;
+                filled += 1;
+                if filled == size {
+                    break;
+                }
+            }
+        }
+        Self { instances, slots }
+    }
+
+    fn instance(&self, slot: usize) -> &Instance {
+        &self.instances[self.slots[slot] as usize]
+    }
+}
+
+#[derive(Debug)]
+pub struct InstancePicker {
+    table: Arc<LookupTable>,
+
+    request_hash: RequestHash,
+
+    /// The last selected slot
+    last_pick: Option<usize>,
+
+    /// The set of instances that have been selected
+    used: HashSet<Address>,
+
+    /// The number of replicas to pick, min(option.replicas, instances.len())
+    replicas: usize,
+}
+
+impl Iterator for InstancePicker {
+    type Item = Address;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let slots = &self.table.slots;
+        if slots.is_empty() || self.used.len() >= self.replicas {
+            return None;
+        }
+
+        let start = match self.last_pick {
+            None => {
+                self.replicas = min(self.replicas, self.table.instances.len());
+                (self.request_hash.0 % slots.len() as u64) as usize
+            }
+            Some(last_pick) => last_pick + 1,
+        };
+        // find the next slot whose instance is not used
+        for i in 0..slots.len() {
+            let slot = (start + i) % slots.len();
+            let addr = &self.table.instance(slot).address;
+            if !self.used.contains(addr) {
+                self.last_pick = Some(slot);
+                self.used.insert(addr.clone());
+                return Some(addr.clone());
+            }
+        }
+        None
+    }
+}
+
+/// [`MaglevBalance`] picks the instance by the [`RequestHash`] through a Maglev lookup table.
+///
+/// Compared with [`ConsistentHashBalance`](super::consistent_hash::ConsistentHashBalance), the
+/// memory is bounded by [`MaglevOption::new`]'s `table_size` rather than the number of virtual
+/// nodes, and a pick costs O(1).
+#[derive(Debug)]
+pub struct MaglevBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    option: MaglevOption,
+    router: DashMap<K, Arc<LookupTable>>,
+}
+
+impl<K> MaglevBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    pub fn with_discover<D>(&mut self, _: &D) -> &mut Self
+    where
+        D: Discover<Key = K>,
+    {
+        self
+    }
+
+    pub fn new(option: MaglevOption) -> Self {
+        Self {
+            option,
+            router: DashMap::new(),
+        }
+    }
+}
+
+impl<D> LoadBalance<D> for MaglevBalance<D::Key>
+where
+    D: Discover,
+{
+    type InstanceIter = InstancePicker;
+
+    async fn get_picker<'future>(
+        &'future self,
+        endpoint: &'future Endpoint,
+        discover: &'future D,
+    ) -> Result<Self::InstanceIter, LoadBalanceError> {
+        let request_hash = current_request_hash(endpoint)?;
+        let key = discover.key(endpoint);
+        let table = if let Some(table) = self.router.get(&key) {
+            table.clone()
+        } else {
+            let table = Arc::new(LookupTable::new(
+                &self.option,
+                discover
+                    .discover(endpoint)
+                    .await
+                    .map_err(|err| err.into())?,
+            ));
+            self.router.insert(key, table.clone());
+            table
+        };
+        Ok(InstancePicker {
+            table,
+            request_hash,
+            last_pick: None,
+            used: HashSet::new(),
+            replicas: self.option.replicas,
+        })
+    }
+
+    fn rebalance(&self, changes: Change<D::Key>) {
+        if let Entry::Occupied(entry) = self.router.entry(changes.key.clone()) {
+            entry.replace_entry(Arc::new(LookupTable::new(&self.option, changes.all)));
+        }
+    }
+
+    fn evict(&self, key: &D::Key) {
+        self.router.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::HashMap, sync::Arc};
+
+    use metainfo::{METAINFO, MetaInfo};
+
+    use super::{LoadBalance, LookupTable, MaglevBalance, MaglevOption, next_prime};
+    use crate::{
+        context::Endpoint,
+        discovery::{Instance, StaticDiscover},
+        loadbalance::RequestHash,
+        net::Address,
+    };
+
+    fn new_instance(address: String, weight: u32) -> Arc<Instance> {
+        Arc::new(Instance {
+            address: Address::Ip(address.parse().unwrap()),
+            weight,
+            tags: Default::default(),
+        })
+    }
+
+    fn set_request_hash(code: u64) {
+        METAINFO
+            .try_with(|m| m.borrow_mut().insert(RequestHash(code)))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_lookup_table() {
+        assert_eq!(next_prime(1000), 1009);
+        assert_eq!(next_prime(65537), 65537);
+
+        let instances: Vec<_> = (0..10)
+            .map(|i| new_instance(format!("127.0.0.1:{i}"), if i < 5 { 10 } else { 20 }))
+            .collect();
+        let option = MaglevOption::new(1, 10000, true);
+        let table = LookupTable::new(&option, instances.clone());
+        assert_eq!(table.slots.len(), option.table_size);
+        let mut counts = HashMap::new();
+        for slot in 0..table.slots.len() {
+            *counts.entry(table.instance(slot).weight).or_insert(0) += 1;
+        }
+        // the instances with double weight get double slots
+        let ratio = counts[&20] as f64 / counts[&10] as f64;
+        assert!((ratio - 2.0).abs() < 0.1, "ratio: {ratio}");
+
+        // removing an instance only changes the slots of it, and a few others
+        let mut removed = instances.clone();
+        let removed_instance = removed.remove(3);
+        let new_table = LookupTable::new(&option, removed);
+        let changed = (0..table.slots.len())
+            .filter(|slot| {
+                let prev = table.instance(*slot);
+                prev.address != removed_instance.address
+                    && prev.address != new_table.instance(*slot).address
+            })
+            .count();
+        assert!(
+            changed < table.slots.len() / 20,
+            "changed: {changed} of {}",
+            table.slots.len()
+        );
+
+        let unweighted = LookupTable::new(&MaglevOption::new(1, 10000, false), instances);
+        let mut counts = HashMap::new();
+        for slot in 0..unweighted.slots.len() {
+            *counts.entry(unweighted.instance(slot).weight).or_insert(0) += 1;
+        }
+        let ratio = counts[&20] as f64 / counts[&10] as f64;
+        assert!((ratio - 1.0).abs() < 0.1, "ratio: {ratio}");
+    }
+
+    #[tokio::test]
+    async fn test_maglev_balance() {
+        METAINFO
+            .scope(RefCell::new(MetaInfo::new()), async {
+                let empty = Endpoint::new("".into());
+                let discover = StaticDiscover::from(vec![
+                    "127.0.0.1:8000".parse().unwrap(),
+                    "127.0.0.2:9000".parse().unwrap(),
+                    "127.0.0.3:9000".parse().unwrap(),
+                ]);
+
+                let lb = MaglevBalance::new(MaglevOption::new(2, 1000, true));
+                for _ in 0..100 {
+                    let request_hash = rand::random::<u64>();
+                    set_request_hash(request_hash);
+                    let all = lb
+                        .get_picker(&empty, &discover)
+                        .await
+                        .unwrap()
+                        .collect::<Vec<_>>();
+                    assert_eq!(all.len(), 2);
+                    assert_ne!(all[0], all[1]);
+                    let again = lb
+                        .get_picker(&empty, &discover)
+                        .await
+                        .unwrap()
+                        .collect::<Vec<_>>();
+                    assert_eq!(all, again);
+                }
+
+                let lb = MaglevBalance::new(MaglevOption::new(5, 1000, true));
+                let all = lb
+                    .get_picker(&empty, &discover)
+                    .await
+                    .unwrap()
+                    .collect::<Vec<_>>();
+                assert_eq!(all.len(), 3);
+            })
+            .await;
+    }
+}
@@ -1,20 +1,107 @@
+pub mod bounded_load;
 pub mod consistent_hash;
 pub mod error;
+pub mod hedge;
 mod layer;
+pub mod maglev;
+pub mod outlier;
+pub mod p2c;
 pub mod random;
+pub mod round_robin;
+pub mod subset;
 
-use std::future::Future;
+use std::{
+    future::Future,
+    marker::PhantomData,
+    time::{Duration, Instant},
+};
 
-use self::{error::LoadBalanceError, layer::LoadBalanceLayer};
+use self::{
+    error::LoadBalanceError,
+    hedge::{Hedging, NoHedging},
+    layer::LoadBalanceLayer,
+};
 use crate::{
     context::Endpoint,
     discovery::{Change, Discover},
     net::Address,
+    retry::{RetryBudget, RetryPolicy},
 };
 
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Ord, Eq, Hash)]
 pub struct RequestHash(pub u64);
 
+impl RequestHash {
+    /// Hashes the key of the request, e.g., the value of a header or a field of the request.
+    pub fn from_key(key: impl AsRef<[u8]>) -> Self {
+        RequestHash(mur3::murmurhash3_x64_128(key.as_ref(), 0).0)
+    }
+}
+
+/// Gets the [`RequestHash`] of the current request, which is required by the hashing load
+/// balancers.
+///
+/// The hash inserted into the callee by the [`HashKeyExtractor`] takes precedence over the one
+/// in the metainfo.
+fn current_request_hash(endpoint: &Endpoint) -> Result<RequestHash, LoadBalanceError> {
+    if let Some(hash) = endpoint.get::<RequestHash>() {
+        return Ok(*hash);
+    }
+    metainfo::METAINFO
+        .try_with(|m| m.borrow().get::<RequestHash>().copied())
+        .map_err(|_| LoadBalanceError::MissRequestHash)?
+        .ok_or(LoadBalanceError::MissRequestHash)
+}
+
+/// [`HashKeyExtractor`] computes the [`RequestHash`] of a request for the hashing load
+/// balancers, e.g., from a field of the request or a header.
+///
+/// The load balance services call it before picking the instances, and insert the hash into the
+/// callee, so that the users don't have to set the [`RequestHash`] in the metainfo before every
+/// call. If it returns `None`, the [`RequestHash`] in the metainfo is used.
+///
+/// It is implemented for the closures `Fn(&Cx, &Req) -> Option<RequestHash>`.
+pub trait HashKeyExtractor<Cx, Req>: Send + Sync + 'static {
+    fn extract(&self, cx: &Cx, req: &Req) -> Option<RequestHash>;
+}
+
+impl<Cx, Req, F> HashKeyExtractor<Cx, Req> for F
+where
+    F: Fn(&Cx, &Req) -> Option<RequestHash> + Send + Sync + 'static,
+{
+    fn extract(&self, cx: &Cx, req: &Req) -> Option<RequestHash> {
+        self(cx, req)
+    }
+}
+
+/// The default [`HashKeyExtractor`], which extracts nothing and leaves the [`RequestHash`] in the
+/// metainfo to the hashing load balancers.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MetaInfoHashKey;
+
+impl<Cx, Req> HashKeyExtractor<Cx, Req> for MetaInfoHashKey {
+    fn extract(&self, _: &Cx, _: &Req) -> Option<RequestHash> {
+        None
+    }
+}
+
+/// The result of a call to an instance picked by [`LoadBalance`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallOutcome {
+    Success,
+    Failure,
+    /// The call was dropped before it finished, e.g., timed out by an outer layer.
+    Cancelled,
+}
+
+/// [`CallFeedback`] is reported to [`LoadBalance::on_call_end`] when a call finishes.
+#[derive(Clone, Copy, Debug)]
+pub struct CallFeedback {
+    pub outcome: CallOutcome,
+    /// The time elapsed since the call was sent to the instance.
+    pub elapsed: Duration,
+}
+
 /// [`LoadBalance`] promise the feature of the load balance policy.
 pub trait LoadBalance<D>: Send + Sync + 'static
 where
@@ -32,6 +119,87 @@ where
     ) -> impl Future<Output = Result<Self::InstanceIter, LoadBalanceError>> + Send;
     /// `rebalance` is the callback method be used in service discovering subscription.
     fn rebalance(&self, changes: Change<D::Key>);
+
+    /// `evict` drops the cached instances of the key, which are discovered again when the key is
+    /// used next time.
+    ///
+    /// It is called with the keys received from [`Discover::watch_evicted`], e.g., the unused
+    /// subsets of [`SubsetDiscover`](subset::SubsetDiscover). The load balancers caching the
+    /// instances should implement it, otherwise the evicted keys stay in the caches without
+    /// being updated anymore.
+    fn evict(&self, _key: &D::Key) {}
+
+    /// `on_call_start` is called before a request is sent to the picked instance.
+    ///
+    /// Every `on_call_start` is paired with exactly one [`LoadBalance::on_call_end`], which
+    /// can be used for tracking the in-flight requests of each instance.
+    fn on_call_start(&self, _address: &Address) {}
+
+    /// `on_call_end` is called when the call to the picked instance finishes, with the outcome
+    /// and latency of the call.
+    fn on_call_end(&self, _address: &Address, _feedback: CallFeedback) {}
+}
+
+/// [`CallTracker`] reports a call to the picked instance to the [`LoadBalance`].
+///
+/// It calls [`LoadBalance::on_call_start`] when created, and [`LoadBalance::on_call_end`] when
+/// [`CallTracker::finish`] is called. If it is dropped without finishing, the call is reported
+/// as [`CallOutcome::Cancelled`].
+pub struct CallTracker<'a, D, LB>
+where
+    D: Discover,
+    LB: LoadBalance<D>,
+{
+    load_balance: &'a LB,
+    address: Address,
+    start: Instant,
+    finished: bool,
+    _marker: PhantomData<fn() -> D>,
+}
+
+impl<'a, D, LB> CallTracker<'a, D, LB>
+where
+    D: Discover,
+    LB: LoadBalance<D>,
+{
+    pub fn new(load_balance: &'a LB, address: Address) -> Self {
+        load_balance.on_call_start(&address);
+        Self {
+            load_balance,
+            address,
+            start: Instant::now(),
+            finished: false,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn finish(mut self, outcome: CallOutcome) {
+        self.report(outcome);
+    }
+
+    fn report(&mut self, outcome: CallOutcome) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+        self.load_balance.on_call_end(
+            &self.address,
+            CallFeedback {
+                outcome,
+                elapsed: self.start.elapsed(),
+            },
+        );
+    }
+}
+
+impl<D, LB> Drop for CallTracker<'_, D, LB>
+where
+    D: Discover,
+    LB: LoadBalance<D>,
+{
+    fn drop(&mut self) {
+        self.report(CallOutcome::Cancelled);
+    }
 }
 
 pub trait MkLbLayer {
@@ -40,10 +208,13 @@ pub trait MkLbLayer {
     fn make(self) -> Self::Layer;
 }
 
-pub struct LbConfig<L, DISC> {
+pub struct LbConfig<L, DISC, H = MetaInfoHashKey, M = NoHedging> {
     load_balance: L,
     discover: DISC,
-    retry_count: usize,
+    hash_key: H,
+    retry_policy: RetryPolicy,
+    retry_budget: Option<RetryBudget>,
+    mode: M,
 }
 
 impl<L, DISC> LbConfig<L, DISC> {
@@ -51,40 +222,96 @@ impl<L, DISC> LbConfig<L, DISC> {
         LbConfig {
             load_balance,
             discover,
-            retry_count: 0,
+            hash_key: MetaInfoHashKey,
+            retry_policy: RetryPolicy::no_retry(),
+            retry_budget: None,
+            mode: NoHedging,
         }
     }
+}
 
-    pub fn load_balance<NL>(self, load_balance: NL) -> LbConfig<NL, DISC> {
+impl<L, DISC, H, M> LbConfig<L, DISC, H, M> {
+    pub fn load_balance<NL>(self, load_balance: NL) -> LbConfig<NL, DISC, H, M> {
         LbConfig {
             load_balance,
             discover: self.discover,
-            retry_count: self.retry_count,
+            hash_key: self.hash_key,
+            retry_policy: self.retry_policy,
+            retry_budget: self.retry_budget,
+            mode: self.mode,
         }
     }
 
-    pub fn discover<NDISC>(self, discover: NDISC) -> LbConfig<L, NDISC> {
+    pub fn discover<NDISC>(self, discover: NDISC) -> LbConfig<L, NDISC, H, M> {
         LbConfig {
             load_balance: self.load_balance,
             discover,
-            retry_count: self.retry_count,
+            hash_key: self.hash_key,
+            retry_policy: self.retry_policy,
+            retry_budget: self.retry_budget,
+            mode: self.mode,
+        }
+    }
+
+    /// Sets the [`HashKeyExtractor`] for the hashing load balancers, the [`RequestHash`] in the
+    /// metainfo is used by default.
+    pub fn hash_key<NH>(self, hash_key: NH) -> LbConfig<L, DISC, NH, M> {
+        LbConfig {
+            load_balance: self.load_balance,
+            discover: self.discover,
+            hash_key,
+            retry_policy: self.retry_policy,
+            retry_budget: self.retry_budget,
+            mode: self.mode,
+        }
+    }
+
+    /// Enables hedging the calls with the [`HedgePolicy`](hedge::HedgePolicy) in the callee tags,
+    /// which requires more bounds of the context, see [`Hedging`].
+    pub fn hedging(self) -> LbConfig<L, DISC, H, Hedging> {
+        LbConfig {
+            load_balance: self.load_balance,
+            discover: self.discover,
+            hash_key: self.hash_key,
+            retry_policy: self.retry_policy,
+            retry_budget: self.retry_budget,
+            mode: Hedging,
         }
     }
 
     /// Sets the retry count of the client.
+    ///
+    /// The requests are retried immediately, use [`LbConfig::retry_policy`] for the backoff.
     pub fn retry_count(mut self, count: usize) -> Self {
-        self.retry_count = count;
+        self.retry_policy = RetryPolicy::new(count + 1);
+        self
+    }
+
+    /// Sets the [`RetryPolicy`] of the client, which can be overridden for a single call by
+    /// inserting a [`RetryPolicy`] into the callee tags.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Limits the retries of the client by the [`RetryBudget`].
+    pub fn retry_budget(mut self, budget: RetryBudget) -> Self {
+        self.retry_budget = Some(budget);
         self
     }
 }
 
 pub struct CustomLayer<L>(pub L);
 
-impl<LB, DISC> MkLbLayer for LbConfig<LB, DISC> {
-    type Layer = LoadBalanceLayer<DISC, LB>;
+impl<LB, DISC, H, M> MkLbLayer for LbConfig<LB, DISC, H, M> {
+    type Layer = LoadBalanceLayer<DISC, LB, H, M>;
 
     fn make(self) -> Self::Layer {
-        LoadBalanceLayer::new(self.discover, self.load_balance, self.retry_count)
+        LoadBalanceLayer::new(self.discover, self.load_balance, 0)
+            .with_retry_policy(self.retry_policy)
+            .with_retry_budget(self.retry_budget)
+            .with_hash_key(self.hash_key)
+            .with_mode(self.mode)
     }
 }
 
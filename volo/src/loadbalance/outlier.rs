@@ -0,0 +1,645 @@
+//! Passive outlier detection.
+//!
+//! [`OutlierDetectionBalance`] wraps a [`LoadBalance`] and watches the results of the calls
+//! reported by [`LoadBalance::on_call_end`]. The instances that fail too often are ejected
+//! temporarily, which means they will not be picked until the ejection expires, unless there
+//! is no other instance to pick.
+//!
+//! Since the calls are reported by the load balance layers of `volo-thrift`, `volo-grpc` and
+//! `volo-http`, it can be used with any of their clients:
+//!
+//! ```rust,ignore
+//! let client = ClientBuilder::new("service")
+//!     .load_balance(OutlierDetectionBalance::new(
+//!         WeightedRandomBalance::new(),
+//!         OutlierDetectionConfig::new().consecutive_errors(5),
+//!     ))
+//!     .build();
+//! ```
+
+use std::{
+    collections::HashSet,
+    fmt,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+
+use super::{CallFeedback, CallOutcome, LoadBalance, error::LoadBalanceError};
+use crate::{
+    context::Endpoint,
+    discovery::{Change, Discover},
+    net::Address,
+};
+
+/// Configuration of [`OutlierDetectionBalance`].
+#[derive(Debug, Clone)]
+pub struct OutlierDetectionConfig {
+    /// The number of consecutive errors before an instance is ejected, `0` disables it.
+    consecutive_errors: u32,
+    /// The error rate in the current interval before an instance is ejected, `None` disables it.
+    error_rate: Option<f64>,
+    /// The minimum number of requests in the current interval for checking the error rate.
+    min_requests: u32,
+    /// The length of the interval for counting the error rate.
+    interval: Duration,
+    /// The ejection time of the first ejection, it is doubled each time the instance is ejected
+    /// again.
+    base_ejection_time: Duration,
+    /// The maximum ejection time.
+    max_ejection_time: Duration,
+    /// The maximum percentage of the instances that can be ejected at the same time, but at
+    /// least one instance can be ejected.
+    max_ejection_percent: u8,
+}
+
+impl Default for OutlierDetectionConfig {
+    fn default() -> Self {
+        Self {
+            consecutive_errors: 5,
+            error_rate: None,
+            min_requests: 20,
+            interval: Duration::from_secs(10),
+            base_ejection_time: Duration::from_secs(30),
+            max_ejection_time: Duration::from_secs(300),
+            max_ejection_percent: 10,
+        }
+    }
+}
+
+impl OutlierDetectionConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn consecutive_errors(mut self, count: u32) -> Self {
+        self.consecutive_errors = count;
+        self
+    }
+
+    /// Ejects the instances whose error rate reaches `rate` in an interval, only if there are
+    /// at least `min_requests` requests in the interval.
+    pub fn error_rate(mut self, rate: f64, min_requests: u32) -> Self {
+        self.error_rate = Some(rate);
+        self.min_requests = min_requests;
+        self
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn base_ejection_time(mut self, time: Duration) -> Self {
+        self.base_ejection_time = time;
+        self
+    }
+
+    pub fn max_ejection_time(mut self, time: Duration) -> Self {
+        self.max_ejection_time = time;
+        self
+    }
+
+    pub fn max_ejection_percent(mut self, percent: u8) -> Self {
+        self.max_ejection_percent = percent.min(100);
+        self
+    }
+
+    fn ejection_time(&self, ejection_count: u32) -> Duration {
+        let factor = 1u32 << ejection_count.saturating_sub(1).min(16);
+        self.base_ejection_time
+            .saturating_mul(factor)
+            .min(self.max_ejection_time)
+    }
+}
+
+#[derive(Debug)]
+struct InstanceStats {
+    consecutive_errors: u32,
+    requests: u32,
+    errors: u32,
+    interval_start: Instant,
+    ejected_until: Option<Instant>,
+    /// The number of times the instance has been ejected recently, which is decreased by one for
+    /// each interval without ejection.
+    ejection_count: u32,
+}
+
+impl InstanceStats {
+    fn new(now: Instant) -> Self {
+        Self {
+            consecutive_errors: 0,
+            requests: 0,
+            errors: 0,
+            interval_start: now,
+            ejected_until: None,
+            ejection_count: 0,
+        }
+    }
+
+    fn is_ejected(&self, now: Instant) -> bool {
+        self.ejected_until.is_some_and(|until| until > now)
+    }
+}
+
+/// The set of the currently ejected instances shared with the pickers.
+type Ejected = Arc<HashSet<Address>>;
+
+/// [`OutlierDetectionBalance`] ejects the failing instances from a [`LoadBalance`].
+pub struct OutlierDetectionBalance<LB, K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    inner: LB,
+    config: OutlierDetectionConfig,
+    stats: DashMap<Address, InstanceStats>,
+    ejected: arc_swap::ArcSwap<HashSet<Address>>,
+    /// The currently discovered instances of each key, which the maximum ejection percent of the
+    /// key is computed from.
+    instances: DashMap<K, HashSet<Address>>,
+    /// Serializes the ejections, so that the maximum ejection percent is not exceeded by the
+    /// concurrent ones.
+    eject_lock: Mutex<()>,
+}
+
+impl<LB, K> OutlierDetectionBalance<LB, K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    pub fn new(inner: LB, config: OutlierDetectionConfig) -> Self {
+        Self {
+            inner,
+            config,
+            stats: DashMap::new(),
+            ejected: Default::default(),
+            instances: DashMap::new(),
+            eject_lock: Mutex::new(()),
+        }
+    }
+
+    /// Returns whether the instance is ejected now.
+    pub fn is_ejected(&self, address: &Address) -> bool {
+        self.stats
+            .get(address)
+            .is_some_and(|stats| stats.is_ejected(Instant::now()))
+    }
+
+    /// Recomputes the set of the ejected instances, the expired ejections are dropped.
+    fn refresh_ejected(&self, now: Instant) {
+        let ejected = self
+            .stats
+            .iter()
+            .filter(|entry| entry.is_ejected(now))
+            .map(|entry| entry.key().clone())
+            .collect::<HashSet<_>>();
+        self.ejected.store(Arc::new(ejected));
+    }
+
+    /// Returns whether the instance can be ejected without exceeding the maximum ejection percent
+    /// of any key it is discovered by.
+    fn can_eject(&self, address: &Address, now: Instant) -> bool {
+        let allowed = |total: usize, ejected: usize| {
+            ejected == 0 || ejected * 100 < total * self.config.max_ejection_percent as usize
+        };
+        let is_ejected = |addr: &Address| {
+            self.stats
+                .get(addr)
+                .is_some_and(|stats| stats.is_ejected(now))
+        };
+
+        let mut discovered = false;
+        for instances in self.instances.iter() {
+            if !instances.contains(address) {
+                continue;
+            }
+            discovered = true;
+            let ejected = instances.iter().filter(|addr| is_ejected(addr)).count();
+            if !allowed(instances.len(), ejected) {
+                return false;
+            }
+        }
+        if discovered {
+            return true;
+        }
+
+        // the instances called before being discovered are counted together
+        let ejected = self
+            .stats
+            .iter()
+            .filter(|entry| entry.is_ejected(now))
+            .count();
+        allowed(self.stats.len(), ejected)
+    }
+
+    fn record(&self, address: &Address, outcome: CallOutcome) {
+        // the cancelled calls tell nothing about the instance
+        if outcome == CallOutcome::Cancelled {
+            return;
+        }
+        let now = Instant::now();
+        let mut stats = self
+            .stats
+            .entry(address.clone())
+            .or_insert_with(|| InstanceStats::new(now));
+
+        if now.saturating_duration_since(stats.interval_start) >= self.config.interval {
+            stats.requests = 0;
+            stats.errors = 0;
+            stats.interval_start = now;
+            if !stats.is_ejected(now) {
+                stats.ejection_count = stats.ejection_count.saturating_sub(1);
+            }
+        }
+        if stats.ejected_until.is_some_and(|until| until <= now) {
+            // readmitted
+            stats.ejected_until = None;
+            stats.consecutive_errors = 0;
+        }
+
+        stats.requests += 1;
+        if outcome == CallOutcome::Success {
+            stats.consecutive_errors = 0;
+            return;
+        }
+        stats.consecutive_errors += 1;
+        stats.errors += 1;
+        if stats.is_ejected(now) {
+            return;
+        }
+
+        let consecutive = self.config.consecutive_errors > 0
+            && stats.consecutive_errors >= self.config.consecutive_errors;
+        let rate = self.config.error_rate.is_some_and(|rate| {
+            stats.requests >= self.config.min_requests
+                && stats.errors as f64 >= rate * stats.requests as f64
+        });
+        if !consecutive && !rate {
+            return;
+        }
+        let (errors, requests) = (stats.errors, stats.requests);
+        // release the entry lock before iterating the stats
+        drop(stats);
+        let _guard = self.eject_lock.lock().unwrap();
+        if !self.can_eject(address, now) {
+            tracing::debug!(
+                "[VOLO] outlier detection: skip ejecting {address}, max ejection percent reached"
+            );
+            return;
+        }
+        if let Some(mut stats) = self.stats.get_mut(address) {
+            if stats.is_ejected(now) {
+                // ejected concurrently
+                return;
+            }
+            stats.ejection_count += 1;
+            let ejection_time = self.config.ejection_time(stats.ejection_count);
+            stats.ejected_until = Some(now + ejection_time);
+            stats.consecutive_errors = 0;
+            tracing::warn!(
+                "[VOLO] outlier detection: eject {address} for {ejection_time:?}, errors in \
+                 current interval: {errors}/{requests}"
+            );
+        }
+        self.refresh_ejected(now);
+    }
+}
+
+impl<LB, K> fmt::Debug for OutlierDetectionBalance<LB, K>
+where
+    LB: fmt::Debug,
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OutlierDetectionBalance")
+            .field("inner", &self.inner)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+/// [`OutlierPicker`] yields the instances of the inner picker except the ejected ones, which are
+/// yielded at last if all the other instances have been yielded.
+#[derive(Debug)]
+pub struct OutlierPicker<I> {
+    inner: I,
+    ejected: Ejected,
+    skipped: Vec<Address>,
+}
+
+impl<I> Iterator for OutlierPicker<I>
+where
+    I: Iterator<Item = Address>,
+{
+    type Item = Address;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for addr in self.inner.by_ref() {
+            if self.ejected.contains(&addr) {
+                self.skipped.push(addr);
+                continue;
+            }
+            return Some(addr);
+        }
+        if self.skipped.is_empty() {
+            None
+        } else {
+            Some(self.skipped.remove(0))
+        }
+    }
+}
+
+impl<D, LB> LoadBalance<D> for OutlierDetectionBalance<LB, D::Key>
+where
+    D: Discover,
+    LB: LoadBalance<D>,
+{
+    type InstanceIter = OutlierPicker<LB::InstanceIter>;
+
+    async fn get_picker<'future>(
+        &'future self,
+        endpoint: &'future Endpoint,
+        discover: &'future D,
+    ) -> Result<Self::InstanceIter, LoadBalanceError> {
+        let key = discover.key(endpoint);
+        if !self.instances.contains_key(&key) {
+            // the discover may not be watchable, so the instances are discovered once here
+            let instances = discover.discover(endpoint).await.map_err(Into::into)?;
+            self.instances.entry(key).or_insert_with(|| {
+                instances
+                    .iter()
+                    .map(|instance| instance.address.clone())
+                    .collect()
+            });
+        }
+        let inner = self.inner.get_picker(endpoint, discover).await?;
+        let mut ejected = self.ejected.load_full();
+        let now = Instant::now();
+        if ejected
+            .iter()
+            .any(|addr| !self.stats.get(addr).is_some_and(|s| s.is_ejected(now)))
+        {
+            // some ejections have expired
+            self.refresh_ejected(now);
+            ejected = self.ejected.load_full();
+        }
+        Ok(OutlierPicker {
+            inner,
+            ejected,
+            skipped: Vec::new(),
+        })
+    }
+
+    fn rebalance(&self, changes: Change<D::Key>) {
+        let instances = changes
+            .all
+            .iter()
+            .map(|instance| instance.address.clone())
+            .collect();
+        self.instances.insert(changes.key.clone(), instances);
+        for instance in changes.removed.iter() {
+            // the instance may still be discovered by the other keys
+            if !self
+                .instances
+                .iter()
+                .any(|instances| instances.contains(&instance.address))
+            {
+                self.stats.remove(&instance.address);
+            }
+        }
+        if !changes.removed.is_empty() {
+            self.refresh_ejected(Instant::now());
+        }
+        self.inner.rebalance(changes);
+    }
+
+    fn evict(&self, key: &D::Key) {
+        self.instances.remove(key);
+        self.inner.evict(key);
+    }
+
+    fn on_call_start(&self, address: &Address) {
+        self.inner.on_call_start(address);
+    }
+
+    fn on_call_end(&self, address: &Address, feedback: CallFeedback) {
+        self.record(address, feedback.outcome);
+        self.inner.on_call_end(address, feedback);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, sync::Arc, time::Duration};
+
+    use async_broadcast::Receiver;
+    use faststr::FastStr;
+
+    use super::{OutlierDetectionBalance, OutlierDetectionConfig};
+    use crate::{
+        context::Endpoint,
+        discovery::{Change, Discover, Instance, StaticDiscover},
+        loadbalance::{CallFeedback, CallOutcome, LoadBalance, random::WeightedRandomBalance},
+        net::Address,
+    };
+
+    fn report(
+        lb: &OutlierDetectionBalance<WeightedRandomBalance<()>, ()>,
+        addr: &Address,
+        outcome: CallOutcome,
+    ) {
+        LoadBalance::<StaticDiscover>::on_call_start(lb, addr);
+        LoadBalance::<StaticDiscover>::on_call_end(
+            lb,
+            addr,
+            CallFeedback {
+                outcome,
+                elapsed: Duration::from_millis(1),
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_eject_consecutive_errors() {
+        let empty = Endpoint::new("".into());
+        let discover = StaticDiscover::from(vec![
+            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.2:8000".parse().unwrap(),
+        ]);
+        let bad = Address::Ip("127.0.0.1:8000".parse().unwrap());
+        let good = Address::Ip("127.0.0.2:8000".parse().unwrap());
+        let lb = OutlierDetectionBalance::new(
+            WeightedRandomBalance::new(),
+            OutlierDetectionConfig::new()
+                .consecutive_errors(3)
+                .max_ejection_percent(50)
+                .base_ejection_time(Duration::from_millis(100)),
+        );
+
+        report(&lb, &good, CallOutcome::Success);
+        for _ in 0..2 {
+            report(&lb, &bad, CallOutcome::Failure);
+        }
+        report(&lb, &bad, CallOutcome::Success);
+        for _ in 0..2 {
+            report(&lb, &bad, CallOutcome::Failure);
+        }
+        assert!(!lb.is_ejected(&bad));
+        report(&lb, &bad, CallOutcome::Failure);
+        assert!(lb.is_ejected(&bad));
+
+        for _ in 0..10 {
+            let picker = lb.get_picker(&empty, &discover).await.unwrap();
+            // the ejected instance is yielded at last
+            assert_eq!(picker.collect::<Vec<_>>(), vec![good.clone(), bad.clone()]);
+        }
+
+        // the good one cannot be ejected since half of the instances are ejected
+        for _ in 0..3 {
+            report(&lb, &good, CallOutcome::Failure);
+        }
+        assert!(!lb.is_ejected(&good));
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(!lb.is_ejected(&bad));
+    }
+
+    #[test]
+    fn test_ejection_time() {
+        let config = OutlierDetectionConfig::new()
+            .base_ejection_time(Duration::from_secs(10))
+            .max_ejection_time(Duration::from_secs(60));
+        assert_eq!(config.ejection_time(1), Duration::from_secs(10));
+        assert_eq!(config.ejection_time(2), Duration::from_secs(20));
+        assert_eq!(config.ejection_time(3), Duration::from_secs(40));
+        assert_eq!(config.ejection_time(4), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_eject_error_rate() {
+        let bad = Address::Ip("127.0.0.1:8000".parse().unwrap());
+        let lb = OutlierDetectionBalance::new(
+            WeightedRandomBalance::new(),
+            OutlierDetectionConfig::new()
+                .consecutive_errors(0)
+                .error_rate(0.5, 10),
+        );
+        for _ in 0..4 {
+            report(&lb, &bad, CallOutcome::Success);
+            report(&lb, &bad, CallOutcome::Failure);
+        }
+        assert!(!lb.is_ejected(&bad));
+        report(&lb, &bad, CallOutcome::Success);
+        report(&lb, &bad, CallOutcome::Failure);
+        assert!(lb.is_ejected(&bad));
+    }
+
+    #[tokio::test]
+    async fn test_max_ejection_percent_of_current_instances() {
+        let empty = Endpoint::new("".into());
+        let a = Address::Ip("127.0.0.1:8000".parse().unwrap());
+        let b = Address::Ip("127.0.0.2:8000".parse().unwrap());
+        let discover = StaticDiscover::from(vec![
+            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.2:8000".parse().unwrap(),
+        ]);
+        let lb = OutlierDetectionBalance::new(
+            WeightedRandomBalance::new(),
+            OutlierDetectionConfig::new()
+                .consecutive_errors(1)
+                .max_ejection_percent(50),
+        );
+        lb.get_picker(&empty, &discover).await.unwrap();
+
+        // the instances which are not discovered do not raise the cap
+        for port in 9000..9004 {
+            let stale = Address::Ip(format!("127.0.0.3:{port}").parse().unwrap());
+            report(&lb, &stale, CallOutcome::Success);
+        }
+        report(&lb, &a, CallOutcome::Failure);
+        assert!(lb.is_ejected(&a));
+        report(&lb, &b, CallOutcome::Failure);
+        assert!(!lb.is_ejected(&b));
+
+        // the cancelled calls are ignored
+        report(&lb, &b, CallOutcome::Cancelled);
+        assert!(!lb.is_ejected(&b));
+    }
+
+    /// Discovers two instances for each service, which is the key.
+    struct ServiceDiscover;
+
+    impl ServiceDiscover {
+        fn address(service: &str, index: u16) -> Address {
+            let host = if service == "a" { 1 } else { 2 };
+            Address::Ip(format!("127.0.0.{host}:{}", 8000 + index).parse().unwrap())
+        }
+    }
+
+    impl Discover for ServiceDiscover {
+        type Key = FastStr;
+        type Error = Infallible;
+
+        async fn discover<'s>(
+            &'s self,
+            endpoint: &'s Endpoint,
+        ) -> Result<Vec<Arc<Instance>>, Self::Error> {
+            Ok((0..2)
+                .map(|index| {
+                    Arc::new(Instance {
+                        address: Self::address(endpoint.service_name_ref(), index),
+                        weight: 10,
+                        tags: Default::default(),
+                    })
+                })
+                .collect())
+        }
+
+        fn key(&self, endpoint: &Endpoint) -> Self::Key {
+            endpoint.service_name()
+        }
+
+        fn watch(&self, _: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+            None
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_ejection_percent_of_each_key() {
+        let lb = OutlierDetectionBalance::new(
+            WeightedRandomBalance::new(),
+            OutlierDetectionConfig::new()
+                .consecutive_errors(1)
+                .max_ejection_percent(50),
+        );
+        let fail = |addr: &Address| {
+            LoadBalance::<ServiceDiscover>::on_call_start(&lb, addr);
+            LoadBalance::<ServiceDiscover>::on_call_end(
+                &lb,
+                addr,
+                CallFeedback {
+                    outcome: CallOutcome::Failure,
+                    elapsed: Duration::from_millis(1),
+                },
+            );
+        };
+
+        for service in ["a", "b"] {
+            lb.get_picker(&Endpoint::new(service.into()), &ServiceDiscover)
+                .await
+                .unwrap();
+        }
+        for service in ["a", "b"] {
+            // the cap of each service is computed from its own instances
+            let (first, second) = (
+                ServiceDiscover::address(service, 0),
+                ServiceDiscover::address(service, 1),
+            );
+            fail(&first);
+            assert!(lb.is_ejected(&first));
+            fail(&second);
+            assert!(!lb.is_ejected(&second));
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    hash::Hash,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use dashmap::{DashMap, mapref::entry::Entry};
+use rand::Rng;
+
+use super::{
+    CallFeedback, CallOutcome, LoadBalance, error::LoadBalanceError, round_robin::InstancePicker,
+};
+use crate::{
+    context::Endpoint,
+    discovery::{Change, Discover, Instance},
+    net::Address,
+};
+
+const DEFAULT_DECAY: Duration = Duration::from_secs(10);
+
+/// Exponentially weighted moving average of the response time, which decays by the time
+/// elapsed since the last observation.
+#[derive(Debug, Default)]
+struct Ewma {
+    /// The average latency in nanoseconds.
+    value: f64,
+    last_update: Option<Instant>,
+}
+
+impl Ewma {
+    fn observe(&mut self, sample: Duration, decay: Duration) {
+        let now = Instant::now();
+        let sample = sample.as_nanos() as f64;
+        self.value = match self.last_update {
+            None => sample,
+            Some(last_update) => {
+                let elapsed = now.saturating_duration_since(last_update).as_secs_f64();
+                let w = (-elapsed / decay.as_secs_f64()).exp();
+                self.value * w + sample * (1.0 - w)
+            }
+        };
+        self.last_update = Some(now);
+    }
+}
+
+#[derive(Debug, Default)]
+struct InstanceStats {
+    in_flight: AtomicUsize,
+    latency: Mutex<Ewma>,
+}
+
+impl InstanceStats {
+    fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    fn latency(&self) -> f64 {
+        self.latency.lock().unwrap().value
+    }
+}
+
+/// [`P2cBalance`] is the power of two choices load balancer.
+///
+/// It picks two instances randomly and chooses the less loaded one, where the load of an
+/// instance is estimated by its in-flight requests and the moving average of its response
+/// time, divided by its weight.
+///
+/// The load of the instances is collected by [`LoadBalance::on_call_start`] and
+/// [`LoadBalance::on_call_end`], so it only works with the load balance layers which report
+/// the calls.
+#[derive(Debug)]
+pub struct P2cBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    decay: Duration,
+    router: DashMap<K, Arc<[Arc<Instance>]>>,
+    stats: DashMap<Address, Arc<InstanceStats>>,
+}
+
+impl<K> P2cBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    pub fn with_discover<D>(_: &D) -> Self
+    where
+        D: Discover<Key = K>,
+    {
+        Self::new()
+    }
+
+    pub fn new() -> Self {
+        Self {
+            decay: DEFAULT_DECAY,
+            router: DashMap::new(),
+            stats: DashMap::new(),
+        }
+    }
+
+    /// Sets the decay time of the response time moving average, the default is 10 seconds.
+    ///
+    /// The smaller the value, the faster the balancer reacts to the latency changes.
+    pub fn with_decay(mut self, decay: Duration) -> Self {
+        self.decay = decay;
+        self
+    }
+
+    fn cost(&self, instance: &Instance) -> f64 {
+        let (in_flight, latency) = match self.stats.get(&instance.address) {
+            Some(stats) => (stats.in_flight(), stats.latency()),
+            None => (0, 0.0),
+        };
+        (latency + 1.0) * (in_flight + 1) as f64 / instance.weight.max(1) as f64
+    }
+
+    fn pick(&self, instances: &[Arc<Instance>]) -> Option<usize> {
+        match instances.len() {
+            0 => None,
+            1 => Some(0),
+            len => {
+                let mut rng = rand::rng();
+                let a = rng.random_range(0..len);
+                let mut b = rng.random_range(0..len - 1);
+                if b >= a {
+                    b += 1;
+                }
+                if self.cost(&instances[a]) <= self.cost(&instances[b]) {
+                    Some(a)
+                } else {
+                    Some(b)
+                }
+            }
+        }
+    }
+}
+
+impl<K> Default for P2cBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> LoadBalance<D> for P2cBalance<D::Key>
+where
+    D: Discover,
+{
+    type InstanceIter = InstancePicker;
+
+    async fn get_picker<'future>(
+        &'future self,
+        endpoint: &'future Endpoint,
+        discover: &'future D,
+    ) -> Result<Self::InstanceIter, LoadBalanceError> {
+        let key = discover.key(endpoint);
+        let instances = if let Some(instances) = self.router.get(&key) {
+            instances.clone()
+        } else {
+            let instances: Arc<[Arc<Instance>]> = discover
+                .discover(endpoint)
+                .await
+                .map_err(|err| err.into())?
+                .into();
+            self.router.insert(key, instances.clone());
+            instances
+        };
+        let offset = self.pick(&instances).ok_or(LoadBalanceError::Retry)?;
+        Ok(InstancePicker::new(instances, offset))
+    }
+
+    fn rebalance(&self, changes: Change<D::Key>) {
+        for instance in changes.removed.iter() {
+            self.stats.remove(&instance.address);
+        }
+        if let Entry::Occupied(entry) = self.router.entry(changes.key.clone()) {
+            entry.replace_entry(changes.all.into());
+        }
+    }
+
+    fn evict(&self, key: &D::Key) {
+        self.router.remove(key);
+    }
+
+    fn on_call_start(&self, address: &Address) {
+        self.stats
+            .entry(address.clone())
+            .or_default()
+            .in_flight
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_call_end(&self, address: &Address, feedback: CallFeedback) {
+        let Some(stats) = self.stats.get(address).map(|stats| stats.clone()) else {
+            return;
+        };
+        let _ = stats
+            .in_flight
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+        if feedback.outcome != CallOutcome::Cancelled {
+            stats
+                .latency
+                .lock()
+                .unwrap()
+                .observe(feedback.elapsed, self.decay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use super::{LoadBalance, P2cBalance};
+    use crate::{
+        context::Endpoint,
+        discovery::StaticDiscover,
+        loadbalance::{CallFeedback, CallOutcome},
+        net::Address,
+    };
+
+    #[tokio::test]
+    async fn test_p2c_picks_all() {
+        let empty = Endpoint::new("".into());
+        let discover = StaticDiscover::from(vec![
+            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.2:9000".parse().unwrap(),
+        ]);
+        let lb = P2cBalance::with_discover(&discover);
+        let picker = lb.get_picker(&empty, &discover).await.unwrap();
+        let all = picker.collect::<Vec<_>>();
+        assert_eq!(all.len(), 2);
+        assert_ne!(all[0], all[1]);
+    }
+
+    #[tokio::test]
+    async fn test_p2c_prefers_less_loaded() {
+        let empty = Endpoint::new("".into());
+        let slow: Address = Address::Ip("127.0.0.1:8000".parse().unwrap());
+        let fast: Address = Address::Ip("127.0.0.2:8000".parse().unwrap());
+        let discover = StaticDiscover::from(vec![
+            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.2:8000".parse().unwrap(),
+        ]);
+        let lb = P2cBalance::with_discover(&discover);
+
+        for (addr, elapsed) in [
+            (&slow, Duration::from_millis(100)),
+            (&fast, Duration::from_millis(1)),
+        ] {
+            LoadBalance::<StaticDiscover>::on_call_start(&lb, addr);
+            LoadBalance::<StaticDiscover>::on_call_end(
+                &lb,
+                addr,
+                CallFeedback {
+                    outcome: CallOutcome::Success,
+                    elapsed,
+                },
+            );
+        }
+
+        let mut counts: HashMap<Address, usize> = HashMap::new();
+        for _ in 0..100 {
+            let mut picker = lb.get_picker(&empty, &discover).await.unwrap();
+            *counts.entry(picker.next().unwrap()).or_default() += 1;
+        }
+        assert_eq!(counts.get(&fast), Some(&100));
+
+        // the in-flight requests make the fast instance busier than the slow one
+        for _ in 0..1000 {
+            LoadBalance::<StaticDiscover>::on_call_start(&lb, &fast);
+        }
+        let mut picker = lb.get_picker(&empty, &discover).await.unwrap();
+        assert_eq!(picker.next(), Some(slow));
+    }
+}
@@ -167,6 +167,10 @@ where
             entry.replace_entry(Arc::new(WeightedInstances::from(changes.all)));
         }
     }
+
+    fn evict(&self, key: &D::Key) {
+        self.router.remove(key);
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,407 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use dashmap::{DashMap, mapref::entry::Entry};
+
+use super::{LoadBalance, error::LoadBalanceError};
+use crate::{
+    context::Endpoint,
+    discovery::{Change, Discover, Instance},
+    net::Address,
+};
+
+/// [`InstancePicker`] yields the instance chosen by the round robin policy first, then the
+/// following instances in order, so that retries are sent to the other instances.
+#[derive(Debug)]
+pub struct InstancePicker {
+    shared_instances: Arc<[Arc<Instance>]>,
+    offset: usize,
+    iter_times: usize,
+}
+
+impl InstancePicker {
+    pub(super) fn new(shared_instances: Arc<[Arc<Instance>]>, offset: usize) -> Self {
+        Self {
+            shared_instances,
+            offset,
+            iter_times: 0,
+        }
+    }
+}
+
+impl Iterator for InstancePicker {
+    type Item = Address;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = self.shared_instances.len();
+        if self.iter_times >= len {
+            return None;
+        }
+        let index = (self.offset + self.iter_times) % len;
+        self.iter_times += 1;
+        Some(self.shared_instances[index].address.clone())
+    }
+}
+
+#[derive(Debug)]
+struct RoundRobinInstances {
+    instances: Arc<[Arc<Instance>]>,
+    next: AtomicUsize,
+}
+
+impl RoundRobinInstances {
+    fn new(instances: Vec<Arc<Instance>>, next: usize) -> Self {
+        Self {
+            instances: instances.into(),
+            next: AtomicUsize::new(next),
+        }
+    }
+
+    fn pick(&self) -> Option<InstancePicker> {
+        if self.instances.is_empty() {
+            return None;
+        }
+        let offset = self.next.fetch_add(1, Ordering::Relaxed) % self.instances.len();
+        Some(InstancePicker::new(self.instances.clone(), offset))
+    }
+}
+
+/// [`RoundRobinBalance`] picks the instances in turn, ignoring their weights.
+#[derive(Debug)]
+pub struct RoundRobinBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    router: DashMap<K, Arc<RoundRobinInstances>>,
+}
+
+impl<K> RoundRobinBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    pub fn with_discover<D>(_: &D) -> Self
+    where
+        D: Discover<Key = K>,
+    {
+        Self::new()
+    }
+
+    pub fn new() -> Self {
+        Self {
+            router: DashMap::new(),
+        }
+    }
+}
+
+impl<K> Default for RoundRobinBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> LoadBalance<D> for RoundRobinBalance<D::Key>
+where
+    D: Discover,
+{
+    type InstanceIter = InstancePicker;
+
+    async fn get_picker<'future>(
+        &'future self,
+        endpoint: &'future Endpoint,
+        discover: &'future D,
+    ) -> Result<Self::InstanceIter, LoadBalanceError> {
+        let key = discover.key(endpoint);
+        let instances = if let Some(instances) = self.router.get(&key) {
+            instances.clone()
+        } else {
+            let instances = Arc::new(RoundRobinInstances::new(
+                discover
+                    .discover(endpoint)
+                    .await
+                    .map_err(|err| err.into())?,
+                0,
+            ));
+            self.router.insert(key, Arc::clone(&instances));
+            instances
+        };
+        instances.pick().ok_or(LoadBalanceError::Retry)
+    }
+
+    fn rebalance(&self, changes: Change<D::Key>) {
+        if let Entry::Occupied(entry) = self.router.entry(changes.key.clone()) {
+            // keep the position so that the rotation continues instead of restarting
+            let next = entry.get().next.load(Ordering::Relaxed);
+            entry.replace_entry(Arc::new(RoundRobinInstances::new(changes.all, next)));
+        }
+    }
+
+    fn evict(&self, key: &D::Key) {
+        self.router.remove(key);
+    }
+}
+
+#[derive(Debug)]
+struct SmoothWeightedInstances {
+    instances: Arc<[Arc<Instance>]>,
+    sum_of_weights: i64,
+    /// The current weight of each instance, the index is the same as `instances`.
+    current_weights: Mutex<Vec<i64>>,
+}
+
+impl SmoothWeightedInstances {
+    fn new(instances: Vec<Arc<Instance>>, prev: Option<&SmoothWeightedInstances>) -> Self {
+        let sum_of_weights = instances.iter().map(|i| i.weight as i64).sum();
+        let current_weights = match prev {
+            Some(prev) => {
+                // inherit the current weights of the remaining instances to keep the sequence
+                // smooth across rebalancing
+                let prev_weights = prev.current_weights.lock().unwrap();
+                let prev_map = prev
+                    .instances
+                    .iter()
+                    .zip(prev_weights.iter())
+                    .map(|(instance, weight)| (&instance.address, *weight))
+                    .collect::<HashMap<_, _>>();
+                instances
+                    .iter()
+                    .map(|i| prev_map.get(&i.address).copied().unwrap_or(0))
+                    .collect()
+            }
+            None => vec![0; instances.len()],
+        };
+        Self {
+            instances: instances.into(),
+            sum_of_weights,
+            current_weights: Mutex::new(current_weights),
+        }
+    }
+
+    /// Picks an instance by the smooth weighted round robin algorithm of nginx.
+    ///
+    /// Each time every instance increases its current weight by its weight, and the instance
+    /// with the largest current weight is selected and decreases its current weight by the sum
+    /// of weights.
+    fn pick(&self) -> Option<InstancePicker> {
+        if self.sum_of_weights == 0 {
+            return None;
+        }
+        let mut current_weights = self.current_weights.lock().unwrap();
+        let mut best: Option<usize> = None;
+        for (index, instance) in self.instances.iter().enumerate() {
+            if instance.weight == 0 {
+                continue;
+            }
+            current_weights[index] += instance.weight as i64;
+            if best.is_none_or(|best| current_weights[index] > current_weights[best]) {
+                best = Some(index);
+            }
+        }
+        let best = best?;
+        current_weights[best] -= self.sum_of_weights;
+        Some(InstancePicker::new(self.instances.clone(), best))
+    }
+}
+
+/// [`WeightedRoundRobinBalance`] is the smooth weighted round robin load balancer, which
+/// distributes the requests proportionally to the weights and interleaves the instances as
+/// evenly as possible.
+///
+/// For weights `{a: 5, b: 1, c: 1}`, the sequence is `a, a, b, a, c, a, a`.
+#[derive(Debug)]
+pub struct WeightedRoundRobinBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    router: DashMap<K, Arc<SmoothWeightedInstances>>,
+}
+
+impl<K> WeightedRoundRobinBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    pub fn with_discover<D>(_: &D) -> Self
+    where
+        D: Discover<Key = K>,
+    {
+        Self::new()
+    }
+
+    pub fn new() -> Self {
+        Self {
+            router: DashMap::new(),
+        }
+    }
+}
+
+impl<K> Default for WeightedRoundRobinBalance<K>
+where
+    K: Hash + PartialEq + Eq + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D> LoadBalance<D> for WeightedRoundRobinBalance<D::Key>
+where
+    D: Discover,
+{
+    type InstanceIter = InstancePicker;
+
+    async fn get_picker<'future>(
+        &'future self,
+        endpoint: &'future Endpoint,
+        discover: &'future D,
+    ) -> Result<Self::InstanceIter, LoadBalanceError> {
+        let key = discover.key(endpoint);
+        let instances = if let Some(instances) = self.router.get(&key) {
+            instances.clone()
+        } else {
+            let instances = Arc::new(SmoothWeightedInstances::new(
+                discover
+                    .discover(endpoint)
+                    .await
+                    .map_err(|err| err.into())?,
+                None,
+            ));
+            self.router.insert(key, Arc::clone(&instances));
+            instances
+        };
+        instances.pick().ok_or(LoadBalanceError::Retry)
+    }
+
+    fn rebalance(&self, changes: Change<D::Key>) {
+        if let Entry::Occupied(entry) = self.router.entry(changes.key.clone()) {
+            let instances = SmoothWeightedInstances::new(changes.all, Some(entry.get()));
+            entry.replace_entry(Arc::new(instances));
+        }
+    }
+
+    fn evict(&self, key: &D::Key) {
+        self.router.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use super::{LoadBalance, RoundRobinBalance, WeightedRoundRobinBalance};
+    use crate::{
+        context::Endpoint,
+        discovery::{Change, Instance, StaticDiscover, WeightedStaticDiscover},
+        net::Address,
+    };
+
+    fn new_instance(address: &str, weight: u32) -> Arc<Instance> {
+        Arc::new(Instance {
+            address: Address::Ip(address.parse().unwrap()),
+            weight,
+            tags: Default::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_round_robin() {
+        let empty = Endpoint::new("".into());
+        let discover = StaticDiscover::from(vec![
+            "127.0.0.1:8000".parse().unwrap(),
+            "127.0.0.2:9000".parse().unwrap(),
+            "127.0.0.3:9000".parse().unwrap(),
+        ]);
+        let lb = RoundRobinBalance::with_discover(&discover);
+
+        let mut firsts = Vec::new();
+        for _ in 0..6 {
+            let mut picker = lb.get_picker(&empty, &discover).await.unwrap();
+            firsts.push(picker.next().unwrap());
+        }
+        assert_eq!(firsts[0..3], firsts[3..6]);
+        assert_ne!(firsts[0], firsts[1]);
+        assert_ne!(firsts[1], firsts[2]);
+
+        // the picker yields every instance exactly once for retrying
+        let picker = lb.get_picker(&empty, &discover).await.unwrap();
+        let mut all = picker.collect::<Vec<_>>();
+        assert_eq!(all.len(), 3);
+        all.sort_by_key(|addr| addr.to_string());
+        all.dedup();
+        assert_eq!(all.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_smooth_weighted_round_robin() {
+        let empty = Endpoint::new("".into());
+        let discover = WeightedStaticDiscover::from(vec![
+            ("127.0.0.1:8000".parse().unwrap(), 5),
+            ("127.0.0.2:8000".parse().unwrap(), 1),
+            ("127.0.0.3:8000".parse().unwrap(), 1),
+        ]);
+        let lb = WeightedRoundRobinBalance::with_discover(&discover);
+
+        let mut seq = Vec::new();
+        for _ in 0..7 {
+            let mut picker = lb.get_picker(&empty, &discover).await.unwrap();
+            seq.push(picker.next().unwrap().to_string());
+        }
+        assert_eq!(
+            seq,
+            vec![
+                "127.0.0.1:8000",
+                "127.0.0.1:8000",
+                "127.0.0.2:8000",
+                "127.0.0.1:8000",
+                "127.0.0.3:8000",
+                "127.0.0.1:8000",
+                "127.0.0.1:8000",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_weighted_round_robin_rebalance() {
+        let empty = Endpoint::new("".into());
+        let discover = WeightedStaticDiscover::from(vec![
+            ("127.0.0.1:8000".parse().unwrap(), 2),
+            ("127.0.0.2:8000".parse().unwrap(), 1),
+        ]);
+        let lb = WeightedRoundRobinBalance::with_discover(&discover);
+        let _ = lb.get_picker(&empty, &discover).await.unwrap();
+
+        let all = vec![
+            new_instance("127.0.0.1:8000", 1),
+            new_instance("127.0.0.3:8000", 3),
+        ];
+        LoadBalance::<WeightedStaticDiscover>::rebalance(
+            &lb,
+            Change {
+                key: (),
+                all: all.clone(),
+                added: vec![all[1].clone()],
+                updated: vec![all[0].clone()],
+                removed: vec![new_instance("127.0.0.2:8000", 1)],
+            },
+        );
+
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for _ in 0..400 {
+            let mut picker = lb.get_picker(&empty, &discover).await.unwrap();
+            *counts
+                .entry(picker.next().unwrap().to_string())
+                .or_default() += 1;
+        }
+        assert_eq!(counts.len(), 2);
+        assert!(!counts.contains_key("127.0.0.2:8000"));
+        assert!((99..=101).contains(&counts["127.0.0.1:8000"]));
+        assert!((299..=301).contains(&counts["127.0.0.3:8000"]));
+    }
+}
@@ -0,0 +1,598 @@
+//! Tag-based subset routing.
+//!
+//! Subset routing filters the instances by their [`Instance::tags`] before load balancing, so
+//! that requests are only sent to a subset of the instances, e.g., the instances in the same
+//! zone as the caller, or the canary instances.
+//!
+//! It consists of two parts:
+//!
+//! - [`SubsetLayer`] evaluates a [`SubsetRule`] with the context of the request, which can read
+//!   the caller's [`Endpoint`] or the metainfo, and inserts the selected [`SubsetSelector`] into
+//!   the callee's [`Endpoint`].
+//! - [`SubsetDiscover`] wraps any [`Discover`] and filters the discovered instances with the
+//!   [`SubsetSelector`] in the callee's [`Endpoint`]. The selector is a part of the discover key,
+//!   so any [`LoadBalance`](super::LoadBalance), such as
+//!   [`WeightedRandomBalance`](super::random::WeightedRandomBalance) or
+//!   [`ConsistentHashBalance`](super::consistent_hash::ConsistentHashBalance), caches and
+//!   balances each subset separately.
+//!
+//! Since the [`SubsetLayer`] must be called before the load balance, it should be added as an
+//! outer layer of the client.
+//!
+//! There are at most [`SubsetDiscover::with_max_subsets`] subsets of each key, and the oldest one
+//! is evicted for a new one, so that the unused ones do not stay in the caches of the load
+//! balance, see [`LoadBalance::evict`](super::LoadBalance::evict).
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! // requests are sent to the instances with the same `zone` tag as the caller first, and
+//! // fall back to all instances if there is no instance in the zone.
+//! let rule = TagRule::new("zone", ValueSource::Caller(|caller| caller.get_faststr::<Zone>().cloned()));
+//!
+//! let client = ClientBuilder::new("service")
+//!     .layer_outer(SubsetLayer::new(rule))
+//!     .discover(SubsetDiscover::new(discover))
+//!     .build();
+//! ```
+
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use async_broadcast::{InactiveReceiver, Receiver, RecvError, Sender};
+use dashmap::DashMap;
+use faststr::FastStr;
+use motore::Service;
+
+use crate::{
+    context::{Context, Endpoint},
+    discovery::{Change, Discover, Instance},
+};
+
+/// What to do if no instance matches the [`SubsetSelector`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Fallback {
+    /// Use all the instances.
+    #[default]
+    All,
+    /// Use no instance, so the request fails.
+    Empty,
+}
+
+/// [`SubsetSelector`] selects the instances whose tag `tag` equals to `value`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SubsetSelector {
+    pub tag: FastStr,
+    pub value: FastStr,
+    pub fallback: Fallback,
+}
+
+impl SubsetSelector {
+    pub fn new(tag: impl Into<FastStr>, value: impl Into<FastStr>) -> Self {
+        Self {
+            tag: tag.into(),
+            value: value.into(),
+            fallback: Fallback::default(),
+        }
+    }
+
+    pub fn with_fallback(mut self, fallback: Fallback) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    #[inline]
+    pub fn matches(&self, instance: &Instance) -> bool {
+        instance
+            .tags
+            .get(self.tag.as_str())
+            .is_some_and(|v| v == self.value.as_str())
+    }
+
+    /// Filters the instances, and applies the fallback if none matches.
+    pub fn filter(&self, instances: &[Arc<Instance>]) -> Vec<Arc<Instance>> {
+        let filtered: Vec<_> = instances
+            .iter()
+            .filter(|instance| self.matches(instance))
+            .cloned()
+            .collect();
+        if filtered.is_empty() && self.fallback == Fallback::All {
+            return instances.to_vec();
+        }
+        filtered
+    }
+}
+
+/// [`SubsetRule`] decides which subset a request should be sent to.
+pub trait SubsetRule: Send + Sync + 'static {
+    /// Returns the selector of the subset, or `None` for using all the instances.
+    fn select<Cx: Context>(&self, cx: &Cx) -> Option<SubsetSelector>;
+}
+
+/// The source of the tag value used by [`TagRule`].
+#[derive(Clone, Debug)]
+pub enum ValueSource {
+    /// Reads the value from the caller's [`Endpoint`], e.g., its `faststr_tags`.
+    Caller(fn(&Endpoint) -> Option<FastStr>),
+    /// Reads the value from the string of the metainfo with the key.
+    MetaInfo(FastStr),
+    /// Always uses the value.
+    Static(FastStr),
+}
+
+impl ValueSource {
+    pub(crate) fn value<Cx: Context>(&self, cx: &Cx) -> Option<FastStr> {
+        match self {
+            Self::Caller(f) => f(cx.rpc_info().caller()),
+            Self::MetaInfo(key) => metainfo::METAINFO
+                .try_with(|m| m.borrow().get_string(key).cloned())
+                .ok()
+                .flatten(),
+            Self::Static(value) => Some(value.clone()),
+        }
+    }
+}
+
+/// [`TagRule`] selects the instances whose tag equals to the value from [`ValueSource`].
+///
+/// If the value is absent, all the instances are used.
+///
+/// For example, `TagRule::new("env", ValueSource::MetaInfo("env".into()))
+/// .with_fallback(Fallback::Empty)` sends the requests with `env=canary` in metainfo to the
+/// instances tagged `env=canary` only.
+#[derive(Clone, Debug)]
+pub struct TagRule {
+    tag: FastStr,
+    source: ValueSource,
+    fallback: Fallback,
+}
+
+impl TagRule {
+    pub fn new(tag: impl Into<FastStr>, source: ValueSource) -> Self {
+        Self {
+            tag: tag.into(),
+            source,
+            fallback: Fallback::default(),
+        }
+    }
+
+    pub fn with_fallback(mut self, fallback: Fallback) -> Self {
+        self.fallback = fallback;
+        self
+    }
+}
+
+impl SubsetRule for TagRule {
+    fn select<Cx: Context>(&self, cx: &Cx) -> Option<SubsetSelector> {
+        let value = self.source.value(cx)?;
+        Some(SubsetSelector {
+            tag: self.tag.clone(),
+            value,
+            fallback: self.fallback,
+        })
+    }
+}
+
+/// A [`Layer`](motore::layer::Layer) that evaluates the [`SubsetRule`] for each request.
+#[derive(Clone, Debug)]
+pub struct SubsetLayer<R> {
+    rule: Arc<R>,
+}
+
+impl<R> SubsetLayer<R> {
+    pub fn new(rule: R) -> Self {
+        Self {
+            rule: Arc::new(rule),
+        }
+    }
+}
+
+impl<S, R> motore::layer::Layer<S> for SubsetLayer<R> {
+    type Service = SubsetService<S, R>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        SubsetService {
+            inner,
+            rule: self.rule,
+        }
+    }
+}
+
+/// The [`Service`] generated by [`SubsetLayer`].
+#[derive(Clone, Debug)]
+pub struct SubsetService<S, R> {
+    inner: S,
+    rule: Arc<R>,
+}
+
+impl<Cx, Req, S, R> Service<Cx, Req> for SubsetService<S, R>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + Sync + 'static,
+    R: SubsetRule,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+
+    async fn call(&self, cx: &mut Cx, req: Req) -> Result<Self::Response, Self::Error> {
+        if let Some(selector) = self.rule.select(cx) {
+            cx.rpc_info_mut().callee_mut().insert(selector);
+        }
+        self.inner.call(cx, req).await
+    }
+}
+
+/// The key of [`SubsetDiscover`], which is the key of the inner [`Discover`] and the selected
+/// subset.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SubsetKey<K> {
+    pub key: K,
+    pub selector: Option<SubsetSelector>,
+}
+
+/// The default max number of the subsets of each key, see [`SubsetDiscover::with_max_subsets`].
+pub const DEFAULT_MAX_SUBSETS: usize = 64;
+
+const EVICTED_CHANNEL_CAPACITY: usize = 64;
+
+/// [`SubsetDiscover`] filters the instances of the inner [`Discover`] by the [`SubsetSelector`]
+/// in the [`Endpoint`].
+pub struct SubsetDiscover<D>
+where
+    D: Discover,
+{
+    inner: D,
+    max_subsets: usize,
+    /// The subsets that have been discovered, used for dispatching the changes of each subset,
+    /// with the time when they are discovered.
+    subsets: Arc<DashMap<D::Key, HashMap<SubsetSelector, Instant>>>,
+    /// The subsets evicted for exceeding `max_subsets`, see [`Discover::watch_evicted`].
+    evicted: Sender<SubsetKey<D::Key>>,
+    evicted_receiver: InactiveReceiver<SubsetKey<D::Key>>,
+}
+
+impl<D> SubsetDiscover<D>
+where
+    D: Discover,
+{
+    pub fn new(inner: D) -> Self {
+        let (mut evicted, evicted_receiver) = async_broadcast::broadcast(EVICTED_CHANNEL_CAPACITY);
+        evicted.set_overflow(true);
+        Self {
+            inner,
+            max_subsets: DEFAULT_MAX_SUBSETS,
+            subsets: Arc::new(DashMap::new()),
+            evicted,
+            evicted_receiver: evicted_receiver.deactivate(),
+        }
+    }
+
+    /// Sets the max number of the subsets of each key, the default is [`DEFAULT_MAX_SUBSETS`].
+    ///
+    /// The values of the selectors may come from the requests, e.g., the metainfo, so the
+    /// subsets are limited to bound the caches of the load balance. The oldest subset is evicted
+    /// when a new one is discovered, and it is discovered again when it is used next time.
+    pub fn with_max_subsets(mut self, max_subsets: usize) -> Self {
+        self.max_subsets = max_subsets.max(1);
+        self
+    }
+
+    /// Records the subset discovered, and evicts the oldest one if there are too many.
+    fn track(&self, key: D::Key, selector: &SubsetSelector) {
+        let mut subsets = self.subsets.entry(key.clone()).or_default();
+        if !subsets.contains_key(selector) && subsets.len() >= self.max_subsets {
+            let oldest = subsets
+                .iter()
+                .min_by_key(|(_, discovered_at)| **discovered_at)
+                .map(|(selector, _)| selector.clone());
+            if let Some(oldest) = oldest {
+                subsets.remove(&oldest);
+                // there is no cache to evict if nobody is watching
+                let _ = self.evicted.try_broadcast(SubsetKey {
+                    key,
+                    selector: Some(oldest),
+                });
+            }
+        }
+        subsets.insert(selector.clone(), Instant::now());
+    }
+}
+
+impl<D> Clone for SubsetDiscover<D>
+where
+    D: Discover + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            max_subsets: self.max_subsets,
+            subsets: self.subsets.clone(),
+            evicted: self.evicted.clone(),
+            evicted_receiver: self.evicted_receiver.clone(),
+        }
+    }
+}
+
+fn filter_change<K>(selector: &SubsetSelector, change: &Change<K>) -> Change<SubsetKey<K>>
+where
+    K: Clone,
+{
+    let fell_back = selector.fallback == Fallback::All
+        && !change.all.iter().any(|instance| selector.matches(instance));
+    let in_subset = |instance: &&Arc<Instance>| fell_back || selector.matches(instance);
+    Change {
+        key: SubsetKey {
+            key: change.key.clone(),
+            selector: Some(selector.clone()),
+        },
+        all: selector.filter(&change.all),
+        added: change.added.iter().filter(in_subset).cloned().collect(),
+        updated: change.updated.iter().filter(in_subset).cloned().collect(),
+        removed: change.removed.iter().filter(in_subset).cloned().collect(),
+    }
+}
+
+impl<D> Discover for SubsetDiscover<D>
+where
+    D: Discover,
+{
+    type Key = SubsetKey<D::Key>;
+    type Error = D::Error;
+
+    async fn discover<'s>(
+        &'s self,
+        endpoint: &'s Endpoint,
+    ) -> Result<Vec<Arc<Instance>>, Self::Error> {
+        let instances = self.inner.discover(endpoint).await?;
+        match endpoint.get::<SubsetSelector>() {
+            Some(selector) => {
+                self.track(self.inner.key(endpoint), selector);
+                Ok(selector.filter(&instances))
+            }
+            None => Ok(instances),
+        }
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        SubsetKey {
+            key: self.inner.key(endpoint),
+            selector: endpoint.get::<SubsetSelector>().cloned(),
+        }
+    }
+
+    fn watch(&self, keys: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+        let inner_keys = keys.map(|keys| keys.iter().map(|k| k.key.clone()).collect::<Vec<_>>());
+        let mut inner = self.inner.watch(inner_keys.as_deref())?;
+        let (mut tx, rx) = async_broadcast::broadcast(inner.capacity().max(1));
+        tx.set_overflow(true);
+        let subsets = self.subsets.clone();
+        tokio::spawn(async move {
+            loop {
+                let change = match inner.recv().await {
+                    Ok(change) => change,
+                    Err(RecvError::Closed) => break,
+                    Err(err) => {
+                        tracing::warn!("[VOLO] subset discover subscription error: {:?}", err);
+                        continue;
+                    }
+                };
+                let mut changes = Vec::new();
+                if let Some(selectors) = subsets.get(&change.key) {
+                    for selector in selectors.keys() {
+                        changes.push(filter_change(selector, &change));
+                    }
+                }
+                changes.push(Change {
+                    key: SubsetKey {
+                        key: change.key,
+                        selector: None,
+                    },
+                    all: change.all,
+                    added: change.added,
+                    updated: change.updated,
+                    removed: change.removed,
+                });
+                for change in changes {
+                    if tx.broadcast(change).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Some(rx)
+    }
+
+    fn watch_evicted(&self) -> Option<Receiver<Self::Key>> {
+        Some(self.evicted_receiver.activate_cloned())
+    }
+}
+
+impl<D> std::fmt::Debug for SubsetDiscover<D>
+where
+    D: Discover + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SubsetDiscover")
+            .field("inner", &self.inner)
+            .field("max_subsets", &self.max_subsets)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, convert::Infallible, sync::Arc, time::Duration};
+
+    use async_broadcast::Receiver;
+
+    use super::{Fallback, SubsetDiscover, SubsetKey, SubsetSelector};
+    use crate::{
+        context::Endpoint,
+        discovery::{Change, Discover, Instance, StaticDiscover},
+        loadbalance::{LoadBalance, random::WeightedRandomBalance},
+        net::Address,
+    };
+
+    struct MockDiscover {
+        instances: Vec<Arc<Instance>>,
+        receiver: Receiver<Change<()>>,
+    }
+
+    impl Discover for MockDiscover {
+        type Key = ();
+        type Error = Infallible;
+
+        async fn discover<'s>(
+            &'s self,
+            _: &'s Endpoint,
+        ) -> Result<Vec<Arc<Instance>>, Self::Error> {
+            Ok(self.instances.clone())
+        }
+
+        fn key(&self, _: &Endpoint) -> Self::Key {}
+
+        fn watch(&self, _: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+            Some(self.receiver.clone())
+        }
+    }
+
+    async fn recv(receiver: &mut Receiver<Change<SubsetKey<()>>>) -> Change<SubsetKey<()>> {
+        tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    fn new_instance(address: &str, zone: &'static str) -> Arc<Instance> {
+        Arc::new(Instance {
+            address: Address::Ip(address.parse().unwrap()),
+            weight: 1,
+            tags: [(Cow::Borrowed("zone"), Cow::Borrowed(zone))].into(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_subset_discover() {
+        let discover = SubsetDiscover::new(StaticDiscover::new(vec![
+            new_instance("127.0.0.1:8000", "a"),
+            new_instance("127.0.0.2:8000", "a"),
+            new_instance("127.0.0.3:8000", "b"),
+        ]));
+
+        let all = Endpoint::new("".into());
+        assert_eq!(discover.discover(&all).await.unwrap().len(), 3);
+
+        let mut zone_b = Endpoint::new("".into());
+        zone_b.insert(SubsetSelector::new("zone", "b"));
+        let instances = discover.discover(&zone_b).await.unwrap();
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].address.to_string(), "127.0.0.3:8000");
+        assert_ne!(discover.key(&all), discover.key(&zone_b));
+
+        let mut zone_c = Endpoint::new("".into());
+        zone_c.insert(SubsetSelector::new("zone", "c"));
+        assert_eq!(discover.discover(&zone_c).await.unwrap().len(), 3);
+
+        let mut zone_c = Endpoint::new("".into());
+        zone_c.insert(SubsetSelector::new("zone", "c").with_fallback(Fallback::Empty));
+        assert!(discover.discover(&zone_c).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_subset_balance() {
+        let discover = SubsetDiscover::new(StaticDiscover::new(vec![
+            new_instance("127.0.0.1:8000", "a"),
+            new_instance("127.0.0.2:8000", "a"),
+            new_instance("127.0.0.3:8000", "b"),
+        ]));
+        let lb = WeightedRandomBalance::with_discover(&discover);
+
+        let mut zone_a = Endpoint::new("".into());
+        zone_a.insert(SubsetSelector::new("zone", "a"));
+        let mut zone_b = Endpoint::new("".into());
+        zone_b.insert(SubsetSelector::new("zone", "b"));
+        for _ in 0..10 {
+            let picker = lb.get_picker(&zone_a, &discover).await.unwrap();
+            let picked = picker.collect::<Vec<_>>();
+            assert_eq!(picked.len(), 2);
+            assert!(
+                picked
+                    .iter()
+                    .all(|addr| addr.to_string() != "127.0.0.3:8000")
+            );
+
+            let picker = lb.get_picker(&zone_b, &discover).await.unwrap();
+            let picked = picker.collect::<Vec<_>>();
+            assert_eq!(picked.len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_evict_subsets() {
+        let (sender, receiver) = async_broadcast::broadcast(8);
+        let instances = vec![
+            new_instance("127.0.0.1:8000", "a"),
+            new_instance("127.0.0.2:8000", "b"),
+            new_instance("127.0.0.3:8000", "c"),
+        ];
+        let discover = SubsetDiscover::new(MockDiscover {
+            instances: instances.clone(),
+            receiver,
+        })
+        .with_max_subsets(2);
+        let mut changes = discover.watch(None).unwrap();
+        let mut evicted = discover.watch_evicted().unwrap();
+        let endpoint = |zone: &'static str| {
+            let mut endpoint = Endpoint::new("".into());
+            endpoint.insert(SubsetSelector::new("zone", zone));
+            endpoint
+        };
+        let key = |zone: &'static str| SubsetKey {
+            key: (),
+            selector: Some(SubsetSelector::new("zone", zone)),
+        };
+
+        discover.discover(&endpoint("a")).await.unwrap();
+        discover.discover(&endpoint("b")).await.unwrap();
+        // the oldest subset is evicted for a new one
+        discover.discover(&endpoint("c")).await.unwrap();
+        let evicted_key = tokio::time::timeout(Duration::from_secs(5), evicted.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(evicted_key, key("a"));
+        assert_eq!(discover.subsets.get(&()).unwrap().len(), 2);
+
+        // the changes are dispatched to the subsets which are not evicted
+        sender
+            .broadcast(Change {
+                key: (),
+                all: instances[1..].to_vec(),
+                added: Vec::new(),
+                updated: Vec::new(),
+                removed: instances[..1].to_vec(),
+            })
+            .await
+            .unwrap();
+        let mut subsets = Vec::new();
+        for _ in 0..2 {
+            let change = recv(&mut changes).await;
+            assert_eq!(change.all.len(), 1);
+            subsets.push(change.key.selector.unwrap().value);
+        }
+        subsets.sort();
+        assert_eq!(subsets, ["b", "c"]);
+        let change = recv(&mut changes).await;
+        assert_eq!(change.key.selector, None);
+        assert_eq!(change.all.len(), 2);
+
+        // the evicted subset is discovered again by the load balance
+        let lb = WeightedRandomBalance::with_discover(&discover);
+        lb.get_picker(&endpoint("a"), &discover).await.unwrap();
+        assert!(discover.subsets.remove(&()).is_some());
+        LoadBalance::<SubsetDiscover<MockDiscover>>::evict(&lb, &key("a"));
+        let picker = lb.get_picker(&endpoint("a"), &discover).await.unwrap();
+        assert_eq!(picker.count(), 1);
+        assert!(discover.subsets.get(&()).is_some());
+    }
+}
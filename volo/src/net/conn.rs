@@ -1,9 +1,12 @@
 use std::{
-    io,
+    fmt, io,
+    net::IpAddr,
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
 };
 
+use faststr::FastStr;
 use pin_project::pin_project;
 #[cfg(target_family = "unix")]
 use tokio::net::{UnixStream, unix};
@@ -12,11 +15,160 @@ use tokio::{
     net::{TcpStream, tcp},
 };
 
-use super::Address;
+use super::{
+    Address,
+    incoming::{admission::AdmissionPermit, proxy_protocol::ProxyHeader},
+};
 
-#[derive(Clone)]
+/// The information of a connection.
+///
+/// More fields may be added in the future, so it can only be created by [`ConnInfo::new`] outside
+/// of this crate, and the other fields can be set after that.
+#[derive(Clone, Default)]
+#[non_exhaustive]
 pub struct ConnInfo {
     pub peer_addr: Option<Address>,
+    /// The certificate of the peer, which is only available for the TLS connections on which the
+    /// peer presents a certificate, e.g., the clients of a server requiring the client auth.
+    pub peer_certificate: Option<PeerCertificate>,
+    /// The server name indicated by the client in the TLS handshake, which is only available for
+    /// the TLS connections accepted by the servers.
+    pub server_name: Option<TlsServerName>,
+    /// The PROXY protocol header prepended by the load balancer, which is only available for the
+    /// connections accepted by
+    /// [`ProxyProtocol`](super::incoming::proxy_protocol::ProxyProtocol).
+    pub proxy_header: Option<ProxyHeader>,
+    /// The admission of the connection, which is only available for the connections accepted by
+    /// [`AdmissionControl`](super::incoming::admission::AdmissionControl), and releases the limits
+    /// when dropped.
+    pub admission: Option<Arc<AdmissionPermit>>,
+    /// The credentials of the peer process, which is only available for the unix domain socket
+    /// connections.
+    pub peer_credentials: Option<PeerCredentials>,
+}
+
+impl ConnInfo {
+    /// Creates a [`ConnInfo`] with the address of the peer, and the other fields are `None`.
+    pub fn new(peer_addr: Option<Address>) -> Self {
+        Self {
+            peer_addr,
+            ..Default::default()
+        }
+    }
+}
+
+/// The credentials of the peer process of a unix domain socket connection, i.e., `SO_PEERCRED`
+/// on Linux or `getpeereid` on the other unix platforms.
+///
+/// The servers insert it into the caller [`Endpoint`](crate::context::Endpoint) of the contexts,
+/// so the handlers can tell which local process is calling.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PeerCredentials {
+    /// The process ID of the peer, which is not available on some platforms.
+    pub pid: Option<i32>,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// The server name indicated by the client with SNI in the TLS handshake.
+///
+/// The servers insert it into the callee [`Endpoint`](crate::context::Endpoint) of the contexts,
+/// so the handlers serving several domains can tell which one is requested.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TlsServerName(pub FastStr);
+
+/// A subject alternative name of a [`PeerCertificate`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SubjectAltName {
+    Dns(String),
+    Email(String),
+    Uri(String),
+    Ip(IpAddr),
+}
+
+/// The end-entity certificate presented by the peer of a TLS connection, which has been verified
+/// in the handshake.
+///
+/// The servers insert it into the caller [`Endpoint`](crate::context::Endpoint) of the contexts,
+/// so the handlers can authorize the requests by the identity of the peer. It is cheap to clone.
+#[derive(Clone)]
+pub struct PeerCertificate(Arc<PeerCertificateInner>);
+
+struct PeerCertificateInner {
+    der: Vec<u8>,
+    subject: String,
+    common_name: Option<String>,
+    subject_alt_names: Vec<SubjectAltName>,
+}
+
+impl PeerCertificate {
+    /// Parses the DER encoded certificate, returns `None` if it is malformed.
+    #[cfg(feature = "__tls")]
+    pub(crate) fn from_der(der: Vec<u8>) -> Option<Self> {
+        use x509_parser::{extensions::GeneralName, prelude::FromDer};
+
+        let (_, cert) = x509_parser::certificate::X509Certificate::from_der(&der).ok()?;
+        let subject = cert.subject().to_string();
+        let common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(ToOwned::to_owned);
+        let mut subject_alt_names = Vec::new();
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in san.value.general_names.iter() {
+                let name = match name {
+                    GeneralName::DNSName(name) => SubjectAltName::Dns((*name).to_owned()),
+                    GeneralName::RFC822Name(name) => SubjectAltName::Email((*name).to_owned()),
+                    GeneralName::URI(name) => SubjectAltName::Uri((*name).to_owned()),
+                    GeneralName::IPAddress(ip) => match ip.len() {
+                        4 => SubjectAltName::Ip(IpAddr::from(<[u8; 4]>::try_from(*ip).ok()?)),
+                        16 => SubjectAltName::Ip(IpAddr::from(<[u8; 16]>::try_from(*ip).ok()?)),
+                        _ => continue,
+                    },
+                    _ => continue,
+                };
+                subject_alt_names.push(name);
+            }
+        }
+        Some(Self(Arc::new(PeerCertificateInner {
+            der,
+            subject,
+            common_name,
+            subject_alt_names,
+        })))
+    }
+
+    /// The DER encoded certificate.
+    pub fn der(&self) -> &[u8] {
+        &self.0.der
+    }
+
+    /// The distinguished name of the subject, e.g., `CN=client, O=CloudWeGo`.
+    pub fn subject(&self) -> &str {
+        &self.0.subject
+    }
+
+    /// The common name of the subject.
+    pub fn common_name(&self) -> Option<&str> {
+        self.0.common_name.as_deref()
+    }
+
+    /// The subject alternative names, e.g., the DNS names or the SPIFFE IDs in the URIs.
+    pub fn subject_alt_names(&self) -> &[SubjectAltName] {
+        &self.0.subject_alt_names
+    }
+}
+
+impl fmt::Debug for PeerCertificate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PeerCertificate")
+            .field("subject", &self.0.subject)
+            .field("subject_alt_names", &self.0.subject_alt_names)
+            .finish()
+    }
 }
 
 #[pin_project(project = IoStreamProj)]
@@ -389,6 +541,41 @@ impl ConnStream {
             Self::Shmipc(s) => Some(Address::from(s.peer_addr())),
         }
     }
+
+    /// Returns the certificate presented by the peer if it is a TLS connection.
+    #[inline]
+    pub fn peer_certificate(&self) -> Option<PeerCertificate> {
+        #[cfg(feature = "__tls")]
+        if let Self::Tls(s) = self {
+            return s.peer_certificate();
+        }
+        None
+    }
+
+    /// Returns the credentials of the peer process if it is a unix domain socket connection.
+    #[inline]
+    pub fn peer_credentials(&self) -> Option<PeerCredentials> {
+        #[cfg(target_family = "unix")]
+        if let Self::Unix(s) = self {
+            return s.peer_cred().ok().map(|cred| PeerCredentials {
+                pid: cred.pid(),
+                uid: cred.uid(),
+                gid: cred.gid(),
+            });
+        }
+        None
+    }
+
+    /// Returns the server name indicated by the client if it is a TLS connection accepted by the
+    /// server.
+    #[inline]
+    pub fn server_name(&self) -> Option<TlsServerName> {
+        #[cfg(feature = "__tls")]
+        if let Self::Tls(s) = self {
+            return s.server_name();
+        }
+        None
+    }
 }
 
 pub struct Conn {
@@ -411,7 +598,20 @@ where
     fn from(i: T) -> Self {
         let i = i.into();
         let peer_addr = i.peer_addr();
-        Conn::new(i, ConnInfo { peer_addr })
+        let peer_certificate = i.peer_certificate();
+        let server_name = i.server_name();
+        let peer_credentials = i.peer_credentials();
+        Conn::new(
+            i,
+            ConnInfo {
+                peer_addr,
+                peer_certificate,
+                server_name,
+                proxy_header: None,
+                admission: None,
+                peer_credentials,
+            },
+        )
     }
 }
 
@@ -1,5 +1,6 @@
 use std::{future::Future, io, net::SocketAddr};
 
+use futures::stream::{FuturesUnordered, StreamExt};
 use motore::{make::MakeConnection, service::UnaryService};
 use socket2::{Domain, Protocol, Socket, Type};
 #[cfg(target_family = "unix")]
@@ -13,6 +14,7 @@ use tokio::{
 use super::{
     Address,
     conn::{Conn, OwnedReadHalf, OwnedWriteHalf},
+    sockopt::SocketOptions,
 };
 
 /// [`MakeTransport`] creates an [`AsyncRead`] and an [`AsyncWrite`] for the given [`Address`].
@@ -27,18 +29,43 @@ pub trait MakeTransport: Clone + Send + Sync + 'static {
     fn set_connect_timeout(&mut self, timeout: Option<Duration>);
     fn set_read_timeout(&mut self, timeout: Option<Duration>);
     fn set_write_timeout(&mut self, timeout: Option<Duration>);
+    /// Sets the options applied to the sockets before connecting.
+    ///
+    /// The default implementation ignores the options, for the transports which are not based on
+    /// sockets.
+    fn set_socket_options(&mut self, options: SocketOptions) {
+        let _ = options;
+    }
 }
 
 #[derive(Default, Debug, Clone)]
 pub struct DefaultMakeTransport {
     cfg: Config,
+    socket_options: SocketOptions,
 }
 
-#[derive(Default, Debug, Clone, Copy)]
+/// The default delay between two connection attempts of Happy Eyeballs, which is recommended by
+/// [RFC 8305](https://www.rfc-editor.org/rfc/rfc8305#section-5).
+pub const DEFAULT_HAPPY_EYEBALLS_DELAY: Duration = Duration::from_millis(250);
+
+/// The config of dialing.
+///
+/// The [`SocketOptions`] are kept by the transports instead, see
+/// [`MakeTransport::set_socket_options`].
+#[derive(Debug, Clone, Copy)]
 pub struct Config {
     pub connect_timeout: Option<Duration>,
     pub read_timeout: Option<Duration>,
     pub write_timeout: Option<Duration>,
+    /// The delay before starting the next connection attempt when connecting to a set of
+    /// addresses, see [`make_happy_eyeballs_connection`].
+    pub happy_eyeballs_delay: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new(None, None, None)
+    }
 }
 
 impl Config {
@@ -51,6 +78,7 @@ impl Config {
             connect_timeout,
             read_timeout,
             write_timeout,
+            happy_eyeballs_delay: DEFAULT_HAPPY_EYEBALLS_DELAY,
         }
     }
 
@@ -68,6 +96,11 @@ impl Config {
         self.write_timeout = timeout;
         self
     }
+
+    pub fn with_happy_eyeballs_delay(mut self, delay: Duration) -> Self {
+        self.happy_eyeballs_delay = delay;
+        self
+    }
 }
 
 impl DefaultMakeTransport {
@@ -82,6 +115,10 @@ impl DefaultMakeTransport {
     pub const fn config_mut(&mut self) -> &mut Config {
         &mut self.cfg
     }
+
+    pub const fn socket_options(&self) -> &SocketOptions {
+        &self.socket_options
+    }
 }
 
 impl MakeTransport for DefaultMakeTransport {
@@ -95,20 +132,157 @@ impl MakeTransport for DefaultMakeTransport {
     }
 
     fn set_connect_timeout(&mut self, timeout: Option<Duration>) {
-        self.cfg = self.cfg.with_connect_timeout(timeout);
+        self.cfg.connect_timeout = timeout;
     }
 
     fn set_read_timeout(&mut self, timeout: Option<Duration>) {
-        self.cfg = self.cfg.with_read_timeout(timeout);
+        self.cfg.read_timeout = timeout;
     }
 
     fn set_write_timeout(&mut self, timeout: Option<Duration>) {
-        self.cfg = self.cfg.with_write_timeout(timeout);
+        self.cfg.write_timeout = timeout;
+    }
+
+    fn set_socket_options(&mut self, options: SocketOptions) {
+        self.socket_options = options;
     }
 }
 
 pub(super) async fn make_tcp_connection(
     cfg: &Config,
+    options: &SocketOptions,
+    addr: SocketAddr,
+) -> Result<TcpStream, io::Error> {
+    let connect = connect_tcp(cfg, options, addr);
+
+    if let Some(conn_timeout) = cfg.connect_timeout {
+        timeout(conn_timeout, connect).await?
+    } else {
+        connect.await
+    }
+}
+
+/// Connects to the first reachable one of the addresses by Happy Eyeballs
+/// ([RFC 8305](https://www.rfc-editor.org/rfc/rfc8305)).
+///
+/// The addresses are sorted by [`sort_addresses`], then the connection attempts are started one by
+/// one, the next one starts when the previous one fails or after
+/// [`Config::happy_eyeballs_delay`]. The first established connection is returned and the others
+/// are dropped. The connect timeout applies to the whole process.
+pub async fn make_happy_eyeballs_connection(
+    cfg: &Config,
+    options: &SocketOptions,
+    addrs: impl IntoIterator<Item = SocketAddr>,
+) -> Result<TcpStream, io::Error> {
+    let addrs = sort_addresses(addrs);
+    if let [addr] = addrs[..] {
+        return make_tcp_connection(cfg, options, addr).await;
+    }
+    let connect = race_tcp_connections(cfg, options, addrs);
+
+    if let Some(conn_timeout) = cfg.connect_timeout {
+        timeout(conn_timeout, connect).await?
+    } else {
+        connect.await
+    }
+}
+
+/// Sorts the addresses for connecting by Happy Eyeballs.
+///
+/// The addresses of the families which are not supported by the local IP stack are skipped unless
+/// there are no others, and the remaining ones are interleaved by family, starting with the family
+/// of the first address, e.g., `[v6, v6, v4, v4]` becomes `[v6, v4, v6, v4]`. The relative order
+/// of each family is kept.
+pub fn sort_addresses(addrs: impl IntoIterator<Item = SocketAddr>) -> Vec<SocketAddr> {
+    let capability = super::probe::probe();
+    let addrs = addrs.into_iter().collect::<Vec<_>>();
+    let supported = addrs
+        .iter()
+        .copied()
+        .filter(|addr| {
+            if addr.is_ipv4() {
+                capability.ipv4
+            } else {
+                capability.ipv6
+            }
+        })
+        .collect::<Vec<_>>();
+    if supported.is_empty() {
+        interleave_addresses(addrs)
+    } else {
+        interleave_addresses(supported)
+    }
+}
+
+fn interleave_addresses(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first_is_ipv4) = addrs.first().map(SocketAddr::is_ipv4) else {
+        return addrs;
+    };
+    let mut sorted = Vec::with_capacity(addrs.len());
+    let (preferred, others): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv4() == first_is_ipv4);
+    let mut preferred = preferred.into_iter();
+    let mut others = others.into_iter();
+    loop {
+        match (preferred.next(), others.next()) {
+            (None, None) => break,
+            (a, b) => sorted.extend(a.into_iter().chain(b)),
+        }
+    }
+    sorted
+}
+
+async fn race_tcp_connections(
+    cfg: &Config,
+    options: &SocketOptions,
+    addrs: Vec<SocketAddr>,
+) -> Result<TcpStream, io::Error> {
+    let mut pending = addrs.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_err = None;
+
+    loop {
+        if attempts.is_empty() {
+            match pending.next() {
+                Some(addr) => attempts.push(connect_tcp(cfg, options, addr)),
+                None => {
+                    return Err(last_err.unwrap_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidInput, "no addresses to connect")
+                    }));
+                }
+            }
+        }
+
+        let next = if pending.len() == 0 {
+            Ok(attempts.next().await)
+        } else {
+            timeout(cfg.happy_eyeballs_delay, attempts.next()).await
+        };
+        match next {
+            Ok(Some(Ok(stream))) => return Ok(stream),
+            Ok(Some(Err(err))) => {
+                tracing::debug!("[VOLO] connection attempt of happy eyeballs failed: {err}");
+                last_err = Some(err);
+                // start the next attempt immediately without waiting for the delay
+                if let Some(addr) = pending.next() {
+                    attempts.push(connect_tcp(cfg, options, addr));
+                }
+            }
+            Ok(None) => {}
+            // the delay is elapsed, start the next attempt while keeping the previous ones
+            Err(_) => {
+                if let Some(addr) = pending.next() {
+                    attempts.push(connect_tcp(cfg, options, addr));
+                }
+            }
+        }
+    }
+}
+
+async fn connect_tcp(
+    cfg: &Config,
+    options: &SocketOptions,
     addr: SocketAddr,
 ) -> Result<TcpStream, io::Error> {
     let domain = Domain::for_address(addr);
@@ -116,6 +290,10 @@ pub(super) async fn make_tcp_connection(
     socket.set_nonblocking(true)?;
     socket.set_read_timeout(cfg.read_timeout)?;
     socket.set_write_timeout(cfg.write_timeout)?;
+    options.apply(&socket, addr.is_ipv4())?;
+    if let Some(local_addr) = options.local_addr {
+        socket.bind(&local_addr.into())?;
+    }
 
     #[cfg(unix)]
     let socket = unsafe {
@@ -128,12 +306,48 @@ pub(super) async fn make_tcp_connection(
         TcpSocket::from_raw_socket(socket.into_raw_socket())
     };
 
-    let connect = socket.connect(addr);
+    socket.connect(addr).await
+}
 
-    if let Some(conn_timeout) = cfg.connect_timeout {
-        timeout(conn_timeout, connect).await?
-    } else {
-        connect.await
+/// Connects to the unix domain socket, including the abstract ones on Linux, which cannot be
+/// connected by [`UnixStream::connect`] with a path.
+#[cfg(target_family = "unix")]
+async fn connect_unix(addr: &std::os::unix::net::SocketAddr) -> io::Result<UnixStream> {
+    #[cfg(target_os = "linux")]
+    if let Some(name) = std::os::linux::net::SocketAddrExt::as_abstract_name(addr) {
+        let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+        socket.set_nonblocking(true)?;
+        match socket.connect(&super::abstract_socket_addr(name)?) {
+            Ok(()) => {}
+            Err(err) if err.raw_os_error() == Some(libc::EINPROGRESS) => {}
+            Err(err) => return Err(err),
+        }
+        let stream = UnixStream::from_std(socket.into())?;
+        stream.writable().await?;
+        if let Some(err) = stream.take_error()? {
+            return Err(err);
+        }
+        return Ok(stream);
+    }
+    UnixStream::connect(addr.as_pathname().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "cannot connect to unnamed socket",
+        )
+    })?)
+    .await
+}
+
+/// Connects to the first reachable one of the resolved addresses of a host, see
+/// [`make_happy_eyeballs_connection`].
+impl UnaryService<Vec<SocketAddr>> for DefaultMakeTransport {
+    type Response = Conn;
+    type Error = io::Error;
+
+    async fn call(&self, addrs: Vec<SocketAddr>) -> Result<Self::Response, Self::Error> {
+        let stream = make_happy_eyeballs_connection(&self.cfg, &self.socket_options, addrs).await?;
+        stream.set_nodelay(self.socket_options.tcp_nodelay.unwrap_or(true))?;
+        Ok(Conn::from(stream))
     }
 }
 
@@ -144,19 +358,12 @@ impl UnaryService<Address> for DefaultMakeTransport {
     async fn call(&self, addr: Address) -> Result<Self::Response, Self::Error> {
         match addr {
             Address::Ip(addr) => {
-                let stream = make_tcp_connection(&self.cfg, addr).await?;
-                stream.set_nodelay(true)?;
+                let stream = make_tcp_connection(&self.cfg, &self.socket_options, addr).await?;
+                stream.set_nodelay(self.socket_options.tcp_nodelay.unwrap_or(true))?;
                 Ok(Conn::from(stream))
             }
             #[cfg(target_family = "unix")]
-            Address::Unix(addr) => UnixStream::connect(addr.as_pathname().ok_or_else(|| {
-                io::Error::new(
-                    io::ErrorKind::AddrNotAvailable,
-                    "cannot connect to unnamed socket",
-                )
-            })?)
-            .await
-            .map(Conn::from),
+            Address::Unix(addr) => connect_unix(&addr).await.map(Conn::from),
             #[cfg(feature = "shmipc")]
             Address::Shmipc(addr) => super::shmipc::addr::ShmipcMakeTransport
                 .call(addr)
@@ -165,3 +372,65 @@ impl UnaryService<Address> for DefaultMakeTransport {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::net::TcpListener;
+
+    use super::{Config, interleave_addresses, make_happy_eyeballs_connection};
+    use crate::net::sockopt::SocketOptions;
+
+    #[test]
+    fn interleave() {
+        let addrs = [
+            "[::1]:1",
+            "[::1]:2",
+            "[::1]:3",
+            "127.0.0.1:4",
+            "127.0.0.1:5",
+        ]
+        .into_iter()
+        .map(|addr| addr.parse::<SocketAddr>().unwrap())
+        .collect::<Vec<_>>();
+        let ports = |addrs: Vec<SocketAddr>| addrs.iter().map(SocketAddr::port).collect::<Vec<_>>();
+
+        assert_eq!(ports(interleave_addresses(addrs.clone())), [1, 4, 2, 5, 3]);
+        assert_eq!(
+            ports(interleave_addresses(addrs.into_iter().rev().collect())),
+            [5, 3, 4, 2, 1]
+        );
+        assert!(interleave_addresses(Vec::new()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn happy_eyeballs() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // the port of a dropped listener refuses the connections
+        let refused = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let cfg = Config::default();
+        let options = SocketOptions::default();
+        let stream = make_happy_eyeballs_connection(&cfg, &options, [refused, addr])
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), addr);
+
+        assert!(
+            make_happy_eyeballs_connection(&cfg, &options, [refused])
+                .await
+                .is_err()
+        );
+        assert!(
+            make_happy_eyeballs_connection(&cfg, &options, [])
+                .await
+                .is_err()
+        );
+    }
+}
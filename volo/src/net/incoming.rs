@@ -1,3 +1,5 @@
+#[cfg(target_family = "unix")]
+use std::net::SocketAddr;
 use std::{
     fmt,
     future::Future,
@@ -14,7 +16,10 @@ use tokio::net::UnixListener;
 use tokio_stream::wrappers::UnixListenerStream;
 use tokio_stream::{StreamExt, wrappers::TcpListenerStream};
 
-use super::{Address, conn::Conn};
+use super::{Address, conn::Conn, sockopt::SocketOptions};
+
+pub mod admission;
+pub mod proxy_protocol;
 
 #[pin_project(project = IncomingProj)]
 #[derive(Debug)]
@@ -56,6 +61,11 @@ impl From<super::shmipc::Listener> for DefaultIncoming {
 
 pub trait Incoming: fmt::Debug + Send + 'static {
     fn accept(&mut self) -> impl Future<Output = io::Result<Option<Conn>>> + Send;
+
+    /// Returns the local address that the incoming is bound to, if there is one.
+    fn local_addr(&self) -> Option<Address> {
+        None
+    }
 }
 
 impl Incoming for DefaultIncoming {
@@ -67,6 +77,16 @@ impl Incoming for DefaultIncoming {
             Ok(None)
         }
     }
+
+    fn local_addr(&self) -> Option<Address> {
+        match self {
+            DefaultIncoming::Tcp(s) => s.as_ref().local_addr().ok().map(Address::from),
+            #[cfg(target_family = "unix")]
+            DefaultIncoming::Unix(s) => s.as_ref().local_addr().ok().map(Address::from),
+            #[cfg(feature = "shmipc")]
+            DefaultIncoming::Shmipc(_) => None,
+        }
+    }
 }
 
 pub trait MakeIncoming {
@@ -75,26 +95,61 @@ pub trait MakeIncoming {
     fn make_incoming(self) -> impl Future<Output = io::Result<Self::Incoming>> + Send;
 }
 
-#[cfg(target_family = "unix")]
+/// [`MakeIncoming`] which listens on the [`Address`] with the [`SocketOptions`].
+///
+/// The options are applied to the TCP listeners, and the accepted connections inherit most of
+/// them on Linux, e.g., TCP keepalive and `TCP_NODELAY`. For the other platforms, the options of
+/// the connections can be applied by [`SocketOptions::apply_to_conn`] after accepting.
+#[derive(Clone, Debug)]
+pub struct ListenConfig {
+    addr: Address,
+    options: SocketOptions,
+}
+
+impl ListenConfig {
+    pub fn new(addr: impl Into<Address>, options: SocketOptions) -> Self {
+        Self {
+            addr: addr.into(),
+            options,
+        }
+    }
+
+    pub fn address(&self) -> &Address {
+        &self.addr
+    }
+
+    pub fn socket_options(&self) -> &SocketOptions {
+        &self.options
+    }
+}
+
 impl MakeIncoming for Address {
     type Incoming = DefaultIncoming;
 
     async fn make_incoming(self) -> io::Result<Self::Incoming> {
-        match self {
+        ListenConfig::new(self, SocketOptions::default())
+            .make_incoming()
+            .await
+    }
+}
+
+#[cfg(target_family = "unix")]
+impl MakeIncoming for ListenConfig {
+    type Incoming = DefaultIncoming;
+
+    async fn make_incoming(self) -> io::Result<Self::Incoming> {
+        match self.addr {
             Address::Ip(addr) => {
-                let listener = unix_helper::create_tcp_listener_with_max_backlog(addr).await;
+                let listener = unix_helper::create_tcp_listener_with_max_backlog(
+                    addr,
+                    addr.to_string(),
+                    &self.options,
+                )
+                .await;
                 TcpListener::from_std(listener?).map(DefaultIncoming::from)
             }
             Address::Unix(addr) => {
-                let listener = unix_helper::create_unix_listener_with_max_backlog(
-                    addr.as_pathname().ok_or_else(|| {
-                        io::Error::new(
-                            io::ErrorKind::AddrNotAvailable,
-                            "cannot create unnamed socket",
-                        )
-                    })?,
-                )
-                .await;
+                let listener = unix_helper::create_unix_listener_with_max_backlog(&addr).await;
                 UnixListener::from_std(listener?).map(DefaultIncoming::from)
             }
             #[cfg(feature = "shmipc")]
@@ -106,16 +161,161 @@ impl MakeIncoming for Address {
 }
 
 #[cfg(not(target_family = "unix"))]
-impl MakeIncoming for Address {
+impl MakeIncoming for ListenConfig {
     type Incoming = DefaultIncoming;
 
     async fn make_incoming(self) -> io::Result<Self::Incoming> {
-        match self {
-            Address::Ip(addr) => TcpListener::bind(addr).await.map(DefaultIncoming::from),
-            #[cfg(target_family = "unix")]
-            Address::Unix(addr) => UnixListener::bind(addr).map(DefaultIncoming::from),
+        match self.addr {
+            Address::Ip(addr) => {
+                use socket2::{Domain, Protocol, Socket, Type};
+
+                let socket =
+                    Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+                self.options.apply(&socket, addr.is_ipv4())?;
+                socket.set_nonblocking(true)?;
+                socket.bind(&addr.into())?;
+                socket.listen(1024)?;
+                TcpListener::from_std(socket.into()).map(DefaultIncoming::from)
+            }
+        }
+    }
+}
+
+/// The number of the accepted connections buffered for each listener of [`MultiIncoming`].
+#[cfg(target_family = "unix")]
+const ACCEPTED_QUEUE_SIZE_PER_LISTENER: usize = 64;
+
+/// [`MakeIncoming`] which binds multiple TCP listeners to the same address with `SO_REUSEPORT`,
+/// and accepts the connections of them concurrently.
+///
+/// The kernel distributes the incoming connections among the listeners, so that accepting is not
+/// bottlenecked by a single accept loop during connection storms.
+///
+/// For hot restart, each listener is registered by
+/// [`HotRestart::register_listener_fd`](crate::hotrestart::HotRestart::register_listener_fd) with
+/// its own key, i.e., the address for the first one and `{addr}#{index}` for the others, so all of
+/// them are handed over to the new process. Note that `server_listener_num` of
+/// [`HotRestart::initialize`](crate::hotrestart::HotRestart::initialize) should count all the
+/// listeners.
+#[cfg(target_family = "unix")]
+#[derive(Clone, Debug)]
+pub struct ReusePortIncoming {
+    addr: SocketAddr,
+    listeners: usize,
+    options: SocketOptions,
+}
+
+#[cfg(target_family = "unix")]
+impl ReusePortIncoming {
+    /// Creates a [`ReusePortIncoming`] which binds `listeners` listeners to `addr`, at least one
+    /// listener is bound.
+    pub fn new(addr: SocketAddr, listeners: usize) -> Self {
+        Self {
+            addr,
+            listeners: listeners.max(1),
+            options: SocketOptions::default(),
+        }
+    }
+
+    /// Sets the socket options of the listeners, `SO_REUSEPORT` is always enabled.
+    pub fn with_socket_options(mut self, options: SocketOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+#[cfg(target_family = "unix")]
+impl MakeIncoming for ReusePortIncoming {
+    type Incoming = MultiIncoming;
+
+    async fn make_incoming(self) -> io::Result<Self::Incoming> {
+        let options = SocketOptions {
+            reuse_port: Some(true),
+            ..self.options
+        };
+        let key = self.addr.to_string();
+        let first = TcpListener::from_std(
+            unix_helper::create_tcp_listener_with_max_backlog(self.addr, key.clone(), &options)
+                .await?,
+        )?;
+        // the port may be allocated by the system, e.g., `0.0.0.0:0`
+        let local_addr = first.local_addr()?;
+
+        let mut listeners = Vec::with_capacity(self.listeners);
+        listeners.push(first);
+        for index in 1..self.listeners {
+            let listener = unix_helper::create_tcp_listener_with_max_backlog(
+                local_addr,
+                format!("{key}#{index}"),
+                &options,
+            )
+            .await?;
+            listeners.push(TcpListener::from_std(listener)?);
+        }
+        Ok(MultiIncoming::new(listeners))
+    }
+}
+
+/// [`Incoming`] which accepts the connections of multiple TCP listeners concurrently, see
+/// [`ReusePortIncoming`].
+///
+/// Each listener is accepted by a spawned task, and the tasks are aborted when it is dropped.
+#[cfg(target_family = "unix")]
+#[derive(Debug)]
+pub struct MultiIncoming {
+    accepted: tokio::sync::mpsc::Receiver<io::Result<Conn>>,
+    local_addr: Option<Address>,
+    _acceptors: tokio::task::JoinSet<()>,
+}
+
+#[cfg(target_family = "unix")]
+impl MultiIncoming {
+    pub fn new(listeners: Vec<TcpListener>) -> Self {
+        let local_addr = listeners
+            .first()
+            .and_then(|listener| listener.local_addr().ok())
+            .map(Address::from);
+        let (tx, accepted) =
+            tokio::sync::mpsc::channel(listeners.len().max(1) * ACCEPTED_QUEUE_SIZE_PER_LISTENER);
+        let mut acceptors = tokio::task::JoinSet::new();
+        for listener in listeners {
+            let tx = tx.clone();
+            acceptors.spawn(async move {
+                loop {
+                    let conn = listener
+                        .accept()
+                        .await
+                        .map(|(stream, _)| Conn::from(stream));
+                    if tx.send(conn).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        Self {
+            accepted,
+            local_addr,
+            _acceptors: acceptors,
+        }
+    }
+}
+
+#[cfg(target_family = "unix")]
+impl Incoming for MultiIncoming {
+    async fn accept(&mut self) -> io::Result<Option<Conn>> {
+        match self.accepted.recv().await {
+            Some(conn) => {
+                let conn = conn?;
+                tracing::trace!("[VOLO] recv a connection from: {:?}", conn.info.peer_addr);
+                Ok(Some(conn))
+            }
+            None => Ok(None),
         }
     }
+
+    fn local_addr(&self) -> Option<Address> {
+        self.local_addr.clone()
+    }
 }
 
 impl Stream for DefaultIncoming {
@@ -144,14 +344,16 @@ mod unix_helper {
         net::{SocketAddr, TcpListener},
         os::{
             fd::{AsRawFd, FromRawFd, IntoRawFd},
-            unix::net::UnixListener,
+            unix::net::{SocketAddr as StdUnixSocketAddr, UnixListener},
         },
-        path::Path,
     };
 
     use socket2::{Domain, Protocol, Socket, Type};
 
-    use crate::hotrestart::DEFAULT_HOT_RESTART;
+    use crate::{
+        hotrestart::DEFAULT_HOT_RESTART,
+        net::{Address, sockopt::SocketOptions},
+    };
 
     /// Returns major and minor kernel version numbers, parsed from
     /// the nix::sys::utsname's release field, or 0, 0 if the version can't be obtained
@@ -259,14 +461,18 @@ mod unix_helper {
         }
     }
 
+    /// Creates a TCP listener, or takes over the one of the parent process with the same `key` for
+    /// hot restart.
     pub async fn create_tcp_listener_with_max_backlog(
         addr: SocketAddr,
+        key: String,
+        options: &SocketOptions,
     ) -> std::io::Result<TcpListener> {
         if let Ok(Some(raw_fd)) = DEFAULT_HOT_RESTART
-            .dup_parent_listener_sock(addr.to_string())
+            .dup_parent_listener_sock(key.clone())
             .await
         {
-            DEFAULT_HOT_RESTART.register_listener_fd(addr.to_string(), raw_fd);
+            DEFAULT_HOT_RESTART.register_listener_fd(key, raw_fd);
             let socket = unsafe { Socket::from_raw_fd(raw_fd) };
             return Ok(socket.into());
         }
@@ -282,6 +488,7 @@ mod unix_helper {
         socket.set_nonblocking(true)?;
         socket.set_reuse_port(true)?;
         socket.set_cloexec(true)?;
+        options.apply(&socket, addr.is_ipv4())?;
 
         socket.bind(&socket2::SockAddr::from(addr))?;
 
@@ -291,49 +498,134 @@ mod unix_helper {
         let backlog = libc::SOMAXCONN;
         socket.listen(backlog)?;
 
-        DEFAULT_HOT_RESTART.register_listener_fd(addr.to_string(), socket.as_raw_fd());
+        DEFAULT_HOT_RESTART.register_listener_fd(key, socket.as_raw_fd());
         Ok(socket.into())
     }
 
-    pub async fn create_unix_listener_with_max_backlog<P: AsRef<Path>>(
-        path: P,
+    /// Creates a unix domain socket listener, or takes over the one of the parent process for hot
+    /// restart.
+    ///
+    /// The listeners are keyed by the paths, or the abstract names prefixed by `@` on Linux.
+    pub async fn create_unix_listener_with_max_backlog(
+        addr: &StdUnixSocketAddr,
     ) -> std::io::Result<UnixListener> {
-        if let Some(path_str) = path.as_ref().to_str() {
-            if let Ok(Some(raw_fd)) = DEFAULT_HOT_RESTART
-                .dup_parent_listener_sock(path_str.to_string())
-                .await
-            {
-                DEFAULT_HOT_RESTART.register_listener_fd(path_str.to_string(), raw_fd);
-                let unix_listener = unsafe { UnixListener::from_raw_fd(raw_fd) };
-                return Ok(unix_listener);
+        #[cfg(target_os = "linux")]
+        let abstract_name = std::os::linux::net::SocketAddrExt::as_abstract_name(addr);
+        #[cfg(not(target_os = "linux"))]
+        let abstract_name: Option<&[u8]> = None;
+        let (key, sock_addr) = match (abstract_name, addr.as_pathname()) {
+            #[cfg(target_os = "linux")]
+            (Some(name), _) => (
+                Address::Unix(addr.clone()).to_string(),
+                crate::net::abstract_socket_addr(name)?,
+            ),
+            (None, Some(path)) => match path.to_str() {
+                Some(path_str) => (path_str.to_string(), socket2::SockAddr::unix(path)?),
+                None => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "invalid path",
+                    ));
+                }
+            },
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AddrNotAvailable,
+                    "cannot create unnamed socket",
+                ));
             }
+        };
 
-            let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
-            socket.set_nonblocking(true)?;
-            socket.set_cloexec(true)?;
+        if let Ok(Some(raw_fd)) = DEFAULT_HOT_RESTART
+            .dup_parent_listener_sock(key.clone())
+            .await
+        {
+            DEFAULT_HOT_RESTART.register_listener_fd(key, raw_fd);
+            let unix_listener = unsafe { UnixListener::from_raw_fd(raw_fd) };
+            return Ok(unix_listener);
+        }
+
+        let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+        socket.set_nonblocking(true)?;
+        socket.set_cloexec(true)?;
 
-            let path = path.as_ref();
+        // there is no file for the abstract names
+        if let Some(path) = addr.as_pathname() {
             if path.exists() {
                 std::fs::remove_file(path)?;
             }
-            socket.bind(&socket2::SockAddr::unix(path)?)?;
-            #[cfg(target_os = "linux")]
-            let backlog = max_listener_backlog();
-            #[cfg(not(target_os = "linux"))]
-            let backlog = libc::SOMAXCONN;
-            socket.listen(backlog)?;
-
-            // Convert the socket into a UnixListener
-            let raw_fd = socket.into_raw_fd();
-            DEFAULT_HOT_RESTART.register_listener_fd(path_str.to_string(), raw_fd);
-            let unix_listener = unsafe { UnixListener::from_raw_fd(raw_fd) };
+        }
+        socket.bind(&sock_addr)?;
+        #[cfg(target_os = "linux")]
+        let backlog = max_listener_backlog();
+        #[cfg(not(target_os = "linux"))]
+        let backlog = libc::SOMAXCONN;
+        socket.listen(backlog)?;
 
-            Ok(unix_listener)
-        } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "invalid path",
-            ))
+        // Convert the socket into a UnixListener
+        let raw_fd = socket.into_raw_fd();
+        DEFAULT_HOT_RESTART.register_listener_fd(key, raw_fd);
+        let unix_listener = unsafe { UnixListener::from_raw_fd(raw_fd) };
+
+        Ok(unix_listener)
+    }
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use std::collections::HashSet;
+
+    use tokio::net::TcpStream;
+
+    use super::{Incoming, MakeIncoming, ReusePortIncoming};
+    use crate::net::Address;
+
+    #[tokio::test]
+    async fn reuse_port_incoming() {
+        let mut incoming = ReusePortIncoming::new("127.0.0.1:0".parse().unwrap(), 4)
+            .make_incoming()
+            .await
+            .unwrap();
+        let Some(Address::Ip(addr)) = incoming.local_addr() else {
+            unreachable!();
+        };
+
+        let mut clients = Vec::new();
+        for _ in 0..32 {
+            clients.push(TcpStream::connect(addr).await.unwrap());
+        }
+        let mut accepted = HashSet::new();
+        for _ in 0..32 {
+            let conn = incoming.accept().await.unwrap().unwrap();
+            accepted.insert(conn.info.peer_addr.unwrap().to_string());
+        }
+        let connected = clients
+            .iter()
+            .map(|client| client.local_addr().unwrap().to_string())
+            .collect::<HashSet<_>>();
+        assert_eq!(accepted, connected);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn abstract_unix_socket() {
+        use motore::service::UnaryService;
+
+        use crate::net::dial::DefaultMakeTransport;
+
+        let addr: Address = format!("@volo-test-{}", std::process::id())
+            .parse()
+            .unwrap();
+        let mut incoming = addr.clone().make_incoming().await.unwrap();
+        assert_eq!(incoming.local_addr(), Some(addr.clone()));
+
+        let client = DefaultMakeTransport::new().call(addr).await.unwrap();
+        let conn = incoming.accept().await.unwrap().unwrap();
+        for info in [&client.info, &conn.info] {
+            let credentials = info.peer_credentials.unwrap();
+            assert_eq!(credentials.pid, Some(std::process::id() as i32));
+            assert_eq!(credentials.uid, unsafe { libc::getuid() });
+            assert_eq!(credentials.gid, unsafe { libc::getgid() });
         }
     }
 }
@@ -0,0 +1,522 @@
+//! Connection admission control for the servers under connection floods.
+//!
+//! [`AdmissionControl`] wraps a [`MakeIncoming`] and limits the number of the live connections,
+//! the number of the live connections of each source IP, and the rate of accepting connections.
+//!
+//! For the servers behind L4 load balancers,
+//! [`ProxyProtocol`](super::proxy_protocol::ProxyProtocol) should be wrapped by
+//! [`AdmissionControl`], i.e.,
+//! `AdmissionControl::new(ProxyProtocol::new(addr))`, so that the limit of each source IP applies
+//! to the real clients instead of the load balancers.
+
+use std::{
+    collections::HashMap,
+    fmt, io,
+    net::IpAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+
+use super::{Incoming, MakeIncoming};
+use crate::net::{Address, conn::Conn};
+
+/// The statistics of the connections of a server, which is cheap to clone, so it can be kept for
+/// exporting as metrics.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionStats(Arc<ConnectionStatsInner>);
+
+#[derive(Debug, Default)]
+struct ConnectionStatsInner {
+    live: AtomicUsize,
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl ConnectionStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of the live connections.
+    pub fn live(&self) -> usize {
+        self.0.live.load(Ordering::Relaxed)
+    }
+
+    /// The total number of the accepted connections.
+    pub fn accepted(&self) -> u64 {
+        self.0.accepted.load(Ordering::Relaxed)
+    }
+
+    /// The total number of the connections rejected by [`AdmissionControl`].
+    pub fn rejected(&self) -> u64 {
+        self.0.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Counts an accepted connection as live until the returned guard is dropped.
+    pub fn track(&self) -> LiveConnection {
+        self.0.accepted.fetch_add(1, Ordering::Relaxed);
+        self.0.live.fetch_add(1, Ordering::Relaxed);
+        LiveConnection(self.clone())
+    }
+
+    fn reject(&self) {
+        self.0.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The guard of a live connection returned by [`ConnectionStats::track`].
+#[derive(Debug)]
+pub struct LiveConnection(ConnectionStats);
+
+impl Drop for LiveConnection {
+    fn drop(&mut self) {
+        self.0.0.live.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// What [`AdmissionControl`] does when the limits are reached.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AdmissionMode {
+    /// Stops accepting until a live connection is closed or the rate allows, so the pending
+    /// connections are queued in the backlog of the listener.
+    #[default]
+    Backpressure,
+    /// Accepts and closes the connections immediately.
+    Reject,
+}
+
+/// [`MakeIncoming`] which limits the connections accepted by the inner one.
+///
+/// The limits of the live connections and the accept rate are enforced by the [`AdmissionMode`],
+/// while the connections exceeding the limit of their source IP are always rejected, since the
+/// source is unknown before accepting.
+///
+/// A connection is live until all the clones of its
+/// [`ConnInfo`](crate::net::conn::ConnInfo) are dropped, which holds the [`AdmissionPermit`].
+#[derive(Clone, Debug)]
+pub struct AdmissionControl<MI> {
+    inner: MI,
+    mode: AdmissionMode,
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    max_accept_rate: Option<u32>,
+    stats: ConnectionStats,
+    /// Whether the admitted connections are counted by `stats`, which is false if they are
+    /// counted by the server sharing the stats.
+    track_live: bool,
+}
+
+impl<MI> AdmissionControl<MI> {
+    pub fn new(inner: MI) -> Self {
+        Self {
+            inner,
+            mode: AdmissionMode::default(),
+            max_connections: None,
+            max_connections_per_ip: None,
+            max_accept_rate: None,
+            stats: ConnectionStats::default(),
+            track_live: true,
+        }
+    }
+
+    /// Sets the mode when the limits are reached, the default is
+    /// [`AdmissionMode::Backpressure`].
+    pub fn mode(mut self, mode: AdmissionMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the max number of the live connections.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Sets the max number of the live connections of each source IP.
+    ///
+    /// The source IP is the peer address of the connections accepted by the inner
+    /// [`MakeIncoming`], so it should wrap [`ProxyProtocol`](super::proxy_protocol::ProxyProtocol)
+    /// for the servers behind L4 load balancers.
+    pub fn max_connections_per_ip(mut self, max: usize) -> Self {
+        self.max_connections_per_ip = Some(max);
+        self
+    }
+
+    /// Sets the max number of the connections accepted per second, which allows bursts of up to
+    /// the same number.
+    pub fn max_accept_rate(mut self, per_second: u32) -> Self {
+        self.max_accept_rate = Some(per_second.max(1));
+        self
+    }
+
+    /// Counts the rejected connections into the stats of the server, e.g., the
+    /// `connection_stats` of the thrift server.
+    ///
+    /// The server counts the live and the accepted connections into the stats itself, so they are
+    /// not counted again by [`AdmissionControl`].
+    pub fn with_stats(mut self, stats: ConnectionStats) -> Self {
+        self.stats = stats;
+        self.track_live = false;
+        self
+    }
+
+    /// Returns the statistics of the admitted and rejected connections.
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats.clone()
+    }
+}
+
+impl<MI> MakeIncoming for AdmissionControl<MI>
+where
+    MI: MakeIncoming + Send,
+{
+    type Incoming = AdmissionIncoming<MI::Incoming>;
+
+    async fn make_incoming(self) -> io::Result<Self::Incoming> {
+        Ok(AdmissionIncoming {
+            inner: self.inner.make_incoming().await?,
+            mode: self.mode,
+            connections: self
+                .max_connections
+                .map(|max| Arc::new(Semaphore::new(max))),
+            max_connections_per_ip: self.max_connections_per_ip,
+            connections_per_ip: Arc::default(),
+            rate: self.max_accept_rate.map(TokenBucket::new),
+            rate_token: false,
+            stats: self.stats,
+            track_live: self.track_live,
+        })
+    }
+}
+
+type ConnectionsPerIp = Arc<Mutex<HashMap<IpAddr, usize>>>;
+
+/// [`Incoming`] made by [`AdmissionControl`].
+pub struct AdmissionIncoming<I> {
+    inner: I,
+    mode: AdmissionMode,
+    connections: Option<Arc<Semaphore>>,
+    max_connections_per_ip: Option<usize>,
+    connections_per_ip: ConnectionsPerIp,
+    rate: Option<TokenBucket>,
+    /// Whether a token of the rate is taken for a connection which is not admitted yet, e.g., the
+    /// accept is cancelled or the connection is rejected by the limit per IP, which is kept for
+    /// the next connection.
+    rate_token: bool,
+    stats: ConnectionStats,
+    track_live: bool,
+}
+
+impl<I: fmt::Debug> fmt::Debug for AdmissionIncoming<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdmissionIncoming")
+            .field("inner", &self.inner)
+            .field("mode", &self.mode)
+            .field("max_connections_per_ip", &self.max_connections_per_ip)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+impl<I> AdmissionIncoming<I> {
+    fn reject(&self, conn: Conn, reason: &str) {
+        tracing::debug!(
+            "[VOLO] reject a connection from {:?}: {reason}",
+            conn.info.peer_addr
+        );
+        self.stats.reject();
+    }
+}
+
+impl<I> Incoming for AdmissionIncoming<I>
+where
+    I: Incoming,
+{
+    async fn accept(&mut self) -> io::Result<Option<Conn>> {
+        loop {
+            let mut connection = None;
+            if self.mode == AdmissionMode::Backpressure {
+                if let Some(connections) = &self.connections {
+                    connection = Some(
+                        connections
+                            .clone()
+                            .acquire_owned()
+                            .await
+                            .expect("the semaphore is never closed"),
+                    );
+                }
+                if let Some(rate) = &mut self.rate {
+                    if !self.rate_token {
+                        rate.acquire().await;
+                        self.rate_token = true;
+                    }
+                }
+            }
+
+            let Some(mut conn) = self.inner.accept().await? else {
+                return Ok(None);
+            };
+
+            if self.mode == AdmissionMode::Reject {
+                if let Some(rate) = &mut self.rate {
+                    if !rate.try_acquire() {
+                        self.reject(conn, "exceeding the accept rate");
+                        continue;
+                    }
+                }
+                if let Some(connections) = &self.connections {
+                    match connections.clone().try_acquire_owned() {
+                        Ok(permit) => connection = Some(permit),
+                        Err(_) => {
+                            self.reject(conn, "exceeding the max connections");
+                            continue;
+                        }
+                    }
+                }
+            }
+
+            let mut ip = None;
+            if let Some(max) = self.max_connections_per_ip {
+                if let Some(Address::Ip(addr)) = &conn.info.peer_addr {
+                    let mut connections_per_ip = self.connections_per_ip.lock().unwrap();
+                    let count = connections_per_ip.entry(addr.ip()).or_default();
+                    if *count >= max {
+                        drop(connections_per_ip);
+                        self.reject(conn, "exceeding the max connections per IP");
+                        continue;
+                    }
+                    *count += 1;
+                    ip = Some((addr.ip(), self.connections_per_ip.clone()));
+                }
+            }
+
+            self.rate_token = false;
+            conn.info.admission = Some(Arc::new(AdmissionPermit {
+                _connection: connection,
+                ip,
+                _live: self.track_live.then(|| self.stats.track()),
+            }));
+            return Ok(Some(conn));
+        }
+    }
+
+    fn local_addr(&self) -> Option<Address> {
+        self.inner.local_addr()
+    }
+}
+
+/// The admission of a connection by [`AdmissionControl`], which releases the limits when it is
+/// dropped.
+pub struct AdmissionPermit {
+    _connection: Option<OwnedSemaphorePermit>,
+    ip: Option<(IpAddr, ConnectionsPerIp)>,
+    _live: Option<LiveConnection>,
+}
+
+impl Drop for AdmissionPermit {
+    fn drop(&mut self) {
+        if let Some((ip, connections_per_ip)) = &self.ip {
+            let mut connections_per_ip = connections_per_ip.lock().unwrap();
+            if let Some(count) = connections_per_ip.get_mut(ip) {
+                *count -= 1;
+                if *count == 0 {
+                    connections_per_ip.remove(ip);
+                }
+            }
+        }
+    }
+}
+
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(per_second: u32) -> Self {
+        Self {
+            rate: per_second as f64,
+            tokens: per_second as f64,
+            last: Instant::now(),
+        }
+    }
+
+    fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    async fn acquire(&mut self) {
+        while !self.try_acquire() {
+            tokio::time::sleep(Duration::from_secs_f64((1.0 - self.tokens) / self.rate)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{io::AsyncReadExt, net::TcpStream};
+
+    use super::{AdmissionControl, AdmissionMode, ConnectionStats};
+    use crate::net::{
+        Address,
+        incoming::{Incoming, MakeIncoming},
+    };
+
+    fn local_addr() -> Address {
+        Address::from("127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap())
+    }
+
+    async fn is_closed(stream: &mut TcpStream) -> bool {
+        let mut buf = [0u8; 1];
+        matches!(
+            tokio::time::timeout(Duration::from_secs(1), stream.read(&mut buf)).await,
+            Ok(Ok(0) | Err(_))
+        )
+    }
+
+    #[tokio::test]
+    async fn reject_connections() {
+        let admission = AdmissionControl::new(local_addr())
+            .mode(AdmissionMode::Reject)
+            .max_connections(2)
+            .max_connections_per_ip(1);
+        let stats = admission.stats();
+        let mut incoming = admission.make_incoming().await.unwrap();
+        let Some(Address::Ip(addr)) = incoming.local_addr() else {
+            unreachable!();
+        };
+
+        let _first = TcpStream::connect(addr).await.unwrap();
+        let conn = incoming.accept().await.unwrap().unwrap();
+        assert_eq!(stats.live(), 1);
+
+        // all the connections are from 127.0.0.1
+        let mut rejected = TcpStream::connect(addr).await.unwrap();
+        let _third = TcpStream::connect(addr).await.unwrap();
+        let accept = tokio::time::timeout(Duration::from_millis(100), incoming.accept()).await;
+        assert!(accept.is_err());
+        assert!(is_closed(&mut rejected).await);
+        assert_eq!(stats.rejected(), 2);
+
+        // the limits are released when the connection is closed
+        drop(conn);
+        assert_eq!(stats.live(), 0);
+        let _fourth = TcpStream::connect(addr).await.unwrap();
+        let _conn = incoming.accept().await.unwrap().unwrap();
+        assert_eq!(stats.live(), 1);
+        assert_eq!(stats.accepted(), 2);
+    }
+
+    #[tokio::test]
+    async fn shared_stats() {
+        let stats = ConnectionStats::new();
+        let admission = AdmissionControl::new(local_addr())
+            .mode(AdmissionMode::Reject)
+            .max_connections(1)
+            .with_stats(stats.clone());
+        let mut incoming = admission.make_incoming().await.unwrap();
+        let Some(Address::Ip(addr)) = incoming.local_addr() else {
+            unreachable!();
+        };
+
+        let _first = TcpStream::connect(addr).await.unwrap();
+        let conn = incoming.accept().await.unwrap().unwrap();
+        // the admitted connections are counted by the server
+        let _live = stats.track();
+        assert_eq!(stats.live(), 1);
+
+        let mut rejected = TcpStream::connect(addr).await.unwrap();
+        let accept = tokio::time::timeout(Duration::from_millis(100), incoming.accept()).await;
+        assert!(accept.is_err());
+        assert!(is_closed(&mut rejected).await);
+        assert_eq!(stats.rejected(), 1);
+        assert_eq!(stats.accepted(), 1);
+        drop(conn);
+    }
+
+    #[tokio::test]
+    async fn backpressure() {
+        let admission = AdmissionControl::new(local_addr()).max_connections(1);
+        let stats = admission.stats();
+        let mut incoming = admission.make_incoming().await.unwrap();
+        let Some(Address::Ip(addr)) = incoming.local_addr() else {
+            unreachable!();
+        };
+
+        let _first = TcpStream::connect(addr).await.unwrap();
+        let conn = incoming.accept().await.unwrap().unwrap();
+        let _second = TcpStream::connect(addr).await.unwrap();
+        let accept = tokio::time::timeout(Duration::from_millis(100), incoming.accept()).await;
+        assert!(accept.is_err());
+        assert_eq!(stats.rejected(), 0);
+
+        // the pending connection is accepted after the live one is closed
+        drop(conn);
+        let _conn = incoming.accept().await.unwrap().unwrap();
+        assert_eq!(stats.accepted(), 2);
+    }
+
+    #[tokio::test]
+    async fn accept_rate() {
+        let admission = AdmissionControl::new(local_addr())
+            .mode(AdmissionMode::Reject)
+            .max_accept_rate(2);
+        let stats = admission.stats();
+        let mut incoming = admission.make_incoming().await.unwrap();
+        let Some(Address::Ip(addr)) = incoming.local_addr() else {
+            unreachable!();
+        };
+
+        let mut clients = Vec::new();
+        for _ in 0..3 {
+            clients.push(TcpStream::connect(addr).await.unwrap());
+        }
+        let mut conns = Vec::new();
+        for _ in 0..2 {
+            conns.push(incoming.accept().await.unwrap().unwrap());
+        }
+        let accept = tokio::time::timeout(Duration::from_millis(100), incoming.accept()).await;
+        assert!(accept.is_err());
+        assert_eq!(stats.rejected(), 1);
+    }
+
+    #[tokio::test]
+    async fn backpressure_accept_rate() {
+        let admission = AdmissionControl::new(local_addr()).max_accept_rate(1);
+        let mut incoming = admission.make_incoming().await.unwrap();
+        let Some(Address::Ip(addr)) = incoming.local_addr() else {
+            unreachable!();
+        };
+
+        // the token taken by the cancelled accept is kept for the next connection
+        let accept = tokio::time::timeout(Duration::from_millis(100), incoming.accept()).await;
+        assert!(accept.is_err());
+        let _first = TcpStream::connect(addr).await.unwrap();
+        let accept = tokio::time::timeout(Duration::from_millis(500), incoming.accept()).await;
+        assert!(accept.unwrap().unwrap().is_some());
+
+        let _second = TcpStream::connect(addr).await.unwrap();
+        let accept = tokio::time::timeout(Duration::from_millis(500), incoming.accept()).await;
+        assert!(accept.is_err());
+    }
+}
@@ -0,0 +1,784 @@
+//! [PROXY protocol](https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt) support for the
+//! servers behind L4 load balancers.
+//!
+//! [`ProxyProtocol`] wraps a [`MakeIncoming`] and reads the v1 (text) or v2 (binary) header
+//! prepended by the load balancer on each accepted TCP connection, then rewrites
+//! [`ConnInfo::peer_addr`](crate::net::conn::ConnInfo::peer_addr) to the address of the real
+//! client and stores the [`ProxyHeader`] in
+//! [`ConnInfo::proxy_header`](crate::net::conn::ConnInfo::proxy_header).
+//!
+//! The header is read before handing the connection to the servers, so it works for all the
+//! servers taking a [`MakeIncoming`], and TLS is terminated after the header is consumed.
+//!
+//! Any client could send a header to spoof its address, so the servers reachable not only through
+//! the load balancers should set [`ProxyProtocol::trusted_cidrs`] to the addresses of the load
+//! balancers.
+
+use std::{
+    fmt, io,
+    mem::MaybeUninit,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::{StreamExt, future::BoxFuture, stream::FuturesUnordered};
+use ipnet::IpNet;
+use socket2::SockRef;
+use tokio::{
+    io::{AsyncReadExt, Interest},
+    net::TcpStream,
+};
+
+use super::{Incoming, MakeIncoming};
+use crate::net::{
+    Address,
+    conn::{Conn, ConnStream},
+};
+
+/// The default timeout of reading the PROXY protocol header.
+pub const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+/// The max length of a v1 header including the CRLF.
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LEN: usize = 16;
+
+/// The header of the PROXY protocol.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyHeader {
+    /// The version of the PROXY protocol, i.e., 1 or 2.
+    pub version: u8,
+    /// Whether the connection is established by the proxy itself, e.g., for health checking,
+    /// i.e., the `LOCAL` command of v2.
+    pub local: bool,
+    /// The address of the client, which is `None` for the local connections, the `UNKNOWN`
+    /// protocol of v1 and the unsupported address families, e.g., `AF_UNIX`.
+    pub source: Option<SocketAddr>,
+    /// The address the client connected to, which is `None` if the source is `None`.
+    pub destination: Option<SocketAddr>,
+    /// The TLVs of v2, which is always empty for v1.
+    pub tlvs: Vec<ProxyTlv>,
+}
+
+impl ProxyHeader {
+    /// Returns the value of the first TLV of the type.
+    pub fn tlv(&self, kind: u8) -> Option<&[u8]> {
+        self.tlvs
+            .iter()
+            .find(|tlv| tlv.kind == kind)
+            .map(|tlv| tlv.value.as_slice())
+    }
+
+    /// The host name requested by the client, e.g., the SNI of TLS, i.e.,
+    /// [`ProxyTlv::AUTHORITY`].
+    pub fn authority(&self) -> Option<&str> {
+        self.tlv(ProxyTlv::AUTHORITY)
+            .and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    /// The unique ID of the connection generated by the proxy, i.e., [`ProxyTlv::UNIQUE_ID`].
+    pub fn unique_id(&self) -> Option<&[u8]> {
+        self.tlv(ProxyTlv::UNIQUE_ID)
+    }
+}
+
+/// A Type-Length-Value of the PROXY protocol v2.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyTlv {
+    pub kind: u8,
+    pub value: Vec<u8>,
+}
+
+impl ProxyTlv {
+    pub const ALPN: u8 = 0x01;
+    pub const AUTHORITY: u8 = 0x02;
+    pub const CRC32C: u8 = 0x03;
+    pub const NOOP: u8 = 0x04;
+    pub const UNIQUE_ID: u8 = 0x05;
+    pub const SSL: u8 = 0x20;
+    pub const NETNS: u8 = 0x30;
+}
+
+/// [`MakeIncoming`] which reads the PROXY protocol header of the connections accepted by the
+/// inner one.
+///
+/// By default, the header is optional and the connections without it are handed on as they are,
+/// [`ProxyProtocol::strict`] rejects them instead. The connections whose header is malformed or
+/// not received within [`ProxyProtocol::header_timeout`] are closed.
+///
+/// Only TCP connections are inspected, the others are handed on as they are.
+#[derive(Clone, Debug)]
+pub struct ProxyProtocol<MI> {
+    inner: MI,
+    strict: bool,
+    header_timeout: Duration,
+    trusted_cidrs: Option<Arc<[IpNet]>>,
+}
+
+impl<MI> ProxyProtocol<MI> {
+    pub fn new(inner: MI) -> Self {
+        Self {
+            inner,
+            strict: false,
+            header_timeout: DEFAULT_HEADER_TIMEOUT,
+            trusted_cidrs: None,
+        }
+    }
+
+    /// Only reads the header of the connections from the addresses in the CIDRs, the default is
+    /// trusting all the addresses.
+    ///
+    /// The connections from the other addresses are handed on as they are without reading the
+    /// header, or rejected if [`ProxyProtocol::strict`] is enabled.
+    pub fn trusted_cidrs<I>(mut self, cidrs: I) -> Self
+    where
+        I: IntoIterator<Item = IpNet>,
+    {
+        self.trusted_cidrs = Some(cidrs.into_iter().collect());
+        self
+    }
+
+    /// Rejects the connections without a PROXY protocol header.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets the timeout of reading the header, the default is [`DEFAULT_HEADER_TIMEOUT`].
+    pub fn header_timeout(mut self, timeout: Duration) -> Self {
+        self.header_timeout = timeout;
+        self
+    }
+}
+
+impl<MI> MakeIncoming for ProxyProtocol<MI>
+where
+    MI: MakeIncoming + Send,
+{
+    type Incoming = ProxyProtocolIncoming<MI::Incoming>;
+
+    async fn make_incoming(self) -> io::Result<Self::Incoming> {
+        Ok(ProxyProtocolIncoming {
+            inner: self.inner.make_incoming().await?,
+            strict: self.strict,
+            header_timeout: self.header_timeout,
+            trusted_cidrs: self.trusted_cidrs,
+            pending: FuturesUnordered::new(),
+            exhausted: false,
+        })
+    }
+}
+
+/// [`Incoming`] made by [`ProxyProtocol`].
+///
+/// The headers of the connections are read concurrently, so a slow client does not block
+/// accepting the others. The `accept` of the inner [`Incoming`] must be cancel safe.
+pub struct ProxyProtocolIncoming<I> {
+    inner: I,
+    strict: bool,
+    header_timeout: Duration,
+    trusted_cidrs: Option<Arc<[IpNet]>>,
+    pending: FuturesUnordered<BoxFuture<'static, io::Result<Conn>>>,
+    exhausted: bool,
+}
+
+impl<I> ProxyProtocolIncoming<I> {
+    fn is_trusted(&self, conn: &Conn) -> bool {
+        let Some(cidrs) = &self.trusted_cidrs else {
+            return true;
+        };
+        match &conn.info.peer_addr {
+            Some(Address::Ip(addr)) => {
+                let ip = addr.ip().to_canonical();
+                cidrs.iter().any(|cidr| cidr.contains(&ip))
+            }
+            // only TCP connections are inspected
+            _ => true,
+        }
+    }
+}
+
+impl<I: fmt::Debug> fmt::Debug for ProxyProtocolIncoming<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyProtocolIncoming")
+            .field("inner", &self.inner)
+            .field("strict", &self.strict)
+            .field("header_timeout", &self.header_timeout)
+            .field("trusted_cidrs", &self.trusted_cidrs)
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
+
+impl<I> Incoming for ProxyProtocolIncoming<I>
+where
+    I: Incoming,
+{
+    async fn accept(&mut self) -> io::Result<Option<Conn>> {
+        loop {
+            if self.exhausted && self.pending.is_empty() {
+                return Ok(None);
+            }
+            tokio::select! {
+                conn = self.inner.accept(), if !self.exhausted => match conn? {
+                    Some(conn) if self.is_trusted(&conn) => {
+                        self.pending.push(Box::pin(read_proxy_header(
+                            conn,
+                            self.strict,
+                            self.header_timeout,
+                        )))
+                    }
+                    Some(conn) if self.strict => {
+                        tracing::debug!(
+                            "[VOLO] rejected the connection from the untrusted address: {:?}",
+                            conn.info.peer_addr
+                        );
+                    }
+                    Some(conn) => return Ok(Some(conn)),
+                    None => self.exhausted = true,
+                },
+                Some(conn) = self.pending.next() => match conn {
+                    Ok(conn) => return Ok(Some(conn)),
+                    Err(err) => {
+                        tracing::debug!("[VOLO] failed to read PROXY protocol header: {err}");
+                    }
+                },
+            }
+        }
+    }
+
+    fn local_addr(&self) -> Option<Address> {
+        self.inner.local_addr()
+    }
+}
+
+async fn read_proxy_header(
+    mut conn: Conn,
+    strict: bool,
+    header_timeout: Duration,
+) -> io::Result<Conn> {
+    let stream = match &mut conn.stream {
+        ConnStream::Tcp(stream) => stream,
+        #[allow(unreachable_patterns)]
+        _ => return Ok(conn),
+    };
+    let header = match tokio::time::timeout(header_timeout, read_header(stream)).await {
+        Ok(header) => header?,
+        Err(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "timed out reading the header",
+            ));
+        }
+    };
+    match header {
+        Some(header) => {
+            if let Some(source) = header.source {
+                conn.info.peer_addr = Some(Address::Ip(source));
+            }
+            conn.info.proxy_header = Some(header);
+        }
+        None if strict => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "missing the header",
+            ));
+        }
+        None => {}
+    }
+    Ok(conn)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Detected {
+    V1,
+    V2,
+    None,
+}
+
+/// Detects the version of the header by the received bytes, returns `None` if they are too few.
+fn detect(buf: &[u8]) -> Option<Detected> {
+    if buf.starts_with(V2_SIGNATURE) {
+        Some(Detected::V2)
+    } else if buf.starts_with(V1_PREFIX) {
+        Some(Detected::V1)
+    } else if V2_SIGNATURE.starts_with(buf) || V1_PREFIX.starts_with(buf) {
+        None
+    } else {
+        Some(Detected::None)
+    }
+}
+
+/// Reads the header without consuming any byte of the stream if there is no header.
+async fn read_header(stream: &mut TcpStream) -> io::Result<Option<ProxyHeader>> {
+    let mut buf = [0u8; V2_HEADER_LEN];
+    let detected = loop {
+        stream.readable().await?;
+        // the readiness is cleared by `WouldBlock`, so it waits for more bytes instead of peeking
+        // the same bytes again and again
+        let result = stream.try_io(Interest::READABLE, || {
+            let n = peek(stream, &mut buf)?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            detect(&buf[..n]).ok_or_else(|| io::ErrorKind::WouldBlock.into())
+        });
+        match result {
+            Ok(detected) => break detected,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            Err(err) => return Err(err),
+        }
+    };
+    match detected {
+        Detected::V1 => read_v1(stream).await.map(Some),
+        Detected::V2 => read_v2(stream).await.map(Some),
+        Detected::None => Ok(None),
+    }
+}
+
+/// Peeks the received bytes without waiting, returns `WouldBlock` if there is none.
+fn peek(stream: &TcpStream, buf: &mut [u8]) -> io::Result<usize> {
+    // SAFETY: `MaybeUninit<u8>` has the same layout as `u8`, and `peek` never writes
+    // uninitialized bytes into the buffer.
+    let buf = unsafe { &mut *(buf as *mut [u8] as *mut [MaybeUninit<u8>]) };
+    SockRef::from(stream).peek(buf)
+}
+
+async fn read_v1(stream: &mut TcpStream) -> io::Result<ProxyHeader> {
+    let mut line = Vec::with_capacity(V1_MAX_LEN);
+    let mut buf = [0u8; V1_MAX_LEN];
+    loop {
+        let n = stream.peek(&mut buf[..V1_MAX_LEN - line.len()]).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        // all the peeked bytes before the LF belong to the header, so they can be consumed
+        let (n, done) = match buf[..n].iter().position(|b| *b == b'\n') {
+            Some(pos) => (pos + 1, true),
+            None => (n, false),
+        };
+        stream.read_exact(&mut buf[..n]).await?;
+        line.extend_from_slice(&buf[..n]);
+        if done {
+            break;
+        }
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("v1 header is too long"));
+        }
+    }
+    match line.strip_suffix(b"\r\n") {
+        Some(line) => parse_v1(line),
+        None => Err(invalid("v1 header does not end with CRLF")),
+    }
+}
+
+async fn read_v2(stream: &mut TcpStream) -> io::Result<ProxyHeader> {
+    let mut header = [0u8; V2_HEADER_LEN];
+    stream.read_exact(&mut header).await?;
+    let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).await?;
+    parse_v2(&header, &payload)
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Parses the v1 header without the CRLF, e.g., `PROXY TCP4 192.0.2.1 192.0.2.2 56324 443`.
+fn parse_v1(line: &[u8]) -> io::Result<ProxyHeader> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid("v1 header is not ASCII"))?;
+    let mut parts = line.split(' ');
+    if parts.next() != Some("PROXY") {
+        return Err(invalid("v1 header does not start with PROXY"));
+    }
+    let ipv4 = match parts.next() {
+        Some("TCP4") => true,
+        Some("TCP6") => false,
+        // the rest of the line should be ignored
+        Some("UNKNOWN") => {
+            return Ok(ProxyHeader {
+                version: 1,
+                local: false,
+                source: None,
+                destination: None,
+                tlvs: Vec::new(),
+            });
+        }
+        _ => return Err(invalid("unknown protocol of v1 header")),
+    };
+    let mut next_ip = || -> io::Result<IpAddr> {
+        let ip = parts
+            .next()
+            .and_then(|ip| ip.parse::<IpAddr>().ok())
+            .ok_or_else(|| invalid("invalid address of v1 header"))?;
+        if ip.is_ipv4() != ipv4 {
+            return Err(invalid("mismatched address family of v1 header"));
+        }
+        Ok(ip)
+    };
+    let source_ip = next_ip()?;
+    let destination_ip = next_ip()?;
+    let mut next_port = || -> io::Result<u16> {
+        parts
+            .next()
+            .and_then(|port| port.parse::<u16>().ok())
+            .ok_or_else(|| invalid("invalid port of v1 header"))
+    };
+    let source_port = next_port()?;
+    let destination_port = next_port()?;
+    if parts.next().is_some() {
+        return Err(invalid("trailing data of v1 header"));
+    }
+    Ok(ProxyHeader {
+        version: 1,
+        local: false,
+        source: Some(SocketAddr::new(source_ip, source_port)),
+        destination: Some(SocketAddr::new(destination_ip, destination_port)),
+        tlvs: Vec::new(),
+    })
+}
+
+/// Parses the v2 header of 16 bytes and the payload following it.
+fn parse_v2(header: &[u8; V2_HEADER_LEN], payload: &[u8]) -> io::Result<ProxyHeader> {
+    if header[12] >> 4 != 2 {
+        return Err(invalid("unknown version of v2 header"));
+    }
+    let local = match header[12] & 0x0f {
+        0x0 => true,
+        0x1 => false,
+        _ => return Err(invalid("unknown command of v2 header")),
+    };
+    // the addresses are meaningless if the transport protocol is unspecified
+    let unspec = match header[13] & 0x0f {
+        // UNSPEC
+        0x0 => true,
+        // STREAM
+        0x1 => false,
+        // DGRAM
+        0x2 if local => true,
+        0x2 => return Err(invalid("DGRAM of v2 header over a stream")),
+        _ => return Err(invalid("unknown transport protocol of v2 header")),
+    };
+    let (addresses, tlvs) = match header[13] >> 4 {
+        // AF_UNSPEC
+        0x0 => (None, payload),
+        // AF_INET
+        0x1 => {
+            let (addrs, tlvs) = split_payload(payload, 12)?;
+            let ip = |at: usize| {
+                IpAddr::from(Ipv4Addr::from(
+                    <[u8; 4]>::try_from(&addrs[at..at + 4]).unwrap(),
+                ))
+            };
+            let port = |at: usize| u16::from_be_bytes([addrs[at], addrs[at + 1]]);
+            (
+                Some((
+                    SocketAddr::new(ip(0), port(8)),
+                    SocketAddr::new(ip(4), port(10)),
+                )),
+                tlvs,
+            )
+        }
+        // AF_INET6
+        0x2 => {
+            let (addrs, tlvs) = split_payload(payload, 36)?;
+            let ip = |at: usize| {
+                IpAddr::from(Ipv6Addr::from(
+                    <[u8; 16]>::try_from(&addrs[at..at + 16]).unwrap(),
+                ))
+            };
+            let port = |at: usize| u16::from_be_bytes([addrs[at], addrs[at + 1]]);
+            (
+                Some((
+                    SocketAddr::new(ip(0), port(32)),
+                    SocketAddr::new(ip(16), port(34)),
+                )),
+                tlvs,
+            )
+        }
+        // AF_UNIX
+        0x3 => (None, split_payload(payload, 216)?.1),
+        _ => return Err(invalid("unknown address family of v2 header")),
+    };
+    // the addresses of the local connections should be ignored
+    let (source, destination) = match addresses {
+        Some((source, destination)) if !local && !unspec => (Some(source), Some(destination)),
+        _ => (None, None),
+    };
+    Ok(ProxyHeader {
+        version: 2,
+        local,
+        source,
+        destination,
+        tlvs: parse_tlvs(tlvs)?,
+    })
+}
+
+fn split_payload(payload: &[u8], len: usize) -> io::Result<(&[u8], &[u8])> {
+    if payload.len() < len {
+        return Err(invalid("truncated addresses of v2 header"));
+    }
+    Ok(payload.split_at(len))
+}
+
+fn parse_tlvs(mut buf: &[u8]) -> io::Result<Vec<ProxyTlv>> {
+    let mut tlvs = Vec::new();
+    while !buf.is_empty() {
+        if buf.len() < 3 {
+            return Err(invalid("truncated TLV of v2 header"));
+        }
+        let len = u16::from_be_bytes([buf[1], buf[2]]) as usize;
+        if buf.len() < 3 + len {
+            return Err(invalid("truncated TLV of v2 header"));
+        }
+        tlvs.push(ProxyTlv {
+            kind: buf[0],
+            value: buf[3..3 + len].to_vec(),
+        });
+        buf = &buf[3 + len..];
+    }
+    Ok(tlvs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::{Detected, ProxyProtocol, ProxyTlv, detect, parse_v1, parse_v2};
+    use crate::net::{
+        Address,
+        incoming::{Incoming, MakeIncoming},
+    };
+
+    fn v2_header(command: u8, family: u8, addrs: &[u8], tlvs: &[u8]) -> Vec<u8> {
+        v2_header_with_protocol(command, family, 0x1, addrs, tlvs)
+    }
+
+    fn v2_header_with_protocol(
+        command: u8,
+        family: u8,
+        protocol: u8,
+        addrs: &[u8],
+        tlvs: &[u8],
+    ) -> Vec<u8> {
+        let mut buf = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+        buf.push(0x20 | command);
+        buf.push((family << 4) | protocol);
+        buf.extend_from_slice(&((addrs.len() + tlvs.len()) as u16).to_be_bytes());
+        buf.extend_from_slice(addrs);
+        buf.extend_from_slice(tlvs);
+        buf
+    }
+
+    #[test]
+    fn detect_version() {
+        assert_eq!(detect(b"PROXY TCP4"), Some(Detected::V1));
+        assert_eq!(detect(b"\r\n\r\n\0\r\nQUIT\n\x21"), Some(Detected::V2));
+        assert_eq!(detect(b"PRO"), None);
+        assert_eq!(detect(b"\r\n"), None);
+        assert_eq!(detect(b"PROPFIND"), Some(Detected::None));
+        assert_eq!(detect(b"GET / HTTP/1.1"), Some(Detected::None));
+    }
+
+    #[test]
+    fn parse_v1_header() {
+        let header = parse_v1(b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443").unwrap();
+        assert_eq!(header.version, 1);
+        assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(header.destination, Some("192.0.2.2:443".parse().unwrap()));
+
+        let header = parse_v1(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443").unwrap();
+        assert_eq!(header.source, Some("[2001:db8::1]:56324".parse().unwrap()));
+
+        let header = parse_v1(b"PROXY UNKNOWN ffff::1 ffff::2 1 2").unwrap();
+        assert_eq!(header.source, None);
+
+        assert!(parse_v1(b"PROXY TCP4 2001:db8::1 192.0.2.2 56324 443").is_err());
+        assert!(parse_v1(b"PROXY TCP4 192.0.2.1 192.0.2.2 56324").is_err());
+        assert!(parse_v1(b"PROXY UDP4 192.0.2.1 192.0.2.2 56324 443").is_err());
+    }
+
+    #[test]
+    fn parse_v2_header() {
+        let addrs = [192, 0, 2, 1, 192, 0, 2, 2, 0xdc, 0x04, 0x01, 0xbb];
+        let tlvs = [
+            &[ProxyTlv::AUTHORITY, 0, 11][..],
+            b"example.com",
+            &[ProxyTlv::UNIQUE_ID, 0, 2, 0xab, 0xcd],
+        ]
+        .concat();
+        let buf = v2_header(0x1, 0x1, &addrs, &tlvs);
+        let header = parse_v2(buf[..16].try_into().unwrap(), &buf[16..]).unwrap();
+        assert_eq!(header.version, 2);
+        assert!(!header.local);
+        assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(header.destination, Some("192.0.2.2:443".parse().unwrap()));
+        assert_eq!(header.authority(), Some("example.com"));
+        assert_eq!(header.unique_id(), Some(&[0xab, 0xcd][..]));
+
+        // the addresses of the local connections are ignored
+        let buf = v2_header(0x0, 0x1, &addrs, &[]);
+        let header = parse_v2(buf[..16].try_into().unwrap(), &buf[16..]).unwrap();
+        assert!(header.local);
+        assert_eq!(header.source, None);
+
+        // truncated addresses
+        let buf = v2_header(0x1, 0x2, &addrs, &[]);
+        assert!(parse_v2(buf[..16].try_into().unwrap(), &buf[16..]).is_err());
+        // truncated TLV
+        let buf = v2_header(0x1, 0x1, &addrs, &[ProxyTlv::NOOP, 0, 4, 0]);
+        assert!(parse_v2(buf[..16].try_into().unwrap(), &buf[16..]).is_err());
+
+        // the addresses of the unspecified transport protocol are ignored
+        let buf = v2_header_with_protocol(0x1, 0x1, 0x0, &addrs, &[]);
+        let header = parse_v2(buf[..16].try_into().unwrap(), &buf[16..]).unwrap();
+        assert_eq!(header.source, None);
+        // DGRAM
+        let buf = v2_header_with_protocol(0x1, 0x1, 0x2, &addrs, &[]);
+        assert!(parse_v2(buf[..16].try_into().unwrap(), &buf[16..]).is_err());
+        // unknown transport protocol
+        let buf = v2_header_with_protocol(0x1, 0x1, 0x3, &addrs, &[]);
+        assert!(parse_v2(buf[..16].try_into().unwrap(), &buf[16..]).is_err());
+    }
+
+    #[tokio::test]
+    async fn proxy_protocol_incoming() {
+        let addr = Address::from("127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap());
+        let mut incoming = ProxyProtocol::new(addr)
+            .header_timeout(Duration::from_millis(200))
+            .make_incoming()
+            .await
+            .unwrap();
+        let Some(Address::Ip(addr)) = incoming.local_addr() else {
+            unreachable!();
+        };
+
+        // the header and the data are read separately
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\nping")
+            .await
+            .unwrap();
+        let mut conn = incoming.accept().await.unwrap().unwrap();
+        assert_eq!(
+            conn.info.peer_addr,
+            Some(Address::from(
+                "192.0.2.1:56324".parse::<std::net::SocketAddr>().unwrap()
+            ))
+        );
+        assert_eq!(conn.info.proxy_header.as_ref().unwrap().version, 1);
+        let mut buf = [0u8; 4];
+        conn.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        // the connections without the header are handed on as they are
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"ping").await.unwrap();
+        let mut conn = incoming.accept().await.unwrap().unwrap();
+        assert_eq!(
+            conn.info.peer_addr,
+            Some(Address::from(client.local_addr().unwrap()))
+        );
+        assert!(conn.info.proxy_header.is_none());
+        conn.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        // a slow client does not block the others
+        let _slow = TcpStream::connect(addr).await.unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let addrs = [192, 0, 2, 1, 192, 0, 2, 2, 0xdc, 0x04, 0x01, 0xbb];
+        client
+            .write_all(&v2_header(0x1, 0x1, &addrs, &[]))
+            .await
+            .unwrap();
+        let conn = incoming.accept().await.unwrap().unwrap();
+        assert_eq!(conn.info.proxy_header.as_ref().unwrap().version, 2);
+
+        // the header received in pieces
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"PRO").await.unwrap();
+        let accept = tokio::spawn(async move { incoming.accept().await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        client
+            .write_all(b"XY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n")
+            .await
+            .unwrap();
+        let conn = accept.await.unwrap().unwrap().unwrap();
+        assert_eq!(conn.info.proxy_header.as_ref().unwrap().version, 1);
+    }
+
+    #[tokio::test]
+    async fn untrusted_proxy_protocol_incoming() {
+        let addr = Address::from("127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap());
+        let mut incoming = ProxyProtocol::new(addr)
+            .trusted_cidrs(["192.0.2.0/24".parse().unwrap()])
+            .make_incoming()
+            .await
+            .unwrap();
+        let Some(Address::Ip(addr)) = incoming.local_addr() else {
+            unreachable!();
+        };
+
+        // the header from an untrusted address is not read
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let header = b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n";
+        client.write_all(header).await.unwrap();
+        let mut conn = incoming.accept().await.unwrap().unwrap();
+        assert_eq!(
+            conn.info.peer_addr,
+            Some(Address::from(client.local_addr().unwrap()))
+        );
+        assert!(conn.info.proxy_header.is_none());
+        let mut buf = [0u8; 42];
+        conn.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, header);
+    }
+
+    #[tokio::test]
+    async fn strict_proxy_protocol_incoming() {
+        let addr = Address::from("127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap());
+        let mut incoming = ProxyProtocol::new(addr)
+            .strict(true)
+            .header_timeout(Duration::from_millis(100))
+            .make_incoming()
+            .await
+            .unwrap();
+        let Some(Address::Ip(addr)) = incoming.local_addr() else {
+            unreachable!();
+        };
+
+        // rejected for missing the header
+        let mut rejected = TcpStream::connect(addr).await.unwrap();
+        rejected.write_all(b"ping").await.unwrap();
+        // rejected for timeout
+        let mut timeout = TcpStream::connect(addr).await.unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n")
+            .await
+            .unwrap();
+
+        let conn = incoming.accept().await.unwrap().unwrap();
+        assert_eq!(
+            conn.info.peer_addr,
+            Some(Address::from(
+                "[2001:db8::1]:56324"
+                    .parse::<std::net::SocketAddr>()
+                    .unwrap()
+            ))
+        );
+        // keeps accepting to close the rejected connections
+        tokio::spawn(async move { incoming.accept().await });
+        let mut buf = [0u8; 1];
+        assert_eq!(rejected.read(&mut buf).await.unwrap_or(0), 0);
+        assert_eq!(timeout.read(&mut buf).await.unwrap_or(0), 0);
+    }
+}
@@ -6,6 +6,7 @@ pub mod incoming;
 pub mod shmipc;
 #[cfg(feature = "shmipc")]
 pub mod shmipc_fallback;
+pub mod sockopt;
 #[cfg(feature = "__tls")]
 #[cfg_attr(docsrs, doc(cfg(any(feature = "rustls", feature = "native-tls"))))]
 pub mod tls;
@@ -20,9 +21,12 @@ use std::{
     fmt,
     hash::Hash,
     net::{Ipv6Addr, SocketAddr},
+    str::FromStr,
 };
 
-pub use incoming::{DefaultIncoming, MakeIncoming};
+pub use incoming::{DefaultIncoming, ListenConfig, MakeIncoming};
+#[cfg(target_family = "unix")]
+pub use incoming::{MultiIncoming, ReusePortIncoming};
 #[cfg(target_family = "unix")]
 use tokio::net::unix::SocketAddr as TokioUnixSocketAddr;
 
@@ -88,6 +92,12 @@ impl PartialEq for Address {
             (Self::Ip(self_ip), Self::Ip(other_ip)) => self_ip == other_ip,
             #[cfg(target_family = "unix")]
             (Self::Unix(self_uds), Self::Unix(other_uds)) => {
+                #[cfg(target_os = "linux")]
+                match (self_uds.as_abstract_name(), other_uds.as_abstract_name()) {
+                    (Some(self_name), Some(other_name)) => return self_name == other_name,
+                    (None, None) => {}
+                    _ => return false,
+                }
                 match (self_uds.as_pathname(), other_uds.as_pathname()) {
                     (Some(self_pathname), Some(other_pathname)) => self_pathname == other_pathname,
                     (None, None) => {
@@ -165,6 +175,12 @@ fn should_favor_ipv6() -> bool {
     !probed.ipv4 || probed.ipv4_mapped_ipv6
 }
 
+/// Formats the address in the format accepted by [`FromStr`], i.e., `ip:port`, the path of a
+/// unix domain socket, or `@` followed by the escaped name of an abstract unix domain socket on
+/// Linux.
+///
+/// Note that the abstract names were formatted without the `@` prefix before, so the ones used as
+/// keys or in logs are changed.
 impl fmt::Display for Address {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -173,7 +189,7 @@ impl fmt::Display for Address {
             Self::Unix(addr) => {
                 #[cfg(target_os = "linux")]
                 if let Some(abs_name) = addr.as_abstract_name() {
-                    return write!(f, "{}", abs_name.escape_ascii());
+                    return write!(f, "@{}", abs_name.escape_ascii());
                 }
                 if let Some(pathname) = addr.as_pathname() {
                     write!(f, "{}", pathname.to_string_lossy())
@@ -187,6 +203,83 @@ impl fmt::Display for Address {
     }
 }
 
+/// An error which can be returned when parsing an [`Address`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseAddressError(String);
+
+impl fmt::Display for ParseAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid address: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseAddressError {}
+
+/// Parses the [`Display`](fmt::Display) of an [`Address`], i.e.,
+///
+/// - an IP address with the port, e.g., `127.0.0.1:8080` or `[::1]:8080`,
+/// - a unix domain socket path containing `/`, e.g., `/tmp/volo.sock` or `./volo.sock`,
+/// - or a Linux abstract unix domain socket name prefixed by `@`, e.g., `@volo`, whose
+///   non-printable bytes are escaped as `\xNN`.
+impl FromStr for Address {
+    type Err = ParseAddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(Self::Ip(addr));
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(name) = s.strip_prefix('@') {
+            return unescape_ascii(name)
+                .and_then(|name| StdUnixSocketAddr::from_abstract_name(name).ok())
+                .map(Self::Unix)
+                .ok_or_else(|| ParseAddressError(s.to_owned()));
+        }
+        #[cfg(target_family = "unix")]
+        if s.contains('/') {
+            return StdUnixSocketAddr::from_pathname(s)
+                .map(Self::Unix)
+                .map_err(|_| ParseAddressError(s.to_owned()));
+        }
+        Err(ParseAddressError(s.to_owned()))
+    }
+}
+
+/// Reverses [`escape_ascii`](slice::escape_ascii).
+#[cfg(target_os = "linux")]
+fn unescape_ascii(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        let b = match iter.next()? {
+            b't' => b'\t',
+            b'r' => b'\r',
+            b'n' => b'\n',
+            b @ (b'\\' | b'\'' | b'"') => b,
+            b'x' => {
+                let hex = [iter.next()?, iter.next()?];
+                u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            _ => return None,
+        };
+        bytes.push(b);
+    }
+    Some(bytes)
+}
+
+/// Creates the socket address of a Linux abstract unix domain socket name.
+#[cfg(target_os = "linux")]
+pub(crate) fn abstract_socket_addr(name: &[u8]) -> std::io::Result<socket2::SockAddr> {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    // the leading NUL byte indicates the abstract namespace
+    socket2::SockAddr::unix(OsStr::from_bytes(&[&[0], name].concat()))
+}
+
 impl From<SocketAddr> for Address {
     fn from(addr: SocketAddr) -> Self {
         Address::Ip(addr)
@@ -232,3 +325,40 @@ impl From<self::shmipc::Address> for Address {
         Address::Shmipc(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Address;
+
+    #[test]
+    fn parse_address() {
+        for addr in ["127.0.0.1:8080", "[::1]:8080"] {
+            let parsed = addr.parse::<Address>().unwrap();
+            assert!(parsed.is_ip());
+            assert_eq!(parsed.to_string(), addr);
+        }
+        assert!("localhost:8080".parse::<Address>().is_err());
+
+        #[cfg(target_family = "unix")]
+        {
+            let parsed = "/tmp/volo.sock".parse::<Address>().unwrap();
+            assert!(parsed.is_unix());
+            assert_eq!(parsed.to_string(), "/tmp/volo.sock");
+        }
+
+        #[cfg(target_os = "linux")]
+        for (addr, name) in [
+            ("@volo", &b"volo"[..]),
+            ("@volo\\x00\\n\\\\", &b"volo\0\n\\"[..]),
+        ] {
+            use std::os::linux::net::SocketAddrExt;
+
+            let parsed = addr.parse::<Address>().unwrap();
+            let unix = parsed.unix_addr().unwrap();
+            assert_eq!(unix.as_abstract_name(), Some(name));
+            assert_eq!(parsed.to_string(), addr);
+            assert_eq!(parsed, addr.parse::<Address>().unwrap());
+            assert_ne!(parsed, "@other".parse::<Address>().unwrap());
+        }
+    }
+}
@@ -149,11 +149,13 @@ impl shmipc::transport::TransportConnect for Connector {
 
     async fn connect(&self, addr: Self::Address) -> io::Result<Self::Stream> {
         match &addr {
-            Address::Tcp(addr) => {
-                crate::net::dial::make_tcp_connection(&Default::default(), addr.to_owned())
-                    .await
-                    .map(crate::net::conn::Conn::from)
-            }
+            Address::Tcp(addr) => crate::net::dial::make_tcp_connection(
+                &Default::default(),
+                &Default::default(),
+                addr.to_owned(),
+            )
+            .await
+            .map(crate::net::conn::Conn::from),
             #[cfg(target_family = "unix")]
             Address::Unix(addr) => {
                 let Some(path) = addr.as_pathname() else {
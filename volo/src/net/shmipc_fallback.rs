@@ -40,6 +40,10 @@ where
     async fn accept(&mut self) -> io::Result<Option<Conn>> {
         self.try_next().await
     }
+
+    fn local_addr(&self) -> Option<Address> {
+        self.default_incoming.local_addr()
+    }
 }
 
 impl<I> ShmipcIncoming<I>
@@ -119,4 +123,9 @@ impl MakeTransport for ShmipcMakeTransportWithFallback {
         self.default_mkt.set_write_timeout(timeout);
         self.shmipc_mkt.set_write_timeout(timeout);
     }
+
+    fn set_socket_options(&mut self, options: super::sockopt::SocketOptions) {
+        self.default_mkt.set_socket_options(options.clone());
+        self.shmipc_mkt.set_socket_options(options);
+    }
 }
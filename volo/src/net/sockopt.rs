@@ -0,0 +1,302 @@
+//! Socket options shared by the dialers and the listeners.
+//!
+//! [`SocketOptions`] is applied to the sockets created by
+//! [`DefaultMakeTransport`](super::dial::DefaultMakeTransport), to the listeners created by
+//! [`ListenConfig`](super::incoming::ListenConfig), and can be applied to the accepted connections
+//! by [`SocketOptions::apply_to_conn`].
+
+use std::{io, net::SocketAddr, time::Duration};
+
+use faststr::FastStr;
+use socket2::{SockRef, Socket};
+
+use super::conn::{Conn, ConnStream};
+
+/// The parameters of TCP keepalive, the system defaults are used for the unset ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TcpKeepalive {
+    /// The idle time before sending the first keepalive probe, i.e., `TCP_KEEPIDLE`.
+    pub idle: Option<Duration>,
+    /// The interval between two keepalive probes, i.e., `TCP_KEEPINTVL`.
+    pub interval: Option<Duration>,
+    /// The number of unacknowledged probes before dropping the connection, i.e., `TCP_KEEPCNT`.
+    pub retries: Option<u32>,
+}
+
+impl TcpKeepalive {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_idle(mut self, idle: Duration) -> Self {
+        self.idle = Some(idle);
+        self
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    fn to_socket2(self) -> socket2::TcpKeepalive {
+        let mut keepalive = socket2::TcpKeepalive::new();
+        if let Some(idle) = self.idle {
+            keepalive = keepalive.with_time(idle);
+        }
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "freebsd",
+            target_os = "windows"
+        ))]
+        if let Some(interval) = self.interval {
+            keepalive = keepalive.with_interval(interval);
+        }
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "freebsd"
+        ))]
+        if let Some(retries) = self.retries {
+            keepalive = keepalive.with_retries(retries);
+        }
+        keepalive
+    }
+}
+
+/// The socket options, the unset ones are left as the system defaults, except that
+/// `TCP_NODELAY` is enabled by default for dialing, and `SO_REUSEPORT` is enabled by default for
+/// listening.
+///
+/// The options which are not supported by the platform are ignored.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// Enables TCP keepalive with the parameters.
+    pub tcp_keepalive: Option<TcpKeepalive>,
+    /// `TCP_NODELAY`
+    pub tcp_nodelay: Option<bool>,
+    /// `SO_SNDBUF`
+    pub send_buffer_size: Option<usize>,
+    /// `SO_RCVBUF`
+    pub recv_buffer_size: Option<usize>,
+    /// `SO_REUSEPORT`, only for unix.
+    pub reuse_port: Option<bool>,
+    /// `TCP_USER_TIMEOUT`, only for Linux.
+    pub tcp_user_timeout: Option<Duration>,
+    /// `IP_TOS` for IPv4, or `IPV6_TCLASS` for IPv6.
+    pub tos: Option<u32>,
+    /// `SO_BINDTODEVICE`, only for Linux.
+    pub bind_device: Option<FastStr>,
+    /// The local address to bind before connecting, only for dialing.
+    pub local_addr: Option<SocketAddr>,
+}
+
+impl SocketOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_tcp_keepalive(mut self, keepalive: TcpKeepalive) -> Self {
+        self.tcp_keepalive = Some(keepalive);
+        self
+    }
+
+    pub fn with_tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.tcp_nodelay = Some(nodelay);
+        self
+    }
+
+    pub fn with_send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    pub fn with_recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    pub fn with_reuse_port(mut self, reuse_port: bool) -> Self {
+        self.reuse_port = Some(reuse_port);
+        self
+    }
+
+    pub fn with_tcp_user_timeout(mut self, timeout: Duration) -> Self {
+        self.tcp_user_timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_tos(mut self, tos: u32) -> Self {
+        self.tos = Some(tos);
+        self
+    }
+
+    pub fn with_bind_device(mut self, device: impl Into<FastStr>) -> Self {
+        self.bind_device = Some(device.into());
+        self
+    }
+
+    pub fn with_local_addr(mut self, addr: SocketAddr) -> Self {
+        self.local_addr = Some(addr);
+        self
+    }
+
+    /// Applies the options of the connections to an accepted connection, the options of the
+    /// listeners, i.e., `SO_REUSEPORT`, `SO_BINDTODEVICE` and the local address, are skipped.
+    ///
+    /// Only TCP connections are affected.
+    pub fn apply_to_conn(&self, conn: &Conn) -> io::Result<()> {
+        match &conn.stream {
+            ConnStream::Tcp(stream) => {
+                let ipv4 = stream.local_addr()?.is_ipv4();
+                self.apply_to_stream(&SockRef::from(stream), ipv4)
+            }
+            #[allow(unreachable_patterns)]
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies all the options except the local address to a TCP socket before connecting or
+    /// listening.
+    pub(crate) fn apply(&self, socket: &Socket, ipv4: bool) -> io::Result<()> {
+        #[cfg(all(
+            target_family = "unix",
+            not(any(target_os = "solaris", target_os = "illumos"))
+        ))]
+        if let Some(reuse_port) = self.reuse_port {
+            socket.set_reuse_port(reuse_port)?;
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(device) = &self.bind_device {
+            socket.bind_device(Some(device.as_bytes()))?;
+        }
+        self.apply_to_stream(socket, ipv4)
+    }
+
+    fn apply_to_stream(&self, socket: &Socket, ipv4: bool) -> io::Result<()> {
+        if let Some(keepalive) = self.tcp_keepalive {
+            socket.set_tcp_keepalive(&keepalive.to_socket2())?;
+        }
+        if let Some(nodelay) = self.tcp_nodelay {
+            socket.set_tcp_nodelay(nodelay)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(timeout) = self.tcp_user_timeout {
+            socket.set_tcp_user_timeout(Some(timeout))?;
+        }
+        if let Some(tos) = self.tos {
+            if ipv4 {
+                socket.set_tos_v4(tos)?;
+            } else {
+                #[cfg(any(
+                    target_os = "linux",
+                    target_os = "android",
+                    target_os = "macos",
+                    target_os = "freebsd"
+                ))]
+                socket.set_tclass_v6(tos)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use socket2::{Domain, Protocol, SockRef, Socket, Type};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::{SocketOptions, TcpKeepalive};
+    use crate::net::conn::{Conn, ConnStream};
+
+    #[tokio::test]
+    async fn apply_options() {
+        let options = SocketOptions::new()
+            .with_tcp_keepalive(
+                TcpKeepalive::new()
+                    .with_idle(Duration::from_secs(30))
+                    .with_interval(Duration::from_secs(5))
+                    .with_retries(3),
+            )
+            .with_tcp_nodelay(true)
+            .with_send_buffer_size(64 * 1024)
+            .with_tos(0x10);
+
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP)).unwrap();
+        options.apply(&socket, true).unwrap();
+        assert!(socket.keepalive().unwrap());
+        assert!(socket.tcp_nodelay().unwrap());
+        assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(
+                socket.tcp_keepalive_time().unwrap(),
+                Duration::from_secs(30)
+            );
+            assert_eq!(
+                socket.tcp_keepalive_interval().unwrap(),
+                Duration::from_secs(5)
+            );
+            assert_eq!(socket.tcp_keepalive_retries().unwrap(), 3);
+            assert_eq!(socket.tos_v4().unwrap(), 0x10);
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let conn = Conn::from(stream);
+        options.apply_to_conn(&conn).unwrap();
+        let ConnStream::Tcp(stream) = &conn.stream else {
+            unreachable!();
+        };
+        assert!(SockRef::from(stream).keepalive().unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn listen_with_options() {
+        use crate::net::{Address, ListenConfig, MakeIncoming, incoming::Incoming};
+
+        let options = SocketOptions::new()
+            .with_tcp_keepalive(TcpKeepalive::new().with_idle(Duration::from_secs(30)))
+            .with_tcp_nodelay(true);
+        let addr: std::net::SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let mut incoming = ListenConfig::new(addr, options)
+            .make_incoming()
+            .await
+            .unwrap();
+        let Some(Address::Ip(addr)) = incoming.local_addr() else {
+            unreachable!();
+        };
+
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let conn = incoming.accept().await.unwrap().unwrap();
+        let ConnStream::Tcp(stream) = &conn.stream else {
+            unreachable!();
+        };
+        // the accepted connections inherit the options of the listener
+        let socket = SockRef::from(stream);
+        assert!(socket.keepalive().unwrap());
+        assert!(socket.tcp_nodelay().unwrap());
+        assert_eq!(
+            socket.tcp_keepalive_time().unwrap(),
+            Duration::from_secs(30)
+        );
+    }
+}
@@ -15,17 +15,23 @@ use tokio::{
     net::TcpStream,
 };
 
-use super::dial::{Config, MakeTransport};
+use super::{
+    dial::{Config, MakeTransport},
+    sockopt::SocketOptions,
+};
 use crate::net::{
     Address,
-    conn::{self, Conn, ConnStream},
+    conn::{self, Conn, ConnStream, PeerCertificate, TlsServerName},
 };
 
 #[cfg(feature = "native-tls")]
 mod native_tls;
+mod reload;
 #[cfg(feature = "rustls")]
 mod rustls;
 
+pub use self::reload::{Reloadable, TlsReloader};
+
 #[cfg(feature = "native-tls")]
 use self::native_tls::{NativeTlsAcceptor, NativeTlsConnector};
 #[cfg(feature = "rustls")]
@@ -94,6 +100,35 @@ impl TlsStream {
             Self::NativeTls(stream) => stream.get_ref().negotiated_alpn().ok().flatten(),
         }
     }
+
+    /// Returns the end-entity certificate presented by the peer.
+    pub fn peer_certificate(&self) -> Option<PeerCertificate> {
+        let der = match self {
+            #[cfg(feature = "rustls")]
+            Self::Rustls(stream) => stream.get_ref().1.peer_certificates()?.first()?.to_vec(),
+            #[cfg(feature = "native-tls")]
+            Self::NativeTls(stream) => stream.get_ref().peer_certificate().ok()??.to_der().ok()?,
+        };
+        PeerCertificate::from_der(der)
+    }
+
+    /// Returns the server name indicated by the client, which is only available on the server
+    /// side and is not supported by native-tls.
+    pub fn server_name(&self) -> Option<TlsServerName> {
+        match self {
+            #[cfg(feature = "rustls")]
+            Self::Rustls(stream) => match stream.as_ref() {
+                tokio_rustls::TlsStream::Server(stream) => stream
+                    .get_ref()
+                    .1
+                    .server_name()
+                    .map(|name| TlsServerName(faststr::FastStr::new(name))),
+                tokio_rustls::TlsStream::Client(_) => None,
+            },
+            #[cfg(feature = "native-tls")]
+            Self::NativeTls(_) => None,
+        }
+    }
 }
 
 #[cfg(feature = "rustls")]
@@ -294,7 +329,7 @@ trait Connector: Sized {
 }
 
 trait Acceptor: Sized {
-    fn from_pem(cert: Vec<u8>, key: Vec<u8>) -> io::Result<Self>;
+    fn build(config: TlsAcceptorBuilder) -> io::Result<Self>;
     fn accept(&self, tcp_stream: TcpStream) -> impl Future<Output = io::Result<TlsStream>> + Send;
 }
 
@@ -317,6 +352,22 @@ impl TlsConnector {
         TlsConnectorBuilder::default()
     }
 
+    /// Replaces the TLS material of this connector and all its clones with the one of `other`.
+    ///
+    /// It takes effect on the new connections, and the established connections are not affected.
+    /// Returns an error if `other` uses a different TLS implementation.
+    pub fn reload(&self, other: &TlsConnector) -> io::Result<()> {
+        match (self, other) {
+            #[cfg(feature = "rustls")]
+            (Self::Rustls(connector), Self::Rustls(other)) => connector.reload(other),
+            #[cfg(feature = "native-tls")]
+            (Self::NativeTls(connector), Self::NativeTls(other)) => connector.reload(other),
+            #[allow(unreachable_patterns)]
+            _ => return Err(mismatched_tls_implementation()),
+        }
+        Ok(())
+    }
+
     pub async fn connect(
         &self,
         server_name: &str,
@@ -338,6 +389,26 @@ impl TlsConnector {
 }
 
 impl TlsAcceptor {
+    pub fn builder(cert: Vec<u8>, key: Vec<u8>) -> TlsAcceptorBuilder {
+        TlsAcceptorBuilder::new(cert, key)
+    }
+
+    /// Replaces the TLS material of this acceptor and all its clones with the one of `other`.
+    ///
+    /// It takes effect on the new handshakes, and the established connections are not affected.
+    /// Returns an error if `other` uses a different TLS implementation.
+    pub fn reload(&self, other: &TlsAcceptor) -> io::Result<()> {
+        match (self, other) {
+            #[cfg(feature = "rustls")]
+            (Self::Rustls(acceptor), Self::Rustls(other)) => acceptor.reload(other),
+            #[cfg(feature = "native-tls")]
+            (Self::NativeTls(acceptor), Self::NativeTls(other)) => acceptor.reload(other),
+            #[allow(unreachable_patterns)]
+            _ => return Err(mismatched_tls_implementation()),
+        }
+        Ok(())
+    }
+
     pub async fn accept(&self, tcp_stream: TcpStream) -> io::Result<ConnStream> {
         match self {
             #[cfg(feature = "rustls")]
@@ -347,14 +418,8 @@ impl TlsAcceptor {
         }
     }
 
-    #[cfg(feature = "rustls")]
     pub fn from_pem(cert: Vec<u8>, key: Vec<u8>) -> io::Result<Self> {
-        RustlsAcceptor::from_pem(cert, key).map(Self::Rustls)
-    }
-
-    #[cfg(not(feature = "rustls"))]
-    pub fn from_pem(cert: Vec<u8>, key: Vec<u8>) -> io::Result<Self> {
-        NativeTlsAcceptor::from_pem(cert, key).map(Self::NativeTls)
+        TlsAcceptorBuilder::new(cert, key).build()
     }
 
     pub fn from_pem_file(
@@ -367,6 +432,13 @@ impl TlsAcceptor {
     }
 }
 
+fn mismatched_tls_implementation() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "cannot reload with a different TLS implementation",
+    )
+}
+
 impl fmt::Debug for TlsConnector {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -395,6 +467,7 @@ pub struct TlsConnectorBuilder {
     pub(super) default_root_certs: bool,
     pub(super) pems: Vec<Vec<u8>>,
     pub(super) alpn_protocols: Vec<String>,
+    pub(super) identity: Option<(Vec<u8>, Vec<u8>)>,
 }
 
 impl Default for TlsConnectorBuilder {
@@ -403,6 +476,7 @@ impl Default for TlsConnectorBuilder {
             default_root_certs: true,
             pems: Vec::new(),
             alpn_protocols: Vec::new(),
+            identity: None,
         }
     }
 }
@@ -440,6 +514,26 @@ impl TlsConnectorBuilder {
         self
     }
 
+    /// Sets the certificate chain and the private key in PEM to present to the servers requiring
+    /// the client auth.
+    ///
+    /// The chain starts with the certificate of the client, and the key should be PKCS#8 encoded
+    /// for native-tls.
+    pub fn with_identity(mut self, cert: Vec<u8>, key: Vec<u8>) -> Self {
+        self.identity = Some((cert, key));
+        self
+    }
+
+    pub fn with_identity_from_file<CP, KP>(self, cert_path: CP, key_path: KP) -> io::Result<Self>
+    where
+        CP: AsRef<Path>,
+        KP: AsRef<Path>,
+    {
+        let cert = std::fs::read(cert_path.as_ref())?;
+        let key = std::fs::read(key_path.as_ref())?;
+        Ok(self.with_identity(cert, key))
+    }
+
     #[cfg(feature = "rustls")]
     pub fn build(self) -> io::Result<TlsConnector> {
         Self::build_rustls(self)
@@ -461,6 +555,135 @@ impl TlsConnectorBuilder {
     }
 }
 
+/// How a server verifies the certificates of the clients.
+///
+/// Only [`ClientAuth::None`] is supported by native-tls, which is unable to ask the clients for
+/// certificates.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAuth {
+    /// The clients are not asked for certificates.
+    #[default]
+    None,
+    /// The clients are asked for certificates, and the connections without certificates are
+    /// accepted, but the presented certificates must be valid.
+    Optional,
+    /// The clients must present valid certificates.
+    Required,
+}
+
+pub struct TlsAcceptorBuilder {
+    pub(super) cert: Vec<u8>,
+    pub(super) key: Vec<u8>,
+    pub(super) server_name_certs: Vec<(String, Vec<u8>, Vec<u8>)>,
+    pub(super) client_ca_pems: Vec<Vec<u8>>,
+    pub(super) client_auth: ClientAuth,
+}
+
+impl TlsAcceptorBuilder {
+    /// Creates a builder with the certificate chain and the private key in PEM of the server.
+    pub fn new(cert: Vec<u8>, key: Vec<u8>) -> Self {
+        Self {
+            cert,
+            key,
+            server_name_certs: Vec::new(),
+            client_ca_pems: Vec::new(),
+            client_auth: ClientAuth::None,
+        }
+    }
+
+    pub fn from_pem_file<CP, KP>(cert_path: CP, key_path: KP) -> io::Result<Self>
+    where
+        CP: AsRef<Path>,
+        KP: AsRef<Path>,
+    {
+        let cert = std::fs::read(cert_path.as_ref())?;
+        let key = std::fs::read(key_path.as_ref())?;
+        Ok(Self::new(cert, key))
+    }
+
+    /// Adds the certificate chain and the private key in PEM for a server name, e.g.,
+    /// `example.com` or `*.example.com`, which is selected by the server name indicated by the
+    /// clients.
+    ///
+    /// The certificate of [`TlsAcceptorBuilder::new`] is the default one for the clients which
+    /// indicate no server name or an unknown one. It is only supported by rustls.
+    pub fn add_server_name_cert(
+        mut self,
+        server_name: impl Into<String>,
+        cert: Vec<u8>,
+        key: Vec<u8>,
+    ) -> Self {
+        self.server_name_certs.push((server_name.into(), cert, key));
+        self
+    }
+
+    pub fn add_server_name_cert_from_file<CP, KP>(
+        self,
+        server_name: impl Into<String>,
+        cert_path: CP,
+        key_path: KP,
+    ) -> io::Result<Self>
+    where
+        CP: AsRef<Path>,
+        KP: AsRef<Path>,
+    {
+        let cert = std::fs::read(cert_path.as_ref())?;
+        let key = std::fs::read(key_path.as_ref())?;
+        Ok(self.add_server_name_cert(server_name, cert, key))
+    }
+
+    /// Adds a bundle of CA certificates in PEM to verify the certificates of the clients.
+    pub fn add_client_ca_pem(mut self, cert: Vec<u8>) -> Self {
+        self.client_ca_pems.push(cert);
+        self
+    }
+
+    pub fn add_client_ca_pem_from_file<CP>(mut self, cert_path: CP) -> io::Result<Self>
+    where
+        CP: AsRef<Path>,
+    {
+        let cert = std::fs::read(cert_path.as_ref())?;
+        self.client_ca_pems.push(cert);
+        Ok(self)
+    }
+
+    /// Sets how to verify the certificates of the clients, the default is [`ClientAuth::None`].
+    ///
+    /// At least one client CA is required unless it is [`ClientAuth::None`].
+    ///
+    /// It is only supported by rustls, building the acceptor of native-tls with the other ones
+    /// fails with [`io::ErrorKind::Unsupported`].
+    pub fn with_client_auth(mut self, client_auth: ClientAuth) -> Self {
+        self.client_auth = client_auth;
+        self
+    }
+
+    #[cfg(feature = "rustls")]
+    pub fn build(self) -> io::Result<TlsAcceptor> {
+        Self::build_rustls(self)
+    }
+
+    #[cfg(not(feature = "rustls"))]
+    pub fn build(self) -> io::Result<TlsAcceptor> {
+        Self::build_native_tls(self)
+    }
+
+    #[cfg(feature = "rustls")]
+    pub fn build_rustls(self) -> io::Result<TlsAcceptor> {
+        Ok(TlsAcceptor::Rustls(RustlsAcceptor::build(self)?))
+    }
+
+    /// Builds the acceptor of native-tls.
+    ///
+    /// It fails with [`io::ErrorKind::Unsupported`] if the client auth is enabled by
+    /// [`TlsAcceptorBuilder::with_client_auth`], or there is any certificate added by
+    /// [`TlsAcceptorBuilder::add_server_name_cert`], which are unsupported by native-tls.
+    #[cfg(feature = "native-tls")]
+    pub fn build_native_tls(self) -> io::Result<TlsAcceptor> {
+        Ok(TlsAcceptor::NativeTls(NativeTlsAcceptor::build(self)?))
+    }
+}
+
 /// TLS config for client
 #[derive(Debug, Clone)]
 pub struct ClientTlsConfig {
@@ -484,6 +707,12 @@ pub struct ServerTlsConfig {
 }
 
 impl ServerTlsConfig {
+    pub fn new(acceptor: impl Into<TlsAcceptor>) -> Self {
+        Self {
+            acceptor: acceptor.into(),
+        }
+    }
+
     pub fn from_pem(cert: Vec<u8>, key: Vec<u8>) -> io::Result<Self> {
         Ok(Self {
             acceptor: TlsAcceptor::from_pem(cert, key)?,
@@ -501,15 +730,26 @@ impl ServerTlsConfig {
     }
 }
 
+impl From<TlsAcceptor> for ServerTlsConfig {
+    fn from(acceptor: TlsAcceptor) -> Self {
+        Self::new(acceptor)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TlsMakeTransport {
     cfg: Config,
+    socket_options: SocketOptions,
     tls_config: ClientTlsConfig,
 }
 
 impl TlsMakeTransport {
     pub fn new(cfg: Config, tls_config: ClientTlsConfig) -> Self {
-        Self { cfg, tls_config }
+        Self {
+            cfg,
+            socket_options: SocketOptions::default(),
+            tls_config,
+        }
     }
 }
 
@@ -520,7 +760,8 @@ impl UnaryService<Address> for TlsMakeTransport {
     async fn call(&self, addr: Address) -> std::result::Result<Self::Response, Self::Error> {
         match addr {
             Address::Ip(addr) => {
-                let tcp = super::dial::make_tcp_connection(&self.cfg, addr).await?;
+                let tcp =
+                    super::dial::make_tcp_connection(&self.cfg, &self.socket_options, addr).await?;
 
                 match &self.tls_config.connector {
                     #[cfg(feature = "rustls")]
@@ -560,14 +801,294 @@ impl MakeTransport for TlsMakeTransport {
     }
 
     fn set_connect_timeout(&mut self, timeout: Option<Duration>) {
-        self.cfg = self.cfg.with_connect_timeout(timeout);
+        self.cfg.connect_timeout = timeout;
     }
 
     fn set_read_timeout(&mut self, timeout: Option<Duration>) {
-        self.cfg = self.cfg.with_read_timeout(timeout);
+        self.cfg.read_timeout = timeout;
     }
 
     fn set_write_timeout(&mut self, timeout: Option<Duration>) {
-        self.cfg = self.cfg.with_write_timeout(timeout);
+        self.cfg.write_timeout = timeout;
+    }
+
+    fn set_socket_options(&mut self, options: SocketOptions) {
+        self.socket_options = options;
+    }
+}
+
+#[cfg(all(test, feature = "rustls"))]
+mod tests {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use rcgen::{
+        BasicConstraints, CertificateParams, CertifiedIssuer, DnType, IsCa, KeyPair, SanType,
+    };
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::{ClientAuth, TlsAcceptor, TlsConnector, TlsReloader};
+    use crate::net::conn::{Conn, SubjectAltName, TlsServerName};
+
+    fn new_ca() -> CertifiedIssuer<'static, KeyPair> {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "volo ca");
+        CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap()
+    }
+
+    /// Returns the certificate and the key in PEM signed by the CA.
+    fn new_identity(
+        ca: &CertifiedIssuer<'static, KeyPair>,
+        common_name: &str,
+        subject_alt_names: Vec<SanType>,
+    ) -> (Vec<u8>, Vec<u8>) {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, common_name);
+        params.subject_alt_names = subject_alt_names;
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, ca).unwrap();
+        (cert.pem().into_bytes(), key.serialize_pem().into_bytes())
+    }
+
+    fn new_acceptor(
+        ca: &CertifiedIssuer<'static, KeyPair>,
+        client_auth: ClientAuth,
+    ) -> TlsAcceptor {
+        let (cert, key) = new_identity(
+            ca,
+            "server",
+            vec![SanType::DnsName("localhost".try_into().unwrap())],
+        );
+        TlsAcceptor::builder(cert, key)
+            .add_client_ca_pem(ca.pem().into_bytes())
+            .with_client_auth(client_auth)
+            .build()
+            .unwrap()
+    }
+
+    fn new_connector(
+        ca: &CertifiedIssuer<'static, KeyPair>,
+        identity: Option<(Vec<u8>, Vec<u8>)>,
+    ) -> TlsConnector {
+        let builder = TlsConnector::builder()
+            .enable_default_root_certs(false)
+            .add_pem(ca.pem().into_bytes());
+        match identity {
+            Some((cert, key)) => builder.with_identity(cert, key),
+            None => builder,
+        }
+        .build()
+        .unwrap()
+    }
+
+    async fn handshake(
+        acceptor: &TlsAcceptor,
+        connector: &TlsConnector,
+    ) -> (io::Result<Conn>, io::Result<Conn>) {
+        handshake_to(acceptor, connector, "localhost").await
+    }
+
+    /// Returns the connections of the client and the server.
+    async fn handshake_to(
+        acceptor: &TlsAcceptor,
+        connector: &TlsConnector,
+        server_name: &str,
+    ) -> (io::Result<Conn>, io::Result<Conn>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (client, server) = tokio::join!(
+            async {
+                let tcp = TcpStream::connect(addr).await?;
+                connector.connect(server_name, tcp).await.map(Conn::from)
+            },
+            async {
+                let (tcp, _) = listener.accept().await?;
+                acceptor.accept(tcp).await.map(Conn::from)
+            },
+        );
+        (client, server)
+    }
+
+    #[tokio::test]
+    async fn mutual_tls() {
+        let ca = new_ca();
+        let acceptor = new_acceptor(&ca, ClientAuth::Required);
+        let identity = new_identity(
+            &ca,
+            "client",
+            vec![SanType::URI("spiffe://volo/client".try_into().unwrap())],
+        );
+
+        let (client, server) = handshake(&acceptor, &new_connector(&ca, Some(identity))).await;
+        let client_cert = server.unwrap().info.peer_certificate.unwrap();
+        assert_eq!(client_cert.common_name(), Some("client"));
+        assert_eq!(client_cert.subject(), "CN=client");
+        assert_eq!(
+            client_cert.subject_alt_names(),
+            &[SubjectAltName::Uri("spiffe://volo/client".to_owned())]
+        );
+        let server_cert = client.unwrap().info.peer_certificate.unwrap();
+        assert_eq!(server_cert.common_name(), Some("server"));
+        assert_eq!(
+            server_cert.subject_alt_names(),
+            &[SubjectAltName::Dns("localhost".to_owned())]
+        );
+
+        // the client without a certificate, or with a certificate of an unknown CA
+        let (_, server) = handshake(&acceptor, &new_connector(&ca, None)).await;
+        assert!(server.is_err());
+        let other = new_identity(&new_ca(), "client", Vec::new());
+        let (_, server) = handshake(&acceptor, &new_connector(&ca, Some(other))).await;
+        assert!(server.is_err());
+    }
+
+    #[tokio::test]
+    async fn optional_client_auth() {
+        let ca = new_ca();
+        let acceptor = new_acceptor(&ca, ClientAuth::Optional);
+
+        let (_, server) = handshake(&acceptor, &new_connector(&ca, None)).await;
+        assert!(server.unwrap().info.peer_certificate.is_none());
+
+        let identity = new_identity(&ca, "client", Vec::new());
+        let (_, server) = handshake(&acceptor, &new_connector(&ca, Some(identity))).await;
+        let client_cert = server.unwrap().info.peer_certificate.unwrap();
+        assert_eq!(client_cert.common_name(), Some("client"));
+
+        // the client CA is required to verify the certificates
+        let (cert, key) = new_identity(&ca, "server", Vec::new());
+        assert!(
+            TlsAcceptor::builder(cert, key)
+                .with_client_auth(ClientAuth::Optional)
+                .build()
+                .is_err()
+        );
+    }
+
+    async fn server_name(acceptor: &TlsAcceptor, connector: &TlsConnector) -> String {
+        let (client, _) = handshake(acceptor, connector).await;
+        let server_cert = client.unwrap().info.peer_certificate.unwrap();
+        server_cert.common_name().unwrap().to_owned()
+    }
+
+    #[tokio::test]
+    async fn reload_certificates() {
+        let ca = new_ca();
+        let connector = new_connector(&ca, None);
+        let identity = Arc::new(Mutex::new(new_identity(
+            &ca,
+            "server-1",
+            vec![SanType::DnsName("localhost".try_into().unwrap())],
+        )));
+        let reloader = TlsReloader::new({
+            let identity = identity.clone();
+            move || {
+                let (cert, key) = identity.lock().unwrap().clone();
+                TlsAcceptor::from_pem(cert, key)
+            }
+        })
+        .unwrap();
+        let acceptor = reloader.get();
+        assert_eq!(server_name(&acceptor, &connector).await, "server-1");
+
+        *identity.lock().unwrap() = new_identity(
+            &ca,
+            "server-2",
+            vec![SanType::DnsName("localhost".try_into().unwrap())],
+        );
+        reloader.reload().unwrap();
+        assert_eq!(server_name(&acceptor, &connector).await, "server-2");
+
+        // the current certificate is kept if the new one is malformed
+        *identity.lock().unwrap() = (b"malformed".to_vec(), b"malformed".to_vec());
+        assert!(reloader.reload().is_err());
+        assert_eq!(server_name(&acceptor, &connector).await, "server-2");
+    }
+
+    #[tokio::test]
+    async fn watch_certificate_files() {
+        let ca = new_ca();
+        let connector = new_connector(&ca, None);
+        let dir = std::env::temp_dir().join(format!("volo-tls-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        let write_identity = |common_name: &str| {
+            let (cert, key) = new_identity(
+                &ca,
+                common_name,
+                vec![SanType::DnsName("localhost".try_into().unwrap())],
+            );
+            std::fs::write(&cert_path, cert).unwrap();
+            std::fs::write(&key_path, key).unwrap();
+        };
+
+        write_identity("server-1");
+        let reloader = TlsReloader::new({
+            let (cert_path, key_path) = (cert_path.clone(), key_path.clone());
+            move || TlsAcceptor::from_pem_file(&cert_path, &key_path)
+        })
+        .unwrap();
+        reloader.watch_files([&cert_path, &key_path], Duration::from_millis(10));
+        let acceptor = reloader.get();
+        assert_eq!(server_name(&acceptor, &connector).await, "server-1");
+
+        write_identity("server-2");
+        let mut reloaded = false;
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            if server_name(&acceptor, &connector).await == "server-2" {
+                reloaded = true;
+                break;
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(reloaded);
+    }
+
+    #[tokio::test]
+    async fn select_certificate_by_server_name() {
+        let ca = new_ca();
+        let dns_names = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| SanType::DnsName((*name).try_into().unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let (cert, key) = new_identity(&ca, "default", dns_names(&["localhost"]));
+        let (example_cert, example_key) =
+            new_identity(&ca, "example", dns_names(&["*.example.com"]));
+        let (volo_cert, volo_key) = new_identity(&ca, "volo", dns_names(&["volo.rs"]));
+        let acceptor = TlsAcceptor::builder(cert, key)
+            .add_server_name_cert("*.example.com", example_cert, example_key)
+            .add_server_name_cert("Volo.rs", volo_cert, volo_key)
+            .build()
+            .unwrap();
+        let connector = new_connector(&ca, None);
+
+        for (server_name, common_name) in [
+            ("api.example.com", "example"),
+            ("volo.rs", "volo"),
+            ("localhost", "default"),
+        ] {
+            let (client, server) = handshake_to(&acceptor, &connector, server_name).await;
+            let server_cert = client.unwrap().info.peer_certificate.unwrap();
+            assert_eq!(server_cert.common_name(), Some(common_name));
+            assert_eq!(
+                server.unwrap().info.server_name,
+                Some(TlsServerName(server_name.into()))
+            );
+        }
+
+        // the wildcard only matches a single label, so the default one is not valid for it
+        let (client, _) = handshake_to(&acceptor, &connector, "a.b.example.com").await;
+        assert!(client.is_err());
     }
 }
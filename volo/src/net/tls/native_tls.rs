@@ -1,18 +1,43 @@
 use std::{io, sync::Arc};
 
+use arc_swap::ArcSwap;
 use native_tls::{Certificate, Identity};
 use tokio::net::TcpStream;
 use tokio_native_tls::{TlsAcceptor, TlsConnector};
 
-use super::{Acceptor, Connector, TlsConnectorBuilder};
+use super::{Acceptor, ClientAuth, Connector, TlsAcceptorBuilder, TlsConnectorBuilder};
 
 /// A wrapper for [`tokio_native_tls::TlsConnector`]
+///
+/// The inner connector can be replaced, and the clones share the replaced one.
 #[derive(Clone)]
-pub struct NativeTlsConnector(pub(super) Arc<TlsConnector>);
+pub struct NativeTlsConnector(pub(super) Arc<ArcSwap<TlsConnector>>);
 
 /// A wrapper for [`tokio_native_tls::TlsAcceptor`]
+///
+/// The inner acceptor can be replaced, and the clones share the replaced one.
 #[derive(Clone)]
-pub struct NativeTlsAcceptor(pub(super) Arc<TlsAcceptor>);
+pub struct NativeTlsAcceptor(pub(super) Arc<ArcSwap<TlsAcceptor>>);
+
+impl NativeTlsConnector {
+    fn new(connector: Arc<TlsConnector>) -> Self {
+        Self(Arc::new(ArcSwap::new(connector)))
+    }
+
+    pub(super) fn reload(&self, other: &Self) {
+        self.0.store(other.0.load_full());
+    }
+}
+
+impl NativeTlsAcceptor {
+    fn new(acceptor: Arc<TlsAcceptor>) -> Self {
+        Self(Arc::new(ArcSwap::new(acceptor)))
+    }
+
+    pub(super) fn reload(&self, other: &Self) {
+        self.0.store(other.0.load_full());
+    }
+}
 
 impl Default for NativeTlsConnector {
     fn default() -> Self {
@@ -35,10 +60,15 @@ impl Connector for NativeTlsConnector {
             .map(AsRef::as_ref)
             .collect::<Vec<&str>>();
         builder.request_alpns(&alpn);
+        if let Some((cert, key)) = config.identity {
+            let identity = Identity::from_pkcs8(&cert, &key)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            builder.identity(identity);
+        }
         let connector = builder
             .build()
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
-        Ok(Self(Arc::new(TlsConnector::from(connector))))
+        Ok(Self::new(Arc::new(TlsConnector::from(connector))))
     }
 
     async fn connect(
@@ -47,7 +77,9 @@ impl Connector for NativeTlsConnector {
         tcp_stream: TcpStream,
     ) -> io::Result<super::TlsStream> {
         tracing::trace!("NativeTlsConnector::connect({server_name})");
-        match self.0.connect(server_name, tcp_stream).await {
+        // the established connections keep the connector of their own
+        let connector = self.0.load_full();
+        match connector.connect(server_name, tcp_stream).await {
             Ok(stream) => Ok(Into::into(stream)),
             Err(e) => Err(io::Error::new(io::ErrorKind::ConnectionRefused, e)),
         }
@@ -55,10 +87,23 @@ impl Connector for NativeTlsConnector {
 }
 
 impl Acceptor for NativeTlsAcceptor {
-    fn from_pem(cert: Vec<u8>, key: Vec<u8>) -> io::Result<Self> {
-        let identity = Identity::from_pkcs8(&cert, &key)
+    fn build(config: TlsAcceptorBuilder) -> io::Result<Self> {
+        // native-tls cannot request and verify the certificates of the clients
+        if config.client_auth != ClientAuth::None {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "client auth is unsupported by native-tls",
+            ));
+        }
+        if !config.server_name_certs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "selecting certificates by server name is unsupported by native-tls",
+            ));
+        }
+        let identity = Identity::from_pkcs8(&config.cert, &config.key)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
-        Ok(Self(Arc::new(
+        Ok(Self::new(Arc::new(
             native_tls::TlsAcceptor::builder(identity)
                 .build()
                 .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
@@ -68,7 +113,8 @@ impl Acceptor for NativeTlsAcceptor {
 
     async fn accept(&self, tcp_stream: TcpStream) -> io::Result<super::TlsStream> {
         tracing::trace!("NativeTlsAcceptor::accept");
-        match self.0.accept(tcp_stream).await {
+        let acceptor = self.0.load_full();
+        match acceptor.accept(tcp_stream).await {
             Ok(stream) => Ok(Into::into(stream)),
             Err(e) => Err(io::Error::new(io::ErrorKind::ConnectionRefused, e)),
         }
@@ -77,36 +123,36 @@ impl Acceptor for NativeTlsAcceptor {
 
 impl From<native_tls::TlsConnector> for super::TlsConnector {
     fn from(value: native_tls::TlsConnector) -> Self {
-        Self::NativeTls(NativeTlsConnector(Arc::new(TlsConnector::from(value))))
+        Self::NativeTls(NativeTlsConnector::new(Arc::new(TlsConnector::from(value))))
     }
 }
 
 impl From<TlsConnector> for super::TlsConnector {
     fn from(value: TlsConnector) -> Self {
-        Self::NativeTls(NativeTlsConnector(Arc::new(value)))
+        Self::NativeTls(NativeTlsConnector::new(Arc::new(value)))
     }
 }
 
 impl From<Arc<TlsConnector>> for super::TlsConnector {
     fn from(value: Arc<TlsConnector>) -> Self {
-        Self::NativeTls(NativeTlsConnector(value))
+        Self::NativeTls(NativeTlsConnector::new(value))
     }
 }
 
 impl From<native_tls::TlsAcceptor> for super::TlsAcceptor {
     fn from(value: native_tls::TlsAcceptor) -> Self {
-        Self::NativeTls(NativeTlsAcceptor(Arc::new(TlsAcceptor::from(value))))
+        Self::NativeTls(NativeTlsAcceptor::new(Arc::new(TlsAcceptor::from(value))))
     }
 }
 
 impl From<TlsAcceptor> for super::TlsAcceptor {
     fn from(value: TlsAcceptor) -> Self {
-        Self::NativeTls(NativeTlsAcceptor(Arc::new(value)))
+        Self::NativeTls(NativeTlsAcceptor::new(Arc::new(value)))
     }
 }
 
 impl From<Arc<TlsAcceptor>> for super::TlsAcceptor {
     fn from(value: Arc<TlsAcceptor>) -> Self {
-        Self::NativeTls(NativeTlsAcceptor(value))
+        Self::NativeTls(NativeTlsAcceptor::new(value))
     }
 }
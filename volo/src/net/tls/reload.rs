@@ -0,0 +1,166 @@
+//! Reloads the TLS material, e.g., the rotated certificates, without restarting the servers and
+//! the clients.
+
+use std::{
+    fmt, io,
+    path::PathBuf,
+    sync::{Arc, Weak},
+    time::{Duration, SystemTime},
+};
+
+use super::{TlsAcceptor, TlsConnector};
+
+/// The TLS acceptors and connectors whose material can be replaced.
+pub trait Reloadable: Clone + Send + Sync + 'static {
+    /// Replaces the material of `self` and all its clones with the one of `other`.
+    fn reload(&self, other: &Self) -> io::Result<()>;
+}
+
+impl Reloadable for TlsAcceptor {
+    fn reload(&self, other: &Self) -> io::Result<()> {
+        TlsAcceptor::reload(self, other)
+    }
+}
+
+impl Reloadable for TlsConnector {
+    fn reload(&self, other: &Self) -> io::Result<()> {
+        TlsConnector::reload(self, other)
+    }
+}
+
+type LoadFn<T> = dyn Fn() -> io::Result<T> + Send + Sync;
+
+/// Reloads a [`TlsAcceptor`] or a [`TlsConnector`] by a function which loads the TLS material,
+/// e.g., reads and parses the PEM files.
+///
+/// The reloading is triggered explicitly by [`TlsReloader::reload`], periodically by
+/// [`TlsReloader::reload_every`], or by [`TlsReloader::watch_files`] when the files are modified.
+/// The new handshakes pick up the new material atomically, and the established connections are
+/// not affected. If the loading fails, the current material is kept and the error is logged.
+///
+/// ```ignore
+/// let reloader = TlsReloader::new(|| TlsAcceptor::from_pem_file("cert.pem", "key.pem"))?;
+/// reloader.watch_files(["cert.pem", "key.pem"], Duration::from_secs(10));
+/// let tls_config = ServerTlsConfig::new(reloader.get());
+/// ```
+#[derive(Clone)]
+pub struct TlsReloader<T> {
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T> {
+    current: T,
+    load: Box<LoadFn<T>>,
+}
+
+impl<T> TlsReloader<T>
+where
+    T: Reloadable,
+{
+    /// Creates a reloader with the function which loads the TLS material, the first loading
+    /// should succeed.
+    pub fn new<F>(load: F) -> io::Result<Self>
+    where
+        F: Fn() -> io::Result<T> + Send + Sync + 'static,
+    {
+        let current = load()?;
+        Ok(Self {
+            inner: Arc::new(Inner {
+                current,
+                load: Box::new(load),
+            }),
+        })
+    }
+
+    /// Returns the acceptor or the connector reloaded by this reloader, which can be used by the
+    /// servers or the clients.
+    pub fn get(&self) -> T {
+        self.inner.current.clone()
+    }
+
+    /// Loads the TLS material and replaces the current one, the current one is kept if it fails.
+    pub fn reload(&self) -> io::Result<()> {
+        self.inner.reload()
+    }
+
+    /// Reloads every `interval` in the background until the reloader is dropped.
+    pub fn reload_every(&self, interval: Duration) {
+        self.spawn(interval, Vec::new());
+    }
+
+    /// Reloads when any of the files is modified until the reloader is dropped.
+    ///
+    /// The modification time of the files is polled every `interval`.
+    pub fn watch_files<I>(&self, paths: I, interval: Duration)
+    where
+        I: IntoIterator,
+        I::Item: Into<PathBuf>,
+    {
+        self.spawn(interval, paths.into_iter().map(Into::into).collect());
+    }
+
+    /// Reloads every `interval`, or only when the files are modified if there are any.
+    fn spawn(&self, interval: Duration, paths: Vec<PathBuf>) {
+        let inner: Weak<Inner<T>> = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            let mut modified = modified_times(&paths);
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(inner) = inner.upgrade() else {
+                    break;
+                };
+                if !paths.is_empty() {
+                    let current = modified_times(&paths);
+                    if current == modified {
+                        continue;
+                    }
+                    // a failed reloading is retried when the files are modified again
+                    modified = current;
+                }
+                let _ = inner.reload();
+            }
+        });
+    }
+}
+
+impl<T> Inner<T>
+where
+    T: Reloadable,
+{
+    fn reload(&self) -> io::Result<()> {
+        match (self.load)().and_then(|new| self.current.reload(&new)) {
+            Ok(()) => {
+                tracing::info!("[VOLO] TLS material reloaded");
+                Ok(())
+            }
+            Err(err) => {
+                tracing::warn!("[VOLO] failed to reload TLS material, keep the current one: {err}");
+                Err(err)
+            }
+        }
+    }
+}
+
+fn modified_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| {
+            std::fs::metadata(path)
+                .and_then(|meta| meta.modified())
+                .ok()
+        })
+        .collect()
+}
+
+impl<T> fmt::Debug for TlsReloader<T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsReloader")
+            .field("current", &self.inner.current)
+            .finish()
+    }
+}
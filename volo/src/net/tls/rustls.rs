@@ -1,19 +1,50 @@
-use std::{io, io::Result, sync::Arc};
+use std::{collections::HashMap, io, io::Result, sync::Arc};
 
-use rustls::{RootCertStore, ServerConfig, pki_types::ServerName};
-use rustls_pki_types::PrivateKeyDer;
+use arc_swap::ArcSwap;
+use rustls::{
+    RootCertStore, ServerConfig,
+    crypto::CryptoProvider,
+    pki_types::ServerName,
+    server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier, danger::ClientCertVerifier},
+    sign::CertifiedKey,
+};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
 use tokio::net::TcpStream;
 use tokio_rustls::{TlsAcceptor, TlsConnector, rustls::ClientConfig};
 
-use super::{Acceptor, Connector, TlsConnectorBuilder};
+use super::{Acceptor, ClientAuth, Connector, TlsAcceptorBuilder, TlsConnectorBuilder};
 
 /// A wrapper for [`tokio_rustls::TlsConnector`]
+///
+/// The inner connector can be replaced, and the clones share the replaced one.
 #[derive(Clone)]
-pub struct RustlsConnector(pub(super) TlsConnector);
+pub struct RustlsConnector(pub(super) Arc<ArcSwap<TlsConnector>>);
 
 /// A wrapper for [`tokio_rustls::TlsAcceptor`]
+///
+/// The inner acceptor can be replaced, and the clones share the replaced one.
 #[derive(Clone)]
-pub struct RustlsAcceptor(pub(super) TlsAcceptor);
+pub struct RustlsAcceptor(pub(super) Arc<ArcSwap<TlsAcceptor>>);
+
+impl RustlsConnector {
+    fn new(connector: TlsConnector) -> Self {
+        Self(Arc::new(ArcSwap::from_pointee(connector)))
+    }
+
+    pub(super) fn reload(&self, other: &Self) {
+        self.0.store(other.0.load_full());
+    }
+}
+
+impl RustlsAcceptor {
+    fn new(acceptor: TlsAcceptor) -> Self {
+        Self(Arc::new(ArcSwap::from_pointee(acceptor)))
+    }
+
+    pub(super) fn reload(&self, other: &Self) {
+        self.0.store(other.0.load_full());
+    }
+}
 
 impl Default for RustlsConnector {
     fn default() -> Self {
@@ -38,6 +69,17 @@ fn rustls_crypto_provider() -> rustls::crypto::CryptoProvider {
     }
 }
 
+/// Parses the certificate chain and the private key in PEM.
+fn load_identity(
+    cert: &[u8],
+    key: &[u8],
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let cert = rustls_pemfile::certs(&mut &cert[..]).collect::<Result<Vec<_>>>()?;
+    let key = rustls_pemfile::private_key(&mut &key[..])?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No private key found"))?;
+    Ok((cert, key))
+}
+
 impl Connector for RustlsConnector {
     fn build(builder: TlsConnectorBuilder) -> Result<Self> {
         let mut certs = if builder.default_root_certs {
@@ -60,19 +102,26 @@ impl Connector for RustlsConnector {
                 .add(cert)
                 .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
         }
-        let mut client_config =
-            ClientConfig::builder_with_provider(Arc::new(rustls_crypto_provider()))
-                .with_protocol_versions(rustls::DEFAULT_VERSIONS)
-                .expect("something wrong on rustls ClientConfig")
-                .with_root_certificates(certs)
-                .with_no_client_auth();
+        let client_config = ClientConfig::builder_with_provider(Arc::new(rustls_crypto_provider()))
+            .with_protocol_versions(rustls::DEFAULT_VERSIONS)
+            .expect("something wrong on rustls ClientConfig")
+            .with_root_certificates(certs);
+        let mut client_config = match builder.identity {
+            Some((cert, key)) => {
+                let (cert, key) = load_identity(&cert, &key)?;
+                client_config
+                    .with_client_auth_cert(cert, key)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+            }
+            None => client_config.with_no_client_auth(),
+        };
         client_config.alpn_protocols = builder
             .alpn_protocols
             .into_iter()
             .map(String::into_bytes)
             .collect();
         let connector = TlsConnector::from(Arc::new(client_config));
-        Ok(Self(connector))
+        Ok(Self::new(connector))
     }
 
     async fn connect(
@@ -84,8 +133,9 @@ impl Connector for RustlsConnector {
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
             .to_owned();
         tracing::trace!("RustlsConnector::connect({server_name:?})");
-        self.0
-            .connect(sni, tcp_stream)
+        // the established connections keep the config of their own
+        let connect = self.0.load().connect(sni, tcp_stream);
+        connect
             .await
             .map(tokio_rustls::TlsStream::Client)
             .map(Into::into)
@@ -93,65 +143,147 @@ impl Connector for RustlsConnector {
 }
 
 impl Acceptor for RustlsAcceptor {
-    fn from_pem(cert: Vec<u8>, key: Vec<u8>) -> Result<Self> {
-        let cert = rustls_pemfile::certs(&mut cert.as_ref()).collect::<Result<Vec<_>>>()?;
-        let key = rustls_pemfile::pkcs8_private_keys(&mut key.as_ref())
-            .collect::<Result<Vec<_>>>()?
-            .pop()
-            .map(PrivateKeyDer::Pkcs8)
-            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No private key found"))?;
-        let server_config = ServerConfig::builder_with_provider(Arc::new(rustls_crypto_provider()))
+    fn build(builder: TlsAcceptorBuilder) -> Result<Self> {
+        let provider = Arc::new(rustls_crypto_provider());
+        let server_config = ServerConfig::builder_with_provider(provider.clone())
             .with_protocol_versions(rustls::DEFAULT_VERSIONS)
-            .expect("something wrong on rustls ServerConfig")
-            .with_no_client_auth()
-            .with_single_cert(cert, key)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            .expect("something wrong on rustls ServerConfig");
+        let server_config = match client_cert_verifier(&builder, provider.clone())? {
+            Some(verifier) => server_config.with_client_cert_verifier(verifier),
+            None => server_config.with_no_client_auth(),
+        };
+        let server_config = if builder.server_name_certs.is_empty() {
+            let (cert, key) = load_identity(&builder.cert, &builder.key)?;
+            server_config
+                .with_single_cert(cert, key)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+        } else {
+            server_config
+                .with_cert_resolver(Arc::new(ServerNameResolver::new(&builder, &provider)?))
+        };
         let acceptor = TlsAcceptor::from(Arc::new(server_config));
-        Ok(Self(acceptor))
+        Ok(Self::new(acceptor))
     }
 
     async fn accept(&self, tcp_stream: TcpStream) -> Result<super::TlsStream> {
         tracing::trace!("RustlsAcceptor::accept");
-        self.0
-            .accept(tcp_stream)
+        let accept = self.0.load().accept(tcp_stream);
+        accept
             .await
             .map(tokio_rustls::TlsStream::Server)
             .map(Into::into)
     }
 }
 
+/// Selects the certificate by the server name indicated by the client.
+#[derive(Debug)]
+struct ServerNameResolver {
+    default: Arc<CertifiedKey>,
+    certs: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ServerNameResolver {
+    fn new(builder: &TlsAcceptorBuilder, provider: &CryptoProvider) -> Result<Self> {
+        let certified_key = |cert: &[u8], key: &[u8]| {
+            let (cert, key) = load_identity(cert, key)?;
+            let key = provider
+                .key_provider
+                .load_private_key(key)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            Ok::<_, io::Error>(Arc::new(CertifiedKey::new(cert, key)))
+        };
+        let mut certs = HashMap::with_capacity(builder.server_name_certs.len());
+        for (server_name, cert, key) in builder.server_name_certs.iter() {
+            certs.insert(server_name.to_ascii_lowercase(), certified_key(cert, key)?);
+        }
+        Ok(Self {
+            default: certified_key(&builder.cert, &builder.key)?,
+            certs,
+        })
+    }
+
+    fn get(&self, server_name: &str) -> Option<Arc<CertifiedKey>> {
+        let server_name = server_name.to_ascii_lowercase();
+        if let Some(cert) = self.certs.get(&server_name) {
+            return Some(cert.clone());
+        }
+        // the wildcard matches a single label
+        let (_, parent) = server_name.split_once('.')?;
+        self.certs.get(&format!("*.{parent}")).cloned()
+    }
+}
+
+impl ResolvesServerCert for ServerNameResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        client_hello
+            .server_name()
+            .and_then(|server_name| self.get(server_name))
+            .or_else(|| Some(self.default.clone()))
+    }
+}
+
+fn client_cert_verifier(
+    builder: &TlsAcceptorBuilder,
+    provider: Arc<rustls::crypto::CryptoProvider>,
+) -> Result<Option<Arc<dyn ClientCertVerifier>>> {
+    if builder.client_auth == ClientAuth::None {
+        return Ok(None);
+    }
+    let mut roots = RootCertStore::empty();
+    for pem in builder.client_ca_pems.iter() {
+        for cert in rustls_pemfile::certs(&mut pem.as_ref()) {
+            roots
+                .add(cert?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        }
+    }
+    let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+    let verifier = match builder.client_auth {
+        ClientAuth::Optional => verifier.allow_unauthenticated(),
+        _ => verifier,
+    };
+    verifier
+        .build()
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
 impl From<ClientConfig> for super::TlsConnector {
     fn from(client_config: ClientConfig) -> Self {
-        Self::Rustls(RustlsConnector(TlsConnector::from(Arc::new(client_config))))
+        Self::Rustls(RustlsConnector::new(TlsConnector::from(Arc::new(
+            client_config,
+        ))))
     }
 }
 
 impl From<Arc<ClientConfig>> for super::TlsConnector {
     fn from(client_config: Arc<ClientConfig>) -> Self {
-        Self::Rustls(RustlsConnector(TlsConnector::from(client_config)))
+        Self::Rustls(RustlsConnector::new(TlsConnector::from(client_config)))
     }
 }
 
 impl From<TlsConnector> for super::TlsConnector {
     fn from(connector: TlsConnector) -> Self {
-        Self::Rustls(RustlsConnector(connector))
+        Self::Rustls(RustlsConnector::new(connector))
     }
 }
 
 impl From<ServerConfig> for super::TlsAcceptor {
     fn from(server_config: ServerConfig) -> Self {
-        Self::Rustls(RustlsAcceptor(TlsAcceptor::from(Arc::new(server_config))))
+        Self::Rustls(RustlsAcceptor::new(TlsAcceptor::from(Arc::new(
+            server_config,
+        ))))
     }
 }
 
 impl From<Arc<ServerConfig>> for super::TlsAcceptor {
     fn from(server_config: Arc<ServerConfig>) -> Self {
-        Self::Rustls(RustlsAcceptor(TlsAcceptor::from(server_config)))
+        Self::Rustls(RustlsAcceptor::new(TlsAcceptor::from(server_config)))
     }
 }
 
 impl From<TlsAcceptor> for super::TlsAcceptor {
     fn from(acceptor: TlsAcceptor) -> Self {
-        Self::Rustls(RustlsAcceptor(acceptor))
+        Self::Rustls(RustlsAcceptor::new(acceptor))
     }
 }
@@ -0,0 +1,573 @@
+//! A rate limit layer for servers.
+//!
+//! The requests are grouped by the key extracted by [`RateLimitKey`], e.g., the caller service
+//! name with [`CallerKey`], the method with [`MethodKey`], or any function returning the key from
+//! the context. The requests without a key are not limited.
+//!
+//! Each key is limited by the [`RateLimiter`], and the local [`TokenBucketLimiter`] and
+//! [`SlidingWindowLimiter`] are provided. An external quota backend, e.g., a shared counter in
+//! redis, can be plugged in by implementing [`RateLimiter`], which is asynchronous.
+//!
+//! The local limiters keep the state of at most [`DEFAULT_MAX_KEYS`] keys by default, and the
+//! other keys share a single state, since the keys may come from the requests, e.g., the caller
+//! service name which is not authenticated.
+//!
+//! The over-limit requests fail with [`RateLimitError`], which can be converted into the protocol
+//! errors: an `ApplicationException` of `INTERNAL_ERROR` for thrift, `RESOURCE_EXHAUSTED` for
+//! gRPC, and `429 Too Many Requests` with the `Retry-After` header for HTTP.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! // 100 requests per second for each caller, with a burst of 200
+//! let server = Server::new(service)
+//!     .layer_front(RateLimitLayer::new(CallerKey, TokenBucketLimiter::new(100.0, 200)));
+//! ```
+
+use std::{
+    fmt,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use faststr::FastStr;
+use motore::Service;
+
+use crate::context::Context;
+
+/// The interval of removing the idle keys from the local limiters.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+/// The default max number of the keys kept by the local limiters.
+pub const DEFAULT_MAX_KEYS: usize = 10_000;
+
+/// The error returned when the rate limit of the key is exceeded.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("rate limit of {key} exceeded")]
+pub struct RateLimitError {
+    key: FastStr,
+    retry_after: Option<Duration>,
+}
+
+impl RateLimitError {
+    pub fn new(key: FastStr, retry_after: Option<Duration>) -> Self {
+        Self { key, retry_after }
+    }
+
+    /// Returns the key whose rate limit is exceeded.
+    pub fn key(&self) -> &FastStr {
+        &self.key
+    }
+
+    /// Returns the suggested time to wait before retrying, if known.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+}
+
+/// Extracts the key of rate limit from the context, the requests without a key are not limited.
+pub trait RateLimitKey<Cx>: Send + Sync + 'static {
+    fn key(&self, cx: &Cx) -> Option<FastStr>;
+}
+
+impl<Cx, F> RateLimitKey<Cx> for F
+where
+    F: Fn(&Cx) -> Option<FastStr> + Send + Sync + 'static,
+{
+    fn key(&self, cx: &Cx) -> Option<FastStr> {
+        self(cx)
+    }
+}
+
+/// Uses the service name of the caller as the key, the requests without the caller service name
+/// are not limited.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CallerKey;
+
+impl<Cx: Context> RateLimitKey<Cx> for CallerKey {
+    fn key(&self, cx: &Cx) -> Option<FastStr> {
+        let caller = cx.rpc_info().caller().service_name();
+        (!caller.is_empty()).then_some(caller)
+    }
+}
+
+/// Uses the method as the key.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MethodKey;
+
+impl<Cx: Context> RateLimitKey<Cx> for MethodKey {
+    fn key(&self, cx: &Cx) -> Option<FastStr> {
+        Some(cx.rpc_info().method().clone())
+    }
+}
+
+/// Uses the service name of the caller and the method as the key, i.e., `{caller}/{method}`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CallerMethodKey;
+
+impl<Cx: Context> RateLimitKey<Cx> for CallerMethodKey {
+    fn key(&self, cx: &Cx) -> Option<FastStr> {
+        let rpc_info = cx.rpc_info();
+        Some(FastStr::new(format!(
+            "{}/{}",
+            rpc_info.caller().service_name_ref(),
+            rpc_info.method()
+        )))
+    }
+}
+
+/// [`RateLimiter`] decides whether a request of the key is allowed.
+///
+/// It is asynchronous, so it can be implemented by an external quota backend shared by the
+/// instances of the server.
+pub trait RateLimiter: Send + Sync + 'static {
+    /// Acquires a permit of the key, or returns [`RateLimitError`] if the limit is exceeded.
+    fn acquire(&self, key: &FastStr) -> impl Future<Output = Result<(), RateLimitError>> + Send;
+}
+
+impl<L: RateLimiter> RateLimiter for Arc<L> {
+    fn acquire(&self, key: &FastStr) -> impl Future<Output = Result<(), RateLimitError>> + Send {
+        (**self).acquire(key)
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+/// A local [`RateLimiter`] that allows `rate` requests per second for each key, with bursts of
+/// up to `burst` requests.
+pub struct TokenBucketLimiter {
+    rate: f64,
+    burst: f64,
+    max_keys: usize,
+    buckets: DashMap<FastStr, Bucket>,
+    /// The bucket shared by the keys exceeding `max_keys`.
+    overflow: Mutex<Bucket>,
+    last_cleanup: Mutex<Instant>,
+}
+
+impl TokenBucketLimiter {
+    pub fn new(rate: f64, burst: u32) -> Self {
+        let burst = burst.max(1) as f64;
+        let now = Instant::now();
+        Self {
+            rate,
+            burst,
+            max_keys: DEFAULT_MAX_KEYS,
+            buckets: DashMap::new(),
+            overflow: Mutex::new(Bucket {
+                tokens: burst,
+                last: now,
+            }),
+            last_cleanup: Mutex::new(now),
+        }
+    }
+
+    /// Sets the max number of the keys which have their own buckets, the default is
+    /// [`DEFAULT_MAX_KEYS`].
+    ///
+    /// The new keys share a single bucket when there are too many keys, until the idle ones are
+    /// removed.
+    pub fn with_max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = max_keys;
+        self
+    }
+
+    fn try_acquire(&self, key: &FastStr) -> Result<(), RateLimitError> {
+        let now = Instant::now();
+        self.cleanup(now);
+        let result = match self.buckets.get_mut(key) {
+            Some(mut bucket) => self.take(&mut bucket, now),
+            None if self.buckets.len() < self.max_keys => {
+                let mut bucket = self.buckets.entry(key.clone()).or_insert_with(|| Bucket {
+                    tokens: self.burst,
+                    last: now,
+                });
+                self.take(&mut bucket, now)
+            }
+            None => self.take(&mut self.overflow.lock().unwrap(), now),
+        };
+        result.map_err(|retry_after| RateLimitError::new(key.clone(), retry_after))
+    }
+
+    /// Takes a token from the bucket, or returns the time until there is a token.
+    fn take(&self, bucket: &mut Bucket, now: Instant) -> Result<(), Option<Duration>> {
+        bucket.tokens = (bucket.tokens
+            + now.saturating_duration_since(bucket.last).as_secs_f64() * self.rate)
+            .min(self.burst);
+        bucket.last = bucket.last.max(now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        Err((self.rate > 0.0).then(|| Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate)))
+    }
+
+    /// Removes the full buckets, which are the same as the new ones.
+    fn cleanup(&self, now: Instant) {
+        {
+            let mut last_cleanup = self.last_cleanup.lock().unwrap();
+            if now.duration_since(*last_cleanup) < CLEANUP_INTERVAL {
+                return;
+            }
+            *last_cleanup = now;
+        }
+        self.buckets.retain(|_, bucket| {
+            bucket.tokens + now.duration_since(bucket.last).as_secs_f64() * self.rate < self.burst
+        });
+    }
+}
+
+impl fmt::Debug for TokenBucketLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenBucketLimiter")
+            .field("rate", &self.rate)
+            .field("burst", &self.burst)
+            .field("max_keys", &self.max_keys)
+            .finish()
+    }
+}
+
+impl RateLimiter for TokenBucketLimiter {
+    async fn acquire(&self, key: &FastStr) -> Result<(), RateLimitError> {
+        self.try_acquire(key)
+    }
+}
+
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    previous: u64,
+    current: u64,
+}
+
+/// A local [`RateLimiter`] that allows `limit` requests in any `window` for each key.
+///
+/// The count of the sliding window is estimated by the counts of the current fixed window and
+/// the previous one, weighted by the overlap with the sliding window.
+pub struct SlidingWindowLimiter {
+    limit: u64,
+    window: Duration,
+    max_keys: usize,
+    windows: DashMap<FastStr, Window>,
+    /// The window shared by the keys exceeding `max_keys`.
+    overflow: Mutex<Window>,
+    last_cleanup: Mutex<Instant>,
+}
+
+impl SlidingWindowLimiter {
+    pub fn new(limit: u64, window: Duration) -> Self {
+        assert!(!window.is_zero(), "the window should not be zero");
+        let now = Instant::now();
+        Self {
+            limit,
+            window,
+            max_keys: DEFAULT_MAX_KEYS,
+            windows: DashMap::new(),
+            overflow: Mutex::new(Window {
+                start: now,
+                previous: 0,
+                current: 0,
+            }),
+            last_cleanup: Mutex::new(now),
+        }
+    }
+
+    /// Sets the max number of the keys which have their own windows, the default is
+    /// [`DEFAULT_MAX_KEYS`].
+    ///
+    /// The new keys share a single window when there are too many keys, until the idle ones are
+    /// removed.
+    pub fn with_max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = max_keys;
+        self
+    }
+
+    fn try_acquire(&self, key: &FastStr) -> Result<(), RateLimitError> {
+        let now = Instant::now();
+        self.cleanup(now);
+        let result = match self.windows.get_mut(key) {
+            Some(mut window) => self.count(&mut window, now),
+            None if self.windows.len() < self.max_keys => {
+                let mut window = self.windows.entry(key.clone()).or_insert_with(|| Window {
+                    start: now,
+                    previous: 0,
+                    current: 0,
+                });
+                self.count(&mut window, now)
+            }
+            None => self.count(&mut self.overflow.lock().unwrap(), now),
+        };
+        result.map_err(|retry_after| RateLimitError::new(key.clone(), retry_after))
+    }
+
+    /// Counts the request in the window, or returns the time until it is allowed.
+    fn count(&self, window: &mut Window, now: Instant) -> Result<(), Option<Duration>> {
+        let now = now.max(window.start);
+        let since_start = now.duration_since(window.start).as_nanos();
+        let passed = since_start / self.window.as_nanos();
+        if passed > 0 {
+            window.previous = if passed == 1 { window.current } else { 0 };
+            window.current = 0;
+            window.start =
+                now - Duration::from_nanos((since_start % self.window.as_nanos()) as u64);
+        }
+
+        let window_secs = self.window.as_secs_f64();
+        let elapsed = now.duration_since(window.start).as_secs_f64() / window_secs;
+        let count = window.previous as f64 * (1.0 - elapsed) + window.current as f64;
+        if count + 1.0 <= self.limit as f64 {
+            window.current += 1;
+            return Ok(());
+        }
+
+        if self.limit == 0 {
+            return Err(None);
+        }
+        // the time until the estimated count drops below the limit
+        let allowed = (self.limit - 1) as f64;
+        let retry_after = if window.current as f64 > allowed {
+            (1.0 - elapsed) + (1.0 - allowed / window.current as f64)
+        } else {
+            (1.0 - (allowed - window.current as f64) / window.previous as f64) - elapsed
+        };
+        Err(Some(Duration::from_secs_f64(
+            retry_after.max(0.0) * window_secs,
+        )))
+    }
+
+    /// Removes the windows which have no request in the sliding window.
+    fn cleanup(&self, now: Instant) {
+        {
+            let mut last_cleanup = self.last_cleanup.lock().unwrap();
+            if now.duration_since(*last_cleanup) < CLEANUP_INTERVAL {
+                return;
+            }
+            *last_cleanup = now;
+        }
+        self.windows
+            .retain(|_, window| now.duration_since(window.start) < self.window * 2);
+    }
+}
+
+impl fmt::Debug for SlidingWindowLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SlidingWindowLimiter")
+            .field("limit", &self.limit)
+            .field("window", &self.window)
+            .field("max_keys", &self.max_keys)
+            .finish()
+    }
+}
+
+impl RateLimiter for SlidingWindowLimiter {
+    async fn acquire(&self, key: &FastStr) -> Result<(), RateLimitError> {
+        self.try_acquire(key)
+    }
+}
+
+/// A [`Layer`](motore::layer::Layer) that limits the requests by the [`RateLimiter`].
+pub struct RateLimitLayer<K, L> {
+    key: Arc<K>,
+    limiter: Arc<L>,
+}
+
+impl<K, L> RateLimitLayer<K, L> {
+    pub fn new(key: K, limiter: L) -> Self {
+        Self {
+            key: Arc::new(key),
+            limiter: Arc::new(limiter),
+        }
+    }
+}
+
+impl<K, L> Clone for RateLimitLayer<K, L> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+impl<S, K, L> motore::layer::Layer<S> for RateLimitLayer<K, L> {
+    type Service = RateLimitService<S, K, L>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            key: self.key,
+            limiter: self.limiter,
+        }
+    }
+}
+
+/// The [`Service`] generated by [`RateLimitLayer`].
+pub struct RateLimitService<S, K, L> {
+    inner: S,
+    key: Arc<K>,
+    limiter: Arc<L>,
+}
+
+impl<S: Clone, K, L> Clone for RateLimitService<S, K, L> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            key: self.key.clone(),
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+impl<Cx, Req, S, K, L> Service<Cx, Req> for RateLimitService<S, K, L>
+where
+    Cx: Context + Send + 'static,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + Sync + 'static,
+    K: RateLimitKey<Cx>,
+    L: RateLimiter,
+    RateLimitError: Into<S::Error>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+
+    async fn call(&self, cx: &mut Cx, req: Req) -> Result<Self::Response, Self::Error> {
+        if let Some(key) = self.key.key(cx) {
+            self.limiter.acquire(&key).await.map_err(Into::into)?;
+        }
+        self.inner.call(cx, req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use faststr::FastStr;
+    use motore::{Service, layer::Layer, service::service_fn};
+
+    use super::{
+        CallerKey, RateLimitError, RateLimitLayer, SlidingWindowLimiter, TokenBucketLimiter,
+    };
+    use crate::context::{Endpoint, Reusable, Role, RpcCx, RpcInfo};
+
+    #[derive(Debug, Default)]
+    struct TestConfig;
+
+    impl Reusable for TestConfig {
+        fn clear(&mut self) {}
+    }
+
+    type TestContext = RpcCx<(), TestConfig>;
+
+    fn new_cx(caller: &'static str) -> TestContext {
+        RpcCx::new(
+            RpcInfo::new(
+                Role::Server,
+                "method".into(),
+                Endpoint::new(caller.into()),
+                Endpoint::new("callee".into()),
+                TestConfig,
+            ),
+            (),
+        )
+    }
+
+    async fn handle(_: &mut TestContext, _: ()) -> Result<(), RateLimitError> {
+        Ok(())
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = TokenBucketLimiter::new(10.0, 2);
+        let key = FastStr::from_static_str("key");
+        limiter.try_acquire(&key).unwrap();
+        limiter.try_acquire(&key).unwrap();
+        let err = limiter.try_acquire(&key).unwrap_err();
+        let retry_after = err.retry_after().unwrap();
+        assert!(
+            retry_after > Duration::from_millis(90) && retry_after <= Duration::from_millis(100)
+        );
+        // the keys are limited separately
+        limiter
+            .try_acquire(&FastStr::from_static_str("other"))
+            .unwrap();
+
+        std::thread::sleep(Duration::from_millis(110));
+        limiter.try_acquire(&key).unwrap();
+        assert!(limiter.try_acquire(&key).is_err());
+    }
+
+    #[test]
+    fn test_max_keys() {
+        let limiter = TokenBucketLimiter::new(1.0, 1).with_max_keys(1);
+        limiter.try_acquire(&FastStr::from_static_str("a")).unwrap();
+        // the keys beyond the max share a bucket
+        limiter.try_acquire(&FastStr::from_static_str("b")).unwrap();
+        let err = limiter
+            .try_acquire(&FastStr::from_static_str("c"))
+            .unwrap_err();
+        assert_eq!(err.key(), "c");
+        assert_eq!(limiter.buckets.len(), 1);
+
+        let limiter = SlidingWindowLimiter::new(1, Duration::from_secs(1)).with_max_keys(1);
+        limiter.try_acquire(&FastStr::from_static_str("a")).unwrap();
+        limiter.try_acquire(&FastStr::from_static_str("b")).unwrap();
+        assert!(limiter.try_acquire(&FastStr::from_static_str("c")).is_err());
+        assert_eq!(limiter.windows.len(), 1);
+    }
+
+    #[test]
+    fn test_sliding_window() {
+        let limiter = SlidingWindowLimiter::new(3, Duration::from_millis(100));
+        let key = FastStr::from_static_str("key");
+        for _ in 0..3 {
+            limiter.try_acquire(&key).unwrap();
+        }
+        let err = limiter.try_acquire(&key).unwrap_err();
+        assert!(err.retry_after().unwrap() > Duration::ZERO);
+
+        // the requests of the previous window still count partially
+        std::thread::sleep(Duration::from_millis(110));
+        assert!(limiter.try_acquire(&key).is_err());
+
+        std::thread::sleep(Duration::from_millis(200));
+        for _ in 0..3 {
+            limiter.try_acquire(&key).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_layer() {
+        let service = RateLimitLayer::new(CallerKey, TokenBucketLimiter::new(1.0, 1))
+            .layer(service_fn(handle));
+
+        service.call(&mut new_cx("a"), ()).await.unwrap();
+        let err = service.call(&mut new_cx("a"), ()).await.unwrap_err();
+        assert_eq!(err.key(), "a");
+        service.call(&mut new_cx("b"), ()).await.unwrap();
+        // the requests without the caller are not limited
+        for _ in 0..3 {
+            service.call(&mut new_cx(""), ()).await.unwrap();
+        }
+
+        // a custom key, and the requests without a key are not limited
+        let service = RateLimitLayer::new(
+            |cx: &TestContext| {
+                let caller = cx.rpc_info.caller().service_name();
+                (caller != "internal").then_some(caller)
+            },
+            TokenBucketLimiter::new(1.0, 1),
+        )
+        .layer(service_fn(handle));
+        for _ in 0..3 {
+            service.call(&mut new_cx("internal"), ()).await.unwrap();
+        }
+        service.call(&mut new_cx("a"), ()).await.unwrap();
+        assert!(service.call(&mut new_cx("a"), ()).await.is_err());
+    }
+}
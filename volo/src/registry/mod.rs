@@ -0,0 +1,425 @@
+//! This module contains the abstraction for service registration of volo.
+//!
+//! [`Registry`] is the server side counterpart of [`Discover`]: a server registers its
+//! [`Instance`] to a registry once the listener is bound, and deregisters it when the graceful
+//! shutdown starts, so that the clients stop sending new requests before the server drains its
+//! connections.
+//!
+//! The servers of volo-thrift, volo-grpc and volo-http accept a [`Registration`], which describes
+//! how the server should be registered, and do the registration and deregistration at the right
+//! time.
+
+use std::{borrow::Cow, collections::HashMap, future::Future, sync::Arc, time::Duration};
+
+use async_broadcast::{InactiveReceiver, Receiver, Sender};
+use dashmap::DashMap;
+use faststr::FastStr;
+use futures::future::BoxFuture;
+use motore::BoxError;
+
+use crate::{
+    context::Endpoint,
+    discovery::{Change, Discover, Instance, diff_instances},
+    net::Address,
+};
+
+const DEFAULT_DEREGISTER_TIMEOUT: Duration = Duration::from_secs(5);
+
+const ERR_NO_ADDRESS: &str = "cannot get the local address of the listener, please set the \
+                              address by `Registration::with_address`";
+
+/// [`Registry`] is the most basic trait for service registration.
+pub trait Registry: Send + Sync + 'static {
+    /// `Error` is the registration error.
+    type Error: Into<BoxError>;
+
+    /// `register` announces the `instance` of the service `service_name`.
+    fn register<'s>(
+        &'s self,
+        service_name: &'s FastStr,
+        instance: &'s Instance,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// `deregister` removes the `instance` of the service `service_name`.
+    fn deregister<'s>(
+        &'s self,
+        service_name: &'s FastStr,
+        instance: &'s Instance,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+}
+
+/// An object safe version of [`Registry`] used by [`Registration`].
+trait DynRegistry: Send + Sync + 'static {
+    fn register<'s>(
+        &'s self,
+        service_name: &'s FastStr,
+        instance: &'s Instance,
+    ) -> BoxFuture<'s, Result<(), BoxError>>;
+
+    fn deregister<'s>(
+        &'s self,
+        service_name: &'s FastStr,
+        instance: &'s Instance,
+    ) -> BoxFuture<'s, Result<(), BoxError>>;
+}
+
+impl<R> DynRegistry for R
+where
+    R: Registry,
+{
+    fn register<'s>(
+        &'s self,
+        service_name: &'s FastStr,
+        instance: &'s Instance,
+    ) -> BoxFuture<'s, Result<(), BoxError>> {
+        Box::pin(async move {
+            Registry::register(self, service_name, instance)
+                .await
+                .map_err(Into::into)
+        })
+    }
+
+    fn deregister<'s>(
+        &'s self,
+        service_name: &'s FastStr,
+        instance: &'s Instance,
+    ) -> BoxFuture<'s, Result<(), BoxError>> {
+        Box::pin(async move {
+            Registry::deregister(self, service_name, instance)
+                .await
+                .map_err(Into::into)
+        })
+    }
+}
+
+/// [`Registration`] describes how a server should be registered to a [`Registry`].
+///
+/// The address of the registered [`Instance`] is the local address of the listener by default.
+/// If the server listens on an unspecified address such as `[::]:8080`, the address which the
+/// clients can reach should be set by [`Registration::with_address`].
+#[derive(Clone)]
+pub struct Registration {
+    registry: Arc<dyn DynRegistry>,
+    service_name: FastStr,
+    address: Option<Address>,
+    weight: u32,
+    tags: HashMap<Cow<'static, str>, Cow<'static, str>>,
+    deregister_timeout: Duration,
+}
+
+impl Registration {
+    /// Creates a [`Registration`] which registers the service `service_name` to `registry`.
+    pub fn new(registry: impl Registry, service_name: impl Into<FastStr>) -> Self {
+        Self {
+            registry: Arc::new(registry),
+            service_name: service_name.into(),
+            address: None,
+            weight: 1,
+            tags: HashMap::new(),
+            deregister_timeout: DEFAULT_DEREGISTER_TIMEOUT,
+        }
+    }
+
+    /// Sets the address to register instead of the local address of the listener.
+    pub fn with_address(mut self, address: impl Into<Address>) -> Self {
+        self.address = Some(address.into());
+        self
+    }
+
+    /// Sets the weight of the instance, the default is 1.
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// Sets the timeout of deregistration, the default is 5 seconds.
+    ///
+    /// The graceful shutdown of the server continues after the timeout, so that an unavailable
+    /// registry does not block the server from exiting.
+    pub fn with_deregister_timeout(mut self, timeout: Duration) -> Self {
+        self.deregister_timeout = timeout;
+        self
+    }
+
+    /// Adds a tag to the instance.
+    pub fn with_tag(
+        mut self,
+        key: impl Into<Cow<'static, str>>,
+        value: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.tags.insert(key.into(), value.into());
+        self
+    }
+
+    /// Registers the instance with the local address of the listener.
+    ///
+    /// This is called by the servers once the listener is bound, and the returned [`Registered`]
+    /// should be deregistered at the start of graceful shutdown.
+    pub async fn register(&self, local_addr: Option<Address>) -> Result<Registered, BoxError> {
+        let address = match (&self.address, local_addr) {
+            (Some(address), _) => address.clone(),
+            (None, Some(Address::Ip(addr))) if addr.ip().is_unspecified() => {
+                return Err(format!(
+                    "cannot register the unspecified address {addr}, please set the address \
+                     by `Registration::with_address`"
+                )
+                .into());
+            }
+            (None, Some(address)) => address,
+            (None, None) => {
+                return Err(ERR_NO_ADDRESS.into());
+            }
+        };
+        let instance = Instance {
+            address,
+            weight: self.weight,
+            tags: self.tags.clone(),
+        };
+        self.registry
+            .register(&self.service_name, &instance)
+            .await?;
+        tracing::info!(
+            "[VOLO] registered service {} at {}",
+            self.service_name,
+            instance.address
+        );
+        Ok(Registered {
+            registry: self.registry.clone(),
+            service_name: self.service_name.clone(),
+            instance,
+            timeout: self.deregister_timeout,
+        })
+    }
+}
+
+impl std::fmt::Debug for Registration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Registration")
+            .field("service_name", &self.service_name)
+            .field("address", &self.address)
+            .field("weight", &self.weight)
+            .field("tags", &self.tags)
+            .field("deregister_timeout", &self.deregister_timeout)
+            .finish()
+    }
+}
+
+/// [`Registered`] is an instance registered by [`Registration::register`].
+pub struct Registered {
+    registry: Arc<dyn DynRegistry>,
+    service_name: FastStr,
+    instance: Instance,
+    timeout: Duration,
+}
+
+impl Registered {
+    /// Returns the registered instance.
+    pub fn instance(&self) -> &Instance {
+        &self.instance
+    }
+
+    /// Deregisters the instance, the error is logged since the server is shutting down anyway.
+    ///
+    /// It gives up after the timeout set by [`Registration::with_deregister_timeout`].
+    pub async fn deregister(self) {
+        let res = tokio::time::timeout(
+            self.timeout,
+            self.registry.deregister(&self.service_name, &self.instance),
+        )
+        .await;
+        match res {
+            Ok(Ok(())) => tracing::info!(
+                "[VOLO] deregistered service {} at {}",
+                self.service_name,
+                self.instance.address
+            ),
+            Ok(Err(err)) => tracing::warn!(
+                "[VOLO] failed to deregister service {} at {}: {err}",
+                self.service_name,
+                self.instance.address
+            ),
+            Err(_) => tracing::warn!(
+                "[VOLO] deregistering service {} at {} timed out after {:?}",
+                self.service_name,
+                self.instance.address,
+                self.timeout
+            ),
+        }
+    }
+}
+
+/// [`InMemoryRegistry`] keeps the registered instances in memory, and is also a [`Discover`] of
+/// them, which is useful for tests.
+///
+/// The key of the [`Discover`] is the service name of the callee.
+#[derive(Clone)]
+pub struct InMemoryRegistry {
+    services: Arc<DashMap<FastStr, Vec<Arc<Instance>>>>,
+    sender: Sender<Change<FastStr>>,
+    receiver: InactiveReceiver<Change<FastStr>>,
+}
+
+impl InMemoryRegistry {
+    /// Creates an empty [`InMemoryRegistry`].
+    pub fn new() -> Self {
+        let (mut sender, receiver) = async_broadcast::broadcast(64);
+        sender.set_overflow(true);
+        Self {
+            services: Default::default(),
+            sender,
+            receiver: receiver.deactivate(),
+        }
+    }
+
+    fn update(&self, service_name: &FastStr, f: impl FnOnce(&mut Vec<Arc<Instance>>)) {
+        let mut instances = self.services.entry(service_name.clone()).or_default();
+        let prev = instances.clone();
+        f(&mut instances);
+        let next = instances.clone();
+        drop(instances);
+        let (change, changed) = diff_instances(service_name.clone(), prev, next);
+        if changed {
+            let _ = self.sender.try_broadcast(change);
+        }
+    }
+}
+
+impl Default for InMemoryRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Registry for InMemoryRegistry {
+    type Error = std::convert::Infallible;
+
+    async fn register<'s>(
+        &'s self,
+        service_name: &'s FastStr,
+        instance: &'s Instance,
+    ) -> Result<(), Self::Error> {
+        self.update(service_name, |instances| {
+            instances.retain(|i| i.address != instance.address);
+            instances.push(Arc::new(instance.clone()));
+        });
+        Ok(())
+    }
+
+    async fn deregister<'s>(
+        &'s self,
+        service_name: &'s FastStr,
+        instance: &'s Instance,
+    ) -> Result<(), Self::Error> {
+        self.update(service_name, |instances| {
+            instances.retain(|i| i.address != instance.address);
+        });
+        Ok(())
+    }
+}
+
+impl Discover for InMemoryRegistry {
+    type Key = FastStr;
+    type Error = std::convert::Infallible;
+
+    async fn discover<'s>(
+        &'s self,
+        endpoint: &'s Endpoint,
+    ) -> Result<Vec<Arc<Instance>>, Self::Error> {
+        Ok(self
+            .services
+            .get(endpoint.service_name_ref())
+            .map(|instances| instances.clone())
+            .unwrap_or_default())
+    }
+
+    fn key(&self, endpoint: &Endpoint) -> Self::Key {
+        endpoint.service_name()
+    }
+
+    fn watch(&self, _keys: Option<&[Self::Key]>) -> Option<Receiver<Change<Self::Key>>> {
+        Some(self.receiver.activate_cloned())
+    }
+}
+
+impl std::fmt::Debug for InMemoryRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InMemoryRegistry")
+            .field("services", &self.services)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        time::{Duration, Instant},
+    };
+
+    use faststr::FastStr;
+
+    use super::{InMemoryRegistry, Registration, Registry};
+    use crate::{
+        context::Endpoint,
+        discovery::{Discover, Instance},
+        net::Address,
+    };
+
+    #[tokio::test]
+    async fn test_in_memory_registry() {
+        let registry = InMemoryRegistry::new();
+        let mut receiver = registry.watch(None).unwrap();
+        let endpoint = Endpoint::new("echo".into());
+        let addr: SocketAddr = "127.0.0.1:8000".parse().unwrap();
+
+        let registration = Registration::new(registry.clone(), "echo")
+            .with_weight(10)
+            .with_tag("zone", "a");
+        assert!(
+            registration
+                .register(Some("[::]:8000".parse::<SocketAddr>().unwrap().into()))
+                .await
+                .is_err()
+        );
+        assert!(registration.register(None).await.is_err());
+
+        let registered = registration.register(Some(addr.into())).await.unwrap();
+        let instances = registry.discover(&endpoint).await.unwrap();
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].address, Address::Ip(addr));
+        assert_eq!(instances[0].weight, 10);
+        assert_eq!(instances[0].tags.get("zone").unwrap(), "a");
+        assert_eq!(receiver.recv().await.unwrap().added.len(), 1);
+
+        registered.deregister().await;
+        assert!(registry.discover(&endpoint).await.unwrap().is_empty());
+        assert_eq!(receiver.recv().await.unwrap().removed.len(), 1);
+    }
+
+    struct PendingRegistry;
+
+    impl Registry for PendingRegistry {
+        type Error = std::convert::Infallible;
+
+        async fn register(&self, _: &FastStr, _: &Instance) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn deregister(&self, _: &FastStr, _: &Instance) -> Result<(), Self::Error> {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deregister_timeout() {
+        let addr: SocketAddr = "127.0.0.1:8000".parse().unwrap();
+        let registered = Registration::new(PendingRegistry, "echo")
+            .with_deregister_timeout(Duration::from_millis(50))
+            .register(Some(addr.into()))
+            .await
+            .unwrap();
+        let start = Instant::now();
+        registered.deregister().await;
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,539 @@
+//! Retry policies for clients.
+//!
+//! A [`RetryPolicy`] decides how many times a request may be sent and how long to wait between
+//! the attempts, and a [`RetryBudget`] caps the extra load caused by the retries of a client.
+//!
+//! The policy can be overridden for a single call by inserting a [`RetryPolicy`] into the callee
+//! tags, e.g. by `CallOpt`, and the retries stop before the rpc timeout expires if the config of
+//! the context implements [`RpcTimeout`].
+//!
+//! The load balance layer of `volo-thrift` retries on the next picked instance with the policy
+//! set by `LbConfig::retry_policy`. The load balance layer of `volo-grpc` does not retry, but it
+//! sends hedged backup attempts by the `HedgePolicy` in the callee tags once the requests can be
+//! copied by a [`Replay`] set with `LoadBalanceLayer::with_replay`. For the other clients,
+//! [`RetryLayer`] can be added as an outer layer, and each attempt goes through the load
+//! balancer again.
+//!
+//! The retries may be stacked, e.g. a [`RetryLayer`] outside the load balance layer of
+//! `volo-thrift`, and a request is only recorded once by a budget shared between them.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let policy = RetryPolicy::new(3).with_backoff(
+//!     Backoff::exponential(Duration::from_millis(10), Duration::from_millis(100)).with_jitter(0.5),
+//! );
+//!
+//! let client = ClientBuilder::new("service")
+//!     .layer_outer(RetryLayer::new(policy).with_budget(RetryBudget::new(0.1)))
+//!     .build();
+//! ```
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use motore::{Service, layer::Layer};
+use rand::Rng;
+
+use crate::{
+    context::{Context, Endpoint},
+    loadbalance::error::Retryable,
+};
+
+/// Configs which carry the timeout of the whole rpc.
+///
+/// It is required by [`RetryLayer`] and the [`Hedging`](crate::loadbalance::hedge::Hedging) mode
+/// of the load balance layer, whose retries are not started if the backoff would exceed the
+/// timeout, since the attempt would be cancelled anyway. The configs of volo-thrift, volo-grpc and
+/// volo-http implement it, and a custom config without a timeout can return `None`.
+pub trait RpcTimeout {
+    fn rpc_timeout(&self) -> Option<Duration>;
+}
+
+/// The backoff between the attempts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    jitter: f64,
+}
+
+impl Backoff {
+    /// Retries immediately.
+    pub const fn none() -> Self {
+        Self {
+            base: Duration::ZERO,
+            max: Duration::ZERO,
+            jitter: 0.0,
+        }
+    }
+
+    /// Waits `base * 2^(n-1)` before the n-th retry, but no longer than `max`.
+    pub const fn exponential(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            jitter: 0.0,
+        }
+    }
+
+    /// Randomly shortens the backoff by up to `jitter` (from 0 to 1) of it, to spread the
+    /// retries of the clients.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Returns the backoff before the n-th retry, which starts from 1.
+    pub fn delay(&self, retry: u32) -> Duration {
+        if self.base.is_zero() {
+            return Duration::ZERO;
+        }
+        let factor = 1u32
+            .checked_shl(retry.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        let delay = self.base.saturating_mul(factor).min(self.max);
+        if self.jitter > 0.0 {
+            delay.mul_f64(1.0 - self.jitter * rand::rng().random::<f64>())
+        } else {
+            delay
+        }
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+/// [`RetryPolicy`] decides how many times a request may be sent and the backoff between them.
+///
+/// Whether an error can be retried is decided by [`Retryable`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    backoff: Backoff,
+}
+
+impl RetryPolicy {
+    /// Creates a policy which sends a request at most `max_attempts` times, including the first
+    /// attempt.
+    pub fn new(max_attempts: usize) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            backoff: Backoff::none(),
+        }
+    }
+
+    /// Creates a policy which never retries.
+    pub fn no_retry() -> Self {
+        Self::new(1)
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn max_attempts(&self) -> usize {
+        self.max_attempts
+    }
+
+    pub fn backoff(&self) -> &Backoff {
+        &self.backoff
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::no_retry()
+    }
+}
+
+const BUDGET_WINDOW: Duration = Duration::from_secs(10);
+const BUDGET_BUCKETS: u64 = 10;
+const DEFAULT_MIN_RETRIES: u32 = 10;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct BudgetBucket {
+    epoch: u64,
+    requests: u32,
+    retries: u32,
+}
+
+#[derive(Debug)]
+struct BudgetWindow {
+    start: Instant,
+    buckets: Mutex<[BudgetBucket; BUDGET_BUCKETS as usize]>,
+}
+
+/// The budgets a request has been recorded by, kept in the callee tags.
+#[derive(Clone, Default)]
+struct Deposited(Vec<Arc<BudgetWindow>>);
+
+/// [`RetryBudget`] limits the retries of a client to a ratio of its requests in the last 10
+/// seconds, plus a few retries which are always allowed.
+///
+/// It is cheap to clone, and the clones share the same budget.
+#[derive(Clone, Debug)]
+pub struct RetryBudget {
+    ratio: f64,
+    min_retries: u32,
+    window: Arc<BudgetWindow>,
+}
+
+impl RetryBudget {
+    /// Creates a budget which allows `ratio` retries per request, e.g. `0.1` allows one retry
+    /// for every ten requests.
+    pub fn new(ratio: f64) -> Self {
+        Self {
+            ratio: ratio.max(0.0),
+            min_retries: DEFAULT_MIN_RETRIES,
+            window: Arc::new(BudgetWindow {
+                start: Instant::now(),
+                buckets: Mutex::new(Default::default()),
+            }),
+        }
+    }
+
+    /// Sets the number of retries which are always allowed in the window, the default is 10.
+    pub fn with_min_retries(mut self, min_retries: u32) -> Self {
+        self.min_retries = min_retries;
+        self
+    }
+
+    fn epoch(&self) -> u64 {
+        (self.window.start.elapsed().as_nanos()
+            / (BUDGET_WINDOW / BUDGET_BUCKETS as u32).as_nanos()) as u64
+    }
+
+    fn update(&self, f: impl FnOnce(&mut BudgetBucket)) {
+        let epoch = self.epoch();
+        let mut buckets = self.window.buckets.lock().unwrap();
+        let bucket = &mut buckets[(epoch % BUDGET_BUCKETS) as usize];
+        if bucket.epoch != epoch {
+            *bucket = BudgetBucket {
+                epoch,
+                ..Default::default()
+            };
+        }
+        f(bucket);
+    }
+
+    /// Records a request.
+    pub fn deposit(&self) {
+        self.update(|bucket| bucket.requests += 1);
+    }
+
+    /// Records a request unless it has been recorded by this budget, so the stacked retries
+    /// sharing a budget count each request once.
+    pub(crate) fn deposit_once(&self, callee: &mut Endpoint) {
+        let mut deposited = callee.get::<Deposited>().cloned().unwrap_or_default();
+        if deposited.0.iter().any(|w| Arc::ptr_eq(w, &self.window)) {
+            return;
+        }
+        self.deposit();
+        deposited.0.push(self.window.clone());
+        callee.insert(deposited);
+    }
+
+    /// Tries to take a retry from the budget, returns `false` if the budget is exhausted.
+    pub fn withdraw(&self) -> bool {
+        let epoch = self.epoch();
+        let (requests, retries) = self
+            .window
+            .buckets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|b| b.epoch + BUDGET_BUCKETS > epoch)
+            .fold((0, 0), |(req, ret), b| (req + b.requests, ret + b.retries));
+        let allowed = self.min_retries as f64 + self.ratio * requests as f64;
+        if (retries as f64) < allowed {
+            self.update(|bucket| bucket.retries += 1);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The retry state of a single call.
+pub(crate) struct RetryState<'a> {
+    policy: RetryPolicy,
+    budget: Option<&'a RetryBudget>,
+    deadline: Option<Instant>,
+    attempts: usize,
+}
+
+impl<'a> RetryState<'a> {
+    /// Starts a call, the policy in the callee tags overrides the default one.
+    pub(crate) fn new(
+        default: &RetryPolicy,
+        budget: Option<&'a RetryBudget>,
+        callee: &mut Endpoint,
+        timeout: Option<Duration>,
+    ) -> Self {
+        if let Some(budget) = budget {
+            budget.deposit_once(callee);
+        }
+        Self {
+            policy: callee.get::<RetryPolicy>().unwrap_or(default).clone(),
+            budget,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            attempts: 1,
+        }
+    }
+
+    /// Returns the backoff before the next attempt, or `None` if the call should not be retried.
+    pub(crate) fn next_backoff(&mut self) -> Option<Duration> {
+        if self.attempts >= self.policy.max_attempts {
+            return None;
+        }
+        let delay = self.policy.backoff.delay(self.attempts as u32);
+        if let Some(deadline) = self.deadline {
+            if Instant::now() + delay >= deadline {
+                return None;
+            }
+        }
+        if let Some(budget) = self.budget {
+            if !budget.withdraw() {
+                return None;
+            }
+        }
+        self.attempts += 1;
+        Some(delay)
+    }
+
+    pub(crate) async fn backoff(&mut self) -> bool {
+        match self.next_backoff() {
+            Some(delay) => {
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A request that can be sent again.
+pub trait Replay<Req> {
+    /// Returns a copy of the request for the next attempt, or `None` if the request cannot be
+    /// sent again, e.g. it has a streaming body.
+    fn replay(&self, req: &Req) -> Option<Req>;
+}
+
+/// Replays the requests by [`Clone`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CloneReplay;
+
+impl<Req: Clone> Replay<Req> for CloneReplay {
+    fn replay(&self, req: &Req) -> Option<Req> {
+        Some(req.clone())
+    }
+}
+
+impl<F, Req> Replay<Req> for F
+where
+    F: Fn(&Req) -> Option<Req>,
+{
+    fn replay(&self, req: &Req) -> Option<Req> {
+        self(req)
+    }
+}
+
+/// Never replays the requests.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoReplay;
+
+impl<Req> Replay<Req> for NoReplay {
+    fn replay(&self, _: &Req) -> Option<Req> {
+        None
+    }
+}
+
+/// A [`Layer`] that retries the requests by the [`RetryPolicy`].
+#[derive(Clone, Debug)]
+pub struct RetryLayer<R = CloneReplay> {
+    policy: RetryPolicy,
+    budget: Option<RetryBudget>,
+    replay: R,
+}
+
+impl RetryLayer {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            budget: None,
+            replay: CloneReplay,
+        }
+    }
+}
+
+impl<R> RetryLayer<R> {
+    /// Limits the retries by the [`RetryBudget`].
+    pub fn with_budget(mut self, budget: RetryBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Sets how to copy the requests for the retries, the default is [`Clone`].
+    pub fn with_replay<NR>(self, replay: NR) -> RetryLayer<NR> {
+        RetryLayer {
+            policy: self.policy,
+            budget: self.budget,
+            replay,
+        }
+    }
+}
+
+impl<S, R> Layer<S> for RetryLayer<R> {
+    type Service = RetryService<S, R>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        RetryService {
+            inner,
+            policy: self.policy,
+            budget: self.budget,
+            replay: self.replay,
+        }
+    }
+}
+
+/// The [`Service`] generated by [`RetryLayer`].
+#[derive(Clone)]
+pub struct RetryService<S, R = CloneReplay> {
+    inner: S,
+    policy: RetryPolicy,
+    budget: Option<RetryBudget>,
+    replay: R,
+}
+
+impl<Cx, Req, S, R> Service<Cx, Req> for RetryService<S, R>
+where
+    Cx: Context + Send + 'static,
+    Cx::Config: RpcTimeout,
+    Req: Send + 'static,
+    S: Service<Cx, Req> + Send + Sync + 'static,
+    S::Error: Retryable + fmt::Debug + Send,
+    R: Replay<Req> + Send + Sync,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+
+    async fn call(&self, cx: &mut Cx, req: Req) -> Result<Self::Response, Self::Error> {
+        let timeout = cx.rpc_info().config().rpc_timeout();
+        let mut state = RetryState::new(
+            &self.policy,
+            self.budget.as_ref(),
+            cx.rpc_info_mut().callee_mut(),
+            timeout,
+        );
+        // the address is set by the load balancer, which should pick again when retrying
+        let address = cx.rpc_info().callee().address.clone();
+        let mut req = req;
+        loop {
+            let next = self.replay.replay(&req);
+            let err = match self.inner.call(cx, req).await {
+                Ok(resp) => return Ok(resp),
+                Err(err) => err,
+            };
+            let Some(next) = next else {
+                return Err(err);
+            };
+            if !err.retryable() || !state.backoff().await {
+                return Err(err);
+            }
+            tracing::debug!(
+                "[VOLO] retry rpcinfo: {:?}, error: {:?}",
+                cx.rpc_info(),
+                err
+            );
+            cx.rpc_info_mut().callee_mut().address = address.clone();
+            req = next;
+        }
+    }
+}
+
+impl<S, R> fmt::Debug for RetryService<S, R>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryService")
+            .field("inner", &self.inner)
+            .field("policy", &self.policy)
+            .field("budget", &self.budget)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Backoff, RetryBudget, RetryPolicy, RetryState};
+    use crate::context::Endpoint;
+
+    #[test]
+    fn test_backoff() {
+        let backoff = Backoff::exponential(Duration::from_millis(10), Duration::from_millis(50));
+        assert_eq!(backoff.delay(1), Duration::from_millis(10));
+        assert_eq!(backoff.delay(2), Duration::from_millis(20));
+        assert_eq!(backoff.delay(3), Duration::from_millis(40));
+        assert_eq!(backoff.delay(4), Duration::from_millis(50));
+        assert_eq!(backoff.delay(100), Duration::from_millis(50));
+
+        let backoff = backoff.with_jitter(0.5);
+        for retry in 1..10 {
+            let delay = backoff.delay(retry);
+            assert!(delay <= Duration::from_millis(50));
+            assert!(delay >= Duration::from_millis(5));
+        }
+        assert_eq!(Backoff::none().delay(3), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_retry_budget() {
+        let budget = RetryBudget::new(0.5).with_min_retries(1);
+        assert!(budget.withdraw());
+        assert!(!budget.withdraw());
+        for _ in 0..4 {
+            budget.deposit();
+        }
+        assert!(budget.withdraw());
+        assert!(budget.withdraw());
+        assert!(!budget.withdraw());
+    }
+
+    #[test]
+    fn test_retry_state() {
+        let mut callee = Endpoint::new("service".into());
+        let policy = RetryPolicy::new(3);
+        let mut state = RetryState::new(&policy, None, &mut callee, None);
+        assert_eq!(state.next_backoff(), Some(Duration::ZERO));
+        assert_eq!(state.next_backoff(), Some(Duration::ZERO));
+        assert_eq!(state.next_backoff(), None);
+
+        // the policy in the callee tags overrides the default one
+        callee.insert(RetryPolicy::no_retry());
+        let mut state = RetryState::new(&policy, None, &mut callee, None);
+        assert_eq!(state.next_backoff(), None);
+
+        // the backoff exceeds the rpc timeout
+        let policy = RetryPolicy::new(3).with_backoff(Backoff::exponential(
+            Duration::from_secs(1),
+            Duration::from_secs(1),
+        ));
+        let mut callee = Endpoint::new("service".into());
+        let mut state =
+            RetryState::new(&policy, None, &mut callee, Some(Duration::from_millis(100)));
+        assert_eq!(state.next_backoff(), None);
+    }
+}